@@ -1,5 +1,5 @@
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::BTreeMap,
     fs::{self, File},
     io::{BufReader, BufWriter},
     path::{Path, PathBuf},
@@ -10,15 +10,20 @@ use mcpr_lib::{
     archive::{
         ArchiveReader, ArchiveWriter,
         directory::DirArchive,
-        zip::{ZipArchiveReader, ZipArchiveWriter},
+        zip::{EntryCompression, ZipArchiveReader, ZipArchiveWriter, ZipCompressionPolicy},
     },
     event::{
-        Event, EventSink, EventSource, PlaybackSpeed, ReplayFormat, ReplayInfo, State, Time,
-        detect_format, is_connection_init,
+        Event, EventSink, EventSource, PlaybackSpeed, ReplayFormat, ReplayInfo, State,
+        detect_format,
     },
     flashback::{FlashbackEventSink, FlashbackReader},
-    mcpr::{McprEventSink, ReplayReader},
+    mcpr::{self, McprEventSink, Packet, ReplayReader, ReplayWriter, SplitStrategy},
+    merge::{ProtocolMismatch, check_protocols},
+    names::display_name,
     protocol::parse_packet_id,
+    script,
+    stream::ReplayStream,
+    warnings::Warnings,
 };
 
 macro_rules! chmax {
@@ -40,6 +45,23 @@ enum OutputFormat {
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
+// --split-* は入力をそのまま区切るだけで、パケットのフィルタや時刻の変換を通さない
+#[command(group(
+    clap::ArgGroup::new("split")
+        .args(["split_every_ms", "split_every_packets"])
+        .conflicts_with_all([
+            "include_packets",
+            "exclude_packets",
+            "interval",
+            "speed",
+            "extract_id",
+            "limit",
+            "estimate_size",
+            "script",
+            "verbatim",
+            "mcversion",
+        ])
+))]
 struct Args {
     #[arg(short, long)]
     input: Vec<PathBuf>,
@@ -77,6 +99,68 @@ struct Args {
     /// flashback 入力で snapshot (初期状態の合成イベント) を読み飛ばす
     #[arg(long, default_value_t = false)]
     skip_snapshot: bool,
+
+    /// 単一の .mcpr 入力 → mcpr 出力で、残すパケットを再エンコードせず
+    /// 元のバイト列のまま写す (include/exclude 等のフィルタのみ適用)
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["interval", "speed", "limit", "estimate_size", "script"]
+    )]
+    verbatim: bool,
+
+    /// この Play パケット id を --extract-out へ .tmcpr として抜き出す
+    /// (--verbatim はイベントを流さないので併用できない)
+    #[arg(
+        long,
+        requires = "extract_out",
+        conflicts_with = "verbatim",
+        value_parser = packet_id_arg
+    )]
+    extract_id: Option<i32>,
+
+    /// --extract-id の書き出し先
+    #[arg(long, requires = "extract_id")]
+    extract_out: Option<PathBuf>,
+
+    /// id が範囲外等の壊れたパケットに当たったらエラーで止める
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// 書き出す前にフィルタだけの dry run を行い、出力サイズの見積もりを表示する
+    #[arg(long, default_value_t = false)]
+    estimate_size: bool,
+
+    /// protocol の違う入力の連結をエラーにせず、警告だけ出して続ける
+    #[arg(long, default_value_t = false)]
+    allow_mixed_protocols: bool,
+
+    /// JSON の編集スクリプトを単一の .mcpr 入力に適用して書き出す
+    /// (他のフィルタ指定は使わない)
+    #[arg(long)]
+    script: Option<PathBuf>,
+
+    /// 接続初期化以外のパケットをこの件数だけ書いて打ち切る (プレビュー用)
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// 単一の .mcpr 入力をリプレイ時刻でこの ms ごとに区切り、
+    /// 出力名に連番を付けた複数ファイルへ書き出す
+    #[arg(long)]
+    split_every_ms: Option<u32>,
+
+    /// 単一の .mcpr 入力を接続初期化以外のパケットこの件数ごとに区切り、
+    /// 出力名に連番を付けた複数ファイルへ書き出す
+    #[arg(long)]
+    split_every_packets: Option<usize>,
+
+    /// 出力の metadata に書く Minecraft バージョン (--protocol と組で指定する)
+    #[arg(long, requires = "protocol", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    mcversion: Option<String>,
+
+    /// 出力の metadata に書くプロトコル番号 (--mcversion と組で指定する)
+    #[arg(long, requires = "mcversion")]
+    protocol: Option<u32>,
 }
 
 impl Args {
@@ -86,6 +170,17 @@ impl Args {
     fn exclude_packets(&self) -> Vec<u8> {
         Self::parse_packet_ids(&self.exclude_packets)
     }
+    /// `--split-every-ms` / `--split-every-packets` (clap の `split` グループが
+    /// 片方だけであることと、ストリームの設定と併用しないことを保証する)。
+    fn split_strategy(&self) -> Option<SplitStrategy> {
+        self.split_every_ms
+            .map(SplitStrategy::EveryMillis)
+            .or(self.split_every_packets.map(SplitStrategy::EveryPackets))
+    }
+    /// `--mcversion` / `--protocol` の上書き (clap が両方揃っていることを保証する)。
+    fn version_override(&self) -> Option<(&str, u32)> {
+        Some((self.mcversion.as_deref()?, self.protocol?))
+    }
     fn parse_packet_ids(args: &[String]) -> Vec<u8> {
         args.iter()
             .map(|x| u8::try_from(parse_packet_id(x).expect("invalid packet id")).unwrap())
@@ -107,7 +202,7 @@ fn detect_and_open(path: &Path) -> anyhow::Result<(ReplayFormat, Box<dyn Archive
 
 fn open_archive_writer(
     path: &Path,
-    compression_level: Option<i64>,
+    policy: ZipCompressionPolicy,
 ) -> anyhow::Result<Box<dyn ArchiveWriter>> {
     if !path.exists()
         && path
@@ -120,7 +215,7 @@ fn open_archive_writer(
         Box::new(DirArchive::new(path))
     } else {
         let writer = BufWriter::new(File::create(path)?);
-        Box::new(ZipArchiveWriter::new(writer, compression_level))
+        Box::new(ZipArchiveWriter::with_policy(writer, policy))
     })
 }
 
@@ -132,9 +227,18 @@ enum AnySink {
 
 impl AnySink {
     fn create(output: &Path, args: &Args, info: &ReplayInfo) -> anyhow::Result<Self> {
-        let archive = open_archive_writer(output, args.compression_level)?;
+        let policy = match args.output_format {
+            OutputFormat::Mcpr => ZipCompressionPolicy::mcpr(args.compression_level),
+            OutputFormat::Flashback => {
+                ZipCompressionPolicy::new(EntryCompression::Deflated(args.compression_level))
+            }
+        };
+        let archive = open_archive_writer(output, policy)?;
+        let protocol = args
+            .version_override()
+            .map_or(info.protocol_version, |(_, protocol)| protocol);
         Ok(match args.output_format {
-            OutputFormat::Mcpr => AnySink::Mcpr(McprEventSink::new(archive, info.protocol_version)),
+            OutputFormat::Mcpr => AnySink::Mcpr(McprEventSink::new(archive, protocol)),
             OutputFormat::Flashback => {
                 AnySink::Flashback(FlashbackEventSink::new(archive, uuid::Uuid::new_v4())?)
             }
@@ -147,45 +251,40 @@ impl AnySink {
             AnySink::Flashback(sink) => sink,
         }
     }
-    fn report(&self) {
+    fn warnings(&self) -> &Warnings {
         match self {
-            AnySink::Mcpr(sink) => {
-                if sink.skipped_custom() > 0 {
-                    eprintln!(
-                        "note: {} custom events have no .mcpr representation and were dropped",
-                        sink.skipped_custom()
-                    );
-                }
-            }
-            AnySink::Flashback(sink) => {
-                if sink.skipped_packets() > 0 {
-                    eprintln!(
-                        "note: {} non-play/configuration packets were dropped",
-                        sink.skipped_packets()
-                    );
-                }
-                if sink.skipped_customs() > 0 {
-                    eprintln!(
-                        "note: {} unknown custom events were dropped",
-                        sink.skipped_customs()
-                    );
-                }
-            }
+            AnySink::Mcpr(sink) => sink.warnings(),
+            AnySink::Flashback(sink) => sink.warnings(),
+        }
+    }
+    /// 出力フォーマットで表現できず落としたものを種別ごとに報告する。
+    fn report(&self) {
+        for warning in self.warnings().iter() {
+            eprintln!("note: {}", warning);
         }
     }
 }
 
+/// state ごとの id 集計表の行数 ([`State`] の variant 数)。
+const STATES: [State; 5] = [
+    State::Handshaking,
+    State::Status,
+    State::Login,
+    State::Configuration,
+    State::Play,
+];
+
 struct Stats {
-    counts: [usize; 256],
-    sizes: [usize; 256],
+    counts: [[usize; 256]; STATES.len()],
+    sizes: [[usize; 256]; STATES.len()],
     customs: BTreeMap<String, (usize, usize)>,
 }
 
 impl Default for Stats {
     fn default() -> Self {
         Self {
-            counts: [0; 256],
-            sizes: [0; 256],
+            counts: [[0; 256]; STATES.len()],
+            sizes: [[0; 256]; STATES.len()],
             customs: BTreeMap::new(),
         }
     }
@@ -194,10 +293,12 @@ impl Default for Stats {
 impl Stats {
     fn record(&mut self, event: &Event) {
         match event {
-            Event::Packet { id, data, .. } => {
+            Event::Packet {
+                state, id, data, ..
+            } => {
                 if (0..256).contains(id) {
-                    self.counts[*id as usize] += 1;
-                    self.sizes[*id as usize] += data.len();
+                    self.counts[*state as usize][*id as usize] += 1;
+                    self.sizes[*state as usize][*id as usize] += data.len();
                 }
             }
             Event::Custom { name, data, .. } => {
@@ -212,25 +313,32 @@ impl Stats {
         }
     }
 
-    fn print(&self) {
+    /// `protocol` はパケット名の解決に使う ([`display_name`])。
+    fn print(&self, protocol: u32) {
         let mut table = vec![[
             "packet".to_string(),
             "count".to_string(),
             "total size".to_string(),
             "avg size".to_string(),
         ]];
-        for id in 0..256 {
-            let count = self.counts[id];
-            let size = self.sizes[id];
-            if count == 0 {
-                continue;
+        for state in STATES {
+            for id in 0..256 {
+                let count = self.counts[state as usize][id];
+                let size = self.sizes[state as usize][id];
+                if count == 0 {
+                    continue;
+                }
+                table.push([
+                    format!(
+                        "  \x1b[38;5;{}m{}\x1b[m",
+                        id,
+                        display_name(state, id as i32, protocol)
+                    ),
+                    format!("{}", count),
+                    format!("{}", size),
+                    format!("{:.2}", size as f32 / count as f32),
+                ]);
             }
-            table.push([
-                format!("  \x1b[38;5;{0}m0x{0:<02x}\x1b[m", id),
-                format!("{}", count),
-                format!("{}", size),
-                format!("{:.2}", size as f32 / count as f32),
-            ]);
         }
         let mut table_size = [0usize; 4];
         for row in &table {
@@ -238,9 +346,9 @@ impl Stats {
                 chmax!(table_size[i], row[i].len());
             }
         }
-        table_size[0] = table[0].len();
+        table_size[0] = table.iter().map(|row| row[0].len()).max().unwrap_or(0);
         for (i, row) in table.iter().enumerate() {
-            print!("{:>3} | {} ", i, row[0]);
+            print!("{:>3} | {:<width$} ", i, row[0], width = table_size[0]);
             for j in 1..4 {
                 print!("| {:>width$} ", row[j], width = table_size[j]);
             }
@@ -262,17 +370,43 @@ impl Stats {
     }
 }
 
+/// `--strict`: パケットイベントを [`Packet::validate`] で検査する。
+fn validate(event: Event) -> anyhow::Result<Event> {
+    let Event::Packet {
+        time,
+        state,
+        id,
+        data,
+    } = event
+    else {
+        return Ok(event);
+    };
+    let packet = Packet::new(
+        u32::try_from(time.as_millis()).unwrap_or(u32::MAX),
+        id,
+        data,
+    );
+    packet
+        .validate()
+        .map_err(|e| anyhow::anyhow!("invalid packet at {}ms: {}", time.as_millis(), e))?;
+    let (_, id, data) = packet.into_parts();
+    Ok(Event::Packet {
+        time,
+        state,
+        id,
+        data,
+    })
+}
+
 /// 1 入力分のイベントを共通パイプラインへ流す。
-fn process<S: EventSource>(
+fn process<S: EventSource + ?Sized>(
     source: &mut S,
     args: &Args,
-    is_first_input: bool,
-    offset_ms: u64,
-    play_filter: &[bool; 256],
+    stream: &mut ReplayStream,
     stats: &mut Option<Stats>,
     sink: &mut Option<AnySink>,
 ) -> anyhow::Result<ReplayInfo> {
-    let info = source.info().clone();
+    let info = source.info();
     eprintln!(
         "  mc {} / protocol {} / duration {}ms",
         info.mc_version, info.protocol_version, info.duration_ms
@@ -281,42 +415,185 @@ fn process<S: EventSource>(
     if sink.is_none()
         && let Some(output) = &args.output
     {
-        *sink = Some(AnySink::create(output, args, &info)?);
+        *sink = Some(AnySink::create(output, args, info)?);
     }
 
-    while let Some(mut event) = source.next_event()? {
-        *event.time_mut() = Time::from_millis(
-            args.speed
-                .scale_millis(event.time().as_millis())
-                .saturating_add(offset_ms),
-        );
-
-        if let Event::Packet { state, id, .. } = &event {
-            // Play パケットの include/exclude フィルタ
-            if *state == State::Play {
-                let keep = if (0..256).contains(id) {
-                    play_filter[*id as usize]
-                } else {
-                    args.unknow_packet
-                };
-                if !keep {
-                    continue;
-                }
-            }
-            // 2 個目以降の入力では接続初期化の重複を避ける
-            if !is_first_input && is_connection_init(*state, *id) {
-                continue;
-            }
-        }
-
+    stream.stream(source, |event| {
+        let event = if args.strict { validate(event)? } else { event };
         if let Some(stats) = stats {
             stats.record(&event);
         }
         if let Some(sink) = sink {
             sink.as_sink().push(event)?;
         }
+        Ok(())
+    })
+}
+
+/// `--estimate-size`: 全入力を dry run し、出力 .tmcpr のサイズ見積もりを返す。
+fn estimate_size(args: &Args, stream: &ReplayStream) -> anyhow::Result<u64> {
+    // McprEventSource は reader を借用するため、先に全入力を開いておく
+    let mut flashback = Vec::new();
+    let mut mcpr_readers = Vec::new();
+    for input in &args.input {
+        let (format, archive) = detect_and_open(input)?;
+        match format {
+            ReplayFormat::Flashback => flashback.push(Some(
+                FlashbackReader::new(archive).event_source(!args.skip_snapshot)?,
+            )),
+            ReplayFormat::ReplayMod => {
+                flashback.push(None);
+                mcpr_readers.push(ReplayReader::new(archive));
+            }
+        }
     }
-    Ok(info)
+    let mut mcpr_readers = mcpr_readers.iter_mut();
+    let mut sources = Vec::new();
+    for source in flashback {
+        let source: Box<dyn EventSource + '_> = match source {
+            Some(source) => Box::new(source),
+            None => Box::new(mcpr_readers.next().unwrap().event_source()?),
+        };
+        sources.push(source);
+    }
+    stream.estimate_output_size(&mut sources)
+}
+
+/// `--verbatim`: フレーム単位でフィルタし、残したパケットをバイト単位で保つ。
+///
+/// 時刻を書き換える設定 (速度・間隔・件数の上限) とは clap の
+/// `conflicts_with_all` で併用させない。
+fn run_verbatim(args: &Args, stream: &mut ReplayStream) -> anyhow::Result<()> {
+    anyhow::ensure!(args.input.len() == 1, "--verbatim takes exactly one input");
+    anyhow::ensure!(
+        args.output_format == OutputFormat::Mcpr,
+        "--verbatim only supports mcpr output"
+    );
+    let output = args
+        .output
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("--verbatim requires --output"))?;
+    let (format, archive) = detect_and_open(&args.input[0])?;
+    anyhow::ensure!(
+        format == ReplayFormat::ReplayMod,
+        "--verbatim only supports ReplayMod input"
+    );
+
+    let mut reader = ReplayReader::new(archive);
+    let mut writer = ReplayWriter::new(open_archive_writer(
+        output,
+        ZipCompressionPolicy::mcpr(args.compression_level),
+    )?);
+    let mut metadata = reader.read_metadata()?;
+    if let Some((mcversion, protocol)) = args.version_override() {
+        metadata.set_version(mcversion, protocol)?;
+    }
+    writer.write_metadata(metadata)?;
+    if let Some(visibility) = reader.read_visibility()? {
+        writer.write_visibility(&visibility)?;
+    }
+    let kept = reader.copy_recording(&mut writer, |reader, writer| {
+        Ok(stream.filter_frames(reader, writer)?)
+    })?;
+    eprintln!("  kept {} packets verbatim", kept);
+    Ok(())
+}
+
+/// 全入力の metadata だけを先に読み、protocol が揃っているかを確かめる。
+fn check_input_protocols(args: &Args) -> anyhow::Result<()> {
+    let mut infos = Vec::new();
+    for input in &args.input {
+        let (format, archive) = detect_and_open(input)?;
+        infos.push(match format {
+            ReplayFormat::ReplayMod => {
+                ReplayInfo::from(&ReplayReader::new(archive).read_metadata()?)
+            }
+            ReplayFormat::Flashback => {
+                ReplayInfo::from(&FlashbackReader::new(archive).get_metadata()?)
+            }
+        });
+    }
+    let policy = if args.allow_mixed_protocols {
+        ProtocolMismatch::Warn
+    } else {
+        ProtocolMismatch::Error
+    };
+    if let Some(warning) = check_protocols(&infos, policy)? {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(())
+}
+
+/// `--script`: 編集スクリプトを単一の ReplayMod 入力に適用する。
+fn run_script(args: &Args, script: &Path) -> anyhow::Result<()> {
+    anyhow::ensure!(args.input.len() == 1, "--script takes exactly one input");
+    anyhow::ensure!(
+        args.output_format == OutputFormat::Mcpr,
+        "--script only supports mcpr output"
+    );
+    let output = args
+        .output
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("--script requires --output"))?;
+    let edits = script::load(BufReader::new(File::open(script)?))?;
+    let (format, archive) = detect_and_open(&args.input[0])?;
+    anyhow::ensure!(
+        format == ReplayFormat::ReplayMod,
+        "--script only supports ReplayMod input"
+    );
+
+    let mut reader = ReplayReader::new(archive);
+    let mut writer = ReplayWriter::new(open_archive_writer(
+        output,
+        ZipCompressionPolicy::mcpr(args.compression_level),
+    )?);
+    script::apply(&mut reader, &mut writer, &edits)?;
+    eprintln!("  applied {} edits", edits.len());
+    Ok(())
+}
+
+/// 区切った `index` 番目の出力先。`out.mcpr` なら `out_1.mcpr`, `out_2.mcpr`, …
+fn numbered_output(output: &Path, index: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}_{}", stem, index + 1);
+    if let Some(ext) = output.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    output.with_file_name(name)
+}
+
+/// `--split-every-ms` / `--split-every-packets`: 単一の ReplayMod 入力を区切って
+/// 複数の .mcpr に書き出す。
+fn run_split(args: &Args, strategy: SplitStrategy) -> anyhow::Result<()> {
+    anyhow::ensure!(args.input.len() == 1, "--split-* takes exactly one input");
+    anyhow::ensure!(
+        args.output_format == OutputFormat::Mcpr,
+        "--split-* only supports mcpr output"
+    );
+    let output = args
+        .output
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("--split-* requires --output"))?;
+    let (format, archive) = detect_and_open(&args.input[0])?;
+    anyhow::ensure!(
+        format == ReplayFormat::ReplayMod,
+        "--split-* only supports ReplayMod input"
+    );
+
+    let mut reader = ReplayReader::new(archive);
+    let parts = mcpr::split(&mut reader, strategy, |index| {
+        let path = numbered_output(output, index);
+        eprintln!("  writing {:?}", path);
+        open_archive_writer(&path, ZipCompressionPolicy::mcpr(args.compression_level))
+    })?;
+    eprintln!("  split into {} files", parts.len());
+    Ok(())
+}
+
+/// clap の value parser: [`parse_packet_id`] の表記のパケット id。
+fn packet_id_arg(s: &str) -> Result<i32, String> {
+    parse_packet_id(s).ok_or_else(|| format!("invalid packet id: {s:?}"))
 }
 
 fn main() -> anyhow::Result<()> {
@@ -329,19 +606,55 @@ fn main() -> anyhow::Result<()> {
         "At least one input file is required"
     );
 
-    let mut play_filter = [args.include_packets.is_empty(); 256];
-    for packet in args.include_packets() {
-        play_filter[packet as usize] = true;
+    if args.input.len() > 1 {
+        check_input_protocols(&args)?;
+    }
+
+    let mut stream = ReplayStream::new();
+    stream
+        .include_packets(args.include_packets())
+        .exclude_packets(args.exclude_packets())
+        .unknown_packets(args.unknow_packet)
+        .interval(args.interval as u64)
+        .speed(args.speed);
+    if let Some(limit) = args.limit {
+        stream.limit_packets(limit);
+    }
+    if let Some(script) = &args.script {
+        run_script(&args, script)?;
+        println!("Finished!");
+        return Ok(());
     }
-    for packet in args.exclude_packets() {
-        play_filter[packet as usize] = false;
+
+    if let Some(strategy) = args.split_strategy() {
+        run_split(&args, strategy)?;
+        println!("Finished!");
+        return Ok(());
+    }
+
+    if args.verbatim {
+        run_verbatim(&args, &mut stream)?;
+        println!("Finished!");
+        return Ok(());
+    }
+
+    if args.estimate_size {
+        // .mcpr は zip 圧縮されるので、実際のファイルはこれより小さい
+        eprintln!(
+            "estimated output size: {} bytes (before compression)",
+            estimate_size(&args, &stream)?
+        );
+    }
+
+    // 抜き出し先はイベントを流す経路でだけ作る (他の経路で空ファイルを残さない)
+    if let (Some(id), Some(out)) = (args.extract_id, &args.extract_out) {
+        stream.extract_packets(id, Box::new(BufWriter::new(File::create(out)?)));
     }
 
     let mut stats = args.packet_details.then(Stats::default);
     let mut sink: Option<AnySink> = None;
-    let mut players = BTreeSet::new();
-    let mut merged_info: Option<ReplayInfo> = None;
-    let mut offset_ms = 0u64;
+    // 先頭入力 (ReplayMod) の visibility。mcpr 出力へそのまま引き継ぐ
+    let mut visibility = None;
 
     for (index, input) in args.input.iter().enumerate() {
         eprintln!();
@@ -356,39 +669,150 @@ fn main() -> anyhow::Result<()> {
             }
             ReplayFormat::ReplayMod => {
                 mcpr_reader = ReplayReader::new(archive);
+                if index == 0 {
+                    visibility = mcpr_reader.read_visibility()?;
+                }
                 Box::new(mcpr_reader.event_source()?)
             }
         };
-        let info = process(
-            &mut source,
-            &args,
-            index == 0,
-            offset_ms,
-            &play_filter,
-            &mut stats,
-            &mut sink,
-        )?;
-
-        players.extend(info.players.iter().cloned());
-        offset_ms += args.speed.scale_millis(info.duration_ms) + args.interval as u64;
-        merged_info.get_or_insert(info);
+        process(&mut source, &args, &mut stream, &mut stats, &mut sink)?;
     }
 
+    stream.flush_extracted()?;
+
     if let Some(mut sink) = sink {
-        let base = merged_info.expect("at least one input was processed");
-        let info = ReplayInfo {
-            duration_ms: offset_ms.saturating_sub(args.interval as u64),
-            players,
-            ..base
-        };
+        let mut info = stream
+            .merged_info()
+            .expect("at least one input was processed");
+        if let Some((mcversion, protocol)) = args.version_override() {
+            info.mc_version = mcversion.to_string();
+            info.protocol_version = protocol;
+        }
         sink.as_sink().finish(&info)?;
         sink.report();
+        if let (AnySink::Mcpr(sink), Some(visibility)) = (sink, visibility) {
+            ReplayWriter::new(sink.into_archive()).write_visibility(&visibility)?;
+        }
     }
 
     println!("Finished!");
 
     if let Some(stats) = &stats {
-        stats.print();
+        let protocol = stream.merged_info().map_or(0, |info| info.protocol_version);
+        stats.print(protocol);
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mcpr_lib::mcpr::MetaData;
+
+    #[test]
+    fn split_conflicts_with_stream_options() {
+        use clap::CommandFactory;
+        Args::command().debug_assert();
+
+        let parse = |extra: &[&str]| {
+            let base = ["mcpr-cli", "-i", "in.mcpr", "-o", "out.mcpr"];
+            Args::try_parse_from(base.iter().chain(extra))
+        };
+        assert!(parse(&["--split-every-ms", "1000"]).is_ok());
+        for extra in [
+            &["--exclude-packets", "0x2c"][..],
+            &["--speed", "2"],
+            &["--limit", "10"],
+            &["--split-every-packets", "10"],
+        ] {
+            let error = parse(&[&["--split-every-ms", "1000"][..], extra].concat()).unwrap_err();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn verbatim_conflicts_with_stream_options() {
+        let parse = |extra: &[&str]| {
+            let base = ["mcpr-cli", "-i", "in.mcpr", "-o", "out.mcpr", "--verbatim"];
+            Args::try_parse_from(base.iter().chain(extra))
+        };
+        assert!(
+            parse(&[
+                "--exclude-packets",
+                "0x2c",
+                "--protocol",
+                "770",
+                "--mcversion",
+                "1.21.5"
+            ])
+            .is_ok()
+        );
+        for extra in [
+            &["--speed", "2"][..],
+            &["--interval", "100"],
+            &["--limit", "10"],
+            &["--estimate-size"],
+            &["--extract-id", "0x3a", "--extract-out", "chats.tmcpr"],
+        ] {
+            let error = parse(extra).unwrap_err();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn extract_id_is_parsed_as_packet_id() {
+        let parse = |id: &str| {
+            Args::try_parse_from([
+                "mcpr-cli",
+                "-i",
+                "in.mcpr",
+                "--extract-id",
+                id,
+                "--extract-out",
+                "chats.tmcpr",
+            ])
+        };
+        assert_eq!(parse("0x3a").unwrap().extract_id, Some(0x3a));
+        let error = parse("chat").unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    /// 入力を開いてイベントを読むまでの CLI の経路で、1.20.2 より前の
+    /// リプレイが Configuration を経ずに Play へ入ること。
+    #[test]
+    fn legacy_replay_is_read_as_play() {
+        let dir = std::env::temp_dir().join(format!("mcpr-cli-legacy-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        {
+            let mut writer = ReplayWriter::new(
+                open_archive_writer(&dir, ZipCompressionPolicy::mcpr(None)).unwrap(),
+            );
+            writer
+                .write_metadata(MetaData {
+                    protocol: 763,
+                    mcversion: "1.20.1".to_string(),
+                    ..Default::default()
+                })
+                .unwrap();
+            let mut packets = writer.get_packet_writer().unwrap();
+            for (time, id) in [(0, 0x02), (0, 0x28), (50, 0x64)] {
+                packets.push(Packet::new(time, id, Box::new([]))).unwrap();
+            }
+            packets.flush().unwrap();
+        }
+
+        let (format, archive) = detect_and_open(&dir).unwrap();
+        assert_eq!(format, ReplayFormat::ReplayMod);
+        let mut reader = ReplayReader::new(archive);
+        let mut source = reader.event_source().unwrap();
+        let mut states = Vec::new();
+        while let Some(event) = source.next_event().unwrap() {
+            if let Event::Packet { state, .. } = event {
+                states.push(state);
+            }
+        }
+        drop(source);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(states, vec![State::Login, State::Play, State::Play]);
+    }
+}
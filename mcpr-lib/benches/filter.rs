@@ -0,0 +1,41 @@
+//! `ReplayStream::stream` のフィルタ処理スループット計測 (Criterion)。
+//!
+//! 合成した 100 万パケットの .tmcpr を読み、Play パケットの一部を
+//! exclude して流す。`cargo bench -p mcpr-lib` で実行する。
+
+use std::{hint::black_box, io::Cursor};
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use mcpr_lib::{
+    bench::make_synthetic_tmcpr, event::ReplayInfo, mcpr::McprEventSource, stream::ReplayStream,
+};
+
+const PACKETS: usize = 1_000_000;
+
+fn filter(c: &mut Criterion) {
+    let tmcpr = make_synthetic_tmcpr(PACKETS);
+    let mut group = c.benchmark_group("filter");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(tmcpr.len() as u64));
+    group.bench_function("exclude_0x00..0x40", |b| {
+        b.iter(|| {
+            let mut stream = ReplayStream::new();
+            stream.exclude_packets(0x00..0x40);
+            let mut source =
+                McprEventSource::new(Cursor::new(tmcpr.as_slice()), ReplayInfo::default());
+            let mut kept = 0usize;
+            stream
+                .stream(&mut source, |event| {
+                    black_box(&event);
+                    kept += 1;
+                    Ok(())
+                })
+                .unwrap();
+            kept
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, filter);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+//! 任意のバイト列を VarInt / VarLong / String / ID or X / ID Set のデコーダに与え、
+//! panic せず `Ok` か `Err` を返すことを確かめる。
+//!
+//! `cd mcpr-lib && cargo +nightly fuzz run decode`
+
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use mcpr_lib::protocol::{Deserializer, read_id_set};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Cursor::new(data).read_varint();
+    let _ = Cursor::new(data).read_varlong();
+    let _ = Cursor::new(data).read_string();
+    let _ = Cursor::new(data).read_id_or(|c| c.read_string());
+    let _ = read_id_set(&mut Cursor::new(data));
+
+    // 先頭から続けて読み、途中で止まるまで消費する
+    let mut cursor = Cursor::new(data);
+    while cursor.read_string().is_ok() {}
+});
@@ -14,6 +14,87 @@ pub trait ArchiveReader {
         &'this mut self,
         filename: &str,
     ) -> anyhow::Result<Box<dyn std::io::Read + 'this>>;
+
+    /// エントリの展開後サイズ (進捗表示用)。分からなければ None。
+    fn entry_len(&mut self, filename: &str) -> anyhow::Result<Option<u64>> {
+        let _ = filename;
+        Ok(None)
+    }
+
+    /// 全エントリの名前 (ディレクトリ区切りは `/`)。一覧を出せない実装はエラー。
+    fn entries(&mut self) -> anyhow::Result<Vec<String>> {
+        anyhow::bail!("this archive cannot list its entries")
+    }
+
+    /// `filename` のエントリがあるか。既定は [`Self::entries`] から探す。
+    fn contains(&mut self, filename: &str) -> anyhow::Result<bool> {
+        Ok(self.entries()?.iter().any(|entry| entry == filename))
+    }
+}
+
+/// `src` の `entries` を中身を解釈せずに `dst` へ写す。`entries` が空なら
+/// [`ArchiveReader::entries`] の全エントリ。戻り値は写したエントリ数。
+///
+/// ディレクトリから zip なら詰め直し、zip から zip なら `dst` の圧縮設定での
+/// 再圧縮になる。
+pub fn recopy(
+    mut src: impl ArchiveReader,
+    mut dst: impl ArchiveWriter,
+    entries: &[&str],
+) -> anyhow::Result<usize> {
+    let listed;
+    let entries: Vec<&str> = if entries.is_empty() {
+        listed = src.entries()?;
+        listed.iter().map(String::as_str).collect()
+    } else {
+        entries.to_vec()
+    };
+    for entry in &entries {
+        let mut reader = src.get_reader(entry)?;
+        let mut writer = dst.get_writer(entry)?;
+        std::io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+    }
+    Ok(entries.len())
+}
+
+/// 展開後 `max` バイトを超えて読もうとするとエラーにする Read。
+///
+/// 圧縮率の極端なエントリ (zip bomb) を読み切る前に止めるためのもの。
+pub struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R> LimitedReader<R> {
+    pub fn new(inner: R, max: u64) -> Self {
+        Self {
+            inner,
+            remaining: max,
+        }
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 {
+            // 上限ちょうどで終わるエントリと区別するため 1 バイトだけ覗く
+            let mut probe = [0u8; 1];
+            return match self.inner.read(&mut probe)? {
+                0 => Ok(0),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "archive entry exceeds the uncompressed size limit",
+                )),
+            };
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..len])?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
 }
 
 impl<T: ?Sized + ArchiveWriter> ArchiveWriter for Box<T> {
@@ -32,6 +113,42 @@ impl<T: ?Sized + ArchiveReader> ArchiveReader for Box<T> {
     ) -> anyhow::Result<Box<dyn std::io::Read + 'this>> {
         (**self).get_reader(filename)
     }
+    fn entry_len(&mut self, filename: &str) -> anyhow::Result<Option<u64>> {
+        (**self).entry_len(filename)
+    }
+    fn entries(&mut self) -> anyhow::Result<Vec<String>> {
+        (**self).entries()
+    }
+    fn contains(&mut self, filename: &str) -> anyhow::Result<bool> {
+        (**self).contains(filename)
+    }
+}
+
+impl<T: ?Sized + ArchiveWriter> ArchiveWriter for &mut T {
+    fn get_writer<'this>(
+        &'this mut self,
+        filename: &str,
+    ) -> anyhow::Result<Box<dyn std::io::Write + 'this>> {
+        (**self).get_writer(filename)
+    }
+}
+
+impl<T: ?Sized + ArchiveReader> ArchiveReader for &mut T {
+    fn get_reader<'this>(
+        &'this mut self,
+        filename: &str,
+    ) -> anyhow::Result<Box<dyn std::io::Read + 'this>> {
+        (**self).get_reader(filename)
+    }
+    fn entry_len(&mut self, filename: &str) -> anyhow::Result<Option<u64>> {
+        (**self).entry_len(filename)
+    }
+    fn entries(&mut self) -> anyhow::Result<Vec<String>> {
+        (**self).entries()
+    }
+    fn contains(&mut self, filename: &str) -> anyhow::Result<bool> {
+        (**self).contains(filename)
+    }
 }
 
 /// crate 内 unit test 共用のメモリ上アーカイブ。
@@ -58,6 +175,14 @@ pub(crate) mod testing {
                 .ok_or_else(|| anyhow::anyhow!("no such file: {}", filename))?;
             Ok(Box::new(Cursor::new(data.clone())))
         }
+        fn entry_len(&mut self, filename: &str) -> anyhow::Result<Option<u64>> {
+            Ok(self.0.get(filename).map(|data| data.len() as u64))
+        }
+        fn entries(&mut self) -> anyhow::Result<Vec<String>> {
+            let mut entries: Vec<String> = self.0.keys().cloned().collect();
+            entries.sort();
+            Ok(entries)
+        }
     }
 
     impl ArchiveWriter for MemArchive {
@@ -69,3 +194,60 @@ pub(crate) mod testing {
         }
     }
 }
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use super::{
+        ArchiveReader, ArchiveWriter,
+        directory::DirArchive,
+        recopy,
+        zip::{ZipArchiveReader, ZipArchiveWriter},
+    };
+
+    #[test]
+    fn recopy_packs_directory_into_zip() {
+        let dir = std::env::temp_dir().join("mcpr_editor_recopy");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let files: [(&str, &[u8]); 3] = [
+            ("metaData.json", b"{}"),
+            ("recording.tmcpr", &[1, 2, 3, 4]),
+            ("level_chunk_caches/0", &[9; 300]),
+        ];
+        let mut source = DirArchive::new(&dir);
+        for (name, content) in files {
+            source.get_writer(name).unwrap().write_all(content).unwrap();
+        }
+
+        let mut zip = ZipArchiveWriter::new(Cursor::new(Vec::new()), Some(9));
+        assert_eq!(recopy(&mut source, &mut zip, &[]).unwrap(), files.len());
+        let mut packed = ZipArchiveReader::new(zip.finish().unwrap()).unwrap();
+        let mut entries = packed.entries().unwrap();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec!["level_chunk_caches/0", "metaData.json", "recording.tmcpr"]
+        );
+        for (name, content) in files {
+            let mut read = Vec::new();
+            packed
+                .get_reader(name)
+                .unwrap()
+                .read_to_end(&mut read)
+                .unwrap();
+            assert_eq!(read, content, "{name}");
+        }
+
+        // 名前を指定すればそれだけを写す
+        let mut zip = ZipArchiveWriter::new(Cursor::new(Vec::new()), None);
+        assert_eq!(
+            recopy(&mut source, &mut zip, &["metaData.json"]).unwrap(),
+            1
+        );
+        let mut packed = ZipArchiveReader::new(zip.finish().unwrap()).unwrap();
+        assert_eq!(packed.entries().unwrap(), vec!["metaData.json"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -15,6 +15,9 @@ impl DirArchive {
             path: path.as_ref().to_path_buf(),
         }
     }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
     pub fn exists<S: AsRef<Path>>(&self, path: S) -> bool {
         self.path.join(path).exists()
     }
@@ -26,6 +29,10 @@ impl ArchiveWriter for DirArchive {
         filename: &str,
     ) -> anyhow::Result<Box<dyn std::io::Write + 'this>> {
         let path = self.path.join(filename);
+        // `level_chunk_caches/0` のような入れ子のエントリ名も書けるように
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
         Ok(Box::new(File::create(path)?))
     }
 }
@@ -38,4 +45,57 @@ impl ArchiveReader for DirArchive {
         let path = self.path.join(filename);
         Ok(Box::new(File::open(path)?))
     }
+    fn entry_len(&mut self, filename: &str) -> anyhow::Result<Option<u64>> {
+        let path = self.path.join(filename);
+        Ok(Some(std::fs::metadata(path)?.len()))
+    }
+    fn contains(&mut self, filename: &str) -> anyhow::Result<bool> {
+        Ok(self.path.join(filename).is_file())
+    }
+    fn entries(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut entries = Vec::new();
+        let mut dirs = vec![self.path.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let relative = path.strip_prefix(&self.path)?;
+                let names: Vec<_> = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect();
+                entries.push(names.join("/"));
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn entry_len_stats_the_file() {
+        let dir = std::env::temp_dir().join("mcpr_editor_dir_entry_len");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut archive = DirArchive::new(&dir);
+        archive
+            .get_writer("recording.tmcpr")
+            .unwrap()
+            .write_all(&[0u8; 123])
+            .unwrap();
+        assert_eq!(archive.entry_len("recording.tmcpr").unwrap(), Some(123));
+        assert!(archive.entry_len("missing").is_err());
+        assert!(archive.contains("recording.tmcpr").unwrap());
+        assert!(!archive.contains("missing").unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
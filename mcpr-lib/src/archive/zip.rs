@@ -1,34 +1,112 @@
 use std::io::{Read, Seek, Write};
 
 use zip::{
-    ZipArchive, ZipWriter,
+    CompressionMethod, ZipArchive, ZipWriter,
     result::ZipError,
     write::{FileOptions, SimpleFileOptions},
 };
 
 use super::{ArchiveReader, ArchiveWriter};
 
+/// 1 エントリの圧縮方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryCompression {
+    /// 無圧縮 (JPEG など圧縮済みデータや、小さな JSON 向け)。
+    Stored,
+    /// Deflate。レベル None はライブラリ既定。
+    Deflated(Option<i64>),
+}
+
+/// エントリ名パターンから圧縮方式を引く規則表。
+///
+/// パターンは完全一致、`*` 始まりの後方一致 (`*.json`)、`*` 終わりの
+/// 前方一致 (`level_chunk_caches/*`) のいずれか。先に追加した規則が優先し、
+/// どれにも当たらなければ既定の方式を使う。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipCompressionPolicy {
+    rules: Vec<(String, EntryCompression)>,
+    default: EntryCompression,
+}
+
+impl ZipCompressionPolicy {
+    /// 規則なし (全エントリ `default`)。
+    pub fn new(default: EntryCompression) -> Self {
+        Self {
+            rules: Vec::new(),
+            default,
+        }
+    }
+    /// .mcpr 向けの既定: JSON と `thumb` (JPEG) は Stored、
+    /// それ以外 (recording.tmcpr 等) は指定レベルの Deflate。
+    pub fn mcpr(compression_level: Option<i64>) -> Self {
+        Self::new(EntryCompression::Deflated(compression_level))
+            .with_rule("*.json", EntryCompression::Stored)
+            .with_rule(crate::mcpr::THUMB_FILE, EntryCompression::Stored)
+    }
+    pub fn with_rule(mut self, pattern: &str, compression: EntryCompression) -> Self {
+        self.rules.push((pattern.to_string(), compression));
+        self
+    }
+    pub fn compression_for(&self, filename: &str) -> EntryCompression {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern_matches(pattern, filename))
+            .map_or(self.default, |(_, compression)| *compression)
+    }
+}
+
+fn pattern_matches(pattern: &str, filename: &str) -> bool {
+    if let Some(suffix) = pattern.strip_prefix('*') {
+        filename.ends_with(suffix)
+    } else if let Some(prefix) = pattern.strip_suffix('*') {
+        filename.starts_with(prefix)
+    } else {
+        pattern == filename
+    }
+}
+
 pub struct ZipArchiveWriter<W: Write + Seek> {
     zip: ZipWriter<W>,
-    option: FileOptions<'static, ()>,
+    policy: ZipCompressionPolicy,
 }
 
 impl<W: Write + Seek> ZipArchiveWriter<W> {
+    /// 全エントリを同じレベルの Deflate で書く。
     pub fn new(writer: W, compression_level: Option<i64>) -> Self {
+        Self::with_policy(
+            writer,
+            ZipCompressionPolicy::new(EntryCompression::Deflated(compression_level)),
+        )
+    }
+
+    /// エントリ名ごとに `policy` の圧縮方式で書く。
+    pub fn with_policy(writer: W, policy: ZipCompressionPolicy) -> Self {
         Self {
             zip: ZipWriter::new(writer),
-            // default() は wasm で未実装の SystemTime::now() を呼ぶため、
-            // mtime 固定の DEFAULT から組み立てる。
-            option: SimpleFileOptions::DEFAULT
-                .compression_method(zip::CompressionMethod::Deflated)
-                .compression_level(compression_level),
+            policy,
+        }
+    }
+
+    fn options_for(&self, filename: &str) -> FileOptions<'static, ()> {
+        // default() は wasm で未実装の SystemTime::now() を呼ぶため、
+        // mtime 固定の DEFAULT から組み立てる。
+        let options = SimpleFileOptions::DEFAULT;
+        match self.policy.compression_for(filename) {
+            EntryCompression::Stored => options.compression_method(CompressionMethod::Stored),
+            EntryCompression::Deflated(level) => options
+                .compression_method(CompressionMethod::Deflated)
+                .compression_level(level),
         }
     }
 
-    /// アーカイブを finalize して内側の writer を取り戻す。
-    /// in-memory 書き出し (`Cursor<Vec<u8>>`) でバイト列を回収するために使う。
-    pub fn finish(self) -> zip::result::ZipResult<W> {
-        self.zip.finish()
+    /// アーカイブを finalize (central directory を書き出し) して内側の
+    /// writer を取り戻す。in-memory 書き出し (`Cursor<Vec<u8>>`) で
+    /// バイト列を回収するために使う。
+    ///
+    /// drop でも finalize されるがエラーは握りつぶされるため、書き込み
+    /// 失敗を検出したい場合は明示的に呼ぶ。
+    pub fn finish(self) -> anyhow::Result<W> {
+        Ok(self.zip.finish()?)
     }
 }
 
@@ -37,7 +115,8 @@ impl<W: Write + Seek> ArchiveWriter for ZipArchiveWriter<W> {
         &'this mut self,
         filename: &str,
     ) -> anyhow::Result<Box<dyn Write + 'this>> {
-        self.zip.start_file(filename, self.option)?;
+        let options = self.options_for(filename);
+        self.zip.start_file(filename, options)?;
         Ok(Box::new(&mut self.zip))
     }
 }
@@ -59,6 +138,20 @@ impl<R: Read + Seek> ArchiveReader for ZipArchiveReader<R> {
         let file = self.zip.by_name(filename)?;
         Ok(Box::new(file))
     }
+    fn entry_len(&mut self, filename: &str) -> anyhow::Result<Option<u64>> {
+        Ok(Some(self.zip.by_name(filename)?.size()))
+    }
+    fn entries(&mut self) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .zip
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .map(str::to_string)
+            .collect())
+    }
+    fn contains(&mut self, filename: &str) -> anyhow::Result<bool> {
+        Ok(self.zip.index_for_name(filename).is_some())
+    }
 }
 
 #[cfg(test)]
@@ -102,6 +195,102 @@ mod tests {
         assert_eq!(b, vec![0u8; 256]);
     }
 
+    #[test]
+    fn entry_len_reports_uncompressed_size() {
+        let mut reader = ZipArchiveReader::new(Cursor::new(write_archive())).unwrap();
+        assert_eq!(reader.entry_len("a.txt").unwrap(), Some(5));
+        assert_eq!(reader.entry_len("dir/b.bin").unwrap(), Some(256));
+        assert!(reader.entry_len("missing").is_err());
+        assert!(reader.contains("dir/b.bin").unwrap());
+        assert!(!reader.contains("missing").unwrap());
+    }
+
+    /// `limit` bytes を超えた書き込みを失敗させる writer。
+    struct FailingWriter {
+        inner: Cursor<Vec<u8>>,
+        limit: u64,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.inner.position() + buf.len() as u64 > self.limit {
+                return Err(std::io::Error::other("disk full"));
+            }
+            self.inner.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FailingWriter {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn finish_surfaces_write_error() {
+        // local header は書けるが、圧縮データ・central directory で溢れる
+        let sink = FailingWriter {
+            inner: Cursor::new(Vec::new()),
+            limit: 40,
+        };
+        let mut writer = ZipArchiveWriter::new(sink, None);
+        writer
+            .get_writer("a.txt")
+            .unwrap()
+            .write_all(&[7u8; 1024])
+            .unwrap();
+        assert!(writer.finish().is_err());
+    }
+
+    #[test]
+    fn policy_picks_compression_per_entry() {
+        let mut writer = ZipArchiveWriter::with_policy(
+            Cursor::new(Vec::new()),
+            ZipCompressionPolicy::mcpr(Some(9)),
+        );
+        for name in ["metaData.json", "thumb", "recording.tmcpr"] {
+            writer
+                .get_writer(name)
+                .unwrap()
+                .write_all(&[0u8; 4096])
+                .unwrap();
+        }
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let mut zip = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let method = |zip: &mut ZipArchive<_>, name| zip.by_name(name).unwrap().compression();
+        assert_eq!(method(&mut zip, "metaData.json"), CompressionMethod::Stored);
+        assert_eq!(method(&mut zip, "thumb"), CompressionMethod::Stored);
+        assert_eq!(
+            method(&mut zip, "recording.tmcpr"),
+            CompressionMethod::Deflated
+        );
+    }
+
+    #[test]
+    fn policy_rules_match_in_order() {
+        let policy = ZipCompressionPolicy::new(EntryCompression::Deflated(None))
+            .with_rule("level_chunk_caches/*", EntryCompression::Deflated(Some(1)))
+            .with_rule("*.flashback", EntryCompression::Stored)
+            .with_rule("c0.flashback", EntryCompression::Deflated(Some(9)));
+        assert_eq!(
+            policy.compression_for("level_chunk_caches/0"),
+            EntryCompression::Deflated(Some(1))
+        );
+        // 先の規則が優先する
+        assert_eq!(
+            policy.compression_for("c0.flashback"),
+            EntryCompression::Stored
+        );
+        assert_eq!(
+            policy.compression_for("metadata.json"),
+            EntryCompression::Deflated(None)
+        );
+    }
+
     #[test]
     fn output_is_deterministic() {
         // mtime を固定しているため同一入力からの出力はバイト単位で一致する。
@@ -0,0 +1,63 @@
+//! パケット列の分割 (分割アップロードやサイズ上限付きの分割書き出し用)。
+
+use crate::mcpr::Packet;
+
+/// 連続するパケットを、.tmcpr 上のバイト数 ([`Packet::frame_size`])
+/// の合計が `max_bytes` を超えない範囲でまとめる。
+///
+/// 次の 1 件を足すと超える時点で区切る。1 件だけで `max_bytes` を超える
+/// パケットは、それだけで 1 つの塊になる。順序は入力のまま。
+pub fn by_byte_size(
+    packets: impl IntoIterator<Item = Packet>,
+    max_bytes: u64,
+) -> impl Iterator<Item = Vec<Packet>> {
+    let mut packets = packets.into_iter().peekable();
+    std::iter::from_fn(move || {
+        let mut batch = Vec::new();
+        let mut size = 0u64;
+        while let Some(packet) = packets.peek() {
+            let len = packet.frame_size();
+            if !batch.is_empty() && size + len > max_bytes {
+                break;
+            }
+            size += len;
+            batch.extend(packets.next());
+        }
+        (!batch.is_empty()).then_some(batch)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// フレーム全体で `size` バイトになるパケット (id 0x2c は VarInt 1 バイト)。
+    fn sized(time: u32, size: usize) -> Packet {
+        Packet::new(time, 0x2c, vec![0; size - 9].into())
+    }
+
+    #[test]
+    fn groups_until_the_next_packet_would_exceed() {
+        let packets = vec![
+            sized(0, 40),
+            sized(1, 50),
+            sized(2, 20),
+            sized(3, 100),
+            sized(4, 300),
+            sized(5, 10),
+        ];
+        let batches: Vec<Vec<u32>> = by_byte_size(packets, 100)
+            .map(|batch| batch.iter().map(Packet::time).collect())
+            .collect();
+        // 40+50 の次の 20 で超える / ちょうど 100 は 1 件で収まる / 300 は単独
+        assert_eq!(
+            batches,
+            vec![vec![0, 1], vec![2], vec![3], vec![4], vec![5]]
+        );
+    }
+
+    #[test]
+    fn empty_input_yields_no_batches() {
+        assert_eq!(by_byte_size(Vec::new(), 100).count(), 0);
+    }
+}
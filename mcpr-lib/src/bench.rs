@@ -0,0 +1,81 @@
+//! 性能計測・負荷試験用の合成リプレイ生成。
+//!
+//! `benches/` の計測と、ライブラリ利用者のストレステストで共通に使う。
+//! 乱数は固定シードの xorshift で、同じ引数からは常に同じバイト列になる。
+
+use crate::{
+    mcpr::Packet,
+    protocol::{FINISH_CONFIGURATION_PACKET_ID, LOGIN_SUCCESS_PACKET_ID},
+};
+
+/// 合成 Play パケットの body 長の上限 (bytes)。
+const MAX_SYNTHETIC_BODY: usize = 512;
+
+/// `n_packets` 件のパケットからなる .tmcpr バイト列を合成する。
+///
+/// 先頭 2 件は Login Success / Finish Configuration (state 遷移が成立し、
+/// 以降は Play として読まれる)。残りは id 0x00..0x80・body 0..512 bytes に
+/// ばらけた Play パケットで、時刻は 0〜2ms 刻みで単調に進む。
+pub fn make_synthetic_tmcpr(n_packets: usize) -> Vec<u8> {
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    let mut buf = Vec::new();
+    let mut time = 0u32;
+    for i in 0..n_packets {
+        let (id, len) = match i {
+            0 => (LOGIN_SUCCESS_PACKET_ID, 0),
+            1 => (FINISH_CONFIGURATION_PACKET_ID, 0),
+            _ => {
+                let r = rng.next();
+                ((r % 0x80) as i32, (r >> 8) as usize % MAX_SYNTHETIC_BODY)
+            }
+        };
+        let data: Box<[u8]> = (0..len).map(|j| (i + j) as u8).collect();
+        Packet::new(time, id, data)
+            .write_to(&mut buf)
+            .expect("writing to Vec never fails");
+        time += (rng.next() % 3) as u32;
+    }
+    buf
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, io::Cursor};
+
+    use super::*;
+    use crate::{event::State, mcpr::ReadablePacketStream};
+
+    #[test]
+    fn synthetic_tmcpr_has_exactly_n_readable_packets() {
+        for n in [0, 1, 2, 1000] {
+            let bytes = make_synthetic_tmcpr(n);
+            let packets: Vec<_> =
+                ReadablePacketStream::new(State::Login, Cursor::new(bytes)).collect();
+            assert_eq!(packets.len(), n);
+        }
+    }
+
+    #[test]
+    fn synthetic_tmcpr_varies_ids_and_sizes() {
+        let bytes = make_synthetic_tmcpr(1000);
+        let packets: Vec<_> = ReadablePacketStream::new(State::Login, Cursor::new(bytes)).collect();
+        let ids: HashSet<i32> = packets.iter().map(|(_, p)| p.id()).collect();
+        let sizes: HashSet<usize> = packets.iter().map(|(_, p)| p.data().len()).collect();
+        assert!(ids.len() > 50);
+        assert!(sizes.len() > 50);
+        assert!(packets.windows(2).all(|w| w[0].1.time() <= w[1].1.time()));
+        assert_eq!(packets[2].0, State::Play);
+        assert_eq!(make_synthetic_tmcpr(1000), make_synthetic_tmcpr(1000));
+    }
+}
@@ -0,0 +1,407 @@
+//! チャットパケットの書き出しと本文の書き換え。
+//!
+//! 1.20.3 以降のテキストコンポーネントは network NBT (root 名無し) で
+//! 送られる。ここでは表示用の平文だけが欲しいので、`text` / `extra` /
+//! `translate` を辿って連結し、スタイル等は読み飛ばす
+//! ([`flatten_component`])。パケット id は 1.21.5 (protocol 770) 以降の値。
+
+use std::io::{self, Cursor, Read, Write};
+
+use serde::Serialize;
+
+use crate::{
+    event::State,
+    mcpr::Packet,
+    nbt::Nbt,
+    protocol::{Deserializer, Serializer, read_chat_type, read_exact_vec},
+};
+
+/// Disguised Chat Message (コマンド経由の署名無しチャット)。
+pub const DISGUISED_CHAT_PACKET_ID: i32 = 0x1d;
+/// Player Chat Message。
+pub const PLAYER_CHAT_PACKET_ID: i32 = 0x3a;
+/// System Chat Message。
+pub const SYSTEM_CHAT_PACKET_ID: i32 = 0x72;
+
+/// JSONL の 1 行。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChatLine {
+    pub time: u32,
+    pub state: String,
+    /// 送信者の表示名。System Chat では None。
+    pub sender: Option<String>,
+    pub text: String,
+}
+
+/// テキストコンポーネント (network NBT) を平文へ潰す。
+///
+/// `text` の後に `translate` (あれば `fallback`、無ければキーと `with` の引数)、
+/// 最後に `extra` を順に連結する。色・クリックイベント等は捨てる。
+pub fn flatten_component<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut out = String::new();
+    flatten_nbt(&reader.read_nbt()?, &mut out);
+    Ok(out)
+}
+
+fn flatten_nbt(nbt: &Nbt, out: &mut String) {
+    match nbt {
+        Nbt::String(text) => out.push_str(text),
+        Nbt::List(_, items) => {
+            for item in items {
+                flatten_nbt(item, out);
+            }
+        }
+        Nbt::Compound(_) => flatten_compound(nbt, out),
+        _ => {}
+    }
+}
+
+fn flatten_compound(nbt: &Nbt, out: &mut String) {
+    let string = |name| match nbt.get(name) {
+        Some(Nbt::String(value)) => Some(value.as_str()),
+        _ => None,
+    };
+    out.push_str(string("text").unwrap_or_default());
+    match (string("fallback"), string("translate"), nbt.get("with")) {
+        (Some(fallback), _, _) => out.push_str(fallback),
+        (None, Some(key), Some(Nbt::List(_, with))) if !with.is_empty() => {
+            let with: Vec<String> = with
+                .iter()
+                .map(|arg| {
+                    let mut text = String::new();
+                    flatten_nbt(arg, &mut text);
+                    text
+                })
+                .collect();
+            out.push_str(&format!("{}[{}]", key, with.join(", ")));
+        }
+        (None, Some(key), _) => out.push_str(key),
+        (None, None, _) => {}
+    }
+    if let Some(extra @ Nbt::List(..)) = nbt.get("extra") {
+        flatten_nbt(extra, out);
+    }
+}
+
+/// 認識できるチャットパケットなら (送信者, 本文)。
+fn read_chat(id: i32, data: &[u8]) -> io::Result<Option<(Option<String>, String)>> {
+    let mut cursor = Cursor::new(data);
+    match id {
+        SYSTEM_CHAT_PACKET_ID => Ok(Some((None, flatten_component(&mut cursor)?))),
+        DISGUISED_CHAT_PACKET_ID => {
+            let text = flatten_component(&mut cursor)?;
+            skip_chat_type(&mut cursor)?;
+            let sender = flatten_component(&mut cursor)?;
+            Ok(Some((Some(sender), text)))
+        }
+        PLAYER_CHAT_PACKET_ID => {
+            let _global_index = cursor.read_varint()?;
+            let _sender_uuid = cursor.read_uuid()?;
+            let _index = cursor.read_varint()?;
+            if cursor.read_bool()? {
+                read_exact_vec(&mut cursor, 256, "message signature")?;
+            }
+            let body = cursor.read_string()?;
+            let _timestamp = cursor.read_long()?;
+            let _salt = cursor.read_long()?;
+            for _ in 0..cursor.read_varint()? {
+                if cursor.read_varint()? == 0 {
+                    read_exact_vec(&mut cursor, 256, "previous message signature")?;
+                }
+            }
+            let unsigned = if cursor.read_bool()? {
+                Some(flatten_component(&mut cursor)?)
+            } else {
+                None
+            };
+            // filter type 2 (PARTIALLY_FILTERED) だけ BitSet が続く
+            if cursor.read_varint()? == 2 {
+                for _ in 0..cursor.read_varint()? {
+                    cursor.read_long()?;
+                }
+            }
+            skip_chat_type(&mut cursor)?;
+            let sender = flatten_component(&mut cursor)?;
+            Ok(Some((Some(sender), unsigned.unwrap_or(body))))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// チャットパケットの本文を `f` に通して書き換えた body。チャット以外は None。
+///
+/// 本文のコンポーネントは `{text: ...}` だけの compound に作り直す
+/// (色・装飾は失われる)。Player Chat は署名付き本文と unsigned content の
+/// 両方を書き換え、合わなくなる署名は外す。送信者名と chat type は元のまま。
+pub fn rewrite_chat_text(
+    id: i32,
+    data: &[u8],
+    f: &mut dyn FnMut(&str) -> String,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut cursor = Cursor::new(data);
+    let mut out = Vec::with_capacity(data.len());
+    match id {
+        SYSTEM_CHAT_PACKET_ID | DISGUISED_CHAT_PACKET_ID => {
+            let text = flatten_component(&mut cursor)?;
+            write_text_component(&mut out, &f(&text))?;
+        }
+        PLAYER_CHAT_PACKET_ID => {
+            cursor.read_varint()?;
+            cursor.read_uuid()?;
+            cursor.read_varint()?;
+            out.extend_from_slice(&data[..cursor.position() as usize]);
+            if cursor.read_bool()? {
+                read_exact_vec(&mut cursor, 256, "message signature")?;
+            }
+            out.push(0); // 署名無し
+            let body = cursor.read_string()?;
+            out.write_string(&f(&body))?;
+            let after_body = cursor.position() as usize;
+            cursor.read_long()?;
+            cursor.read_long()?;
+            for _ in 0..cursor.read_varint()? {
+                if cursor.read_varint()? == 0 {
+                    read_exact_vec(&mut cursor, 256, "previous message signature")?;
+                }
+            }
+            out.extend_from_slice(&data[after_body..cursor.position() as usize]);
+            if cursor.read_bool()? {
+                let unsigned = flatten_component(&mut cursor)?;
+                out.push(1);
+                write_text_component(&mut out, &f(&unsigned))?;
+            } else {
+                out.push(0);
+            }
+        }
+        _ => return Ok(None),
+    }
+    out.extend_from_slice(&data[cursor.position() as usize..]);
+    Ok(Some(out))
+}
+
+/// `{text: "<text>"}` のテキストコンポーネント (network NBT)。
+fn write_text_component(out: &mut Vec<u8>, text: &str) -> io::Result<()> {
+    out.write_nbt(&Nbt::Compound(vec![(
+        "text".to_string(),
+        Nbt::String(text.to_string()),
+    )]))
+}
+
+/// Chat Type (ID or X)。インライン定義は translation key, parameters, style (NBT)
+/// が 2 組 (chat / narration) 並ぶ。
+fn skip_chat_type(cursor: &mut Cursor<&[u8]>) -> io::Result<()> {
+    if cursor.read_varint()? != 0 {
+        return Ok(());
+    }
+    for _ in 0..2 {
+        read_chat_type(cursor)?;
+    }
+    Ok(())
+}
+
+/// パケット列からチャットを拾い、1 件 1 行の JSON ([`ChatLine`]) で書く。
+///
+/// 巨大なリプレイでも逐次書けるよう JSONL にしている。戻り値は書いた行数。
+/// 読めないチャットパケットはエラーにする。
+pub fn export_jsonl<W: Write>(
+    packets: impl IntoIterator<Item = (State, Packet)>,
+    writer: &mut W,
+) -> io::Result<usize> {
+    let mut lines = 0;
+    for (state, packet) in packets {
+        if state != State::Play {
+            continue;
+        }
+        let Some((sender, text)) = read_chat(packet.id(), packet.data())? else {
+            continue;
+        };
+        let line = ChatLine {
+            time: packet.time(),
+            state: format!("{:?}", state),
+            sender,
+            text,
+        };
+        serde_json::to_writer(&mut *writer, &line)?;
+        writer.write_all(b"\n")?;
+        lines += 1;
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nbt::{TAG_COMPOUND, TAG_END, TAG_LIST, TAG_STRING};
+
+    fn nbt_string(out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    /// `{text: "<text>", color: "red", extra: ["<extra>"]}`
+    fn component(text: &str, extra: &str) -> Vec<u8> {
+        let mut out = vec![TAG_COMPOUND];
+        out.push(TAG_STRING);
+        nbt_string(&mut out, "text");
+        nbt_string(&mut out, text);
+        out.push(TAG_STRING);
+        nbt_string(&mut out, "color");
+        nbt_string(&mut out, "red");
+        out.push(TAG_LIST);
+        nbt_string(&mut out, "extra");
+        out.push(TAG_STRING);
+        out.extend_from_slice(&1i32.to_be_bytes());
+        nbt_string(&mut out, extra);
+        out.push(TAG_END);
+        out
+    }
+
+    fn plain(text: &str) -> Vec<u8> {
+        let mut out = vec![TAG_STRING];
+        nbt_string(&mut out, text);
+        out
+    }
+
+    #[test]
+    fn flattens_text_and_extra() {
+        let bytes = component("Hello, ", "world");
+        assert_eq!(
+            flatten_component(&mut bytes.as_slice()).unwrap(),
+            "Hello, world"
+        );
+    }
+
+    #[test]
+    fn flattens_modified_utf8_and_translations() {
+        let mut bytes = Vec::new();
+        bytes
+            .write_nbt(&Nbt::Compound(vec![
+                ("text".to_string(), Nbt::String("😀\0".to_string())),
+                (
+                    "translate".to_string(),
+                    Nbt::String("chat.type.text".to_string()),
+                ),
+                (
+                    "with".to_string(),
+                    Nbt::list(vec![Nbt::String("Alex".to_string())]),
+                ),
+            ]))
+            .unwrap();
+        assert_eq!(
+            flatten_component(&mut bytes.as_slice()).unwrap(),
+            "😀\0chat.type.text[Alex]"
+        );
+    }
+
+    #[test]
+    fn rewrites_player_chat_body_and_unsigned_content() {
+        let mut player = Vec::new();
+        player.write_varint(3).unwrap();
+        player.extend_from_slice(&[7; 16]);
+        player.write_varint(0).unwrap();
+        player.push(1);
+        player.extend_from_slice(&[9; 256]); // signature
+        player.write_string("darn").unwrap();
+        player.extend_from_slice(&[0; 16]); // timestamp, salt
+        player.write_varint(0).unwrap(); // previous messages
+        player.push(1);
+        player.extend(component("darn ", "it"));
+        player.write_varint(0).unwrap(); // PASS_THROUGH
+        player.write_varint(1).unwrap();
+        player.extend(plain("Alex"));
+        player.push(0);
+
+        let mut redact = |text: &str| text.replace("darn", "****");
+        let rewritten = rewrite_chat_text(PLAYER_CHAT_PACKET_ID, &player, &mut redact)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            read_chat(PLAYER_CHAT_PACKET_ID, &rewritten).unwrap(),
+            Some((Some("Alex".to_string()), "**** it".to_string()))
+        );
+        let mut cursor = Cursor::new(rewritten.as_slice());
+        cursor.read_varint().unwrap();
+        cursor.read_uuid().unwrap();
+        cursor.read_varint().unwrap();
+        assert!(!cursor.read_bool().unwrap(), "署名は外れる");
+        assert_eq!(cursor.read_string().unwrap(), "****");
+
+        assert_eq!(rewrite_chat_text(0x2c, &[], &mut redact).unwrap(), None);
+    }
+
+    #[test]
+    fn rewritten_text_is_modified_utf8() {
+        let mut system = component("hi", "");
+        system.push(0); // overlay
+        let mut replace = |_: &str| "😀\0".to_string();
+        let rewritten = rewrite_chat_text(SYSTEM_CHAT_PACKET_ID, &system, &mut replace)
+            .unwrap()
+            .unwrap();
+        // 補助文字はサロゲートペア (3 バイト × 2)、NUL は 0xc0 0x80
+        let encoded = [0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80, 0xc0, 0x80];
+        assert!(rewritten.windows(encoded.len()).any(|w| w == encoded));
+        let mut cursor = Cursor::new(rewritten.as_slice());
+        assert_eq!(
+            cursor.read_nbt().unwrap().get("text"),
+            Some(&Nbt::String("😀\0".to_string()))
+        );
+        assert!(!cursor.read_bool().unwrap(), "overlay は元のまま");
+    }
+
+    #[test]
+    fn exports_one_line_per_chat_packet() {
+        let mut system = component("Server ", "restarting");
+        system.push(0); // overlay
+
+        let mut player = Vec::new();
+        player.write_varint(0).unwrap();
+        player.extend_from_slice(&[7; 16]);
+        player.write_varint(0).unwrap();
+        player.push(0); // signature 無し
+        player.write_string("hi all").unwrap();
+        player.extend_from_slice(&[0; 16]); // timestamp, salt
+        player.write_varint(0).unwrap(); // previous messages
+        player.push(0); // unsigned content 無し
+        player.write_varint(0).unwrap(); // PASS_THROUGH
+        player.write_varint(1).unwrap(); // chat type (registry id + 1)
+        player.extend(plain("Alex"));
+        player.push(0); // target 無し
+
+        let mut disguised = plain("/say done");
+        disguised.write_varint(1).unwrap();
+        disguised.extend(plain("Server"));
+        disguised.push(0);
+
+        let packets = vec![
+            (State::Play, Packet::new(0, 0x2c, Box::new([]))),
+            (
+                State::Play,
+                Packet::new(100, SYSTEM_CHAT_PACKET_ID, system.into()),
+            ),
+            (
+                State::Play,
+                Packet::new(200, PLAYER_CHAT_PACKET_ID, player.into()),
+            ),
+            (
+                State::Play,
+                Packet::new(300, DISGUISED_CHAT_PACKET_ID, disguised.into()),
+            ),
+        ];
+        let mut out = Vec::new();
+        assert_eq!(export_jsonl(packets, &mut out).unwrap(), 3);
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            serde_json::json!({"time": 100, "state": "Play", "sender": null, "text": "Server restarting"})
+        );
+        assert_eq!(lines[1]["sender"], "Alex");
+        assert_eq!(lines[1]["text"], "hi all");
+        assert_eq!(lines[2]["text"], "/say done");
+    }
+}
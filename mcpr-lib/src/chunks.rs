@@ -0,0 +1,77 @@
+//! リプレイ中に読み込まれたチャンクの集合 (マップの網羅範囲の把握用)。
+//!
+//! Level Chunk with Light の先頭 (chunk x, chunk z の Int 2 つ) だけを読む。
+//! Forget Level Chunk は z, x の順 (クライアントは 1 つの Long として読む)。
+//! パケット id は 1.21.5 (protocol 770) の値 ([`crate::reconstruct`] と共通)。
+
+use std::{
+    collections::BTreeSet,
+    io::{self, Cursor},
+};
+
+use crate::{
+    event::State,
+    mcpr::Packet,
+    protocol::{Deserializer, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID, RESPAWN_PACKET_ID},
+    reconstruct::FORGET_LEVEL_CHUNK_PACKET_ID,
+};
+
+/// [`loaded_set`] の結果。座標はチャンク単位の (x, z)。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadedChunks {
+    /// 一度でも読み込まれたチャンク。
+    pub ever_loaded: BTreeSet<(i32, i32)>,
+    /// 最後の時点で読み込まれたままのチャンク (Forget Level Chunk で外し、
+    /// Respawn で全て外す)。
+    pub net_loaded: BTreeSet<(i32, i32)>,
+}
+
+/// Play パケットを順に読み、読み込まれたチャンクの集合を返す。
+pub fn loaded_set(packets: impl IntoIterator<Item = (State, Packet)>) -> io::Result<LoadedChunks> {
+    let mut chunks = LoadedChunks::default();
+    for (state, packet) in packets {
+        if state != State::Play {
+            continue;
+        }
+        let mut cursor = Cursor::new(packet.data());
+        match packet.id() {
+            LEVEL_CHUNK_WITH_LIGHT_PACKET_ID => {
+                let pos = (cursor.read_int()?, cursor.read_int()?);
+                chunks.ever_loaded.insert(pos);
+                chunks.net_loaded.insert(pos);
+            }
+            FORGET_LEVEL_CHUNK_PACKET_ID => {
+                let z = cursor.read_int()?;
+                let x = cursor.read_int()?;
+                chunks.net_loaded.remove(&(x, z));
+            }
+            RESPAWN_PACKET_ID => chunks.net_loaded.clear(),
+            _ => {}
+        }
+    }
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(time: u32, id: i32, ints: [i32; 2]) -> (State, Packet) {
+        let data: Vec<u8> = ints.iter().flat_map(|i| i.to_be_bytes()).collect();
+        (State::Play, Packet::new(time, id, data.into()))
+    }
+
+    #[test]
+    fn ever_and_net_loaded_chunks() {
+        let packets = vec![
+            play(0, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID, [1, 2]),
+            play(10, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID, [-3, 4]),
+            play(20, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID, [1, 2]),
+            // z, x の順
+            play(30, FORGET_LEVEL_CHUNK_PACKET_ID, [4, -3]),
+        ];
+        let chunks = loaded_set(packets).unwrap();
+        assert_eq!(chunks.ever_loaded, BTreeSet::from([(-3, 4), (1, 2)]));
+        assert_eq!(chunks.net_loaded, BTreeSet::from([(1, 2)]));
+    }
+}
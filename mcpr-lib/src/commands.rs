@@ -0,0 +1,289 @@
+//! Commands (Declare Commands) パケットのコマンドグラフ。
+//!
+//! body はノードの配列 + ルートノードの添字 (VarInt)。各ノードは
+//! flags (Byte), 子ノードの添字の配列, (flags 次第で) redirect 先, 名前,
+//! 引数パーサの id とそのプロパティ, 補完の種類。
+//! パケット id と引数パーサの id は 1.21 (protocol 767) の値。
+//!
+//! パーサのプロパティは解釈せずバイト列のまま持つ。長さはパーサ id ごとに
+//! 決まっているので、その表 ([`properties_len`]) だけを持つ。
+
+use std::io::{self, Cursor, Read};
+
+use crate::protocol::{Deserializer, Serializer, checked_len_i32, invalid_data};
+
+/// Commands パケットの id。
+/// 注意: バージョン間で安定しない (1.21 / protocol 767 の値)。
+pub const COMMANDS_PACKET_ID: i32 = 0x11;
+
+/// flags 下位 2 ビットのノード種別。
+pub const NODE_TYPE_MASK: u8 = 0x03;
+pub const NODE_ROOT: u8 = 0;
+pub const NODE_LITERAL: u8 = 1;
+pub const NODE_ARGUMENT: u8 = 2;
+pub const FLAG_EXECUTABLE: u8 = 0x04;
+pub const FLAG_REDIRECT: u8 = 0x08;
+pub const FLAG_SUGGESTIONS: u8 = 0x10;
+
+/// コマンドグラフの 1 ノード。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandNode {
+    pub flags: u8,
+    /// 子ノードの添字。
+    pub children: Vec<i32>,
+    /// [`FLAG_REDIRECT`] のときの転送先ノードの添字。
+    pub redirect: Option<i32>,
+    /// literal / argument ノードの名前。
+    pub name: Option<String>,
+    /// argument ノードの (パーサ id, プロパティの生バイト列)。
+    pub parser: Option<(i32, Vec<u8>)>,
+    /// [`FLAG_SUGGESTIONS`] のときの補完の種類 (Identifier)。
+    pub suggestions: Option<String>,
+}
+
+impl CommandNode {
+    /// [`NODE_ROOT`] / [`NODE_LITERAL`] / [`NODE_ARGUMENT`]。
+    pub fn node_type(&self) -> u8 {
+        self.flags & NODE_TYPE_MASK
+    }
+}
+
+/// Commands パケットの body 全体。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commands {
+    pub nodes: Vec<CommandNode>,
+    /// ルートノードの添字。
+    pub root: i32,
+}
+
+/// 引数パーサのプロパティ部のバイト数を、先頭 `data` から求める。
+/// パーサ id はバージョン間で安定しない (1.21 / protocol 767 の値)。
+///
+/// 数値パーサ (float / double / integer / long) は flags の min/max ビットで
+/// 長さが変わる。知らないパーサ id はエラー。
+pub fn properties_len(parser_id: i32, data: &[u8]) -> io::Result<usize> {
+    let mut cursor = Cursor::new(data);
+    Ok(match parser_id {
+        // brigadier:float / double / integer / long: flags + min? + max?
+        1..=4 => {
+            let flags = cursor.read_unsigned_byte()?;
+            let width = match parser_id {
+                1 | 3 => 4,
+                _ => 8,
+            };
+            1 + width * ((flags & 0x01) + ((flags & 0x02) >> 1)) as usize
+        }
+        // brigadier:string: VarInt の種類
+        5 => {
+            cursor.read_varint()?;
+            cursor.position() as usize
+        }
+        // minecraft:entity / minecraft:score_holder: flags
+        6 | 30 => 1,
+        // minecraft:time: 最小値 (Int)
+        42 => 4,
+        // resource_or_tag / resource_or_tag_key / resource / resource_key: レジストリ名
+        43..=46 => {
+            cursor.read_string()?;
+            cursor.position() as usize
+        }
+        0 | 7..=29 | 31..=41 | 47..=53 => 0,
+        _ => {
+            return Err(invalid_data(format!(
+                "unknown argument parser id {parser_id}"
+            )));
+        }
+    })
+}
+
+/// Commands パケットの body を読む。
+pub fn read_commands(body: &[u8]) -> io::Result<Commands> {
+    let mut cursor = Cursor::new(body);
+    let count = checked_len_i32(cursor.read_varint()?, "command node count")?;
+    let mut nodes = Vec::new();
+    for _ in 0..count {
+        let flags = cursor.read_unsigned_byte()?;
+        let children_len = checked_len_i32(cursor.read_varint()?, "command children length")?;
+        let children = (0..children_len)
+            .map(|_| cursor.read_varint())
+            .collect::<io::Result<_>>()?;
+        let redirect = if flags & FLAG_REDIRECT != 0 {
+            Some(cursor.read_varint()?)
+        } else {
+            None
+        };
+        let node_type = flags & NODE_TYPE_MASK;
+        let name = if node_type == NODE_LITERAL || node_type == NODE_ARGUMENT {
+            Some(cursor.read_string()?)
+        } else {
+            None
+        };
+        let parser = if node_type == NODE_ARGUMENT {
+            let id = cursor.read_varint()?;
+            let rest = &body[cursor.position() as usize..];
+            let len = properties_len(id, rest)?;
+            let mut properties = vec![0; len];
+            cursor.read_exact(&mut properties)?;
+            Some((id, properties))
+        } else {
+            None
+        };
+        let suggestions = if node_type == NODE_ARGUMENT && flags & FLAG_SUGGESTIONS != 0 {
+            Some(cursor.read_string()?)
+        } else {
+            None
+        };
+        nodes.push(CommandNode {
+            flags,
+            children,
+            redirect,
+            name,
+            parser,
+            suggestions,
+        });
+    }
+    let root = cursor.read_varint()?;
+    Ok(Commands { nodes, root })
+}
+
+/// [`read_commands`] の逆。flags と各フィールドの有無が食い違うノードはエラー。
+pub fn write_commands(commands: &Commands) -> io::Result<Vec<u8>> {
+    let mismatch = |index: usize, field: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("command node {index}: {field} does not match its flags"),
+        )
+    };
+    let mut buf = Vec::new();
+    buf.write_varint(commands.nodes.len() as i32)?;
+    for (index, node) in commands.nodes.iter().enumerate() {
+        let node_type = node.node_type();
+        buf.push(node.flags);
+        buf.write_varint(node.children.len() as i32)?;
+        for child in &node.children {
+            buf.write_varint(*child)?;
+        }
+        match (node.flags & FLAG_REDIRECT != 0, node.redirect) {
+            (true, Some(redirect)) => buf.write_varint(redirect)?,
+            (false, None) => {}
+            _ => return Err(mismatch(index, "redirect")),
+        }
+        match (node_type != NODE_ROOT, &node.name) {
+            (true, Some(name)) => buf.write_string(name)?,
+            (false, None) => {}
+            _ => return Err(mismatch(index, "name")),
+        }
+        match (node_type == NODE_ARGUMENT, &node.parser) {
+            (true, Some((id, properties))) => {
+                buf.write_varint(*id)?;
+                buf.extend_from_slice(properties);
+            }
+            (false, None) => {}
+            _ => return Err(mismatch(index, "parser")),
+        }
+        let has_suggestions = node_type == NODE_ARGUMENT && node.flags & FLAG_SUGGESTIONS != 0;
+        match (has_suggestions, &node.suggestions) {
+            (true, Some(suggestions)) => buf.write_string(suggestions)?,
+            (false, None) => {}
+            _ => return Err(mismatch(index, "suggestions")),
+        }
+    }
+    buf.write_varint(commands.root)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// root → "tp" → <target: entity> (実行可能, 補完付き) → <pos: vec3>,
+    /// root → "teleport" (tp へ redirect), root → "time" → "set" → <t: integer 0..>。
+    fn captured() -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_varint(8).unwrap();
+        // 0: root
+        buf.push(NODE_ROOT);
+        buf.write_varint(3).unwrap();
+        for child in [1, 4, 5] {
+            buf.write_varint(child).unwrap();
+        }
+        // 1: literal "tp"
+        buf.push(NODE_LITERAL);
+        buf.write_varint(1).unwrap();
+        buf.write_varint(2).unwrap();
+        buf.write_string("tp").unwrap();
+        // 2: argument "target" (minecraft:entity, single entity)
+        buf.push(NODE_ARGUMENT | FLAG_EXECUTABLE | FLAG_SUGGESTIONS);
+        buf.write_varint(1).unwrap();
+        buf.write_varint(3).unwrap();
+        buf.write_string("target").unwrap();
+        buf.write_varint(6).unwrap();
+        buf.push(0x01);
+        buf.write_string("minecraft:ask_server").unwrap();
+        // 3: argument "pos" (minecraft:vec3, プロパティ無し)
+        buf.push(NODE_ARGUMENT | FLAG_EXECUTABLE);
+        buf.write_varint(0).unwrap();
+        buf.write_string("pos").unwrap();
+        buf.write_varint(10).unwrap();
+        // 4: literal "teleport" → 1
+        buf.push(NODE_LITERAL | FLAG_REDIRECT);
+        buf.write_varint(0).unwrap();
+        buf.write_varint(1).unwrap();
+        buf.write_string("teleport").unwrap();
+        // 5: literal "time"
+        buf.push(NODE_LITERAL);
+        buf.write_varint(1).unwrap();
+        buf.write_varint(6).unwrap();
+        buf.write_string("time").unwrap();
+        // 6: literal "set"
+        buf.push(NODE_LITERAL);
+        buf.write_varint(1).unwrap();
+        buf.write_varint(7).unwrap();
+        buf.write_string("set").unwrap();
+        // 7: argument "t" (brigadier:integer, min 0)
+        buf.push(NODE_ARGUMENT | FLAG_EXECUTABLE);
+        buf.write_varint(0).unwrap();
+        buf.write_string("t").unwrap();
+        buf.write_varint(3).unwrap();
+        buf.push(0x01);
+        buf.extend_from_slice(&0i32.to_be_bytes());
+        // root index
+        buf.write_varint(0).unwrap();
+        buf
+    }
+
+    #[test]
+    fn command_graph_roundtrip() {
+        let body = captured();
+        let commands = read_commands(&body).unwrap();
+        assert_eq!(commands.nodes.len(), 8);
+        assert_eq!(commands.root, 0);
+        assert_eq!(commands.nodes[0].node_type(), NODE_ROOT);
+        assert_eq!(commands.nodes[4].redirect, Some(1));
+        assert_eq!(
+            commands.nodes[2].parser,
+            Some((6, vec![0x01])),
+            "entity のプロパティは flags 1 バイト"
+        );
+        assert_eq!(
+            commands.nodes[2].suggestions.as_deref(),
+            Some("minecraft:ask_server")
+        );
+        assert_eq!(commands.nodes[7].parser, Some((3, vec![0x01, 0, 0, 0, 0])));
+
+        let written = write_commands(&commands).unwrap();
+        assert_eq!(written, body);
+        let reread = read_commands(&written).unwrap();
+        assert_eq!(reread.nodes.len(), commands.nodes.len());
+        assert_eq!(reread.root, commands.root);
+    }
+
+    #[test]
+    fn inconsistent_node_is_rejected() {
+        let mut commands = read_commands(&captured()).unwrap();
+        commands.nodes[4].redirect = None;
+        assert!(write_commands(&commands).is_err());
+
+        assert!(properties_len(999, &[]).is_err());
+    }
+}
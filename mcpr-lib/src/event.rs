@@ -124,13 +124,47 @@ impl State {
     /// clientbound パケット `packet_id` を観測した後の次の state。
     ///
     /// 遷移 id は protocol 764 (1.20.2) 以降で安定している値
-    /// ([`crate::protocol`] の定数)。それ以前のプロトコルを扱う場合は
-    /// ここを protocol_version 依存にする。
+    /// ([`crate::protocol`] の定数)。それ以前のプロトコルは
+    /// [`StateTransitions::Legacy`] を使う。
     pub fn advance(self, packet_id: i32) -> State {
-        match (self, packet_id) {
-            (State::Login, LOGIN_SUCCESS_PACKET_ID) => State::Configuration,
-            (State::Configuration, FINISH_CONFIGURATION_PACKET_ID) => State::Play,
-            _ => self,
+        StateTransitions::Modern.advance(self, packet_id)
+    }
+}
+
+/// Configuration phase が導入された protocol (1.20.2)。
+pub const CONFIGURATION_PHASE_PROTOCOL: u32 = 764;
+
+/// state 遷移の規則。プロトコルの版で Configuration phase の有無が変わる。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StateTransitions {
+    /// protocol 764 (1.20.2) 以降: Login → Configuration → Play。
+    #[default]
+    Modern,
+    /// 1.20.2 より前: Configuration phase が無く、Login Success で直接 Play に入る。
+    Legacy,
+}
+
+impl StateTransitions {
+    /// metadata の `protocol` に合う規則。
+    pub fn for_protocol(protocol: u32) -> Self {
+        if protocol < CONFIGURATION_PHASE_PROTOCOL {
+            StateTransitions::Legacy
+        } else {
+            StateTransitions::Modern
+        }
+    }
+
+    /// `state` で clientbound パケット `packet_id` を観測した後の次の state。
+    pub fn advance(self, state: State, packet_id: i32) -> State {
+        match (self, state, packet_id) {
+            (StateTransitions::Modern, State::Login, LOGIN_SUCCESS_PACKET_ID) => {
+                State::Configuration
+            }
+            (StateTransitions::Modern, State::Configuration, FINISH_CONFIGURATION_PACKET_ID) => {
+                State::Play
+            }
+            (StateTransitions::Legacy, State::Login, LOGIN_SUCCESS_PACKET_ID) => State::Play,
+            _ => state,
         }
     }
 }
@@ -145,6 +179,42 @@ pub fn is_connection_init(state: State, id: i32) -> bool {
     state != State::Play || id == crate::protocol::LOGIN_PLAY_PACKET_ID
 }
 
+/// 時間で切り詰める操作 (区間切り出し・先頭の間引き等) で、区間外に落ちた
+/// 接続初期化イベント ([`is_connection_init`]) をどこまで残すか。
+///
+/// 切り詰め操作ごとに「handshake は残す」を書き分けず、この型で共通化する。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HandshakePolicy {
+    /// 接続初期化を全て残す。単体で再生できるリプレイになる。
+    #[default]
+    KeepAll,
+    /// Login phase と phase 遷移 (Finish Configuration)、Login (play) だけ残し、
+    /// Configuration の中身 (registry / tags 等) を落とす。
+    KeepLoginOnly,
+    /// 全て落とす。ワールドを読み込み済みのクライアント向けで、単体では再生できない。
+    KeepNone,
+}
+
+impl HandshakePolicy {
+    /// 切り詰めの区間外にある `(state, id)` のパケットを残すか。
+    /// 接続初期化でないパケットは常に false。
+    pub fn keeps(self, state: State, id: i32) -> bool {
+        if !is_connection_init(state, id) {
+            return false;
+        }
+        match self {
+            HandshakePolicy::KeepAll => true,
+            HandshakePolicy::KeepLoginOnly => match state {
+                State::Login => true,
+                State::Configuration => id == FINISH_CONFIGURATION_PACKET_ID,
+                State::Play => id == crate::protocol::LOGIN_PLAY_PACKET_ID,
+                State::Handshaking | State::Status => false,
+            },
+            HandshakePolicy::KeepNone => false,
+        }
+    }
+}
+
 /// フォーマット非依存の論理イベント。
 #[derive(Debug, Clone, PartialEq)]
 pub enum Event {
@@ -290,6 +360,49 @@ impl<T: ?Sized + EventSource> EventSource for Box<T> {
     }
 }
 
+/// crate 内 unit test 共用のイベント列。
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::{Event, EventSource, ReplayInfo, State, Time};
+
+    /// メモリ上のイベント列をそのまま流す EventSource。
+    pub(crate) struct VecSource {
+        pub(crate) info: ReplayInfo,
+        pub(crate) events: std::vec::IntoIter<Event>,
+    }
+
+    impl EventSource for VecSource {
+        fn info(&self) -> &ReplayInfo {
+            &self.info
+        }
+        fn next_event(&mut self) -> anyhow::Result<Option<Event>> {
+            Ok(self.events.next())
+        }
+    }
+
+    /// protocol はクレートのパケット id の前提 ([`crate::stream::PACKET_ID_PROTOCOL`])。
+    pub(crate) fn source(duration_ms: u64, events: Vec<Event>) -> VecSource {
+        VecSource {
+            info: ReplayInfo {
+                duration_ms,
+                protocol_version: crate::stream::PACKET_ID_PROTOCOL,
+                ..Default::default()
+            },
+            events: events.into_iter(),
+        }
+    }
+
+    /// body 空のパケットイベント。
+    pub(crate) fn packet(time_ms: u64, state: State, id: i32) -> Event {
+        Event::Packet {
+            time: Time::from_millis(time_ms),
+            state,
+            id,
+            data: Box::new([]),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -324,6 +437,21 @@ mod tests {
         assert!("NaN".parse::<PlaybackSpeed>().is_err());
     }
 
+    #[test]
+    fn legacy_transitions_skip_configuration() {
+        assert_eq!(
+            StateTransitions::for_protocol(763),
+            StateTransitions::Legacy
+        );
+        assert_eq!(
+            StateTransitions::for_protocol(764),
+            StateTransitions::Modern
+        );
+        let legacy = StateTransitions::Legacy;
+        assert_eq!(legacy.advance(State::Login, 0x02), State::Play);
+        assert_eq!(legacy.advance(State::Play, 0x03), State::Play);
+    }
+
     #[test]
     fn state_advance() {
         assert_eq!(State::Login.advance(0x02), State::Configuration);
@@ -346,4 +474,37 @@ mod tests {
         assert!(is_connection_init(State::Handshaking, 0x00));
         assert!(is_connection_init(State::Status, 0x00));
     }
+
+    #[test]
+    fn handshake_policy_during_time_trim() {
+        use crate::protocol::LOGIN_PLAY_PACKET_ID;
+        let events = [
+            (0, State::Login, LOGIN_SUCCESS_PACKET_ID),
+            (0, State::Configuration, 0x07),
+            (0, State::Configuration, FINISH_CONFIGURATION_PACKET_ID),
+            (0, State::Play, LOGIN_PLAY_PACKET_ID),
+            (100, State::Play, 0x2c),
+            (600, State::Play, 0x2d),
+        ];
+        // 500ms 以降を切り出す
+        let trim = |policy: HandshakePolicy| -> Vec<_> {
+            events
+                .iter()
+                .filter(|&&(time, state, id)| time >= 500 || policy.keeps(state, id))
+                .map(|&(_, state, id)| (state, id))
+                .collect()
+        };
+        assert_eq!(trim(HandshakePolicy::default()).len(), 5);
+        assert!(trim(HandshakePolicy::KeepAll).contains(&(State::Login, LOGIN_SUCCESS_PACKET_ID)));
+        assert_eq!(
+            trim(HandshakePolicy::KeepLoginOnly),
+            vec![
+                (State::Login, LOGIN_SUCCESS_PACKET_ID),
+                (State::Configuration, FINISH_CONFIGURATION_PACKET_ID),
+                (State::Play, LOGIN_PLAY_PACKET_ID),
+                (State::Play, 0x2d),
+            ]
+        );
+        assert_eq!(trim(HandshakePolicy::KeepNone), vec![(State::Play, 0x2d)]);
+    }
 }
@@ -1,5 +1,6 @@
 use std::{
     collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
     io::{self, BufReader, BufWriter, Cursor, Read, Write},
     str::FromStr,
 };
@@ -9,11 +10,87 @@ use serde::{Deserialize, Serialize};
 use crate::{
     archive::{ArchiveReader, ArchiveWriter},
     event::{Event, EventSink, EventSource, ReplayInfo, State, Time},
+    mcpr::Packet,
+    names,
     protocol::{
         Deserializer, Serializer, checked_len_i32, read_exact_vec, read_exact_vec_from_cursor,
     },
+    warnings::{WarningKind, Warnings},
 };
 
+/// Flashback の読み書きのエラー。
+///
+/// `std::error::Error` を実装しているので、`anyhow::Result` の文脈では
+/// そのまま `?` で持ち上げられる。
+#[derive(Debug)]
+pub enum Error {
+    /// chunk ファイル先頭が [`MAGIC_NUMBER`] でない。
+    BadMagic(i32),
+    /// action id が chunk の action テーブルに無い (読み) / 登録されていない (書き)。
+    UnknownAction(String),
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// metadata が参照する chunk ファイル、または chunk cache のエントリが無い。
+    MissingChunk(String),
+    /// chunk ヘッダの action テーブルが [`MAX_ACTION_REGISTRY_ENTRIES`] を超える。
+    TooManyActions(usize),
+    /// action テーブルの件数、または action id が負。
+    NegativeIndex {
+        field: &'static str,
+        value: i32,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadMagic(magic) => write!(f, "invalid flashback chunk magic: 0x{:08x}", magic),
+            Error::UnknownAction(action) => write!(f, "unknown action: {}", action),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Json(e) => write!(f, "invalid metadata.json: {}", e),
+            Error::MissingChunk(name) => write!(f, "missing chunk: {}", name),
+            Error::TooManyActions(count) => write!(
+                f,
+                "action count is too large: {} (limit: {})",
+                count, MAX_ACTION_REGISTRY_ENTRIES
+            ),
+            Error::NegativeIndex { field, value } => {
+                write!(f, "{} cannot be negative: {}", field, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+/// アーカイブ層のエラー (anyhow) を Io として包む。
+fn archive_error(filename: &str, e: anyhow::Error) -> Error {
+    Error::Io(io::Error::other(format!("{}: {:#}", filename, e)))
+}
+
+/// Flashback の公称 tick rate (1 tick = [`Time::MS_PER_TICK`] ms)。
+pub const NOMINAL_TICK_RATE: f64 = 1000.0 / Time::MS_PER_TICK as f64;
+
 /// level_chunk_caches の 1 ファイルあたり最大エントリ数。
 /// (Flashback mod の ReplayChunkCache.CHUNK_CACHE_SIZE に対応)
 pub const CHUNK_CACHE_SIZE: u32 = 10000;
@@ -102,6 +179,19 @@ impl Action {
     pub fn into_data(self) -> Box<[u8]> {
         self.data
     }
+    /// `GamePacket` / `ConfigurationPacket` の payload (`VarInt packet id` + body) を
+    /// 時刻 `time` の .mcpr パケットとして取り出す。
+    ///
+    /// それ以外の action や、packet id が読めない payload は None。
+    pub fn to_mcpr_packet(&self, time: u32) -> Option<Packet> {
+        match self.kind {
+            ActionKind::GamePacket | ActionKind::ConfigurationPacket => {
+                let (id, data) = split_packet_payload(&self.data).ok()?;
+                Some(Packet::new(time, id, data))
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -142,6 +232,19 @@ impl MetaData {
         names.sort_by(|a, b| numeric_key(a).cmp(&numeric_key(b)).then_with(|| a.cmp(b)));
         names.into_iter().cloned().collect()
     }
+    /// 再生順の (chunk 名, 開始 tick) 一覧。開始 tick は先行 chunk の
+    /// `duration` の累積和で、chunk を読まずに目的の位置を特定できる。
+    pub fn chunk_start_ticks(&self) -> Vec<(String, u64)> {
+        let mut tick = 0u64;
+        self.chunks_in_order()
+            .into_iter()
+            .map(|name| {
+                let start = tick;
+                tick = tick.saturating_add(self.chunks[&name].duration);
+                (name, start)
+            })
+            .collect()
+    }
 }
 
 /// アーカイブ内のメタデータファイル名 (フォーマット判別の根拠でもある)。
@@ -159,18 +262,14 @@ pub struct ChunkReader<R> {
 }
 
 impl<R: Read> ChunkReader<R> {
-    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
         let magic = reader.read_int()?;
         if magic != MAGIC_NUMBER {
-            anyhow::bail!("invalid flashback chunk magic: 0x{:08x}", magic);
+            return Err(Error::BadMagic(magic));
         }
-        let action_count = checked_len_i32(reader.read_varint()?, "action count")?;
+        let action_count = checked_index(reader.read_varint()?, "action count")?;
         if action_count > MAX_ACTION_REGISTRY_ENTRIES {
-            anyhow::bail!(
-                "action count is too large: {} (limit: {})",
-                action_count,
-                MAX_ACTION_REGISTRY_ENTRIES
-            );
+            return Err(Error::TooManyActions(action_count));
         }
         let mut actions = Vec::with_capacity(action_count);
         for _ in 0..action_count {
@@ -192,28 +291,35 @@ impl<R: Read> ChunkReader<R> {
         &self.snapshot
     }
     /// 次の action を読む。終端で `Ok(None)`、途中破損はエラー。
-    pub fn next_action(&mut self) -> anyhow::Result<Option<Action>> {
+    pub fn next_action(&mut self) -> Result<Option<Action>, Error> {
         read_action_from(&mut self.reader, &self.actions)
     }
 }
 
+/// action テーブルの件数・index。負なら [`Error::NegativeIndex`]。
+fn checked_index(value: i32, field: &'static str) -> Result<usize, Error> {
+    usize::try_from(value).map_err(|_| Error::NegativeIndex { field, value })
+}
+
 /// action 列 (`VarInt action_id` + `i32 size` + data) から 1 件読む。
 /// chunk 本体と snapshot は同じ表現なので両方で使う。
 fn read_action_from<R: Read>(
     reader: &mut R,
     actions: &[ActionKind],
-) -> anyhow::Result<Option<Action>> {
+) -> Result<Option<Action>, Error> {
     let action_id = match reader.read_varint() {
         Ok(id) => id,
         Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
         Err(e) => return Err(e.into()),
     };
-    let action_id = checked_len_i32(action_id, "action id")?;
+    let action_id = checked_index(action_id, "action id")?;
     let length = checked_len_i32(reader.read_int()?, "action length")?;
     let data = read_exact_vec(reader, length, "action")?;
     let kind = actions
         .get(action_id)
-        .ok_or_else(|| anyhow::anyhow!("action id {} out of registry range", action_id))?
+        .ok_or_else(|| {
+            Error::UnknownAction(format!("action id {} out of registry range", action_id))
+        })?
         .clone();
     Ok(Some(Action::new(kind, data.into_boxed_slice())))
 }
@@ -231,7 +337,7 @@ pub struct ChunkWriter<W: Write> {
 }
 
 impl<W: Write> ChunkWriter<W> {
-    pub fn new(mut writer: W, actions: &[ActionKind], snapshot: &[u8]) -> anyhow::Result<Self> {
+    pub fn new(mut writer: W, actions: &[ActionKind], snapshot: &[u8]) -> Result<Self, Error> {
         writer.write_all(&MAGIC_NUMBER.to_be_bytes())?;
         writer.write_varint(actions.len() as i32)?;
         let mut index = HashMap::with_capacity(actions.len());
@@ -245,7 +351,7 @@ impl<W: Write> ChunkWriter<W> {
         writer.write_all(snapshot)?;
         Ok(Self { writer, index })
     }
-    pub fn push(&mut self, action: &Action) -> anyhow::Result<()> {
+    pub fn push(&mut self, action: &Action) -> Result<(), Error> {
         self.write_action(&action.kind, &action.data, &[])
     }
     /// パケット action (`VarInt packet id` + body) を結合バッファなしで書く。
@@ -254,7 +360,7 @@ impl<W: Write> ChunkWriter<W> {
         kind: &ActionKind,
         packet_id: i32,
         body: &[u8],
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), Error> {
         let mut head = [0u8; 5];
         let mut cursor = Cursor::new(&mut head[..]);
         cursor.write_varint(packet_id)?;
@@ -262,11 +368,11 @@ impl<W: Write> ChunkWriter<W> {
         self.write_action(kind, &head[..head_len], body)
     }
     /// action data を 2 スライスの連結として書く。
-    fn write_action(&mut self, kind: &ActionKind, head: &[u8], tail: &[u8]) -> anyhow::Result<()> {
+    fn write_action(&mut self, kind: &ActionKind, head: &[u8], tail: &[u8]) -> Result<(), Error> {
         let id = self
             .index
             .get(kind)
-            .ok_or_else(|| anyhow::anyhow!("action {:?} not in registry", kind))?;
+            .ok_or_else(|| Error::UnknownAction(format!("{} not in registry", kind.as_str())))?;
         self.writer.write_varint(*id as i32)?;
         self.writer
             .write_all(&((head.len() + tail.len()) as i32).to_be_bytes())?;
@@ -281,6 +387,9 @@ impl<W: Write> ChunkWriter<W> {
     }
 }
 
+/// アーカイブから丸ごとメモリへ読み込んだ chunk の reader。
+pub type LoadedChunk = ChunkReader<Cursor<Vec<u8>>>;
+
 pub struct FlashbackReader<R: ArchiveReader> {
     reader: R,
 }
@@ -289,22 +398,122 @@ impl<R: ArchiveReader> FlashbackReader<R> {
     pub fn new(reader: R) -> Self {
         Self { reader }
     }
-    pub fn get_metadata(&mut self) -> anyhow::Result<MetaData> {
-        let reader = BufReader::new(self.reader.get_reader(METADATA_FILE)?);
+    pub fn get_metadata(&mut self) -> Result<MetaData, Error> {
+        let reader = BufReader::new(
+            self.reader
+                .get_reader(METADATA_FILE)
+                .map_err(|e| archive_error(METADATA_FILE, e))?,
+        );
         let metadata = serde_json::from_reader(reader)?;
         Ok(metadata)
     }
+    /// 再生全体を通した平均の 1 秒あたり tick 数。
+    ///
+    /// metadata.json は tick 数しか持たないので、chunk を順に読み、記録された
+    /// Ticking State (`/tick rate` の変更) で区間ごとの rate を決めて実時間に直す。
+    /// 最初の chunk の snapshot にある値は tick 0 から効く。変更が無ければ、
+    /// あるいは protocol の Ticking State の id が分からなければ公称値
+    /// ([`NOMINAL_TICK_RATE`])。
+    pub fn tick_rate(&mut self) -> Result<f64, Error> {
+        let metadata = self.get_metadata()?;
+        let Some(ticking_state) =
+            names::packet_id(State::Play, "TickingState", metadata.protocol_version)
+        else {
+            return Ok(NOMINAL_TICK_RATE);
+        };
+        let mut rate = NOMINAL_TICK_RATE;
+        let mut seconds = 0.0;
+        let mut last_change = 0u64;
+        for (index, chunk) in self.chunks()?.enumerate() {
+            let (mut tick, mut chunk) = chunk?;
+            if index == 0 {
+                let mut snapshot = Cursor::new(chunk.snapshot().to_vec());
+                while let Some(action) = read_action_from(&mut snapshot, chunk.actions())? {
+                    rate = recorded_tick_rate(&action, ticking_state).unwrap_or(rate);
+                }
+            }
+            while let Some(action) = chunk.next_action()? {
+                if *action.kind() == ActionKind::NextTick {
+                    tick += 1;
+                } else if let Some(changed) = recorded_tick_rate(&action, ticking_state) {
+                    seconds += tick.saturating_sub(last_change) as f64 / rate;
+                    rate = changed;
+                    last_change = tick;
+                }
+            }
+        }
+        let total_ticks = metadata.total_ticks.max(last_change);
+        seconds += (total_ticks - last_change) as f64 / rate;
+        Ok(if seconds > 0.0 {
+            total_ticks as f64 / seconds
+        } else {
+            rate
+        })
+    }
+    /// chunk ファイルを開く。無ければ [`Error::MissingChunk`]、あるのに
+    /// 開けなければ [`Error::Io`]。
     pub fn get_chunk_reader<'a>(
         &'a mut self,
         filename: &str,
-    ) -> anyhow::Result<ChunkReader<impl Read + 'a>> {
-        let reader = BufReader::new(self.reader.get_reader(filename)?);
+    ) -> Result<ChunkReader<impl Read + 'a>, Error> {
+        let reader = BufReader::new(self.open_existing(filename)?);
         ChunkReader::new(reader)
     }
+    /// アーカイブにあるファイルを開く。無ければ [`Error::MissingChunk`]、
+    /// あるのに開けなければ [`Error::Io`] (chunk / chunk cache 用)。
+    fn open_existing<'a>(&'a mut self, filename: &str) -> Result<Box<dyn Read + 'a>, Error> {
+        if !self
+            .reader
+            .contains(filename)
+            .map_err(|e| archive_error(filename, e))?
+        {
+            return Err(Error::MissingChunk(filename.to_string()));
+        }
+        self.reader
+            .get_reader(filename)
+            .map_err(|e| archive_error(filename, e))
+    }
+    /// 再生に使う chunk の順序 ([`MetaData::chunks_in_order`])。
+    ///
+    /// metadata の `chunks` は辞書順なので、ゼロ埋めされていない名前
+    /// (`chunk_2`, `chunk_10`) ではそのままの順序と異なる。
+    pub fn chunk_order(&mut self) -> Result<Vec<String>, Error> {
+        Ok(self.get_metadata()?.chunks_in_order())
+    }
+    /// metadata が参照する chunk ファイルが全てアーカイブにあるか確かめる。
+    /// 無いものがあれば、再生順で最初のものを [`Error::MissingChunk`] で返す。
+    pub fn verify_chunks(&mut self) -> Result<(), Error> {
+        for name in self.chunk_order()? {
+            if !self
+                .reader
+                .contains(&name)
+                .map_err(|e| archive_error(&name, e))?
+            {
+                return Err(Error::MissingChunk(name));
+            }
+        }
+        Ok(())
+    }
+    /// 再生順に chunk を 1 本ずつ開く Iterator (開始 tick 付き)。
+    ///
+    /// chunk ファイルは `next` で初めて読まれる。action の時刻は
+    /// 開始 tick に chunk 内の `NextTick` 数を足したもの。特定の chunk へ
+    /// シークするだけなら [`MetaData::chunk_start_ticks`] と
+    /// [`Self::get_chunk_reader`] で先行 chunk を読まずに済む。
+    pub fn chunks(
+        &mut self,
+    ) -> Result<impl Iterator<Item = Result<(u64, LoadedChunk), Error>> + '_, Error> {
+        let starts = self.get_metadata()?.chunk_start_ticks();
+        Ok(starts.into_iter().map(move |(name, start)| {
+            let bytes = self.read_file_fully(&name)?;
+            Ok((start, ChunkReader::new(Cursor::new(bytes))?))
+        }))
+    }
     /// アーカイブ内のファイルを丸ごと読む。
     /// (借用を保持しないため、読みながら別ファイルを開ける)
-    fn read_file_fully(&mut self, filename: &str) -> anyhow::Result<Vec<u8>> {
-        let mut reader = self.reader.get_reader(filename)?;
+    /// 無ければ [`Error::MissingChunk`]、あるのに読めなければ [`Error::Io`]。
+    fn read_file_fully(&mut self, filename: &str) -> Result<Vec<u8>, Error> {
+        let mut reader = self.open_existing(filename)?;
         let mut bytes = Vec::new();
         reader.read_to_end(&mut bytes)?;
         Ok(bytes)
@@ -318,7 +527,7 @@ impl<R: ArchiveReader> FlashbackReader<R> {
     pub fn event_source(
         mut self,
         include_snapshot: bool,
-    ) -> anyhow::Result<FlashbackEventSource<R>> {
+    ) -> Result<FlashbackEventSource<R>, Error> {
         let metadata = self.get_metadata()?;
         let info = ReplayInfo::from(&metadata);
         let pending_chunks = metadata.chunks_in_order().into();
@@ -335,11 +544,24 @@ impl<R: ArchiveReader> FlashbackReader<R> {
 }
 
 struct CurrentChunk {
-    reader: ChunkReader<Cursor<Vec<u8>>>,
+    reader: LoadedChunk,
     /// 流すべき snapshot の残り。読み終わったら None。
     snapshot: Option<Cursor<Vec<u8>>>,
 }
 
+/// Ticking State (`Float tick rate` + `Boolean frozen`) の GamePacket なら、
+/// その tick rate。正の有限値でなければ None。
+fn recorded_tick_rate(action: &Action, ticking_state_id: i32) -> Option<f64> {
+    if *action.kind() != ActionKind::GamePacket {
+        return None;
+    }
+    let packet = action
+        .to_mcpr_packet(0)
+        .filter(|packet| packet.id() == ticking_state_id)?;
+    let rate = Cursor::new(packet.data()).read_float().ok()?;
+    (rate.is_finite() && rate > 0.0).then_some(f64::from(rate))
+}
+
 /// パケットの (id, body) 分解表現。
 type PacketParts = (i32, Box<[u8]>);
 
@@ -365,7 +587,7 @@ pub struct FlashbackEventSource<R: ArchiveReader> {
 
 impl<R: ArchiveReader> FlashbackEventSource<R> {
     /// chunk / snapshot を横断して次の action を返す。
-    fn next_action(&mut self) -> anyhow::Result<Option<Action>> {
+    fn next_action(&mut self) -> Result<Option<Action>, Error> {
         loop {
             if self.current.is_none() {
                 let Some(name) = self.pending_chunks.pop_front() else {
@@ -401,7 +623,7 @@ impl<R: ArchiveReader> FlashbackEventSource<R> {
 
     /// `level_chunk_caches` からグローバル index のチャンクパケット
     /// (packet id, body) を引く。
-    fn cached_chunk_packet(&mut self, index: u32) -> anyhow::Result<PacketParts> {
+    fn cached_chunk_packet(&mut self, index: u32) -> Result<PacketParts, Error> {
         let cache_index = index / CHUNK_CACHE_SIZE;
         let offset = (index % CHUNK_CACHE_SIZE) as usize;
         if !self.chunk_caches.contains_key(&cache_index) {
@@ -412,27 +634,28 @@ impl<R: ArchiveReader> FlashbackEventSource<R> {
             .get(offset)
             .map(|(id, body)| (*id, body.clone()))
             .ok_or_else(|| {
-                anyhow::anyhow!(
-                    "cached chunk index {} not found (cache file {} has fewer entries)",
-                    index,
-                    cache_index
-                )
+                Error::MissingChunk(format!(
+                    "cached chunk index {} (cache file {} has fewer entries)",
+                    index, cache_index
+                ))
             })
     }
 
     /// `level_chunk_caches/<N>` を読み、`i32 BE size` + データの連結を
     /// (packet id, body) のエントリ列に分割する。N=0 で存在しない場合は
     /// 旧形式の単一ファイル `level_chunk_cache` にフォールバックする。
-    fn load_chunk_cache(&mut self, cache_index: u32) -> anyhow::Result<Vec<PacketParts>> {
+    fn load_chunk_cache(&mut self, cache_index: u32) -> Result<Vec<PacketParts>, Error> {
         let bytes = match self
             .reader
             .read_file_fully(&format!("level_chunk_caches/{}", cache_index))
         {
             Ok(bytes) => bytes,
-            Err(e) if cache_index == 0 => self
-                .reader
-                .read_file_fully("level_chunk_cache")
-                .map_err(|_| e)?,
+            Err(e @ Error::MissingChunk(_)) if cache_index == 0 => {
+                match self.reader.read_file_fully("level_chunk_cache") {
+                    Err(Error::MissingChunk(_)) => return Err(e),
+                    legacy => legacy?,
+                }
+            }
             Err(e) => return Err(e),
         };
         let mut entries = Vec::new();
@@ -451,7 +674,7 @@ impl<R: ArchiveReader> FlashbackEventSource<R> {
     }
 
     /// action を論理イベントへ変換する。時間進行のみの action は None。
-    fn action_to_event(&mut self, action: Action) -> anyhow::Result<Option<Event>> {
+    fn action_to_event(&mut self, action: Action) -> Result<Option<Event>, Error> {
         let time = Time::from_ticks(self.tick);
         match action.kind() {
             ActionKind::NextTick => {
@@ -479,7 +702,10 @@ impl<R: ArchiveReader> FlashbackEventSource<R> {
             ActionKind::LevelChunkCached => {
                 let index = Cursor::new(action.data()).read_varint()?;
                 if index < 0 {
-                    anyhow::bail!("negative level_chunk_cached index: {}", index);
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("negative level_chunk_cached index: {}", index),
+                    )));
                 }
                 let (id, data) = self.cached_chunk_packet(index as u32)?;
                 Ok(Some(Event::Packet {
@@ -502,7 +728,7 @@ impl<R: ArchiveReader> FlashbackEventSource<R> {
 }
 
 /// パケットペイロード (`VarInt packet id` + body) を分解する。
-fn split_packet_payload(payload: &[u8]) -> anyhow::Result<PacketParts> {
+fn split_packet_payload(payload: &[u8]) -> Result<PacketParts, Error> {
     let mut cursor = Cursor::new(payload);
     let id = cursor.read_varint()?;
     let body_start = cursor.position() as usize;
@@ -533,8 +759,12 @@ impl<W: ArchiveWriter> FlashbackWriter<W> {
     pub fn new(writer: W) -> Self {
         Self { writer }
     }
-    pub fn write_metadata(&mut self, metadata: &MetaData) -> anyhow::Result<()> {
-        let writer = BufWriter::new(self.writer.get_writer(METADATA_FILE)?);
+    pub fn write_metadata(&mut self, metadata: &MetaData) -> Result<(), Error> {
+        let writer = BufWriter::new(
+            self.writer
+                .get_writer(METADATA_FILE)
+                .map_err(|e| archive_error(METADATA_FILE, e))?,
+        );
         serde_json::to_writer(writer, metadata)?;
         Ok(())
     }
@@ -543,8 +773,12 @@ impl<W: ArchiveWriter> FlashbackWriter<W> {
         filename: &str,
         actions: &[ActionKind],
         snapshot: &[u8],
-    ) -> anyhow::Result<ChunkWriter<impl Write + 'a>> {
-        let writer = BufWriter::new(self.writer.get_writer(filename)?);
+    ) -> Result<ChunkWriter<impl Write + 'a>, Error> {
+        let writer = BufWriter::new(
+            self.writer
+                .get_writer(filename)
+                .map_err(|e| archive_error(filename, e))?,
+        );
         ChunkWriter::new(writer, actions, snapshot)
     }
 }
@@ -557,7 +791,7 @@ impl<W: ArchiveWriter> FlashbackWriter<W> {
 ///   `ConfigurationPacket`。チャンクパケットも dedup せず
 ///   `GamePacket` としてインラインに書く
 /// - `Packet` (Login / Handshaking / Status) → 対応する action が
-///   無いためスキップ ([`Self::skipped_packets`])
+///   無いためスキップ ([`Self::skipped_packets`], 詳細は [`Self::warnings`])
 /// - `Custom` → 既知の flashback action 名ならそのまま書き戻す
 ///   (flashback → flashback で move_entities 等がロスレスに残る)。
 ///   未知名は action テーブルがヘッダ先書きのため登録できず
@@ -572,8 +806,7 @@ pub struct FlashbackEventSink<W: ArchiveWriter> {
     chunk: Option<ChunkWriter<Vec<u8>>>,
     tick: u64,
     uuid: uuid::Uuid,
-    skipped_packets: usize,
-    skipped_customs: usize,
+    warnings: Warnings,
 }
 
 impl<W: ArchiveWriter> FlashbackEventSink<W> {
@@ -586,17 +819,20 @@ impl<W: ArchiveWriter> FlashbackEventSink<W> {
             chunk: Some(chunk),
             tick: 0,
             uuid,
-            skipped_packets: 0,
-            skipped_customs: 0,
+            warnings: Warnings::new(),
         })
     }
     /// 対応 action が無くスキップした非 Play/Configuration パケット数。
     pub fn skipped_packets(&self) -> usize {
-        self.skipped_packets
+        self.warnings.total(WarningKind::SkippedPacket)
     }
     /// 未知名のためスキップした Custom イベント数。
     pub fn skipped_customs(&self) -> usize {
-        self.skipped_customs
+        self.warnings.total(WarningKind::SkippedCustom)
+    }
+    /// 変換で落としたものの集計。
+    pub fn warnings(&self) -> &Warnings {
+        &self.warnings
     }
     pub fn into_archive(self) -> W {
         self.archive
@@ -633,7 +869,10 @@ impl<W: ArchiveWriter> EventSink for FlashbackEventSink<W> {
                     State::Play => ActionKind::GamePacket,
                     State::Configuration => ActionKind::ConfigurationPacket,
                     _ => {
-                        self.skipped_packets += 1;
+                        self.warnings.record(
+                            WarningKind::SkippedPacket,
+                            &format!("{:?} 0x{:02x}", state, id),
+                        );
                         return Ok(());
                     }
                 };
@@ -643,7 +882,7 @@ impl<W: ArchiveWriter> EventSink for FlashbackEventSink<W> {
             Event::Custom { time, name, data } => {
                 let kind = ActionKind::parse(&name);
                 if matches!(kind, ActionKind::Unknown(_)) {
-                    self.skipped_customs += 1;
+                    self.warnings.record(WarningKind::SkippedCustom, &name);
                     return Ok(());
                 }
                 self.advance_tick(time.as_ticks())?;
@@ -723,6 +962,26 @@ mod tests {
         assert_eq!(k.as_str(), name);
     }
 
+    #[test]
+    fn action_accessors() {
+        let action = Action::new(ActionKind::GamePacket, vec![1, 2, 3].into());
+        assert_eq!(action.kind(), &ActionKind::GamePacket);
+        assert_eq!(action.data(), &[1, 2, 3]);
+        assert_eq!(&*action.into_data(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn game_packet_action_converts_to_mcpr_packet() {
+        let action = Action::new(ActionKind::GamePacket, vec![0x2c, 1, 2].into());
+        let packet = action.to_mcpr_packet(500).unwrap();
+        assert_eq!(packet.time(), 500);
+        assert_eq!(packet.id(), 0x2c);
+        assert_eq!(packet.data(), &[1, 2]);
+
+        let tick = Action::new(ActionKind::NextTick, Box::new([]));
+        assert!(tick.to_mcpr_packet(500).is_none());
+    }
+
     #[test]
     fn chunk_roundtrip() {
         let actions = vec![
@@ -763,9 +1022,92 @@ mod tests {
         let mut buf: Vec<u8> = Vec::new();
         buf.extend_from_slice(&0i32.to_be_bytes());
         let err = ChunkReader::new(Cursor::new(&buf)).unwrap_err();
+        assert!(matches!(err, Error::BadMagic(0)));
         assert!(err.to_string().contains("magic"));
     }
 
+    #[test]
+    fn error_variants_match_malformed_input() {
+        // action id がテーブル外
+        let mut buf: Vec<u8> = Vec::new();
+        ChunkWriter::new(&mut buf, &[ActionKind::NextTick], &[])
+            .unwrap()
+            .finish()
+            .unwrap();
+        buf.write_varint(3).unwrap();
+        buf.extend_from_slice(&0i32.to_be_bytes());
+        let mut reader = ChunkReader::new(Cursor::new(&buf)).unwrap();
+        assert!(matches!(reader.next_action(), Err(Error::UnknownAction(_))));
+
+        // 途中で切れた chunk
+        let truncated = &buf[..2];
+        assert!(matches!(
+            ChunkReader::new(Cursor::new(truncated)),
+            Err(Error::Io(_))
+        ));
+
+        // 壊れた metadata.json
+        let mut archive = MemArchive::default();
+        archive
+            .0
+            .insert(METADATA_FILE.to_string(), b"{not json".to_vec());
+        let mut reader = FlashbackReader::new(archive);
+        assert!(matches!(reader.get_metadata(), Err(Error::Json(_))));
+
+        // metadata が参照する chunk が無い
+        let mut archive = MemArchive::default();
+        archive.0.insert(
+            METADATA_FILE.to_string(),
+            serde_json::to_vec(&serde_json::json!({
+                "uuid": "00000000-0000-0000-0000-000000000000",
+                "name": "", "version_string": "", "world_name": null,
+                "data_version": 0, "protocol_version": 0, "total_ticks": 0,
+                "markers": null,
+                "chunks": {"c0.flashback": {"duration": 0}},
+            }))
+            .unwrap(),
+        );
+        let mut reader = FlashbackReader::new(archive);
+        let err = reader.get_chunk_reader("c0.flashback").err().unwrap();
+        assert!(matches!(&err, Error::MissingChunk(name) if name == "c0.flashback"));
+
+        // anyhow の文脈ではそのまま ? で使える
+        let as_anyhow: anyhow::Error = err.into();
+        assert!(as_anyhow.to_string().contains("c0.flashback"));
+    }
+
+    /// エントリの一覧は出せるが、中身を開こうとすると失敗するアーカイブ。
+    struct UnreadableArchive(MemArchive);
+
+    impl ArchiveReader for UnreadableArchive {
+        fn get_reader<'this>(
+            &'this mut self,
+            _filename: &str,
+        ) -> anyhow::Result<Box<dyn Read + 'this>> {
+            anyhow::bail!("disk error")
+        }
+        fn entries(&mut self) -> anyhow::Result<Vec<String>> {
+            self.0.entries()
+        }
+    }
+
+    #[test]
+    fn unreadable_chunk_is_io_error_not_missing() {
+        let mut archive = MemArchive::default();
+        archive.0.insert("c0.flashback".to_string(), Vec::new());
+        let mut reader = FlashbackReader::new(UnreadableArchive(archive));
+        let err = reader.get_chunk_reader("c0.flashback").err().unwrap();
+        assert!(matches!(&err, Error::Io(_)), "{err}");
+        assert!(err.to_string().contains("disk error"));
+        let err = reader.read_file_fully("c0.flashback").unwrap_err();
+        assert!(matches!(&err, Error::Io(_)), "{err}");
+        // 無いものは従来どおり MissingChunk
+        assert!(matches!(
+            reader.read_file_fully("c1.flashback"),
+            Err(Error::MissingChunk(_))
+        ));
+    }
+
     #[test]
     fn chunk_reader_rejects_negative_action_count_without_allocating() {
         let mut buf: Vec<u8> = Vec::new();
@@ -773,7 +1115,27 @@ mod tests {
         buf.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x0f]);
 
         let err = ChunkReader::new(Cursor::new(&buf)).unwrap_err();
-        assert!(err.to_string().contains("action count"));
+        assert!(
+            matches!(
+                err,
+                Error::NegativeIndex {
+                    field: "action count",
+                    value: -1
+                }
+            ),
+            "{err}"
+        );
+
+        // 上限を超える件数も確保の前に弾く
+        let mut buf: Vec<u8> = Vec::new();
+        buf.extend_from_slice(&MAGIC_NUMBER.to_be_bytes());
+        buf.write_varint(MAX_ACTION_REGISTRY_ENTRIES as i32 + 1)
+            .unwrap();
+        let err = ChunkReader::new(Cursor::new(&buf)).unwrap_err();
+        assert!(
+            matches!(err, Error::TooManyActions(count) if count == MAX_ACTION_REGISTRY_ENTRIES + 1),
+            "{err}"
+        );
     }
 
     #[test]
@@ -791,6 +1153,25 @@ mod tests {
         assert!(err.to_string().contains("action"));
     }
 
+    #[test]
+    fn action_length_past_the_end_is_eof() {
+        let mut buf: Vec<u8> = Vec::new();
+        {
+            let w = ChunkWriter::new(&mut buf, &[ActionKind::GamePacket], &[]).unwrap();
+            w.finish().unwrap();
+        }
+        buf.write_varint(0).unwrap();
+        buf.extend_from_slice(&1000i32.to_be_bytes());
+        buf.extend_from_slice(&[1, 2]);
+
+        let mut reader = ChunkReader::new(Cursor::new(buf)).unwrap();
+        let Err(Error::Io(err)) = reader.next_action() else {
+            panic!("expected an io error");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(err.to_string().contains("exceeds remaining 2 bytes"));
+    }
+
     #[test]
     fn chunk_writer_rejects_unregistered_action() {
         let actions = vec![ActionKind::NextTick];
@@ -802,6 +1183,46 @@ mod tests {
         assert!(err.to_string().contains("not in registry"));
     }
 
+    #[test]
+    fn chunk_order_is_numeric_and_missing_chunks_are_reported() {
+        let chunk = {
+            let mut buf = Vec::new();
+            ChunkWriter::new(&mut buf, &ActionKind::KNOWN, &[])
+                .unwrap()
+                .finish()
+                .unwrap();
+            buf
+        };
+        let chunks: serde_json::Map<_, _> = ["chunk_10", "chunk_2", "chunk_1"]
+            .into_iter()
+            .map(|name| (name.to_string(), serde_json::json!({"duration": 1})))
+            .collect();
+        let mut archive = MemArchive::default();
+        archive.0.insert(
+            METADATA_FILE.to_string(),
+            serde_json::to_vec(&serde_json::json!({
+                "uuid": "00000000-0000-0000-0000-000000000000",
+                "name": "", "version_string": "", "world_name": null,
+                "data_version": 0, "protocol_version": 0, "total_ticks": 3,
+                "markers": null, "chunks": chunks,
+            }))
+            .unwrap(),
+        );
+        archive.0.insert("chunk_1".to_string(), chunk.clone());
+        archive.0.insert("chunk_10".to_string(), chunk.clone());
+
+        let mut reader = FlashbackReader::new(archive);
+        // 辞書順なら chunk_10 が chunk_2 より前に来てしまう
+        assert_eq!(
+            reader.chunk_order().unwrap(),
+            vec!["chunk_1", "chunk_2", "chunk_10"]
+        );
+        assert!(matches!(
+            reader.verify_chunks(),
+            Err(Error::MissingChunk(name)) if name == "chunk_2"
+        ));
+    }
+
     #[test]
     fn chunks_in_order_sorts_numerically() {
         let meta_json = serde_json::json!({
@@ -1013,6 +1434,42 @@ mod tests {
         assert_eq!(&with_snapshot[1..], &without_snapshot[..]);
     }
 
+    #[test]
+    fn chunks_yield_accumulated_start_ticks() {
+        let mut archive = build_test_archive(&[]);
+        let chunk = archive.0["c0.flashback"].clone();
+        archive.0.insert("c1.flashback".to_string(), chunk.clone());
+        archive.0.insert("c2.flashback".to_string(), chunk);
+        let mut metadata: MetaData = serde_json::from_slice(&test_metadata_json()).unwrap();
+        for (name, duration) in [("c1.flashback", 5), ("c2.flashback", 7)] {
+            metadata.chunks.insert(
+                name.to_string(),
+                ChunkMeta {
+                    duration,
+                    force_play_snapshot: false,
+                },
+            );
+        }
+        archive.0.insert(
+            "metadata.json".to_string(),
+            serde_json::to_vec(&metadata).unwrap(),
+        );
+
+        let mut reader = FlashbackReader::new(archive);
+        let chunks: Vec<_> = reader
+            .chunks()
+            .unwrap()
+            .collect::<Result<_, Error>>()
+            .unwrap();
+        // c0 = 3 ticks, c1 = 5 ticks
+        let starts: Vec<u64> = chunks.iter().map(|(start, _)| *start).collect();
+        assert_eq!(starts, vec![0, 3, 8]);
+        // 各 chunk は独立に読める
+        for (_, chunk) in chunks {
+            assert_eq!(chunk.count(), 7);
+        }
+    }
+
     #[test]
     fn event_source_falls_back_to_legacy_cache_file() {
         let mut archive = build_test_archive(&[]);
@@ -1103,6 +1560,39 @@ mod tests {
         assert_eq!(read, expected);
     }
 
+    #[test]
+    fn tick_rate_follows_recorded_ticking_state() {
+        let finish = |events: Vec<Event>| {
+            let mut sink =
+                FlashbackEventSink::new(MemArchive::default(), uuid::Uuid::nil()).unwrap();
+            for event in events {
+                sink.push(event).unwrap();
+            }
+            sink.finish(&ReplayInfo {
+                duration_ms: 3000,
+                protocol_version: 770,
+                ..Default::default()
+            })
+            .unwrap();
+            FlashbackReader::new(sink.into_archive())
+        };
+        assert_eq!(finish(vec![]).tick_rate().unwrap(), NOMINAL_TICK_RATE);
+
+        // 1 秒 (20 tick) 後に rate 10 へ: 残り 40 tick は 4 秒かかる
+        let mut body = Vec::new();
+        body.write_float(10.0).unwrap();
+        body.write_bool(false).unwrap();
+        let id = names::packet_id(State::Play, "TickingState", 770).unwrap();
+        let mut reader = finish(vec![Event::Packet {
+            time: Time::from_millis(1000),
+            state: State::Play,
+            id,
+            data: body.into(),
+        }]);
+        assert_eq!(reader.get_metadata().unwrap().total_ticks, 60);
+        assert_eq!(reader.tick_rate().unwrap(), 60.0 / 5.0);
+    }
+
     #[test]
     fn event_sink_synthesizes_next_ticks() {
         let mut sink = FlashbackEventSink::new(MemArchive::default(), uuid::Uuid::nil()).unwrap();
@@ -0,0 +1,122 @@
+//! URL で共有されたリプレイの取得 (`http` feature)。
+//!
+//! zip の読み出しには `Seek` が要るので、本体はメモリへ溜めてから開く。
+//! 取得は `reqwest` の blocking クライアント (TLS は rustls) で行い、
+//! リダイレクトはそのまま辿る。接続・読み出しには [`TIMEOUT`] を掛ける。
+
+use std::{
+    io::{Cursor, Read},
+    time::Duration,
+};
+
+use crate::{archive::zip::ZipArchiveReader, mcpr::ReplayReader};
+
+/// [`download_mcpr`] の既定の最大サイズ (256 MiB)。
+pub const DEFAULT_MAX_BYTES: u64 = 256 * 1024 * 1024;
+/// 接続と、1 回ごとの読み出しのタイムアウト。
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `url` の .mcpr を取得して [`ReplayReader`] として開く。
+///
+/// 本体が `max_bytes` を超えたら (Content-Length の時点でも、読み進めた
+/// 時点でも) エラーにする。
+pub fn download_mcpr(
+    url: &str,
+    max_bytes: u64,
+) -> anyhow::Result<ReplayReader<ZipArchiveReader<Cursor<Vec<u8>>>>> {
+    let bytes = get(url, max_bytes)?;
+    Ok(ReplayReader::new(ZipArchiveReader::new(Cursor::new(
+        bytes,
+    ))?))
+}
+
+/// `url` を GET して本体を返す。
+fn get(url: &str, max_bytes: u64) -> anyhow::Result<Vec<u8>> {
+    let client = reqwest::blocking::Client::builder()
+        .connect_timeout(TIMEOUT)
+        .timeout(TIMEOUT)
+        .build()?;
+    let response = client.get(url).send()?.error_for_status()?;
+    if let Some(len) = response.content_length() {
+        anyhow::ensure!(
+            len <= max_bytes,
+            "response is too large: {} bytes (limit: {})",
+            len,
+            max_bytes
+        );
+    }
+
+    let mut body = Vec::new();
+    response
+        .take(max_bytes.saturating_add(1))
+        .read_to_end(&mut body)?;
+    anyhow::ensure!(
+        body.len() as u64 <= max_bytes,
+        "response is too large: over {} bytes",
+        max_bytes
+    );
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::{
+        archive::zip::ZipArchiveWriter,
+        mcpr::{MetaData, ReplayWriter},
+    };
+
+    /// 1 リクエストだけ `body` を返すローカルサーバ。戻り値はその URL。
+    fn serve_once(body: Vec<u8>, content_length: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while request.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let mut head = "HTTP/1.0 200 OK\r\n".to_string();
+            if content_length {
+                head += &format!("Content-Length: {}\r\n", body.len());
+            }
+            head += "\r\n";
+            stream.write_all(head.as_bytes()).unwrap();
+            let _ = stream.write_all(&body);
+        });
+        format!("http://{}/replay.mcpr", addr)
+    }
+
+    fn fixture() -> Vec<u8> {
+        let mut writer = ReplayWriter::new(ZipArchiveWriter::new(Cursor::new(Vec::new()), None));
+        writer
+            .write_metadata(MetaData {
+                protocol: 767,
+                ..Default::default()
+            })
+            .unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn downloads_and_reads_metadata() {
+        let url = serve_once(fixture(), true);
+        let mut reader = download_mcpr(&url, DEFAULT_MAX_BYTES).unwrap();
+        assert_eq!(reader.read_metadata().unwrap().protocol, 767);
+    }
+
+    #[test]
+    fn rejects_oversized_bodies() {
+        let body = fixture();
+        let limit = body.len() as u64 - 1;
+        // Content-Length で弾く場合と、読み進めて弾く場合
+        assert!(download_mcpr(&serve_once(body.clone(), true), limit).is_err());
+        assert!(download_mcpr(&serve_once(body, false), limit).is_err());
+    }
+}
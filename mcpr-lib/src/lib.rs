@@ -1,5 +1,30 @@
 pub mod archive;
+pub mod batch;
+pub mod bench;
+pub mod chat;
+pub mod chunks;
+pub mod commands;
 pub mod event;
 pub mod flashback;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "fs")]
+pub mod library;
 pub mod mcpr;
+pub mod merge;
+pub mod names;
+pub mod nbt;
+pub mod path;
+pub mod players;
 pub mod protocol;
+pub mod raw;
+pub mod reconstruct;
+pub mod script;
+pub mod spawn;
+pub mod stats;
+pub mod stream;
+#[cfg(feature = "image")]
+pub mod thumbnail;
+pub mod time;
+pub mod tmcpr;
+pub mod warnings;
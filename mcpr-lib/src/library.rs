@@ -0,0 +1,76 @@
+//! リプレイの並ぶフォルダの一覧作り (リプレイ管理ツール向け)。
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::zip::ZipArchiveReader,
+    mcpr::{MetaData, ReplayReader},
+};
+
+/// `dir` 直下の `.mcpr` ファイルそれぞれの metaData.json を読む。
+///
+/// recording は開かない。壊れたファイルがあっても止まらず、そのファイルの
+/// 結果を `Err` にして続ける (`dir` 自体が読めない場合だけ全体がエラー)。
+/// 結果はパス順。サブディレクトリは辿らない。
+pub fn scan<P: AsRef<Path>>(dir: P) -> anyhow::Result<Vec<(PathBuf, anyhow::Result<MetaData>)>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "mcpr") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            let metadata = read_metadata(&path);
+            (path, metadata)
+        })
+        .collect())
+}
+
+fn read_metadata(path: &Path) -> anyhow::Result<MetaData> {
+    let archive = ZipArchiveReader::new(BufReader::new(File::open(path)?))?;
+    ReplayReader::new(archive).read_metadata()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{archive::zip::ZipArchiveWriter, mcpr::ReplayWriter};
+
+    #[test]
+    fn scan_reports_each_file() {
+        let dir = std::env::temp_dir().join("mcpr_editor_library_scan");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let metadata = MetaData {
+            serverName: "valid".to_string(),
+            ..Default::default()
+        };
+        let mut writer = ReplayWriter::new(ZipArchiveWriter::new(Cursor::new(Vec::new()), None));
+        writer.write_metadata(metadata.clone()).unwrap();
+        let zip = writer.finish().unwrap().into_inner();
+        std::fs::write(dir.join("a.mcpr"), zip).unwrap();
+        std::fs::write(dir.join("b.mcpr"), b"not a zip").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"ignored").unwrap();
+
+        let scanned = scan(&dir).unwrap();
+        assert_eq!(scanned.len(), 2);
+        assert_eq!(scanned[0].0, dir.join("a.mcpr"));
+        assert_eq!(scanned[0].1.as_ref().unwrap(), &metadata);
+        assert_eq!(scanned[1].0, dir.join("b.mcpr"));
+        assert!(scanned[1].1.is_err());
+
+        assert!(scan(dir.join("missing")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
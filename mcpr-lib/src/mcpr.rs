@@ -1,25 +1,56 @@
 use std::{
-    collections::BTreeSet,
-    io::{self, BufReader, BufWriter, Cursor, Read, Write},
+    collections::{BTreeSet, HashSet, VecDeque},
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek, Write},
 };
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    archive::{ArchiveReader, ArchiveWriter},
-    event::{Event, EventSink, EventSource, ReplayInfo, State, Time},
+    archive::{
+        ArchiveReader, ArchiveWriter, LimitedReader, recopy,
+        zip::{EntryCompression, ZipArchiveReader, ZipArchiveWriter, ZipCompressionPolicy},
+    },
+    event::{
+        Event, EventSink, EventSource, ReplayInfo, State, StateTransitions, Time,
+        is_connection_init,
+    },
+    players::{PLAYER_INFO_UPDATE_PACKET_ID, read_player_info_update},
     protocol::{
-        Deserializer, FINISH_CONFIGURATION_PACKET_ID, LOGIN_SUCCESS_PACKET_ID, Serializer,
-        checked_len_u32, login_success_payload, read_exact_vec, varint_len,
+        CONFIGURATION_PLUGIN_MESSAGE_PACKET_ID, Deserializer, FINISH_CONFIGURATION_PACKET_ID,
+        LOGIN_SUCCESS_PACKET_ID, PLAY_PLUGIN_MESSAGE_PACKET_ID, Serializer, checked_len_u32,
+        login_success_payload, read_exact_vec, read_plugin_message, varint_len,
+        write_plugin_message,
     },
+    warnings::{WarningKind, Warnings},
 };
 
 /// アーカイブ内のメタデータファイル名 (フォーマット判別の根拠でもある)。
 pub const METADATA_FILE: &str = "metaData.json";
 /// アーカイブ内の録画ストリームのファイル名。
 pub const RECORDING_FILE: &str = "recording.tmcpr";
+/// アーカイブ内の観戦者表示切り替え記録のファイル名 (任意エントリ)。
+pub const VISIBILITY_FILE: &str = "visibility";
+/// アーカイブ内の必須 mod 一覧のファイル名 (Forge / Fabric の任意エントリ)。
+pub const MODS_FILE: &str = "mods.json";
+/// アーカイブ内のサムネイルのファイル名 (任意エントリ)。
+pub const THUMB_FILE: &str = "thumb";
+/// metaData.json の `fileFormat`。
+pub const FILE_FORMAT: &str = "MCPR";
+/// 書き出す `fileFormatVersion` (読める最新でもある)。
+pub const FILE_FORMAT_VERSION: u32 = 14;
+/// recording が Login phase から始まる最初の `fileFormatVersion`。
+/// それより前の版は Play の途中から記録されている。
+const LOGIN_PHASE_FILE_FORMAT_VERSION: u32 = 14;
+/// ReplayMod が `thumb` の JPEG の前に置くマジックバイト列。
+const THUMB_MAGIC: [u8; 7] = [0, 1, 1, 2, 3, 5, 8];
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+/// [`Packet::validate`] が許す id の上限。現行プロトコルの clientbound id は
+/// どの phase でも 1 バイトに収まる。
+pub const MAX_PACKET_ID: i32 = 0xff;
+
+/// `PartialOrd` は derive しない (`data` まで比べる順序は並べ替えに使えない)。
+/// 時刻順に並べるときは [`Packet::cmp_by_time`] を安定ソートで使う。
+#[derive(Debug, Clone, PartialEq)]
 pub struct Packet {
     time: u32,
     id: i32,
@@ -30,12 +61,28 @@ impl Packet {
     pub fn new(time: u32, id: i32, data: Box<[u8]>) -> Self {
         Self { time, id, data }
     }
+    /// 空のバッファへ `write` で body を書かせてパケットにする。
+    /// body の各フィールドは [`Serializer`] のメソッドで書く。
+    pub fn build(
+        time: u32,
+        id: i32,
+        write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>,
+    ) -> io::Result<Self> {
+        let mut data = Vec::new();
+        write(&mut data)?;
+        Ok(Self::new(time, id, data.into()))
+    }
     pub fn time(&self) -> u32 {
         self.time
     }
     pub fn time_mut(&mut self) -> &mut u32 {
         &mut self.time
     }
+    /// 時刻だけで比べる。同時刻は `Equal` なので、安定ソートと組み合わせれば
+    /// 元の並び順が保たれる。
+    pub fn cmp_by_time(&self, other: &Self) -> std::cmp::Ordering {
+        self.time.cmp(&other.time)
+    }
     pub fn id(&self) -> i32 {
         self.id
     }
@@ -48,41 +95,168 @@ impl Packet {
     pub fn length(&self) -> u32 {
         (varint_len(self.id) + self.data.len()) as u32
     }
+    /// .tmcpr 上のフレーム全体のバイト数 (ヘッダ 8 + `VarInt id` + body)。
+    /// [`Self::write_to`] が書くバイト数と一致する。確保は行わない。
+    pub fn frame_size(&self) -> u64 {
+        8 + varint_len(self.id) as u64 + self.data.len() as u64
+    }
+    /// 壊れたデータから読んだらしいパケットを弾くための検査。
+    ///
+    /// id が [`MAX_PACKET_ID`] 以下の非負であることと、フレーム長
+    /// (`VarInt id` + body) が u32 に収まることを確かめる。
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0..=MAX_PACKET_ID).contains(&self.id) {
+            return Err(format!(
+                "packet id {} is out of range 0..=0x{:02x}",
+                self.id, MAX_PACKET_ID
+            ));
+        }
+        let length = varint_len(self.id)
+            .checked_add(self.data.len())
+            .filter(|&length| u32::try_from(length).is_ok());
+        if length.is_none() {
+            return Err(format!(
+                "packet 0x{:02x} is too large: {} bytes",
+                self.id,
+                self.data.len()
+            ));
+        }
+        Ok(())
+    }
+    /// フレーム本体 (`VarInt id` + body、.tmcpr ヘッダの length が指す範囲)
+    /// から組み立てる。id だけをデコードし、残りは body としてそのまま持つ。
+    pub fn from_raw_frame(time: u32, mut frame: Vec<u8>) -> io::Result<Self> {
+        let (id, body_start) = {
+            let mut cur = Cursor::new(frame.as_slice());
+            let id = cur.read_varint()?;
+            (id, cur.position() as usize)
+        };
+        let data = frame.split_off(body_start);
+        Ok(Self::new(time, id, data.into_boxed_slice()))
+    }
+    /// [`Self::from_raw_frame`] の逆。`VarInt id` + body を連結して返す。
+    pub fn raw_frame_bytes(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(self.length() as usize);
+        frame
+            .write_varint(self.id)
+            .expect("writing to Vec never fails");
+        frame.extend_from_slice(&self.data);
+        frame
+    }
     /// from .tmcpr
     pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        match RawFrame::read_from(reader)? {
+            Some(frame) => Ok(Some(frame.into_packet()?)),
+            None => Ok(None),
+        }
+    }
+    /// to .tmcpr
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.time.to_be_bytes())?;
+        writer.write_all(&self.length().to_be_bytes())?;
+        writer.write_varint(self.id)?;
+        writer.write_all(&self.data)?;
+        Ok(())
+    }
+    /// .tmcpr のバイト列の先頭 1 フレームを読む ([`Self::read_from`] の `&[u8]` 版)。
+    ///
+    /// 空なら `Ok(None)`。ヘッダが 8 バイトに満たない、または length が
+    /// 残りのバイト数を超えるなら `UnexpectedEof`。
+    pub fn from_tmcpr_bytes(bytes: &[u8]) -> io::Result<Option<Self>> {
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        match Self::read_from(&mut Cursor::new(bytes))? {
+            Some(packet) => Ok(Some(packet)),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("truncated frame header: {} bytes", bytes.len()),
+            )),
+        }
+    }
+    /// [`Self::write_to`] で書いた 1 フレーム分のバイト列。
+    pub fn to_tmcpr_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.frame_size() as usize);
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// `time | length | body` の 1 フレーム。body は id を分離せず不透明に持つ。
+///
+/// 通常の .tmcpr では body の先頭が `VarInt id` だが、id を別に持たない
+/// 実験的なダンプもこの形で読み書きできる。id が必要なら
+/// [`Self::into_packet`] で [`Packet`] へ変換する。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawFrame {
+    pub time: u32,
+    pub body: Vec<u8>,
+}
+
+impl RawFrame {
+    pub fn new(time: u32, body: Vec<u8>) -> Self {
+        Self { time, body }
+    }
+    /// body を `VarInt id` + データとして解釈する ([`Packet::from_raw_frame`])。
+    pub fn into_packet(self) -> io::Result<Packet> {
+        Packet::from_raw_frame(self.time, self.body)
+    }
+    /// ストリーム終端 (ヘッダ先頭で EOF) なら `Ok(None)`。
+    pub fn read_from<R: Read + ?Sized>(reader: &mut R) -> io::Result<Option<Self>> {
         let mut header = [0u8; 8];
         match reader.read_exact(&mut header) {
             Ok(()) => {
                 let time = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
                 let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
-                let mut data =
+                let body =
                     read_exact_vec(reader, checked_len_u32(length, "packet length")?, "packet")?;
-                let (packet_id, body_start) = {
-                    let mut cur = Cursor::new(data.as_slice());
-                    let packet_id = cur.read_varint()?;
-                    (packet_id, cur.position() as usize)
-                };
-                let packet_data = data.split_off(body_start);
-                Ok(Some(Packet::new(
-                    time,
-                    packet_id,
-                    packet_data.into_boxed_slice(),
-                )))
+                Ok(Some(Self::new(time, body)))
             }
             Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
             Err(e) => Err(e),
         }
     }
-    /// to .tmcpr
-    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let length = u32::try_from(self.body.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
         writer.write_all(&self.time.to_be_bytes())?;
-        writer.write_all(&self.length().to_be_bytes())?;
-        writer.write_varint(self.id)?;
-        writer.write_all(&self.data)?;
+        writer.write_all(&length.to_be_bytes())?;
+        writer.write_all(&self.body)?;
         Ok(())
     }
 }
 
+impl From<&Packet> for RawFrame {
+    fn from(packet: &Packet) -> Self {
+        Self::new(packet.time(), packet.raw_frame_bytes())
+    }
+}
+
+/// tmcpr をフレーム単位でフィルタし、残すフレームは元のバイト列のまま書く。
+///
+/// `keep` には (state, id, id 以降の body) を渡す。id の VarInt が冗長な
+/// エンコードでも再エンコードしないので、残したフレームはバイト単位で一致する。
+/// 戻り値は残したフレーム数。
+pub fn filter_frames_verbatim<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+    mut keep: impl FnMut(State, i32, &[u8]) -> bool,
+) -> io::Result<usize> {
+    let mut state = State::Login;
+    let mut kept = 0;
+    while let Some(frame) = RawFrame::read_from(reader)? {
+        let mut cur = Cursor::new(frame.body.as_slice());
+        let id = cur.read_varint()?;
+        let body = &frame.body[cur.position() as usize..];
+        if keep(state, id, body) {
+            frame.write_to(writer)?;
+            kept += 1;
+        }
+        state = state.advance(id);
+    }
+    Ok(kept)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct MetaData {
@@ -119,38 +293,423 @@ impl Default for MetaData {
     }
 }
 
+impl MetaData {
+    /// `mcversion` と `protocol` を組で書き換える (片方だけ変えると
+    /// ReplayMod が別バージョンとして扱うため)。`mcversion` は空にできない。
+    pub fn set_version(&mut self, mcversion: &str, protocol: u32) -> anyhow::Result<()> {
+        anyhow::ensure!(!mcversion.is_empty(), "mcversion must not be empty");
+        self.mcversion = mcversion.to_string();
+        self.protocol = protocol;
+        Ok(())
+    }
+    /// `players` に載っているのに、ストリーム中のプレイヤー一覧
+    /// (Player Info Update の Add Player) に一度も現れない UUID。
+    pub fn players_not_seen<R: ArchiveReader>(
+        &self,
+        reader: &mut ReplayReader<R>,
+    ) -> anyhow::Result<HashSet<uuid::Uuid>> {
+        let seen = players_in_recording(reader)?;
+        Ok(self
+            .players
+            .difference(&seen.into_iter().collect())
+            .copied()
+            .collect())
+    }
+    /// ストリーム中のプレイヤー一覧に現れるのに、`players` に載っていない UUID
+    /// ([`Self::players_not_seen`] の逆)。
+    pub fn players_seen_not_listed<R: ArchiveReader>(
+        &self,
+        reader: &mut ReplayReader<R>,
+    ) -> anyhow::Result<HashSet<uuid::Uuid>> {
+        let mut seen = players_in_recording(reader)?;
+        seen.retain(|uuid| !self.players.contains(uuid));
+        Ok(seen)
+    }
+}
+
+/// recording.tmcpr の Player Info Update で追加されたプレイヤーの UUID。
+fn players_in_recording<R: ArchiveReader>(
+    reader: &mut ReplayReader<R>,
+) -> anyhow::Result<HashSet<uuid::Uuid>> {
+    let mut seen = HashSet::new();
+    for (state, packet) in reader.get_packet_reader()? {
+        if state != State::Play || packet.id() != PLAYER_INFO_UPDATE_PACKET_ID {
+            continue;
+        }
+        let entries = read_player_info_update(packet.data()).map_err(|e| {
+            anyhow::anyhow!("malformed player info update at {}ms: {}", packet.time(), e)
+        })?;
+        seen.extend(
+            entries
+                .into_iter()
+                .filter(|e| e.name.is_some())
+                .map(|e| e.uuid),
+        );
+    }
+    Ok(seen)
+}
+
+/// `mods.json`: 再生に必要な mod の一覧。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct Mods {
+    pub requiredMods: Vec<ModInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct ModInfo {
+    pub modID: String,
+    pub modName: String,
+    pub modVersion: String,
+}
+
 pub struct ReadablePacketStream<R> {
     state: State,
+    transitions_rule: StateTransitions,
     reader: R,
+    /// 次に読むパケットの通し番号。
+    index: usize,
+    transitions: Vec<(usize, State, State)>,
+    resync: bool,
+    /// resync モードの先読みバッファ (未消費のバイト)。
+    lookahead: VecDeque<u8>,
+    /// resync で読み飛ばしたバイト数。
+    skipped_bytes: usize,
+    /// resync モードで最後に受理したフレームの時刻。
+    last_time: u32,
 }
 impl<R> ReadablePacketStream<R> {
     pub fn new(state: State, reader: R) -> Self {
-        Self { state, reader }
+        Self {
+            state,
+            transitions_rule: StateTransitions::Modern,
+            reader,
+            index: 0,
+            transitions: Vec::new(),
+            resync: false,
+            lookahead: VecDeque::new(),
+            skipped_bytes: 0,
+            last_time: 0,
+        }
+    }
+    /// 壊れたフレームに当たっても止まらず、1 バイトずつずらしながら
+    /// もっともらしいヘッダを探して読み直すモード (既定 off)。
+    ///
+    /// ヒューリスティックであり、採用するのは「length が
+    /// [`RESYNC_MAX_FRAME_LEN`] 以下で末尾まで読め、body 先頭が 0..256 の
+    /// id の VarInt で、time が直前のフレーム以上」のフレーム。ゴミが偶然
+    /// これを満たせば誤ったパケットとして読まれうる。
+    pub fn resync(mut self, enabled: bool) -> Self {
+        self.resync = enabled;
+        self
+    }
+    /// state 遷移の規則 (既定は [`StateTransitions::Modern`])。
+    pub fn state_transitions(mut self, rule: StateTransitions) -> Self {
+        self.transitions_rule = rule;
+        self
+    }
+    /// resync モードで読み飛ばしたバイト数。
+    pub fn skipped_bytes(&self) -> usize {
+        self.skipped_bytes
+    }
+    /// ここまでに起きた state 遷移 `(遷移を起こしたパケットの通し番号, 遷移前, 遷移後)`。
+    pub fn transition_log(&self) -> &[(usize, State, State)] {
+        &self.transitions
+    }
+}
+
+/// resync モードで受理するフレーム長の上限 (プロトコル上のパケット長上限 2^21)。
+pub const RESYNC_MAX_FRAME_LEN: u32 = 1 << 21;
+
+impl<R: Read> ReadablePacketStream<R> {
+    /// 先読みバッファを `len` バイト以上にする。EOF で足りなければ false。
+    fn fill_lookahead(&mut self, len: usize) -> bool {
+        let mut chunk = [0u8; 8192];
+        while self.lookahead.len() < len {
+            match self.reader.read(&mut chunk) {
+                Ok(0) | Err(_) => return false,
+                Ok(n) => self.lookahead.extend(&chunk[..n]),
+            }
+        }
+        true
+    }
+
+    /// フレームヘッダ (time, length) が resync で受理できる範囲か。
+    fn is_plausible(&self, time: u32, length: u32) -> bool {
+        length != 0 && length <= RESYNC_MAX_FRAME_LEN && time >= self.last_time
+    }
+
+    /// 先読みバッファ先頭がもっともらしいフレームなら、その (time, length)。
+    fn plausible_header(&mut self) -> Option<(u32, usize)> {
+        let header: Vec<u8> = self.lookahead.range(..8).copied().collect();
+        let time = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        if !self.is_plausible(time, length) {
+            return None;
+        }
+        let length = length as usize;
+        if !self.fill_lookahead(8 + length) {
+            return None;
+        }
+        let id_bytes: Vec<u8> = self
+            .lookahead
+            .range(8..8 + length.min(8 + 5))
+            .copied()
+            .collect();
+        has_plausible_id(&id_bytes).then_some((time, length))
+    }
+
+    /// 先読みが空のとき、先読みを通さずに 1 フレーム読む。もっともらしく
+    /// なければ読んだバイトを先読みへ積み、None (以降は走査で探す)。
+    fn read_direct(&mut self) -> Option<Packet> {
+        let mut header = [0u8; 8];
+        let read = read_up_to(&mut self.reader, &mut header);
+        self.lookahead.extend(&header[..read]);
+        if read < 8 {
+            return None;
+        }
+        let time = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        if !self.is_plausible(time, length) {
+            return None;
+        }
+        let mut frame = Vec::new();
+        let _ = (&mut self.reader)
+            .take(u64::from(length))
+            .read_to_end(&mut frame);
+        if frame.len() == length as usize && has_plausible_id(&frame) {
+            self.lookahead.clear();
+            self.last_time = time;
+            return Packet::from_raw_frame(time, frame).ok();
+        }
+        self.lookahead.extend(&frame);
+        None
     }
+
+    /// resync モードの 1 フレーム。通常どおり読めてもっともらしければそのまま返し、
+    /// そうでなければ 1 バイトずつずらしてもっともらしいヘッダを探す。
+    fn next_resync(&mut self) -> Option<Packet> {
+        if self.lookahead.is_empty()
+            && let Some(packet) = self.read_direct()
+        {
+            return Some(packet);
+        }
+        loop {
+            if !self.fill_lookahead(8) {
+                self.skipped_bytes += self.lookahead.len();
+                self.lookahead.clear();
+                return None;
+            }
+            match self.plausible_header() {
+                Some((time, length)) => {
+                    let frame: Vec<u8> = self.lookahead.drain(..8 + length).skip(8).collect();
+                    match Packet::from_raw_frame(time, frame) {
+                        Ok(packet) => {
+                            self.last_time = time;
+                            return Some(packet);
+                        }
+                        Err(_) => self.skipped_bytes += 8 + length,
+                    }
+                }
+                None => {
+                    self.lookahead.pop_front();
+                    self.skipped_bytes += 1;
+                }
+            }
+        }
+    }
+}
+
+/// body 先頭が 0..256 の id の VarInt か (resync の受理条件)。
+fn has_plausible_id(frame: &[u8]) -> bool {
+    let id_bytes = &frame[..frame.len().min(5)];
+    Cursor::new(id_bytes)
+        .read_varint()
+        .is_ok_and(|id| (0..256).contains(&id))
+}
+
+/// `buf` が埋まるか EOF (またはエラー) まで読み、読めたバイト数を返す。
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> usize {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => filled += n,
+        }
+    }
+    filled
 }
+
 impl<R: Read> Iterator for ReadablePacketStream<R> {
     type Item = (State, Packet);
     fn next(&mut self) -> Option<Self::Item> {
-        Packet::read_from(&mut self.reader)
-            .unwrap_or_default()
-            .map(|packet| {
-                let old_state = self.state;
-                self.state = old_state.advance(packet.id());
-                (old_state, packet)
-            })
+        let packet = if self.resync {
+            self.next_resync()
+        } else {
+            Packet::read_from(&mut self.reader).unwrap_or_default()
+        };
+        packet.map(|packet| {
+            let old_state = self.state;
+            self.state = self.transitions_rule.advance(old_state, packet.id());
+            if self.state != old_state {
+                self.transitions.push((self.index, old_state, self.state));
+            }
+            self.index += 1;
+            (old_state, packet)
+        })
+    }
+}
+
+/// 展開済みの recording 全体 (`&[u8]`) から、body をコピーせずに
+/// `(time, id, body)` を読む。統計や検索のような読むだけの処理向け。
+///
+/// [`ReadablePacketStream`] と同じく、壊れたフレームに当たったらそこで止まる。
+pub struct BorrowedPacketReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> BorrowedPacketReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+    /// まだ読んでいない残りのバイト列。
+    pub fn remaining(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<'a> Iterator for BorrowedPacketReader<'a> {
+    type Item = (u32, i32, &'a [u8]);
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.bytes.get(..8)?;
+        let time = u32::from_be_bytes(header[..4].try_into().unwrap());
+        let length = u32::from_be_bytes(header[4..].try_into().unwrap()) as usize;
+        let frame = self.bytes.get(8..8usize.checked_add(length)?)?;
+        let mut cursor = Cursor::new(frame);
+        let id = cursor.read_varint().ok()?;
+        self.bytes = &self.bytes[8 + length..];
+        Some((time, id, &frame[cursor.position() as usize..]))
     }
 }
+
+/// [`ReplayWriter::get_packet_writer_with_flush`] が自動で flush する間隔。
+/// どちらかに達したら flush する。両方 None なら自動では flush しない。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlushPolicy {
+    /// この件数を push するごとに flush する。
+    pub every_packets: Option<usize>,
+    /// 前回の flush からこの時間 (実時間) が経った後の push で flush する。
+    pub every: Option<std::time::Duration>,
+}
+
+/// 書いたバイト列全体の CRC32 を計算しながら `W` へ書く [`Write`]。
+///
+/// キャプチャ中に [`Self::checksum`] をログへ残しておけば、後からディスク上の
+/// recording と突き合わせて破損を検出できる。
+pub struct ChecksumWriter<W> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+    /// ここまでに書いたバイト列の CRC32。
+    pub fn checksum(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 pub struct WritablePacketStream<W> {
     writer: W,
+    flush_policy: FlushPolicy,
+    /// 前回の flush 以降に push した件数。
+    unflushed: usize,
+    /// 前回の flush の時刻 (`flush_policy.every` があるときだけ測る)。
+    last_flush: Option<std::time::Instant>,
+    /// 最後に push したパケットの時刻。
+    last_time: Option<u32>,
 }
 impl<W> WritablePacketStream<W> {
     fn new(writer: W) -> Self {
-        Self { writer }
+        Self::with_flush_policy(writer, FlushPolicy::default())
+    }
+    fn with_flush_policy(writer: W, flush_policy: FlushPolicy) -> Self {
+        Self {
+            writer,
+            flush_policy,
+            unflushed: 0,
+            last_flush: flush_policy.every.map(|_| std::time::Instant::now()),
+            last_time: None,
+        }
+    }
+    /// 書き込み先の writer ([`ChecksumWriter::checksum`] を読むときなど)。
+    pub fn get_ref(&self) -> &W {
+        &self.writer
     }
 }
 impl<W: Write> WritablePacketStream<W> {
+    /// 時刻順を確かめずに書く (後で並べ替える前提の呼び出し側向け)。
     pub fn push(&mut self, packet: Packet) -> Result<(), io::Error> {
-        packet.write_to(&mut self.writer)
+        self.last_time = Some(packet.time());
+        packet.write_to(&mut self.writer)?;
+        self.unflushed += 1;
+        let by_count = self
+            .flush_policy
+            .every_packets
+            .is_some_and(|n| self.unflushed >= n);
+        let by_time = self
+            .flush_policy
+            .every
+            .zip(self.last_flush)
+            .is_some_and(|(every, last)| last.elapsed() >= every);
+        if by_count || by_time {
+            self.flush()?;
+        }
+        Ok(())
+    }
+    /// [`Self::push`] と同じだが、直前に push したパケットより時刻が戻る
+    /// なら書かずにエラーにする (リプレイを合成するコードのバグ検出用)。
+    pub fn push_checked(&mut self, packet: Packet) -> io::Result<()> {
+        if let Some(last) = self.last_time
+            && packet.time() < last
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "packet time {}ms is before the previous packet at {}ms",
+                    packet.time(),
+                    last
+                ),
+            ));
+        }
+        self.push(packet)
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.unflushed = 0;
+        if self.last_flush.is_some() {
+            self.last_flush = Some(std::time::Instant::now());
+        }
+        self.writer.flush()
     }
 }
 
@@ -162,6 +721,7 @@ impl<W: Write> WritablePacketStream<W> {
 pub struct McprEventSource<R> {
     reader: R,
     state: State,
+    transitions: StateTransitions,
     info: ReplayInfo,
 }
 
@@ -170,9 +730,24 @@ impl<R: Read> McprEventSource<R> {
         Self {
             reader,
             state: State::Login,
+            transitions: StateTransitions::Modern,
+            info,
+        }
+    }
+    /// 先頭のパケットの state を指定して開く (Play から始まる古い版向け)。
+    pub fn with_initial_state(reader: R, info: ReplayInfo, state: State) -> Self {
+        Self {
+            reader,
+            state,
+            transitions: StateTransitions::Modern,
             info,
         }
     }
+    /// state 遷移の規則 (既定は [`StateTransitions::Modern`])。
+    pub fn with_transitions(mut self, transitions: StateTransitions) -> Self {
+        self.transitions = transitions;
+        self
+    }
 }
 
 impl<R: Read> EventSource for McprEventSource<R> {
@@ -184,7 +759,7 @@ impl<R: Read> EventSource for McprEventSource<R> {
             return Ok(None);
         };
         let state = self.state;
-        self.state = state.advance(packet.id());
+        self.state = self.transitions.advance(state, packet.id());
         let (time, id, data) = packet.into_parts();
         Ok(Some(Event::Packet {
             time: Time::from_millis(time as u64),
@@ -197,28 +772,249 @@ impl<R: Read> EventSource for McprEventSource<R> {
 
 pub struct ReplayReader<R: ArchiveReader> {
     reader: R,
+    /// エントリごとの展開後サイズの上限。
+    max_uncompressed: Option<u64>,
+    /// recording の先頭のパケットの state ([`Self::open_dispatched`] が版で決める)。
+    initial_state: State,
+    /// state 遷移の規則。None なら初めて要るときに metadata の protocol で決める。
+    transitions: Option<StateTransitions>,
 }
 
 impl<R: ArchiveReader> ReplayReader<R> {
     pub fn new(reader: R) -> Self {
-        Self { reader }
+        Self {
+            reader,
+            max_uncompressed: None,
+            initial_state: State::Login,
+            transitions: None,
+        }
+    }
+    /// metaData.json の `fileFormat` / `fileFormatVersion` を読み、版に合わせて開く。
+    ///
+    /// `fileFormat` が `MCPR` 以外、または `fileFormatVersion` が 1..=14 の
+    /// 範囲外ならエラー。13 以前の recording は Login phase を含まず Play から
+    /// 始まるので、パケット列の先頭 state を Play にする。
+    /// protocol が 1.20.2 より前なら Configuration phase を経ない遷移規則
+    /// ([`StateTransitions::Legacy`]) で読む。
+    pub fn open_dispatched(reader: R) -> anyhow::Result<Self> {
+        Self::new(reader).dispatch()
+    }
+    /// 開いた reader に [`Self::open_dispatched`] と同じ版の判別を掛ける。
+    ///
+    /// [`Self::with_limits`] で上限を付けた reader を版に合わせて読むときに使う
+    /// (metadata の読み出しにも上限が掛かる)。
+    pub fn dispatch(mut self) -> anyhow::Result<Self> {
+        let metadata = self.read_metadata()?;
+        anyhow::ensure!(
+            metadata.fileFormat == FILE_FORMAT,
+            "unsupported file format {:?}",
+            metadata.fileFormat
+        );
+        anyhow::ensure!(
+            (1..=FILE_FORMAT_VERSION).contains(&metadata.fileFormatVersion),
+            "unsupported file format version {}: 1..={} is supported",
+            metadata.fileFormatVersion,
+            FILE_FORMAT_VERSION
+        );
+        if metadata.fileFormatVersion < LOGIN_PHASE_FILE_FORMAT_VERSION {
+            self.initial_state = State::Play;
+        }
+        self.transitions = Some(transitions_for(&metadata));
+        Ok(self)
+    }
+    /// 各エントリの展開後サイズを `max_uncompressed` バイトまでに制限して開く。
+    ///
+    /// 信頼できないアーカイブ (zip bomb) 向け。上限を超えたエントリの読み出しは
+    /// `InvalidData` エラーになる (パケット列では超えた位置で打ち切られる)。
+    pub fn with_limits(reader: R, max_uncompressed: u64) -> Self {
+        Self {
+            reader,
+            max_uncompressed: Some(max_uncompressed),
+            initial_state: State::Login,
+            transitions: None,
+        }
+    }
+    /// state 遷移の規則。まだ決まっていなければ metadata の protocol で決める
+    /// (metadata が読めなければ [`StateTransitions::Modern`])。
+    fn transitions(&mut self) -> StateTransitions {
+        if let Some(transitions) = self.transitions {
+            return transitions;
+        }
+        let transitions = self
+            .read_metadata()
+            .map_or(StateTransitions::Modern, |metadata| {
+                transitions_for(&metadata)
+            });
+        self.transitions = Some(transitions);
+        transitions
+    }
+    fn open_entry(&mut self, filename: &str) -> anyhow::Result<Box<dyn Read + '_>> {
+        let reader = self.reader.get_reader(filename)?;
+        Ok(match self.max_uncompressed {
+            Some(max) => Box::new(LimitedReader::new(reader, max)),
+            None => reader,
+        })
     }
     pub fn read_metadata(&mut self) -> anyhow::Result<MetaData> {
-        let reader = BufReader::new(self.reader.get_reader(METADATA_FILE)?);
+        let reader = BufReader::new(self.open_entry(METADATA_FILE)?);
         let metadata = serde_json::from_reader(reader)?;
         Ok(metadata)
     }
+    /// メタデータの protocol が `max` 以下であることを確かめる。
+    ///
+    /// 対応範囲外のリプレイをパケットを読む前に弾くためのガード。
+    pub fn require_protocol_max(&mut self, max: u32) -> anyhow::Result<()> {
+        let metadata = self.read_metadata()?;
+        anyhow::ensure!(
+            metadata.protocol <= max,
+            "unsupported protocol version {} ({}): at most {} is supported",
+            metadata.protocol,
+            metadata.mcversion,
+            max
+        );
+        Ok(())
+    }
+    /// recording の最大のパケット時刻 (ms)。パケットが無ければ 0。
+    ///
+    /// metaData.json の `duration` は実際の末尾とずれていることが多いので、
+    /// 正しい値が要るときはこちらを使う。
+    pub fn actual_duration(&mut self) -> anyhow::Result<u32> {
+        Ok(self
+            .get_packet_reader()?
+            .map(|(_, packet)| packet.time())
+            .max()
+            .unwrap_or(0))
+    }
+    /// `visibility` エントリの生バイト列。存在しなければ `Ok(None)`、
+    /// あるのに読めなければエラー。
+    ///
+    /// 中身は解釈せず、編集後の書き戻し ([`ReplayWriter::write_visibility`])
+    /// でそのまま保存するために使う。
+    pub fn read_visibility(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        if !self.reader.contains(VISIBILITY_FILE)? {
+            return Ok(None);
+        }
+        let mut reader = self.open_entry(VISIBILITY_FILE)?;
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+    /// `mods.json` エントリ。存在しなければ `Ok(None)`、あるのに読めなければエラー。
+    pub fn read_mods(&mut self) -> anyhow::Result<Option<Mods>> {
+        if !self.reader.contains(MODS_FILE)? {
+            return Ok(None);
+        }
+        let reader = self.open_entry(MODS_FILE)?;
+        Ok(Some(serde_json::from_reader(BufReader::new(reader))?))
+    }
+    /// `thumb` エントリの JPEG。存在しなければ `Ok(None)`、あるのに読めなければエラー。
+    ///
+    /// 先頭のマジックバイト列は取り除いて返す。
+    pub fn read_thumbnail(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        if !self.reader.contains(THUMB_FILE)? {
+            return Ok(None);
+        }
+        let mut reader = self.open_entry(THUMB_FILE)?;
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let jpeg = bytes
+            .strip_prefix(&THUMB_MAGIC[..])
+            .ok_or_else(|| anyhow::anyhow!("{} has no magic header", THUMB_FILE))?;
+        Ok(Some(jpeg.to_vec()))
+    }
+    /// recording.tmcpr を [`filter_frames_verbatim`] で `out` へ写す。
+    /// 残したパケットは元のバイト列のまま書かれる。
+    pub fn filter_recording_verbatim<W: ArchiveWriter>(
+        &mut self,
+        out: &mut ReplayWriter<W>,
+        keep: impl FnMut(State, i32, &[u8]) -> bool,
+    ) -> anyhow::Result<usize> {
+        self.copy_recording(out, |reader, writer| {
+            Ok(filter_frames_verbatim(reader, writer, keep)?)
+        })
+    }
+    /// recording.tmcpr の生バイト列を `copy` で `out` の recording.tmcpr へ写す
+    /// (フレーム単位の独自の写し方をする呼び出し側向け)。
+    pub fn copy_recording<W: ArchiveWriter, T>(
+        &mut self,
+        out: &mut ReplayWriter<W>,
+        copy: impl FnOnce(&mut dyn Read, &mut dyn Write) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut reader = BufReader::new(self.open_entry(RECORDING_FILE)?);
+        let mut writer = BufWriter::new(out.writer.get_writer(RECORDING_FILE)?);
+        let result = copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+        Ok(result)
+    }
     pub fn get_packet_reader<'a>(
         &'a mut self,
     ) -> anyhow::Result<ReadablePacketStream<impl Read + 'a>> {
-        let reader = BufReader::new(self.reader.get_reader(RECORDING_FILE)?);
-        Ok(ReadablePacketStream::new(State::Login, reader))
+        let (state, transitions) = (self.initial_state, self.transitions());
+        let reader = BufReader::new(self.open_entry(RECORDING_FILE)?);
+        Ok(ReadablePacketStream::new(state, reader).state_transitions(transitions))
+    }
+    /// recording をバックグラウンドスレッドで読み、容量 `cap` の
+    /// `sync_channel` へ 1 パケットずつ送る (GUI スレッドで受け取る用)。
+    ///
+    /// 受け手が追いつかなければ送り手が待つので、読み進みすぎない。
+    /// 壊れたフレームに当たったら、そのエラーを最後の `Err` として送って終わる。
+    /// リーダーごとスレッドへ移し、recording のエントリはスレッドの中で開いて
+    /// 先頭から順に読む (全体をメモリへ読み込まない)。
+    /// 受け手を drop するとスレッドも止まる。
+    #[cfg(feature = "threads")]
+    pub fn spawn_channel(
+        mut self,
+        cap: usize,
+    ) -> std::sync::mpsc::Receiver<io::Result<(State, Packet)>>
+    where
+        R: Send + 'static,
+    {
+        let mut state = self.initial_state;
+        let transitions = self.transitions();
+        let (sender, receiver) = std::sync::mpsc::sync_channel(cap);
+        std::thread::spawn(move || {
+            let mut reader = match self.open_entry(RECORDING_FILE) {
+                Ok(entry) => BufReader::new(entry),
+                Err(e) => {
+                    let _ = sender.send(Err(io::Error::other(format!("{:#}", e))));
+                    return;
+                }
+            };
+            loop {
+                let item = match Packet::read_from(&mut reader) {
+                    Ok(Some(packet)) => {
+                        let old_state = state;
+                        state = transitions.advance(old_state, packet.id());
+                        Ok((old_state, packet))
+                    }
+                    Ok(None) => break,
+                    Err(e) => Err(e),
+                };
+                let failed = item.is_err();
+                if sender.send(item).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        receiver
     }
     /// メタデータを読んだうえで論理イベント列リーダーを開く。
     pub fn event_source<'a>(&'a mut self) -> anyhow::Result<McprEventSource<impl Read + 'a>> {
-        let info = ReplayInfo::from(&self.read_metadata()?);
-        let reader = BufReader::new(self.reader.get_reader(RECORDING_FILE)?);
-        Ok(McprEventSource::new(reader, info))
+        let metadata = self.read_metadata()?;
+        let info = ReplayInfo::from(&metadata);
+        let transitions = *self.transitions.get_or_insert(transitions_for(&metadata));
+        let state = self.initial_state;
+        let reader = BufReader::new(self.open_entry(RECORDING_FILE)?);
+        Ok(McprEventSource::with_initial_state(reader, info, state).with_transitions(transitions))
+    }
+}
+
+/// metadata の protocol に合う state 遷移の規則。protocol 0 (不明) は
+/// [`StateTransitions::Modern`] とみなす。
+fn transitions_for(metadata: &MetaData) -> StateTransitions {
+    match metadata.protocol {
+        0 => StateTransitions::Modern,
+        protocol => StateTransitions::for_protocol(protocol),
     }
 }
 
@@ -230,47 +1026,390 @@ impl<W: ArchiveWriter> ReplayWriter<W> {
     pub fn new(writer: W) -> Self {
         Self { writer }
     }
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
 
     pub fn write_metadata(&mut self, metadata: MetaData) -> anyhow::Result<()> {
         let writer = BufWriter::new(self.writer.get_writer(METADATA_FILE)?);
         serde_json::to_writer(writer, &metadata)?;
         Ok(())
     }
+    /// `reader` のメタデータを、`duration` を [`ReplayReader::actual_duration`]
+    /// に直して書く。書いたメタデータを返す。
+    pub fn write_metadata_with_actual_duration<R: ArchiveReader>(
+        &mut self,
+        reader: &mut ReplayReader<R>,
+    ) -> anyhow::Result<MetaData> {
+        let mut metadata = reader.read_metadata()?;
+        metadata.duration = u64::from(reader.actual_duration()?);
+        self.write_metadata(metadata.clone())?;
+        Ok(metadata)
+    }
+    /// [`ReplayReader::read_visibility`] で読んだ `visibility` を書き戻す。
+    pub fn write_visibility(&mut self, visibility: &[u8]) -> anyhow::Result<()> {
+        let mut writer = self.writer.get_writer(VISIBILITY_FILE)?;
+        writer.write_all(visibility)?;
+        writer.flush()?;
+        Ok(())
+    }
+    pub fn write_mods(&mut self, mods: &Mods) -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(self.writer.get_writer(MODS_FILE)?);
+        serde_json::to_writer(&mut writer, mods)?;
+        writer.flush()?;
+        Ok(())
+    }
+    /// `thumb` エントリを書く。`jpeg_bytes` の前に ReplayMod のマジックバイト列を付ける。
+    pub fn write_thumbnail(&mut self, jpeg_bytes: &[u8]) -> anyhow::Result<()> {
+        let mut writer = self.writer.get_writer(THUMB_FILE)?;
+        writer.write_all(&THUMB_MAGIC)?;
+        writer.write_all(jpeg_bytes)?;
+        writer.flush()?;
+        Ok(())
+    }
     pub fn get_packet_writer<'a>(
         &'a mut self,
     ) -> anyhow::Result<WritablePacketStream<impl Write + 'a>> {
         let writer = BufWriter::new(self.writer.get_writer(RECORDING_FILE)?);
         Ok(WritablePacketStream::new(writer))
     }
+    /// [`Self::get_packet_writer`] と同じだが、`policy` の間隔で自動的に flush する。
+    ///
+    /// 長時間のライブ録画を展開済みディレクトリへ書くとき、途中で落ちても
+    /// 失うのが最後の flush 以降の分だけで済むようにする。
+    pub fn get_packet_writer_with_flush<'a>(
+        &'a mut self,
+        policy: FlushPolicy,
+    ) -> anyhow::Result<WritablePacketStream<impl Write + 'a>> {
+        let writer = BufWriter::new(self.writer.get_writer(RECORDING_FILE)?);
+        Ok(WritablePacketStream::with_flush_policy(writer, policy))
+    }
+    /// [`Self::get_packet_writer`] と同じだが、recording に書いたバイト列の
+    /// CRC32 を [`ChecksumWriter`] で計算し続ける。
+    /// `get_ref().checksum()` で途中経過を読める。
+    pub fn get_packet_writer_with_checksum<'a>(
+        &'a mut self,
+    ) -> anyhow::Result<WritablePacketStream<ChecksumWriter<impl Write + 'a>>> {
+        let writer = BufWriter::new(self.writer.get_writer(RECORDING_FILE)?);
+        Ok(WritablePacketStream::new(ChecksumWriter::new(writer)))
+    }
 }
 
-/// 論理イベント列を .mcpr アーカイブとして書き出す Sink。
-///
-/// ReplayMod の再生互換のため、ソースに存在しない接続フェーズ遷移
-/// パケットを合成する:
-/// - 最初のイベントが Login state でない場合 (= Flashback 由来)、
-///   Login Success (0x02) をダミーの profile で合成する
-/// - Configuration → Play の境目に Finish Configuration (0x03) が
-///   無ければ合成する
-///
-/// `Event::Custom` (Flashback 独自 action) はパケットに対応物が
-/// 無いためスキップし、件数を [`Self::skipped_custom`] で報告する。
-///
-/// tmcpr は内部バッファに構築し、[`EventSink::finish`] で
-/// recording.tmcpr → metaData.json の順にアーカイブへ書き出す
-/// (zip アーカイブは同時に 1 エントリしか開けないため)。
-pub struct McprEventSink<W: ArchiveWriter> {
-    archive: W,
-    buffer: Vec<u8>,
-    protocol_version: u32,
-    written_state: State,
-    last_time: u32,
-    skipped_custom: usize,
-    finished: bool,
+#[cfg(feature = "fs")]
+impl ReplayWriter<crate::archive::directory::DirArchive> {
+    /// 展開済みリプレイの `index` 番目のパケットを `new` で置き換える。
+    ///
+    /// フレームは可変長なので、基本は先頭〜N 番目の直前までをコピーし、
+    /// 新しいフレーム、残りの順に一時ファイルへ書いてから差し替える。
+    /// フレーム長 (`length`) が変わらない場合はそのまま `Seek` で
+    /// 上書きする (ファイル全体を書き直さない高速経路)。
+    pub fn replace_packet(&mut self, index: usize, new: Packet) -> anyhow::Result<()> {
+        use std::fs::{self, File, OpenOptions};
+        use std::io::SeekFrom;
+
+        let path = self.writer.path().join(RECORDING_FILE);
+        let mut reader = BufReader::new(File::open(&path)?);
+
+        // index 番目のフレームの位置と length を探す
+        let mut offset = 0u64;
+        let mut header = [0u8; 8];
+        let mut current = 0;
+        let old_length = loop {
+            reader.read_exact(&mut header).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => {
+                    anyhow::anyhow!("packet index {} out of range ({} packets)", index, current)
+                }
+                _ => e.into(),
+            })?;
+            let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+            if current == index {
+                break length;
+            }
+            reader.seek_relative(length as i64)?;
+            offset += 8 + length as u64;
+            current += 1;
+        };
+        drop(reader);
+
+        if new.length() == old_length {
+            let mut file = OpenOptions::new().write(true).open(&path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            new.write_to(&mut file)?;
+            file.flush()?;
+            return Ok(());
+        }
+
+        let tmp_path = path.with_extension("tmcpr.tmp");
+        {
+            let mut src = BufReader::new(File::open(&path)?);
+            let mut dst = BufWriter::new(File::create(&tmp_path)?);
+            io::copy(&mut (&mut src).take(offset), &mut dst)?;
+            new.write_to(&mut dst)?;
+            src.seek_relative(8 + old_length as i64)?;
+            io::copy(&mut src, &mut dst)?;
+            dst.flush()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
 }
 
-impl<W: ArchiveWriter> McprEventSink<W> {
-    /// `protocol_version` は遷移パケット合成の形式判定に使う
+impl<W: Write + Seek> ReplayWriter<ZipArchiveWriter<W>> {
+    /// 全エントリを Stored (無圧縮) で書く、デバッグ用の .mcpr。
+    /// `unzip` やバイナリエディタで recording.tmcpr のバイト列をそのまま覗ける。
+    pub fn uncompressed(writer: W) -> Self {
+        Self::new(ZipArchiveWriter::with_policy(
+            writer,
+            ZipCompressionPolicy::new(EntryCompression::Stored),
+        ))
+    }
+    /// zip を finalize して内側の writer を返す ([`ZipArchiveWriter::finish`])。
+    pub fn finish(self) -> anyhow::Result<W> {
+        self.writer.finish()
+    }
+}
+
+impl<W: Read + Write + Seek> ReplayWriter<ZipArchiveWriter<W>> {
+    /// [`Self::finish`] した後、書いたアーカイブを [`ReplayReader`] で開き直し、
+    /// metaData.json が読めて recording から 1 件以上パケットが読めることを
+    /// 確かめてから writer を返す (位置は末尾に戻す)。
+    pub fn finish_verified(self) -> anyhow::Result<W> {
+        let mut inner = self.finish()?;
+        inner.seek(io::SeekFrom::Start(0))?;
+        {
+            let mut reader = ReplayReader::new(ZipArchiveReader::new(&mut inner)?);
+            reader.read_metadata()?;
+            anyhow::ensure!(
+                reader.get_packet_reader()?.next().is_some(),
+                "written {} has no readable packet",
+                RECORDING_FILE
+            );
+        }
+        inner.seek(io::SeekFrom::End(0))?;
+        Ok(inner)
+    }
+}
+
+/// 展開済みリプレイのディレクトリから `metaData.json` だけを読む。
+///
+/// recording.tmcpr は開かない (無くてもよい) ので、リプレイの並ぶ
+/// フォルダから一覧を作るときに使う。`path` がディレクトリでない場合と
+/// `metaData.json` が無い場合は、それと分かるエラーを返す。
+#[cfg(feature = "fs")]
+pub fn read_dir_metadata<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<MetaData> {
+    let path = path.as_ref();
+    anyhow::ensure!(path.is_dir(), "{} is not a directory", path.display());
+    let metadata_path = path.join(METADATA_FILE);
+    let file = std::fs::File::open(&metadata_path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => {
+            anyhow::anyhow!("{} has no {}", path.display(), METADATA_FILE)
+        }
+        _ => anyhow::anyhow!("failed to open {}: {}", metadata_path.display(), e),
+    })?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
+/// サーバー brand を載せる plugin message のチャンネル。
+pub const BRAND_CHANNEL: &str = "minecraft:brand";
+
+/// サーバー名を metaData.json の `serverName` と、ストリーム中の
+/// `minecraft:brand` plugin message の両方で `new_name` に書き換えて写す。
+///
+/// brand パケットが無いリプレイでは metadata だけを書き換える。
+/// visibility / thumb / mods.json はそのまま引き継ぐ。戻り値は書き換えた brand パケット数。
+pub fn rename_server<R: ArchiveReader, W: ArchiveWriter>(
+    reader: &mut ReplayReader<R>,
+    writer: &mut ReplayWriter<W>,
+    new_name: &str,
+) -> anyhow::Result<usize> {
+    let mut metadata = reader.read_metadata()?;
+    metadata.serverName = new_name.to_string();
+    writer.write_metadata(metadata)?;
+    if let Some(visibility) = reader.read_visibility()? {
+        writer.write_visibility(&visibility)?;
+    }
+    if let Some(thumbnail) = reader.read_thumbnail()? {
+        writer.write_thumbnail(&thumbnail)?;
+    }
+    if reader.reader.contains(MODS_FILE)? {
+        recopy(&mut reader.reader, &mut writer.writer, &[MODS_FILE])?;
+    }
+
+    let mut brand = Vec::new();
+    brand.write_string(new_name)?;
+    let brand_body = write_plugin_message(BRAND_CHANNEL, &brand)?;
+
+    let mut renamed = 0;
+    let mut packets = writer.get_packet_writer()?;
+    for (state, packet) in reader.get_packet_reader()? {
+        let is_plugin_message = match state {
+            State::Configuration => packet.id() == CONFIGURATION_PLUGIN_MESSAGE_PACKET_ID,
+            State::Play => packet.id() == PLAY_PLUGIN_MESSAGE_PACKET_ID,
+            _ => false,
+        };
+        let is_brand = is_plugin_message
+            && read_plugin_message(packet.data()).is_ok_and(|m| m.channel == BRAND_CHANNEL);
+        if is_brand {
+            packets.push(Packet::new(
+                packet.time(),
+                packet.id(),
+                brand_body.clone().into(),
+            ))?;
+            renamed += 1;
+        } else {
+            packets.push(packet)?;
+        }
+    }
+    packets.flush()?;
+    Ok(renamed)
+}
+
+/// [`split`] でリプレイを区切る単位。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// リプレイ時刻で `n` ms ごと。
+    EveryMillis(u32),
+    /// 接続初期化 ([`is_connection_init`]) 以外のパケット `n` 件ごと。
+    EveryPackets(usize),
+}
+
+/// `reader` のリプレイを `strategy` の単位で区切り、`open(0)`, `open(1)`, … で
+/// 開いたアーカイブへ順に書く。書いたアーカイブを順に返す。
+///
+/// 各ファイルが単体で再生できるよう、それまでに見た接続初期化パケット
+/// (Login / Configuration と Login (play)) を 2 個目以降の先頭へ時刻 0 で
+/// 入れ直し、残りの時刻はそのファイルの最初のパケットからの相対にする。
+/// 再設定で Configuration phase に入り直した場合は最新の phase だけを入れ直す
+/// ([`push_handshake`])。
+/// 区切りは接続初期化でないパケットの直前にだけ置く。区切り位置が
+/// state 遷移パケット (Login Success / Finish Configuration) や Login (play) に
+/// 当たった場合は次の通常パケットまで遅らせ、遷移パケットは前のファイルに残す。
+///
+/// metadata は `duration` を各ファイルの最後のパケットの時刻に直して書き、
+/// thumb も引き継ぐ (visibility は時刻が合わなくなるため引き継がない)。
+/// 区切りより前に届いたワールドの状態 (チャンク等) は入れ直さない。
+pub fn split<R: ArchiveReader, W: ArchiveWriter>(
+    reader: &mut ReplayReader<R>,
+    strategy: SplitStrategy,
+    mut open: impl FnMut(usize) -> anyhow::Result<W>,
+) -> anyhow::Result<Vec<W>> {
+    anyhow::ensure!(
+        !matches!(
+            strategy,
+            SplitStrategy::EveryMillis(0) | SplitStrategy::EveryPackets(0)
+        ),
+        "split interval must be positive: {strategy:?}"
+    );
+    let metadata = reader.read_metadata()?;
+    let thumbnail = reader.read_thumbnail()?;
+
+    let mut written = Vec::new();
+    let mut handshake: Vec<(State, Packet)> = Vec::new();
+    let mut part: Vec<Packet> = Vec::new();
+    // 今のファイルの時刻の基準と、書いた通常パケットの数
+    let mut start = 0;
+    let mut counted = 0;
+    for (state, mut packet) in reader.get_packet_reader()? {
+        if is_connection_init(state, packet.id()) {
+            push_handshake(
+                &mut handshake,
+                state,
+                Packet::new(0, packet.id(), packet.data().into()),
+            );
+        } else {
+            let due = match strategy {
+                SplitStrategy::EveryMillis(ms) => packet.time().saturating_sub(start) >= ms,
+                SplitStrategy::EveryPackets(n) => counted >= n,
+            };
+            if due && counted > 0 {
+                let archive = open(written.len())?;
+                written.push(write_split_part(archive, &metadata, &thumbnail, part)?);
+                part = handshake.iter().map(|(_, packet)| packet.clone()).collect();
+                start = packet.time();
+                counted = 0;
+            }
+            counted += 1;
+        }
+        *packet.time_mut() = packet.time().saturating_sub(start);
+        part.push(packet);
+    }
+    let archive = open(written.len())?;
+    written.push(write_split_part(archive, &metadata, &thumbnail, part)?);
+    Ok(written)
+}
+
+/// [`split`] の入れ直す接続初期化へ `packet` を積む。
+///
+/// 終わった Configuration phase の後にまた Configuration のパケットが来たら
+/// (再設定)、前の phase と Login (play) を捨てて Login phase だけを残す。
+/// Login (play) も最新の 1 個だけを残す。
+fn push_handshake(handshake: &mut Vec<(State, Packet)>, state: State, packet: Packet) {
+    let reconfiguring = state == State::Configuration
+        && handshake.last().is_some_and(|(last, previous)| {
+            *last == State::Play
+                || (*last == State::Configuration
+                    && previous.id() == FINISH_CONFIGURATION_PACKET_ID)
+        });
+    if reconfiguring {
+        handshake.retain(|(state, _)| *state == State::Login);
+    } else if state == State::Play {
+        handshake.retain(|(state, _)| *state != State::Play);
+    }
+    handshake.push((state, packet));
+}
+
+/// [`split`] の 1 ファイル分を書く。
+fn write_split_part<W: ArchiveWriter>(
+    archive: W,
+    metadata: &MetaData,
+    thumbnail: &Option<Vec<u8>>,
+    packets: Vec<Packet>,
+) -> anyhow::Result<W> {
+    let mut writer = ReplayWriter::new(archive);
+    writer.write_metadata(MetaData {
+        duration: packets.last().map_or(0, |packet| packet.time() as u64),
+        ..metadata.clone()
+    })?;
+    if let Some(thumbnail) = thumbnail {
+        writer.write_thumbnail(thumbnail)?;
+    }
+    {
+        let mut out = writer.get_packet_writer()?;
+        for packet in packets {
+            out.push(packet)?;
+        }
+        out.flush()?;
+    }
+    Ok(writer.into_inner())
+}
+
+/// 論理イベント列を .mcpr アーカイブとして書き出す Sink。
+///
+/// ReplayMod の再生互換のため、ソースに存在しない接続フェーズ遷移
+/// パケットを合成する:
+/// - 最初のイベントが Login state でない場合 (= Flashback 由来)、
+///   Login Success (0x02) をダミーの profile で合成する
+/// - Configuration → Play の境目に Finish Configuration (0x03) が
+///   無ければ合成する
+///
+/// `Event::Custom` (Flashback 独自 action) はパケットに対応物が
+/// 無いためスキップし、action 名ごとの件数を [`Self::warnings`] で報告する。
+///
+/// tmcpr は内部バッファに構築し、[`EventSink::finish`] で
+/// recording.tmcpr → metaData.json の順にアーカイブへ書き出す
+/// (zip アーカイブは同時に 1 エントリしか開けないため)。
+pub struct McprEventSink<W: ArchiveWriter> {
+    archive: W,
+    buffer: Vec<u8>,
+    protocol_version: u32,
+    written_state: State,
+    last_time: u32,
+    warnings: Warnings,
+    finished: bool,
+}
+
+impl<W: ArchiveWriter> McprEventSink<W> {
+    /// `protocol_version` は遷移パケット合成の形式判定に使う
     /// (通常はソースの [`ReplayInfo::protocol_version`])。
     pub fn new(archive: W, protocol_version: u32) -> Self {
         Self {
@@ -279,13 +1418,17 @@ impl<W: ArchiveWriter> McprEventSink<W> {
             protocol_version,
             written_state: State::Login,
             last_time: 0,
-            skipped_custom: 0,
+            warnings: Warnings::new(),
             finished: false,
         }
     }
     /// パケットへ変換できずスキップした Custom イベントの件数。
     pub fn skipped_custom(&self) -> usize {
-        self.skipped_custom
+        self.warnings.total(WarningKind::SkippedCustom)
+    }
+    /// 変換で落としたものの集計。
+    pub fn warnings(&self) -> &Warnings {
+        &self.warnings
     }
     pub fn into_archive(self) -> W {
         self.archive
@@ -337,7 +1480,7 @@ impl<W: ArchiveWriter> EventSink for McprEventSink<W> {
                 self.written_state = self.written_state.advance(id);
                 self.last_time = self.last_time.max(time);
             }
-            Event::Custom { .. } => self.skipped_custom += 1,
+            Event::Custom { name, .. } => self.warnings.record(WarningKind::SkippedCustom, &name),
         }
         Ok(())
     }
@@ -354,8 +1497,8 @@ impl<W: ArchiveWriter> EventSink for McprEventSink<W> {
         let metadata = MetaData {
             duration: info.duration_ms.max(self.last_time as u64),
             mcversion: info.mc_version.clone(),
-            fileFormat: "MCPR".to_string(),
-            fileFormatVersion: 14,
+            fileFormat: FILE_FORMAT.to_string(),
+            fileFormatVersion: FILE_FORMAT_VERSION,
             protocol: info.protocol_version,
             generator: "mcpr-lib".to_string(),
             players: info.players.clone(),
@@ -395,6 +1538,137 @@ mod tests {
         );
     }
 
+    #[test]
+    fn raw_frame_roundtrip() {
+        for packet in [
+            Packet::new(0, 0x00, Box::new([])),
+            Packet::new(1234, 0x2c, vec![1, 2, 3].into()),
+            Packet::new(u32::MAX, 0x1234, vec![0; 300].into()),
+        ] {
+            let frame = packet.raw_frame_bytes();
+            assert_eq!(frame.len(), packet.length() as usize);
+            assert_eq!(
+                Packet::from_raw_frame(packet.time(), frame).unwrap(),
+                packet
+            );
+        }
+        // id の VarInt すら無いフレームはエラー
+        assert!(Packet::from_raw_frame(0, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn verbatim_filter_keeps_overlong_ids_intact() {
+        // id 0x1234 を 5 バイトの冗長な VarInt で持つフレーム
+        let overlong = RawFrame::new(10, vec![0xb4, 0xa4, 0x80, 0x80, 0x00, 0xaa]);
+        let dropped = Packet::new(20, 0x2c, vec![1].into());
+        let mut input = Vec::new();
+        overlong.write_to(&mut input).unwrap();
+        dropped.write_to(&mut input).unwrap();
+
+        let mut seen = Vec::new();
+        let mut output = Vec::new();
+        let kept = filter_frames_verbatim(&mut input.as_slice(), &mut output, |state, id, body| {
+            seen.push((state, id, body.to_vec()));
+            id != 0x2c
+        })
+        .unwrap();
+        assert_eq!(kept, 1);
+        assert_eq!(
+            seen,
+            vec![
+                (State::Login, 0x1234, vec![0xaa]),
+                (State::Login, 0x2c, vec![1]),
+            ]
+        );
+        // 再エンコードしていれば 3 バイトの VarInt に縮む
+        let mut expected = Vec::new();
+        overlong.write_to(&mut expected).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn resync_skips_garbage_between_packets() {
+        let first = Packet::new(0, 0x02, vec![1, 2].into());
+        let second = Packet::new(50, 0x2c, vec![3].into());
+        let mut bytes = Vec::new();
+        first.write_to(&mut bytes).unwrap();
+        let garbage = [0xff; 13];
+        bytes.extend_from_slice(&garbage);
+        second.write_to(&mut bytes).unwrap();
+
+        // 通常モードはゴミで止まる
+        let plain: Vec<_> = ReadablePacketStream::new(State::Login, Cursor::new(bytes.clone()))
+            .map(|(_, p)| p)
+            .collect();
+        assert_eq!(plain, vec![first.clone()]);
+
+        let mut stream = ReadablePacketStream::new(State::Login, Cursor::new(bytes)).resync(true);
+        let packets: Vec<_> = stream.by_ref().map(|(_, p)| p).collect();
+        assert_eq!(packets, vec![first, second]);
+        assert_eq!(stream.skipped_bytes(), garbage.len());
+    }
+
+    #[test]
+    fn resync_reads_clean_frames_without_lookahead() {
+        let mut bytes = Vec::new();
+        for (time, id) in [(0, 0x02), (5, 0x2c)] {
+            Packet::new(time, id, vec![7; 3].into())
+                .write_to(&mut bytes)
+                .unwrap();
+        }
+        let mut stream = ReadablePacketStream::new(State::Login, Cursor::new(bytes)).resync(true);
+        let (_, first) = stream.next().unwrap();
+        assert_eq!((first.time(), first.id()), (0, 0x02));
+        // 壊れていなければ 1 フレーム分しか読まない
+        assert_eq!(stream.reader.position(), first.frame_size());
+        assert!(stream.lookahead.is_empty());
+        assert_eq!(stream.map(|(_, p)| p.id()).collect::<Vec<_>>(), [0x2c]);
+    }
+
+    #[test]
+    fn transition_log_records_login_success() {
+        let mut bytes = Vec::new();
+        for (id, data) in [(0x00, vec![1]), (0x02, vec![]), (0x07, vec![2])] {
+            Packet::new(0, id, data.into())
+                .write_to(&mut bytes)
+                .unwrap();
+        }
+        let mut stream = ReadablePacketStream::new(State::Login, Cursor::new(bytes));
+        assert!(stream.transition_log().is_empty());
+        assert_eq!(stream.by_ref().count(), 3);
+        assert_eq!(
+            stream.transition_log(),
+            &[(1, State::Login, State::Configuration)]
+        );
+    }
+
+    #[test]
+    fn raw_frame_roundtrip_is_opaque() {
+        // 先頭が VarInt として不正 (継続ビットのみ) でも body として素通しする
+        let frames = [
+            RawFrame::new(0, Vec::new()),
+            RawFrame::new(50, vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+        ];
+        let mut buf = Vec::new();
+        for frame in &frames {
+            frame.write_to(&mut buf).unwrap();
+        }
+        let mut cur = Cursor::new(buf);
+        for frame in &frames {
+            assert_eq!(RawFrame::read_from(&mut cur).unwrap().as_ref(), Some(frame));
+        }
+        assert_eq!(RawFrame::read_from(&mut cur).unwrap(), None);
+
+        // 通常フレームは Packet と同じバイト列になる
+        let packet = Packet::new(7, 0x2c, vec![1, 2].into());
+        let mut via_packet = Vec::new();
+        packet.write_to(&mut via_packet).unwrap();
+        let mut via_raw = Vec::new();
+        RawFrame::from(&packet).write_to(&mut via_raw).unwrap();
+        assert_eq!(via_packet, via_raw);
+        assert_eq!(RawFrame::from(&packet).into_packet().unwrap(), packet);
+    }
+
     #[test]
     fn event_source_tracks_state() {
         // Login(0x00) -> LoginSuccess(0x02) -> RegistryData(0x07)
@@ -574,4 +1848,1006 @@ mod tests {
         // 実際に書いた最終 time の方が大きければそちらを採用
         assert_eq!(metadata.duration, 12345);
     }
+
+    #[test]
+    fn visibility_survives_pack_roundtrip() {
+        use crate::archive::zip::{ZipArchiveReader, ZipArchiveWriter};
+
+        let visibility = br#"{"version":1,"hidden":["e6ceb512-c347-474b-af6b-a96ba3ac946b"]}"#;
+        let mut source = MemArchive::default();
+        {
+            let mut writer = ReplayWriter::new(&mut source);
+            writer.write_metadata(MetaData::default()).unwrap();
+            writer.write_visibility(visibility).unwrap();
+        }
+
+        // 読み出した visibility を zip へ詰め直し、再度読み出す
+        let read = ReplayReader::new(&mut source).read_visibility().unwrap();
+        assert_eq!(read.as_deref(), Some(&visibility[..]));
+        let mut packed = ZipArchiveWriter::new(Cursor::new(Vec::new()), None);
+        {
+            let mut writer = ReplayWriter::new(&mut packed);
+            writer.write_metadata(MetaData::default()).unwrap();
+            writer.write_visibility(&read.unwrap()).unwrap();
+        }
+        let zip = ReplayWriter::new(packed).finish().unwrap().into_inner();
+        let mut reader = ReplayReader::new(ZipArchiveReader::new(Cursor::new(zip)).unwrap());
+        assert_eq!(
+            reader.read_visibility().unwrap().as_deref(),
+            Some(&visibility[..])
+        );
+    }
+
+    #[test]
+    fn replay_writer_finish_yields_readable_archive() {
+        use crate::archive::zip::ZipArchiveReader;
+
+        let mut writer = ReplayWriter::new(ZipArchiveWriter::new(Cursor::new(Vec::new()), None));
+        writer.write_metadata(MetaData::default()).unwrap();
+        {
+            let mut packets = writer.get_packet_writer().unwrap();
+            packets.push(Packet::new(5, 0x2c, vec![1].into())).unwrap();
+        }
+        let zip = writer.finish().unwrap().into_inner();
+
+        let mut reader = ReplayReader::new(ZipArchiveReader::new(Cursor::new(zip)).unwrap());
+        assert_eq!(reader.read_metadata().unwrap(), MetaData::default());
+        let packets: Vec<_> = reader.get_packet_reader().unwrap().collect();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].1.time(), 5);
+    }
+
+    #[cfg(feature = "threads")]
+    #[test]
+    fn spawn_channel_delivers_packets_in_order() {
+        use crate::archive::testing::MemArchive;
+
+        let packets: Vec<Packet> = [LOGIN_SUCCESS_PACKET_ID, FINISH_CONFIGURATION_PACKET_ID]
+            .into_iter()
+            .chain((0..100).map(|_| 0x2c))
+            .enumerate()
+            .map(|(time, id)| Packet::new(time as u32, id, vec![time as u8].into()))
+            .collect();
+        let mut recording = Vec::new();
+        for packet in &packets {
+            packet.write_to(&mut recording).unwrap();
+        }
+        let mut archive = MemArchive::default();
+        archive
+            .0
+            .insert(RECORDING_FILE.to_string(), recording.clone());
+
+        // 容量 1 でも全件が順に届く
+        let received: Vec<_> = ReplayReader::new(archive)
+            .spawn_channel(1)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(received.len(), packets.len());
+        assert_eq!(received[1].0, State::Configuration);
+        assert_eq!(received[2].0, State::Play);
+        assert!(received.iter().map(|(_, p)| p).eq(&packets));
+
+        // 途中で切れていれば最後に Err が届く
+        recording.truncate(recording.len() - 1);
+        let mut archive = MemArchive::default();
+        archive.0.insert(RECORDING_FILE.to_string(), recording);
+        let received: Vec<_> = ReplayReader::new(archive)
+            .spawn_channel(4)
+            .into_iter()
+            .collect();
+        assert_eq!(received.len(), packets.len());
+        assert!(received.last().unwrap().is_err());
+
+        // recording が無ければ Err が 1 つだけ届く
+        let received: Vec<_> = ReplayReader::new(MemArchive::default())
+            .spawn_channel(4)
+            .into_iter()
+            .collect();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].is_err());
+    }
+
+    #[test]
+    fn frame_size_matches_written_bytes() {
+        for packet in [
+            Packet::new(0, 0x00, Box::new([])),
+            Packet::new(5, 0x2c, vec![1, 2, 3].into()),
+            Packet::new(9, 0x7f, vec![0; 200].into()),
+            Packet::new(9, 0x80, vec![0; 70_000].into()),
+            Packet::new(u32::MAX, MAX_PACKET_ID, vec![7].into()),
+        ] {
+            let mut bytes = Vec::new();
+            packet.write_to(&mut bytes).unwrap();
+            assert_eq!(
+                packet.frame_size(),
+                bytes.len() as u64,
+                "id {}",
+                packet.id()
+            );
+        }
+    }
+
+    #[test]
+    fn set_version_is_written_to_metadata() {
+        use crate::archive::zip::ZipArchiveReader;
+
+        let mut metadata = MetaData {
+            mcversion: "1.21.4".to_string(),
+            protocol: 769,
+            ..Default::default()
+        };
+        assert!(metadata.set_version("", 770).is_err());
+        assert_eq!(
+            (metadata.mcversion.as_str(), metadata.protocol),
+            ("1.21.4", 769)
+        );
+        metadata.set_version("1.21.5", 770).unwrap();
+
+        let mut writer = ReplayWriter::new(ZipArchiveWriter::new(Cursor::new(Vec::new()), None));
+        writer.write_metadata(metadata).unwrap();
+        let zip = writer.finish().unwrap().into_inner();
+        let mut reader = ReplayReader::new(ZipArchiveReader::new(Cursor::new(zip)).unwrap());
+        let read = reader.read_metadata().unwrap();
+        assert_eq!((read.mcversion.as_str(), read.protocol), ("1.21.5", 770));
+    }
+
+    #[test]
+    fn uncompressed_writer_stores_entries() {
+        use crate::archive::zip::ZipArchiveReader;
+
+        let mut writer = ReplayWriter::uncompressed(Cursor::new(Vec::new()));
+        writer.write_metadata(MetaData::default()).unwrap();
+        {
+            let mut packets = writer.get_packet_writer().unwrap();
+            for time in 0..100 {
+                packets
+                    .push(Packet::new(time, 0x2c, vec![0; 64].into()))
+                    .unwrap();
+            }
+        }
+        let zip = writer.finish().unwrap().into_inner();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(zip.clone())).unwrap();
+        let recording = archive.by_name(RECORDING_FILE).unwrap();
+        assert_eq!(recording.compression(), zip::CompressionMethod::Stored);
+        assert_eq!(recording.compressed_size(), recording.size());
+        drop(recording);
+
+        // 無圧縮でも通常の .mcpr として読める
+        let mut reader = ReplayReader::new(ZipArchiveReader::new(Cursor::new(zip)).unwrap());
+        assert_eq!(reader.read_metadata().unwrap(), MetaData::default());
+        assert_eq!(reader.get_packet_reader().unwrap().count(), 100);
+    }
+
+    #[test]
+    fn players_not_seen_reports_stale_metadata_entries() {
+        use crate::players::testing::add_players;
+
+        let listed = uuid::Uuid::from_u128(1);
+        let stale = uuid::Uuid::from_u128(2);
+        let unlisted = uuid::Uuid::from_u128(3);
+        let metadata = MetaData {
+            players: BTreeSet::from([listed, stale]),
+            ..Default::default()
+        };
+        let mut archive = MemArchive::default();
+        {
+            let mut writer = ReplayWriter::new(&mut archive);
+            writer.write_metadata(metadata.clone()).unwrap();
+            let mut packets = writer.get_packet_writer().unwrap();
+            packets
+                .push(Packet::new(0, LOGIN_SUCCESS_PACKET_ID, Box::new([])))
+                .unwrap();
+            packets
+                .push(Packet::new(0, FINISH_CONFIGURATION_PACKET_ID, Box::new([])))
+                .unwrap();
+            let body = add_players(&[(listed, "alice"), (unlisted, "bob")]);
+            packets
+                .push(Packet::new(10, PLAYER_INFO_UPDATE_PACKET_ID, body.into()))
+                .unwrap();
+            packets.flush().unwrap();
+        }
+        let mut reader = ReplayReader::new(&mut archive);
+        assert_eq!(
+            metadata.players_not_seen(&mut reader).unwrap(),
+            HashSet::from([stale])
+        );
+        assert_eq!(
+            metadata.players_seen_not_listed(&mut reader).unwrap(),
+            HashSet::from([unlisted])
+        );
+    }
+
+    #[test]
+    fn finish_verified_rejects_unreadable_archives() {
+        let write = |packets: usize, metadata: &[u8]| {
+            let mut writer =
+                ReplayWriter::new(ZipArchiveWriter::new(Cursor::new(Vec::new()), None));
+            writer
+                .writer
+                .get_writer(METADATA_FILE)
+                .unwrap()
+                .write_all(metadata)
+                .unwrap();
+            let mut stream = writer.get_packet_writer().unwrap();
+            for time in 0..packets as u32 {
+                stream.push(Packet::new(time, 0x2c, Box::new([]))).unwrap();
+            }
+            stream.flush().unwrap();
+            drop(stream);
+            writer.finish_verified()
+        };
+        let valid = serde_json::to_vec(&MetaData::default()).unwrap();
+
+        let inner = write(3, &valid).unwrap();
+        assert_eq!(inner.position(), inner.get_ref().len() as u64);
+        assert!(write(3, b"{\"broken").is_err());
+        let err = write(0, &valid).unwrap_err().to_string();
+        assert!(err.contains("no readable packet"), "{err}");
+    }
+
+    #[test]
+    fn visibility_is_none_when_absent() {
+        let mut archive = MemArchive::default();
+        ReplayWriter::new(&mut archive)
+            .write_metadata(MetaData::default())
+            .unwrap();
+        assert_eq!(
+            ReplayReader::new(&mut archive).read_visibility().unwrap(),
+            None
+        );
+
+        // あるのに読めなければ (ここでは展開後サイズの上限超え) エラー
+        archive.0.insert(VISIBILITY_FILE.to_string(), vec![0; 64]);
+        assert!(
+            ReplayReader::with_limits(&mut archive, 16)
+                .read_visibility()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn open_dispatched_selects_initial_state_by_version() {
+        let archive = |version: u32| {
+            let mut archive = MemArchive::default();
+            let mut writer = ReplayWriter::new(&mut archive);
+            writer
+                .write_metadata(MetaData {
+                    fileFormat: FILE_FORMAT.to_string(),
+                    fileFormatVersion: version,
+                    ..Default::default()
+                })
+                .unwrap();
+            let mut packets = writer.get_packet_writer().unwrap();
+            packets.push(Packet::new(0, 0x2c, Box::new([]))).unwrap();
+            packets.flush().unwrap();
+            drop(packets);
+            archive
+        };
+        let first_state = |archive: MemArchive| {
+            let mut reader = ReplayReader::open_dispatched(archive).unwrap();
+            reader.get_packet_reader().unwrap().next().unwrap().0
+        };
+        assert_eq!(first_state(archive(14)), State::Login);
+        assert_eq!(first_state(archive(9)), State::Play);
+
+        let err = ReplayReader::open_dispatched(archive(0))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("version 0"), "{err}");
+        assert!(ReplayReader::open_dispatched(archive(15)).is_err());
+
+        // 上限付きの reader にも掛けられ、上限はそのまま残る
+        let mut reader = ReplayReader::with_limits(archive(9), 1 << 20)
+            .dispatch()
+            .unwrap();
+        assert_eq!(reader.max_uncompressed, Some(1 << 20));
+        assert_eq!(
+            reader.get_packet_reader().unwrap().next().unwrap().0,
+            State::Play
+        );
+        assert!(ReplayReader::with_limits(archive(9), 4).dispatch().is_err());
+    }
+
+    #[test]
+    fn legacy_protocol_enters_play_without_configuration() {
+        let mut archive = MemArchive::default();
+        let mut writer = ReplayWriter::new(&mut archive);
+        writer
+            .write_metadata(MetaData {
+                fileFormat: FILE_FORMAT.to_string(),
+                fileFormatVersion: FILE_FORMAT_VERSION,
+                protocol: 763,
+                mcversion: "1.20.1".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        let mut packets = writer.get_packet_writer().unwrap();
+        for (time, id) in [(0, 0x02), (0, 0x28), (50, 0x03), (100, 0x64)] {
+            packets.push(Packet::new(time, id, Box::new([]))).unwrap();
+        }
+        packets.flush().unwrap();
+        drop(packets);
+
+        let mut reader = ReplayReader::open_dispatched(&mut archive).unwrap();
+        let states: Vec<_> = reader
+            .get_packet_reader()
+            .unwrap()
+            .map(|(state, _)| state)
+            .collect();
+        assert_eq!(
+            states,
+            vec![State::Login, State::Play, State::Play, State::Play]
+        );
+        let mut source = reader.event_source().unwrap();
+        let mut last = None;
+        while let Some(event) = source.next_event().unwrap() {
+            if let Event::Packet { state, .. } = event {
+                last = Some(state);
+            }
+        }
+        assert_eq!(last, Some(State::Play));
+        drop(source);
+
+        // ReplayReader::new でも metadata の protocol から規則を選ぶ
+        let mut reader = ReplayReader::new(&mut archive);
+        let mut source = reader.event_source().unwrap();
+        let mut states = Vec::new();
+        while let Some(event) = source.next_event().unwrap() {
+            if let Event::Packet { state, .. } = event {
+                states.push(state);
+            }
+        }
+        assert_eq!(
+            states,
+            vec![State::Login, State::Play, State::Play, State::Play]
+        );
+        drop(source);
+        let states: Vec<_> = ReplayReader::new(&mut archive)
+            .get_packet_reader()
+            .unwrap()
+            .map(|(state, _)| state)
+            .collect();
+        assert_eq!(states[1], State::Play);
+    }
+
+    #[test]
+    fn require_protocol_max_rejects_newer_replays() {
+        let mut archive = MemArchive::default();
+        ReplayWriter::new(&mut archive)
+            .write_metadata(MetaData {
+                protocol: 767,
+                mcversion: "1.21.1".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        let mut reader = ReplayReader::new(&mut archive);
+        reader.require_protocol_max(767).unwrap();
+        reader.require_protocol_max(770).unwrap();
+        let err = reader.require_protocol_max(766).unwrap_err().to_string();
+        assert!(err.contains("767"), "{}", err);
+        assert!(err.contains("766"), "{}", err);
+    }
+
+    #[test]
+    fn thumbnail_roundtrips() {
+        let jpeg = [0xff, 0xd8, 0x12, 0x34, 0xff, 0xd9];
+        let mut archive = MemArchive::default();
+        ReplayWriter::new(&mut archive)
+            .write_thumbnail(&jpeg)
+            .unwrap();
+        assert_eq!(&archive.0[THUMB_FILE][..7], &THUMB_MAGIC);
+        assert_eq!(
+            ReplayReader::new(&mut archive)
+                .read_thumbnail()
+                .unwrap()
+                .as_deref(),
+            Some(&jpeg[..])
+        );
+    }
+
+    #[test]
+    fn thumbnail_is_none_when_absent() {
+        let mut archive = MemArchive::default();
+        assert_eq!(
+            ReplayReader::new(&mut archive).read_thumbnail().unwrap(),
+            None
+        );
+
+        // あるのに読めなければエラー
+        archive.0.insert(THUMB_FILE.to_string(), vec![0; 64]);
+        assert!(
+            ReplayReader::with_limits(&mut archive, 16)
+                .read_thumbnail()
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn periodic_flush_reaches_disk_before_drop() {
+        use crate::archive::directory::DirArchive;
+
+        let dir = std::env::temp_dir().join("mcpr_editor_periodic_flush");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut writer = ReplayWriter::new(DirArchive::new(&dir));
+        let mut packets = writer
+            .get_packet_writer_with_flush(FlushPolicy {
+                every_packets: Some(3),
+                every: None,
+            })
+            .unwrap();
+        for time in 0..4 {
+            packets
+                .push(Packet::new(time, 0x2c, vec![0; 10].into()))
+                .unwrap();
+        }
+        let on_disk = std::fs::read(dir.join(RECORDING_FILE)).unwrap();
+        let read = ReadablePacketStream::new(State::Play, on_disk.as_slice()).count();
+        assert!(read >= 3, "only {read} packets on disk");
+        drop(packets);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tmcpr_bytes_roundtrip() {
+        let packet = Packet::new(1234, 0x2c, vec![1, 2, 3].into());
+        let bytes = packet.to_tmcpr_bytes().unwrap();
+        assert_eq!(bytes.len() as u64, packet.frame_size());
+        assert_eq!(Packet::from_tmcpr_bytes(&bytes).unwrap(), Some(packet));
+
+        assert_eq!(Packet::from_tmcpr_bytes(&[]).unwrap(), None);
+        let err = Packet::from_tmcpr_bytes(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(Packet::from_tmcpr_bytes(&bytes[..5]).is_err());
+    }
+
+    #[test]
+    fn push_checked_rejects_time_going_backwards() {
+        let mut writer = ReplayWriter::new(MemArchive::default());
+        let mut out = writer.get_packet_writer().unwrap();
+        out.push_checked(Packet::new(2000, 0x2c, Box::new([])))
+            .unwrap();
+        out.push_checked(Packet::new(2000, 0x2c, Box::new([])))
+            .unwrap();
+        let err = out
+            .push_checked(Packet::new(1000, 0x2c, Box::new([])))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // 確かめない push は通る
+        out.push(Packet::new(1000, 0x2c, Box::new([]))).unwrap();
+        out.flush().unwrap();
+        drop(out);
+        let times: Vec<_> = ReplayReader::new(writer.into_inner())
+            .get_packet_reader()
+            .unwrap()
+            .map(|(_, packet)| packet.time())
+            .collect();
+        assert_eq!(times, vec![2000, 2000, 1000]);
+    }
+
+    #[test]
+    fn checksum_depends_only_on_written_packets() {
+        fn checksum(packets: &[Packet]) -> u32 {
+            let mut writer = ReplayWriter::new(MemArchive::default());
+            let mut out = writer.get_packet_writer_with_checksum().unwrap();
+            for packet in packets {
+                out.push(packet.clone()).unwrap();
+            }
+            out.flush().unwrap();
+            out.get_ref().checksum()
+        }
+        let packets = [
+            Packet::new(0, 0x2c, vec![1, 2, 3].into()),
+            Packet::new(50, 0x6a, vec![0; 16].into()),
+        ];
+        assert_eq!(checksum(&packets), checksum(&packets));
+        let mut changed = packets.clone();
+        changed[1] = Packet::new(50, 0x6a, vec![1; 16].into());
+        assert_ne!(checksum(&packets), checksum(&changed));
+
+        let mut raw = ChecksumWriter::new(Vec::new());
+        for packet in &packets {
+            packet.write_to(&mut raw).unwrap();
+        }
+        assert_eq!(raw.checksum(), checksum(&packets));
+        assert_eq!(raw.checksum(), crc32fast::hash(&raw.into_inner()));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn read_dir_metadata_without_recording() {
+        use crate::archive::directory::DirArchive;
+
+        let dir = std::env::temp_dir().join("mcpr_editor_read_dir_metadata");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(
+            read_dir_metadata(&dir)
+                .unwrap_err()
+                .to_string()
+                .contains(METADATA_FILE)
+        );
+
+        let metadata = MetaData {
+            serverName: "library".to_string(),
+            ..Default::default()
+        };
+        ReplayWriter::new(DirArchive::new(&dir))
+            .write_metadata(metadata.clone())
+            .unwrap();
+        assert!(!dir.join(RECORDING_FILE).exists());
+        assert_eq!(read_dir_metadata(&dir).unwrap(), metadata);
+
+        assert!(read_dir_metadata(dir.join(METADATA_FILE)).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn replace_packet_in_directory_replay() {
+        use crate::archive::directory::DirArchive;
+
+        let dir = std::env::temp_dir().join("mcpr_editor_replace_packet");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let original = vec![
+            Packet::new(0, 0x02, vec![1].into()),
+            Packet::new(50, 0x2c, vec![2, 2].into()),
+            Packet::new(100, 0x2d, vec![3, 3, 3].into()),
+        ];
+        let mut writer = ReplayWriter::new(DirArchive::new(&dir));
+        {
+            let mut packets = writer.get_packet_writer().unwrap();
+            for packet in &original {
+                packets.push(packet.clone()).unwrap();
+            }
+        }
+        let read_back = |dir: &std::path::Path| -> Vec<Packet> {
+            ReplayReader::new(DirArchive::new(dir))
+                .get_packet_reader()
+                .unwrap()
+                .map(|(_, packet)| packet)
+                .collect()
+        };
+
+        // 同じ長さ: その場で上書き
+        let same = Packet::new(60, 0x2c, vec![9, 9].into());
+        writer.replace_packet(1, same.clone()).unwrap();
+        assert_eq!(
+            read_back(&dir),
+            vec![original[0].clone(), same.clone(), original[2].clone()]
+        );
+
+        // 長さが変わる: 書き直し
+        let longer = Packet::new(0, 0x02, vec![7; 300].into());
+        writer.replace_packet(0, longer.clone()).unwrap();
+        assert_eq!(read_back(&dir), vec![longer, same, original[2].clone()]);
+
+        assert!(writer.replace_packet(3, original[0].clone()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flashback_conversion_reports_skipped_move_entities() {
+        use crate::flashback::{FlashbackEventSink, FlashbackReader};
+
+        const MOVE_ENTITIES: &str = "flashback:action/move_entities";
+        let mut flashback =
+            FlashbackEventSink::new(MemArchive::default(), uuid::Uuid::nil()).unwrap();
+        flashback
+            .push(packet_event(0, State::Play, 0x2c, &[1]))
+            .unwrap();
+        for tick in 1..=3 {
+            flashback
+                .push(Event::Custom {
+                    time: Time::from_ticks(tick),
+                    name: MOVE_ENTITIES.to_string(),
+                    data: vec![0].into(),
+                })
+                .unwrap();
+        }
+        flashback.finish(&ReplayInfo::default()).unwrap();
+
+        let mut source = FlashbackReader::new(flashback.into_archive())
+            .event_source(false)
+            .unwrap();
+        let mut sink = McprEventSink::new(MemArchive::default(), 774);
+        while let Some(event) = source.next_event().unwrap() {
+            sink.push(event).unwrap();
+        }
+        sink.finish(source.info()).unwrap();
+
+        assert_eq!(
+            sink.warnings()
+                .count(WarningKind::SkippedCustom, MOVE_ENTITIES),
+            3
+        );
+        assert_eq!(sink.skipped_custom(), 3);
+    }
+
+    #[test]
+    fn rename_server_updates_metadata_and_brand() {
+        let brand = |name: &str| {
+            let mut payload = Vec::new();
+            payload.write_string(name).unwrap();
+            write_plugin_message(BRAND_CHANNEL, &payload).unwrap()
+        };
+        let mut source = MemArchive::default();
+        {
+            let mut writer = ReplayWriter::new(&mut source);
+            writer
+                .write_metadata(MetaData {
+                    serverName: "old".to_string(),
+                    ..Default::default()
+                })
+                .unwrap();
+            let mut packets = writer.get_packet_writer().unwrap();
+            packets
+                .push(Packet::new(0, LOGIN_SUCCESS_PACKET_ID, Box::new([])))
+                .unwrap();
+            packets
+                .push(Packet::new(
+                    0,
+                    CONFIGURATION_PLUGIN_MESSAGE_PACKET_ID,
+                    brand("vanilla").into(),
+                ))
+                .unwrap();
+            packets.push(Packet::new(0, 0x07, vec![1].into())).unwrap();
+        }
+
+        let mods = br#"{"requiredMods":[]}"#.to_vec();
+        source.0.insert(MODS_FILE.to_string(), mods.clone());
+
+        let mut out = MemArchive::default();
+        let renamed = rename_server(
+            &mut ReplayReader::new(&mut source),
+            &mut ReplayWriter::new(&mut out),
+            "My Server",
+        )
+        .unwrap();
+        assert_eq!(renamed, 1);
+        // mods.json はそのまま引き継ぐ
+        assert_eq!(out.0[MODS_FILE], mods);
+
+        let mut reader = ReplayReader::new(&mut out);
+        assert_eq!(reader.read_metadata().unwrap().serverName, "My Server");
+        let packets: Vec<_> = reader
+            .get_packet_reader()
+            .unwrap()
+            .map(|(_, p)| p)
+            .collect();
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[1].data(), brand("My Server").as_slice());
+        assert_eq!(packets[2].data(), &[1]);
+
+        // brand が無ければ metadata だけ変わる
+        let mut out2 = MemArchive::default();
+        let mut no_brand = MemArchive::default();
+        ReplayWriter::new(&mut no_brand)
+            .write_metadata(MetaData::default())
+            .unwrap();
+        ReplayWriter::new(&mut no_brand)
+            .get_packet_writer()
+            .unwrap();
+        let renamed = rename_server(
+            &mut ReplayReader::new(&mut no_brand),
+            &mut ReplayWriter::new(&mut out2),
+            "x",
+        )
+        .unwrap();
+        assert_eq!(renamed, 0);
+        assert_eq!(
+            ReplayReader::new(&mut out2)
+                .read_metadata()
+                .unwrap()
+                .serverName,
+            "x"
+        );
+    }
+
+    #[test]
+    fn borrowed_reader_matches_owning_reader() {
+        let packets = vec![
+            Packet::new(0, LOGIN_SUCCESS_PACKET_ID, vec![1, 2].into()),
+            Packet::new(10, 0x300, vec![0; 40].into()),
+            Packet::new(20, 0x07, Box::new([])),
+        ];
+        let mut bytes = Vec::new();
+        for packet in &packets {
+            packet.write_to(&mut bytes).unwrap();
+        }
+        // 途中で切れたフレームは読まない
+        bytes.extend_from_slice(&[0, 0, 0, 30, 0, 0, 0, 9, 0x01]);
+
+        let owned: Vec<_> = ReadablePacketStream::new(State::Login, bytes.as_slice())
+            .map(|(_, p)| (p.time(), p.id(), p.data().len()))
+            .collect();
+        let mut reader = BorrowedPacketReader::new(&bytes);
+        let borrowed: Vec<_> = reader
+            .by_ref()
+            .map(|(time, id, data)| (time, id, data.len()))
+            .collect();
+        assert_eq!(borrowed, owned);
+        assert_eq!(borrowed.len(), 3);
+        assert_eq!(reader.remaining().len(), 9);
+    }
+
+    #[test]
+    fn mods_roundtrip() {
+        let fixture =
+            r#"{"requiredMods":[{"modID":"sodium","modName":"Sodium","modVersion":"0.6.0"}]}"#;
+        let mut archive = MemArchive::default();
+        assert_eq!(ReplayReader::new(&mut archive).read_mods().unwrap(), None);
+
+        archive
+            .0
+            .insert(MODS_FILE.to_string(), fixture.as_bytes().to_vec());
+        let mods = ReplayReader::new(&mut archive)
+            .read_mods()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            mods.requiredMods,
+            vec![ModInfo {
+                modID: "sodium".to_string(),
+                modName: "Sodium".to_string(),
+                modVersion: "0.6.0".to_string(),
+            }]
+        );
+
+        let mut out = MemArchive::default();
+        ReplayWriter::new(&mut out).write_mods(&mods).unwrap();
+        assert_eq!(out.0[MODS_FILE], fixture.as_bytes());
+
+        // あるのに読めなければ (ここでは展開後サイズの上限超え) エラー
+        assert!(
+            ReplayReader::with_limits(&mut archive, 16)
+                .read_mods()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn with_limits_rejects_oversized_entries() {
+        let mut archive = MemArchive::default();
+        ReplayWriter::new(&mut archive)
+            .write_metadata(MetaData::default())
+            .unwrap();
+        let metadata_len = archive.0[METADATA_FILE].len() as u64;
+
+        // 上限ちょうどなら読める
+        let mut reader = ReplayReader::with_limits(&mut archive, metadata_len);
+        assert_eq!(reader.read_metadata().unwrap(), MetaData::default());
+
+        let mut reader = ReplayReader::with_limits(&mut archive, 16);
+        let err = reader.read_metadata().unwrap_err();
+        assert!(err.to_string().contains("size limit"), "{err}");
+    }
+
+    #[test]
+    fn build_matches_manual_encode() {
+        let uuid = uuid::Uuid::from_u128(7);
+        let built = Packet::build(40, 0x2c, |body| {
+            body.write_varint(300)?;
+            body.write_string("hello")?;
+            body.write_uuid(&uuid)
+        })
+        .unwrap();
+
+        let mut manual = Vec::new();
+        manual.write_varint(300).unwrap();
+        manual.write_string("hello").unwrap();
+        manual.write_uuid(&uuid).unwrap();
+        assert_eq!(built, Packet::new(40, 0x2c, manual.into()));
+
+        let err = Packet::build(0, 0x2c, |_| Err(io::Error::other("boom"))).unwrap_err();
+        assert_eq!(err.to_string(), "boom");
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_ids() {
+        assert!(Packet::new(0, 0x2c, vec![0; 8].into()).validate().is_ok());
+        assert!(
+            Packet::new(0, MAX_PACKET_ID, Box::new([]))
+                .validate()
+                .is_ok()
+        );
+        // 冗長な 5 バイト VarInt 等から読めてしまう負の巨大な id
+        let err = Packet::new(0, -0x7fff_0000, Box::new([]))
+            .validate()
+            .unwrap_err();
+        assert!(err.contains("out of range"), "{err}");
+        assert!(Packet::new(0, 0x1234, Box::new([])).validate().is_err());
+    }
+
+    #[test]
+    fn actual_duration_uses_last_packet_time() {
+        let mut archive = MemArchive::default();
+        {
+            let mut writer = ReplayWriter::new(&mut archive);
+            writer
+                .write_metadata(MetaData {
+                    duration: 99_999,
+                    ..Default::default()
+                })
+                .unwrap();
+            let mut packets = writer.get_packet_writer().unwrap();
+            for time in [0, 50, 1234, 1200] {
+                packets.push(Packet::new(time, 0x07, Box::new([]))).unwrap();
+            }
+        }
+        let mut reader = ReplayReader::new(&mut archive);
+        assert_eq!(reader.actual_duration().unwrap(), 1234);
+
+        let mut out = MemArchive::default();
+        let fixed = ReplayWriter::new(&mut out)
+            .write_metadata_with_actual_duration(&mut reader)
+            .unwrap();
+        assert_eq!(fixed.duration, 1234);
+        assert_eq!(
+            ReplayReader::new(&mut out)
+                .read_metadata()
+                .unwrap()
+                .duration,
+            1234
+        );
+    }
+
+    /// `split` で書いた各ファイルの `(time, id)` 列と metadata の duration。
+    fn split_parts(packets: &[(u32, i32)], strategy: SplitStrategy) -> Vec<(Vec<(u32, i32)>, u64)> {
+        let mut source = MemArchive::default();
+        {
+            let mut writer = ReplayWriter::new(&mut source);
+            writer.write_metadata(MetaData::default()).unwrap();
+            let mut out = writer.get_packet_writer().unwrap();
+            for &(time, id) in packets {
+                out.push(Packet::new(time, id, Box::new([]))).unwrap();
+            }
+        }
+        let mut opened = Vec::new();
+        let parts = split(&mut ReplayReader::new(source), strategy, |index| {
+            opened.push(index);
+            Ok(MemArchive::default())
+        })
+        .unwrap();
+        assert_eq!(opened, (0..parts.len()).collect::<Vec<_>>());
+        parts
+            .into_iter()
+            .map(|part| {
+                let mut reader = ReplayReader::new(part);
+                let packets = reader
+                    .get_packet_reader()
+                    .unwrap()
+                    .map(|(_, packet)| (packet.time(), packet.id()))
+                    .collect();
+                (packets, reader.read_metadata().unwrap().duration)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_reemits_handshake_in_every_part() {
+        use crate::protocol::LOGIN_PLAY_PACKET_ID;
+        let parts = split_parts(
+            &[
+                (0, LOGIN_SUCCESS_PACKET_ID),
+                (0, FINISH_CONFIGURATION_PACKET_ID),
+                (0, LOGIN_PLAY_PACKET_ID),
+                (100, 0x2c),
+                (600, 0x2c),
+                (1200, 0x2c),
+                (1300, 0x2c),
+            ],
+            SplitStrategy::EveryMillis(1000),
+        );
+        let handshake = [
+            (0, LOGIN_SUCCESS_PACKET_ID),
+            (0, FINISH_CONFIGURATION_PACKET_ID),
+            (0, LOGIN_PLAY_PACKET_ID),
+        ];
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].0[..3], handshake);
+        assert_eq!(parts[0].0[3..], [(100, 0x2c), (600, 0x2c)]);
+        assert_eq!(parts[0].1, 600);
+        assert_eq!(parts[1].0[..3], handshake);
+        assert_eq!(parts[1].0[3..], [(0, 0x2c), (100, 0x2c)]);
+        assert_eq!(parts[1].1, 100);
+
+        let parts = split_parts(
+            &[
+                (0, LOGIN_SUCCESS_PACKET_ID),
+                (0, FINISH_CONFIGURATION_PACKET_ID),
+                (0, 0x2c),
+                (10, 0x2c),
+                (20, 0x2c),
+            ],
+            SplitStrategy::EveryPackets(2),
+        );
+        let times: Vec<Vec<u32>> = parts
+            .iter()
+            .map(|(packets, _)| packets.iter().map(|&(time, _)| time).collect())
+            .collect();
+        assert_eq!(times, vec![vec![0, 0, 0, 10], vec![0, 0, 0]]);
+    }
+
+    #[test]
+    fn split_handshake_keeps_latest_configuration() {
+        use crate::protocol::LOGIN_PLAY_PACKET_ID;
+        let mut handshake = Vec::new();
+        for (state, id) in [
+            (State::Login, LOGIN_SUCCESS_PACKET_ID),
+            (State::Configuration, 0x07),
+            (State::Configuration, FINISH_CONFIGURATION_PACKET_ID),
+            (State::Play, LOGIN_PLAY_PACKET_ID),
+            // 再設定
+            (State::Configuration, 0x0d),
+            (State::Configuration, FINISH_CONFIGURATION_PACKET_ID),
+            (State::Play, LOGIN_PLAY_PACKET_ID),
+            (State::Play, LOGIN_PLAY_PACKET_ID),
+        ] {
+            push_handshake(&mut handshake, state, Packet::new(0, id, Box::new([])));
+        }
+        let kept: Vec<_> = handshake
+            .iter()
+            .map(|(state, packet)| (*state, packet.id()))
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                (State::Login, LOGIN_SUCCESS_PACKET_ID),
+                (State::Configuration, 0x0d),
+                (State::Configuration, FINISH_CONFIGURATION_PACKET_ID),
+                (State::Play, LOGIN_PLAY_PACKET_ID),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_is_deferred_past_transition_packets() {
+        use crate::protocol::LOGIN_PLAY_PACKET_ID;
+        // 100 ms の区切りが Login (play) に当たる: 次の通常パケットまで遅らせる
+        let parts = split_parts(
+            &[
+                (0, LOGIN_SUCCESS_PACKET_ID),
+                (0, FINISH_CONFIGURATION_PACKET_ID),
+                (0, 0x2c),
+                (100, LOGIN_PLAY_PACKET_ID),
+                (150, 0x2c),
+            ],
+            SplitStrategy::EveryMillis(100),
+        );
+        assert_eq!(
+            parts
+                .iter()
+                .map(|(packets, _)| packets.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                vec![
+                    (0, LOGIN_SUCCESS_PACKET_ID),
+                    (0, FINISH_CONFIGURATION_PACKET_ID),
+                    (0, 0x2c),
+                    (100, LOGIN_PLAY_PACKET_ID),
+                ],
+                vec![
+                    (0, LOGIN_SUCCESS_PACKET_ID),
+                    (0, FINISH_CONFIGURATION_PACKET_ID),
+                    (0, LOGIN_PLAY_PACKET_ID),
+                    (0, 0x2c),
+                ],
+            ]
+        );
+
+        assert!(
+            split(
+                &mut ReplayReader::new(MemArchive::default()),
+                SplitStrategy::EveryPackets(0),
+                |_| Ok(MemArchive::default()),
+            )
+            .is_err()
+        );
+    }
 }
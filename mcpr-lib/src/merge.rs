@@ -0,0 +1,257 @@
+//! 複数のパケット列の合成。
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+};
+
+use crate::{
+    event::{ReplayInfo, State},
+    mcpr::Packet,
+};
+
+/// 入力間で protocol が揃っていないときの扱い ([`check_protocols`])。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProtocolMismatch {
+    /// エラーにして止める。違うバージョンを混ぜたリプレイは再生できない。
+    #[default]
+    Error,
+    /// 食い違いの説明を返して続ける。
+    Warn,
+}
+
+/// マージ・連結する前に、各入力の protocol が揃っているかを確かめる。
+///
+/// 揃っていれば `Ok(None)`。揃っていなければ protocol ごとに該当する入力の
+/// 添字を並べた説明を作り、`policy` が Error ならそれをエラーに、
+/// Warn なら `Ok(Some(説明))` で返す。
+pub fn check_protocols(
+    inputs: &[ReplayInfo],
+    policy: ProtocolMismatch,
+) -> anyhow::Result<Option<String>> {
+    // protocol → (mc バージョン, 入力の添字)
+    let mut versions: BTreeMap<u32, (&str, Vec<usize>)> = BTreeMap::new();
+    for (index, info) in inputs.iter().enumerate() {
+        versions
+            .entry(info.protocol_version)
+            .or_insert_with(|| (&info.mc_version, Vec::new()))
+            .1
+            .push(index);
+    }
+    if versions.len() <= 1 {
+        return Ok(None);
+    }
+    let listed: Vec<String> = versions
+        .iter()
+        .map(|(protocol, (mc_version, indices))| {
+            format!("protocol {protocol} ({mc_version}) in inputs {indices:?}")
+        })
+        .collect();
+    let message = format!("mixed protocol versions: {}", listed.join(", "));
+    match policy {
+        ProtocolMismatch::Error => Err(anyhow::anyhow!(message)),
+        ProtocolMismatch::Warn => Ok(Some(message)),
+    }
+}
+
+/// どの入力から来たかを添えたパケット ([`tagged`] の出力)。
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourcedPacket {
+    /// 入力の添字 (`readers` の何番目か)。
+    pub source: usize,
+    pub state: State,
+    pub packet: Packet,
+}
+
+/// 時刻順に並んだ複数のパケット列を、時刻順の 1 本へ k-way マージする。
+///
+/// 同時に録った別視点の .tmcpr を交互に並べる用途。各入力は個別に
+/// `time()` の昇順であることが前提 (崩れていれば出力も崩れる)。
+/// 同時刻のパケットは入力の並び順 (`streams` の添字順) で出る。
+pub fn by_timestamp<I: Iterator<Item = Packet>>(streams: Vec<I>) -> impl Iterator<Item = Packet> {
+    ByTimestamp::new(streams)
+}
+
+/// [`by_timestamp`] と同じ順で複数の入力をマージし、各パケットに
+/// 入力の添字を付ける。
+///
+/// 分析用に複数ファイルを混ぜたとき、問題のパケットがどのリプレイ由来かを
+/// 追うためのもの。`readers` は [`crate::mcpr::ReadablePacketStream`] 等の
+/// `(State, Packet)` 列。
+pub fn tagged<I: Iterator<Item = (State, Packet)>>(
+    readers: Vec<I>,
+) -> impl Iterator<Item = SourcedPacket> {
+    let streams = readers
+        .into_iter()
+        .enumerate()
+        .map(|(source, reader)| {
+            reader.map(move |(state, packet)| SourcedPacket {
+                source,
+                state,
+                packet,
+            })
+        })
+        .collect();
+    ByTimestamp::new(streams)
+}
+
+/// 1 本のパケット列を時刻順に並べ直す。
+///
+/// 安定ソートなので、同時刻のパケットは入力の並び順を保つ
+/// (同 tick 内のパケット順序はクライアントの状態に効くため崩せない)。
+pub fn sort_by_time(packets: &mut [Packet]) {
+    packets.sort_by(Packet::cmp_by_time);
+}
+
+/// マージの並び替えに使う時刻。
+trait Timed {
+    fn time(&self) -> u32;
+}
+
+impl Timed for Packet {
+    fn time(&self) -> u32 {
+        Packet::time(self)
+    }
+}
+
+impl Timed for SourcedPacket {
+    fn time(&self) -> u32 {
+        self.packet.time()
+    }
+}
+
+struct ByTimestamp<I: Iterator> {
+    streams: Vec<I>,
+    /// 各入力の先頭 (取り出し待ち)。
+    heads: Vec<Option<I::Item>>,
+    /// (時刻, 入力の添字) の最小ヒープ。
+    heap: BinaryHeap<Reverse<(u32, usize)>>,
+}
+
+impl<I: Iterator<Item: Timed>> ByTimestamp<I> {
+    fn new(mut streams: Vec<I>) -> Self {
+        let mut heads = Vec::with_capacity(streams.len());
+        let mut heap = BinaryHeap::with_capacity(streams.len());
+        for (index, stream) in streams.iter_mut().enumerate() {
+            let head = stream.next();
+            if let Some(packet) = &head {
+                heap.push(Reverse((packet.time(), index)));
+            }
+            heads.push(head);
+        }
+        Self {
+            streams,
+            heads,
+            heap,
+        }
+    }
+}
+
+impl<I: Iterator<Item: Timed>> Iterator for ByTimestamp<I> {
+    type Item = I::Item;
+    fn next(&mut self) -> Option<I::Item> {
+        let Reverse((_, index)) = self.heap.pop()?;
+        let packet = self.heads[index].take();
+        self.heads[index] = self.streams[index].next();
+        if let Some(next) = &self.heads[index] {
+            self.heap.push(Reverse((next.time(), index)));
+        }
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_protocols_are_reported() {
+        let info = |mc_version: &str, protocol_version| ReplayInfo {
+            mc_version: mc_version.to_string(),
+            protocol_version,
+            ..Default::default()
+        };
+        let same = [info("1.21", 767), info("1.21.1", 767)];
+        assert!(
+            check_protocols(&same, ProtocolMismatch::Error)
+                .unwrap()
+                .is_none()
+        );
+
+        let mixed = [info("1.20.2", 764), info("1.21", 767)];
+        let err = check_protocols(&mixed, ProtocolMismatch::Error).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "mixed protocol versions: protocol 764 (1.20.2) in inputs [0], \
+             protocol 767 (1.21) in inputs [1]"
+        );
+        assert_eq!(
+            check_protocols(&mixed, ProtocolMismatch::Warn).unwrap(),
+            Some(err.to_string())
+        );
+    }
+
+    fn packets(times: &[u32], id: i32) -> Vec<Packet> {
+        times
+            .iter()
+            .map(|&time| Packet::new(time, id, Box::new([])))
+            .collect()
+    }
+
+    #[test]
+    fn merges_sorted_streams_into_sorted_output() {
+        let a = packets(&[0, 50, 50, 200], 0x01);
+        let b = packets(&[10, 50, 300], 0x02);
+        let merged: Vec<_> =
+            by_timestamp(vec![a.into_iter(), b.into_iter(), Vec::new().into_iter()])
+                .map(|p| (p.time(), p.id()))
+                .collect();
+        assert_eq!(
+            merged,
+            vec![
+                (0, 0x01),
+                (10, 0x02),
+                (50, 0x01),
+                (50, 0x01),
+                (50, 0x02),
+                (200, 0x01),
+                (300, 0x02),
+            ]
+        );
+    }
+
+    #[test]
+    fn tagged_carries_source_index() {
+        let play = |times: &[u32], id| {
+            packets(times, id)
+                .into_iter()
+                .map(|p| (State::Play, p))
+                .collect::<Vec<_>>()
+                .into_iter()
+        };
+        let tagged: Vec<_> = tagged(vec![play(&[0, 100], 0x01), play(&[50, 100], 0x02)])
+            .map(|p| (p.source, p.packet.time(), p.packet.id()))
+            .collect();
+        assert_eq!(
+            tagged,
+            vec![(0, 0, 0x01), (1, 50, 0x02), (0, 100, 0x01), (1, 100, 0x02)]
+        );
+    }
+
+    #[test]
+    fn sort_by_time_keeps_order_within_a_timestamp() {
+        let mut all = vec![
+            Packet::new(50, 0x01, vec![9].into()),
+            Packet::new(10, 0x02, Box::new([])),
+            Packet::new(50, 0x03, vec![1].into()),
+            Packet::new(0, 0x04, Box::new([])),
+            Packet::new(50, 0x05, vec![5].into()),
+        ];
+        sort_by_time(&mut all);
+        let order: Vec<_> = all.iter().map(|p| (p.time(), p.id())).collect();
+        assert_eq!(
+            order,
+            vec![(0, 0x04), (10, 0x02), (50, 0x01), (50, 0x03), (50, 0x05)]
+        );
+    }
+}
@@ -0,0 +1,396 @@
+//! clientbound パケット id → 名前の対応表 (表示用)。
+//!
+//! Login / Configuration は protocol 767 (1.21) 以降で共通の表を、
+//! Play はバージョン間で id がずれるため確認済みのバージョンの表だけを持つ。
+//! 表に無いものは None を返すので、呼び出し側は 16 進 id で表示する。
+
+use crate::event::State;
+
+/// Login phase (protocol 767 以降)。
+const LOGIN: &[&str] = &[
+    "Disconnect",
+    "Hello",
+    "LoginFinished",
+    "LoginCompression",
+    "CustomQuery",
+    "CookieRequest",
+];
+
+/// Configuration phase (protocol 767 以降、末尾への追加のみ)。
+const CONFIGURATION: &[&str] = &[
+    "CookieRequest",
+    "CustomPayload",
+    "Disconnect",
+    "FinishConfiguration",
+    "KeepAlive",
+    "Ping",
+    "ResetChat",
+    "RegistryData",
+    "ResourcePackPop",
+    "ResourcePackPush",
+    "StoreCookie",
+    "Transfer",
+    "UpdateEnabledFeatures",
+    "UpdateTags",
+    "SelectKnownPacks",
+    "CustomReportDetails",
+    "ServerLinks",
+];
+
+/// Play phase (protocol 767 / 1.21 〜 1.21.1)。
+const PLAY_767: &[&str] = &[
+    "BundleDelimiter",
+    "AddEntity",
+    "AddExperienceOrb",
+    "Animate",
+    "AwardStats",
+    "BlockChangedAck",
+    "BlockDestruction",
+    "BlockEntityData",
+    "BlockEvent",
+    "BlockUpdate",
+    "BossEvent",
+    "ChangeDifficulty",
+    "ChunkBatchFinished",
+    "ChunkBatchStart",
+    "ChunksBiomes",
+    "ClearTitles",
+    "CommandSuggestions",
+    "Commands",
+    "ContainerClose",
+    "ContainerSetContent",
+    "ContainerSetData",
+    "ContainerSetSlot",
+    "CookieRequest",
+    "Cooldown",
+    "CustomChatCompletions",
+    "CustomPayload",
+    "DamageEvent",
+    "DebugSample",
+    "DeleteChat",
+    "Disconnect",
+    "DisguisedChat",
+    "EntityEvent",
+    "Explode",
+    "ForgetLevelChunk",
+    "GameEvent",
+    "HorseScreenOpen",
+    "HurtAnimation",
+    "InitializeBorder",
+    "KeepAlive",
+    "LevelChunkWithLight",
+    "LevelEvent",
+    "LevelParticles",
+    "LightUpdate",
+    "Login",
+    "MapItemData",
+    "MerchantOffers",
+    "MoveEntityPos",
+    "MoveEntityPosRot",
+    "MoveEntityRot",
+    "MoveVehicle",
+    "OpenBook",
+    "OpenScreen",
+    "OpenSignEditor",
+    "Ping",
+    "PongResponse",
+    "PlaceGhostRecipe",
+    "PlayerAbilities",
+    "PlayerChat",
+    "PlayerCombatEnd",
+    "PlayerCombatEnter",
+    "PlayerCombatKill",
+    "PlayerInfoRemove",
+    "PlayerInfoUpdate",
+    "PlayerLookAt",
+    "PlayerPosition",
+    "Recipe",
+    "RemoveEntities",
+    "RemoveMobEffect",
+    "ResetScore",
+    "ResourcePackPop",
+    "ResourcePackPush",
+    "Respawn",
+    "RotateHead",
+    "SectionBlocksUpdate",
+    "SelectAdvancementsTab",
+    "ServerData",
+    "SetActionBarText",
+    "SetBorderCenter",
+    "SetBorderLerpSize",
+    "SetBorderSize",
+    "SetBorderWarningDelay",
+    "SetBorderWarningDistance",
+    "SetCamera",
+    "SetCarriedItem",
+    "SetChunkCacheCenter",
+    "SetChunkCacheRadius",
+    "SetDefaultSpawnPosition",
+    "SetDisplayObjective",
+    "SetEntityData",
+    "SetEntityLink",
+    "SetEntityMotion",
+    "SetEquipment",
+    "SetExperience",
+    "SetHealth",
+    "SetObjective",
+    "SetPassengers",
+    "SetPlayerTeam",
+    "SetScore",
+    "SetSimulationDistance",
+    "SetSubtitleText",
+    "SetTime",
+    "SetTitleText",
+    "SetTitlesAnimation",
+    "SoundEntity",
+    "Sound",
+    "StartConfiguration",
+    "StopSound",
+    "StoreCookie",
+    "SystemChat",
+    "TabList",
+    "TagQuery",
+    "TakeItemEntity",
+    "TeleportEntity",
+    "TickingState",
+    "TickingStep",
+    "Transfer",
+    "UpdateAdvancements",
+    "UpdateAttributes",
+    "UpdateMobEffect",
+    "UpdateRecipes",
+    "UpdateTags",
+    "ProjectilePower",
+    "CustomReportDetails",
+    "ServerLinks",
+];
+
+/// Play phase (protocol 770 / 1.21.5、クレートの既定)。
+const PLAY_770: &[&str] = &[
+    "BundleDelimiter",
+    "AddEntity",
+    "Animate",
+    "AwardStats",
+    "BlockChangedAck",
+    "BlockDestruction",
+    "BlockEntityData",
+    "BlockEvent",
+    "BlockUpdate",
+    "BossEvent",
+    "ChangeDifficulty",
+    "ChunkBatchFinished",
+    "ChunkBatchStart",
+    "ChunksBiomes",
+    "ClearTitles",
+    "CommandSuggestions",
+    "Commands",
+    "ContainerClose",
+    "ContainerSetContent",
+    "ContainerSetData",
+    "ContainerSetSlot",
+    "CookieRequest",
+    "Cooldown",
+    "CustomChatCompletions",
+    "CustomPayload",
+    "DamageEvent",
+    "DebugSample",
+    "DeleteChat",
+    "Disconnect",
+    "DisguisedChat",
+    "EntityEvent",
+    "EntityPositionSync",
+    "Explode",
+    "ForgetLevelChunk",
+    "GameEvent",
+    "HorseScreenOpen",
+    "HurtAnimation",
+    "InitializeBorder",
+    "KeepAlive",
+    "LevelChunkWithLight",
+    "LevelEvent",
+    "LevelParticles",
+    "LightUpdate",
+    "Login",
+    "MapItemData",
+    "MerchantOffers",
+    "MoveEntityPos",
+    "MoveEntityPosRot",
+    "MoveMinecartAlongTrack",
+    "MoveEntityRot",
+    "MoveVehicle",
+    "OpenBook",
+    "OpenScreen",
+    "OpenSignEditor",
+    "Ping",
+    "PongResponse",
+    "PlaceGhostRecipe",
+    "PlayerAbilities",
+    "PlayerChat",
+    "PlayerCombatEnd",
+    "PlayerCombatEnter",
+    "PlayerCombatKill",
+    "PlayerInfoRemove",
+    "PlayerInfoUpdate",
+    "PlayerLookAt",
+    "PlayerPosition",
+    "PlayerRotation",
+    "RecipeBookAdd",
+    "RecipeBookRemove",
+    "RecipeBookSettings",
+    "RemoveEntities",
+    "RemoveMobEffect",
+    "ResetScore",
+    "ResourcePackPop",
+    "ResourcePackPush",
+    "Respawn",
+    "RotateHead",
+    "SectionBlocksUpdate",
+    "SelectAdvancementsTab",
+    "ServerData",
+    "SetActionBarText",
+    "SetBorderCenter",
+    "SetBorderLerpSize",
+    "SetBorderSize",
+    "SetBorderWarningDelay",
+    "SetBorderWarningDistance",
+    "SetCamera",
+    "SetChunkCacheCenter",
+    "SetChunkCacheRadius",
+    "SetCursorItem",
+    "SetDefaultSpawnPosition",
+    "SetDisplayObjective",
+    "SetEntityData",
+    "SetEntityLink",
+    "SetEntityMotion",
+    "SetEquipment",
+    "SetExperience",
+    "SetHealth",
+    "SetHeldSlot",
+    "SetObjective",
+    "SetPassengers",
+    "SetPlayerInventory",
+    "SetPlayerTeam",
+    "SetScore",
+    "SetSimulationDistance",
+    "SetSubtitleText",
+    "SetTime",
+    "SetTitleText",
+    "SetTitlesAnimation",
+    "SoundEntity",
+    "Sound",
+    "StartConfiguration",
+    "StopSound",
+    "StoreCookie",
+    "SystemChat",
+    "TabList",
+    "TagQuery",
+    "TakeItemEntity",
+    "TeleportEntity",
+    "TestInstanceBlockStatus",
+    "TickingState",
+    "TickingStep",
+    "Transfer",
+    "UpdateAdvancements",
+    "UpdateAttributes",
+    "UpdateMobEffect",
+    "UpdateRecipes",
+    "UpdateTags",
+    "ProjectilePower",
+    "CustomReportDetails",
+    "ServerLinks",
+];
+
+/// `state` の clientbound パケット `id` の名前。不明なら None。
+pub fn packet_name(state: State, id: i32, protocol: u32) -> Option<&'static str> {
+    if protocol < 767 {
+        return None;
+    }
+    match state {
+        State::Login => usize::try_from(id).ok().and_then(|i| LOGIN.get(i)).copied(),
+        State::Configuration => usize::try_from(id)
+            .ok()
+            .and_then(|i| CONFIGURATION.get(i))
+            .copied(),
+        State::Play => {
+            let table = match protocol {
+                767 => PLAY_767,
+                770 => PLAY_770,
+                _ => return None,
+            };
+            usize::try_from(id).ok().and_then(|i| table.get(i)).copied()
+        }
+        _ => None,
+    }
+}
+
+/// [`packet_name`] の逆引き。`state` の clientbound パケット `name` の id。
+/// 表に無ければ None。
+pub fn packet_id(state: State, name: &str, protocol: u32) -> Option<i32> {
+    (0..=0xff).find(|&id| packet_name(state, id, protocol) == Some(name))
+}
+
+/// `Play/SystemChat` 形式の表示名。名前が不明なら `Play/0x6c` のように 16 進で表す。
+pub fn display_name(state: State, id: i32, protocol: u32) -> String {
+    match packet_name(state, id, protocol) {
+        Some(name) => format!("{:?}/{}", state, name),
+        None => format!("{:?}/0x{:02x}", state, id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_ids_resolve_to_names() {
+        assert_eq!(packet_name(State::Login, 0x02, 767), Some("LoginFinished"));
+        assert_eq!(
+            packet_name(State::Configuration, 0x07, 774),
+            Some("RegistryData")
+        );
+        assert_eq!(display_name(State::Play, 0x6c, 767), "Play/SystemChat");
+        assert_eq!(packet_name(State::Play, 0x2c, 767), Some("MapItemData"));
+        assert_eq!(packet_name(State::Play, 0x7b, 767), Some("ServerLinks"));
+        assert_eq!(packet_id(State::Play, "ServerLinks", 767), Some(0x7b));
+        assert_eq!(packet_id(State::Play, "ServerLinks", 774), None);
+        assert_eq!(packet_id(State::Login, "NoSuchPacket", 767), None);
+    }
+
+    #[test]
+    fn play_770_matches_crate_packet_ids() {
+        use crate::stream::{COSMETIC_PACKET_IDS, PACKET_ID_PROTOCOL};
+        assert_eq!(PACKET_ID_PROTOCOL, 770);
+        let names: Vec<_> = COSMETIC_PACKET_IDS
+            .iter()
+            .map(|&id| packet_name(State::Play, id as i32, 770).unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "Animate",
+                "BlockDestruction",
+                "DamageEvent",
+                "HurtAnimation",
+                "LevelEvent",
+                "LevelParticles",
+                "SoundEntity",
+                "Sound",
+            ]
+        );
+        assert_eq!(display_name(State::Play, 0x72, 770), "Play/SystemChat");
+        assert_eq!(packet_name(State::Play, 0x83, 770), None);
+    }
+
+    #[test]
+    fn unknown_ids_stay_hex() {
+        // Play の表が無いバージョン、範囲外 id、古いプロトコル
+        assert_eq!(packet_name(State::Play, 0x6c, 774), None);
+        assert_eq!(display_name(State::Play, 0x6c, 774), "Play/0x6c");
+        assert_eq!(
+            display_name(State::Configuration, 0x7f, 767),
+            "Configuration/0x7f"
+        );
+        assert_eq!(packet_name(State::Login, -1, 767), None);
+        assert_eq!(packet_name(State::Login, 0x02, 340), None);
+    }
+}
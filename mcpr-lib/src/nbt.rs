@@ -0,0 +1,387 @@
+//! NBT の読み書き。
+//!
+//! 1.20.2 以降の network NBT は root の compound に名前が無い
+//! ([`Deserializer::read_nbt`])。それより前や .nbt ファイルの形式は root にも
+//! 名前が付く ([`Deserializer::read_named_nbt`])。文字列は Java の modified UTF-8
+//! (NUL は 2 バイト、BMP 外の文字はサロゲートペアを 3 バイトずつ)。
+//!
+//! [`Deserializer::read_nbt`]: crate::protocol::Deserializer::read_nbt
+//! [`Deserializer::read_named_nbt`]: crate::protocol::Deserializer::read_named_nbt
+
+use std::io::{self, Read, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::protocol::{checked_len_i32, ensure_alloc_len, invalid_data, read_exact_vec};
+
+/// 入れ子の上限 (Minecraft 本体と同じ 512)。
+pub const MAX_DEPTH: usize = 512;
+
+pub const TAG_END: u8 = 0;
+pub const TAG_BYTE: u8 = 1;
+pub const TAG_SHORT: u8 = 2;
+pub const TAG_INT: u8 = 3;
+pub const TAG_LONG: u8 = 4;
+pub const TAG_FLOAT: u8 = 5;
+pub const TAG_DOUBLE: u8 = 6;
+pub const TAG_BYTE_ARRAY: u8 = 7;
+pub const TAG_STRING: u8 = 8;
+pub const TAG_LIST: u8 = 9;
+pub const TAG_COMPOUND: u8 = 10;
+pub const TAG_INT_ARRAY: u8 = 11;
+pub const TAG_LONG_ARRAY: u8 = 12;
+
+/// NBT のタグ。
+#[derive(Debug, Clone, PartialEq)]
+pub enum Nbt {
+    /// network NBT の root が TAG_End (値が無い) のとき。
+    End,
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    /// 要素型 (`TAG_*`) と要素。要素は全てその型。空のリストも読んだときの
+    /// 要素型を保つ (書き戻したバイト列が元と一致するように)。
+    List(u8, Vec<Nbt>),
+    /// 出現順を保つ (書き戻したバイト列が元と一致するように)。
+    Compound(Vec<(String, Nbt)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Nbt {
+    /// タグの種類 (`TAG_*`)。
+    pub fn tag(&self) -> u8 {
+        match self {
+            Nbt::End => TAG_END,
+            Nbt::Byte(_) => TAG_BYTE,
+            Nbt::Short(_) => TAG_SHORT,
+            Nbt::Int(_) => TAG_INT,
+            Nbt::Long(_) => TAG_LONG,
+            Nbt::Float(_) => TAG_FLOAT,
+            Nbt::Double(_) => TAG_DOUBLE,
+            Nbt::ByteArray(_) => TAG_BYTE_ARRAY,
+            Nbt::String(_) => TAG_STRING,
+            Nbt::List(..) => TAG_LIST,
+            Nbt::Compound(_) => TAG_COMPOUND,
+            Nbt::IntArray(_) => TAG_INT_ARRAY,
+            Nbt::LongArray(_) => TAG_LONG_ARRAY,
+        }
+    }
+
+    /// 要素型を先頭の要素から決めたリスト。空なら要素型は TAG_End。
+    pub fn list(items: Vec<Nbt>) -> Self {
+        Nbt::List(items.first().map_or(TAG_END, Nbt::tag), items)
+    }
+
+    /// compound の `name` の値。compound でなければ None。
+    pub fn get(&self, name: &str) -> Option<&Nbt> {
+        match self {
+            Nbt::Compound(entries) => entries.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+/// root のタグ種類を読んだ後の payload を読む。
+pub(crate) fn read_payload<R: Read + ?Sized>(
+    reader: &mut R,
+    tag: u8,
+    depth: usize,
+) -> io::Result<Nbt> {
+    if depth > MAX_DEPTH {
+        return Err(invalid_data("NBT is nested too deeply"));
+    }
+    Ok(match tag {
+        TAG_END => Nbt::End,
+        TAG_BYTE => Nbt::Byte(reader.read_i8()?),
+        TAG_SHORT => Nbt::Short(reader.read_i16::<BigEndian>()?),
+        TAG_INT => Nbt::Int(reader.read_i32::<BigEndian>()?),
+        TAG_LONG => Nbt::Long(reader.read_i64::<BigEndian>()?),
+        TAG_FLOAT => Nbt::Float(reader.read_f32::<BigEndian>()?),
+        TAG_DOUBLE => Nbt::Double(reader.read_f64::<BigEndian>()?),
+        TAG_BYTE_ARRAY => {
+            let len = read_len(reader, "NBT byte array length")?;
+            let bytes = read_exact_vec(reader, len, "NBT byte array")?;
+            Nbt::ByteArray(bytes.into_iter().map(|b| b as i8).collect())
+        }
+        TAG_STRING => Nbt::String(read_string(reader)?),
+        TAG_LIST => {
+            let element = reader.read_u8()?;
+            let len = read_len(reader, "NBT list length")?;
+            if element == TAG_END && len > 0 {
+                return Err(invalid_data(format!(
+                    "NBT list of TAG_End has {len} elements"
+                )));
+            }
+            let mut items = Vec::new();
+            for _ in 0..len {
+                items.push(read_payload(reader, element, depth + 1)?);
+            }
+            Nbt::List(element, items)
+        }
+        TAG_COMPOUND => {
+            let mut entries = Vec::new();
+            loop {
+                let tag = reader.read_u8()?;
+                if tag == TAG_END {
+                    break;
+                }
+                let name = read_string(reader)?;
+                entries.push((name, read_payload(reader, tag, depth + 1)?));
+            }
+            Nbt::Compound(entries)
+        }
+        TAG_INT_ARRAY => {
+            let len = read_len(reader, "NBT int array length")?;
+            ensure_alloc_len(len.saturating_mul(4), "NBT int array")?;
+            let bytes = read_exact_vec(reader, len * 4, "NBT int array")?;
+            Nbt::IntArray(
+                bytes
+                    .chunks_exact(4)
+                    .map(|b| i32::from_be_bytes(b.try_into().unwrap()))
+                    .collect(),
+            )
+        }
+        TAG_LONG_ARRAY => {
+            let len = read_len(reader, "NBT long array length")?;
+            ensure_alloc_len(len.saturating_mul(8), "NBT long array")?;
+            let bytes = read_exact_vec(reader, len * 8, "NBT long array")?;
+            Nbt::LongArray(
+                bytes
+                    .chunks_exact(8)
+                    .map(|b| i64::from_be_bytes(b.try_into().unwrap()))
+                    .collect(),
+            )
+        }
+        _ => return Err(invalid_data(format!("unknown NBT tag {tag}"))),
+    })
+}
+
+/// タグ種類を除いた payload を書く。
+pub(crate) fn write_payload<W: Write + ?Sized>(writer: &mut W, nbt: &Nbt) -> io::Result<()> {
+    match nbt {
+        Nbt::End => Ok(()),
+        Nbt::Byte(value) => writer.write_i8(*value),
+        Nbt::Short(value) => writer.write_i16::<BigEndian>(*value),
+        Nbt::Int(value) => writer.write_i32::<BigEndian>(*value),
+        Nbt::Long(value) => writer.write_i64::<BigEndian>(*value),
+        Nbt::Float(value) => writer.write_f32::<BigEndian>(*value),
+        Nbt::Double(value) => writer.write_f64::<BigEndian>(*value),
+        Nbt::ByteArray(values) => {
+            write_len(writer, values.len())?;
+            let bytes: Vec<u8> = values.iter().map(|b| *b as u8).collect();
+            writer.write_all(&bytes)
+        }
+        Nbt::String(value) => write_string(writer, value),
+        Nbt::List(element, items) => {
+            let element = *element;
+            if items.iter().any(|item| item.tag() != element) {
+                return Err(invalid_input("NBT list elements differ in type"));
+            }
+            if element == TAG_END && !items.is_empty() {
+                return Err(invalid_input("NBT list cannot contain TAG_End"));
+            }
+            writer.write_u8(element)?;
+            write_len(writer, items.len())?;
+            items
+                .iter()
+                .try_for_each(|item| write_payload(writer, item))
+        }
+        Nbt::Compound(entries) => {
+            for (name, value) in entries {
+                if matches!(value, Nbt::End) {
+                    return Err(invalid_input(format!(
+                        "NBT compound entry {name:?} cannot be TAG_End"
+                    )));
+                }
+                writer.write_u8(value.tag())?;
+                write_string(writer, name)?;
+                write_payload(writer, value)?;
+            }
+            writer.write_u8(TAG_END)
+        }
+        Nbt::IntArray(values) => {
+            write_len(writer, values.len())?;
+            values
+                .iter()
+                .try_for_each(|value| writer.write_i32::<BigEndian>(*value))
+        }
+        Nbt::LongArray(values) => {
+            write_len(writer, values.len())?;
+            values
+                .iter()
+                .try_for_each(|value| writer.write_i64::<BigEndian>(*value))
+        }
+    }
+}
+
+fn invalid_input(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+fn read_len<R: Read + ?Sized>(reader: &mut R, field: &str) -> io::Result<usize> {
+    checked_len_i32(reader.read_i32::<BigEndian>()?, field)
+}
+
+fn write_len<W: Write + ?Sized>(writer: &mut W, len: usize) -> io::Result<()> {
+    let len =
+        i32::try_from(len).map_err(|_| invalid_input(format!("NBT array is too long: {len}")))?;
+    writer.write_i32::<BigEndian>(len)
+}
+
+/// u16 長 + modified UTF-8 の文字列。
+pub(crate) fn read_string<R: Read + ?Sized>(reader: &mut R) -> io::Result<String> {
+    let len = reader.read_u16::<BigEndian>()? as usize;
+    let bytes = read_exact_vec(reader, len, "NBT string")?;
+    decode_modified_utf8(&bytes)
+}
+
+pub(crate) fn write_string<W: Write + ?Sized>(writer: &mut W, value: &str) -> io::Result<()> {
+    let bytes = encode_modified_utf8(value);
+    let len = u16::try_from(bytes.len())
+        .map_err(|_| invalid_input(format!("NBT string too long: {} bytes", bytes.len())))?;
+    writer.write_u16::<BigEndian>(len)?;
+    writer.write_all(&bytes)
+}
+
+fn decode_modified_utf8(bytes: &[u8]) -> io::Result<String> {
+    let malformed = || invalid_data("malformed modified UTF-8 in NBT string");
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let (unit, width) = match b {
+            0x01..=0x7f => (u16::from(b), 1),
+            0xc0..=0xdf => {
+                let b1 = *bytes.get(i + 1).ok_or_else(malformed)?;
+                if b1 & 0xc0 != 0x80 {
+                    return Err(malformed());
+                }
+                ((u16::from(b & 0x1f) << 6) | u16::from(b1 & 0x3f), 2)
+            }
+            0xe0..=0xef => {
+                let b1 = *bytes.get(i + 1).ok_or_else(malformed)?;
+                let b2 = *bytes.get(i + 2).ok_or_else(malformed)?;
+                if b1 & 0xc0 != 0x80 || b2 & 0xc0 != 0x80 {
+                    return Err(malformed());
+                }
+                (
+                    (u16::from(b & 0x0f) << 12)
+                        | (u16::from(b1 & 0x3f) << 6)
+                        | u16::from(b2 & 0x3f),
+                    3,
+                )
+            }
+            _ => return Err(malformed()),
+        };
+        units.push(unit);
+        i += width;
+    }
+    String::from_utf16(&units).map_err(|_| malformed())
+}
+
+fn encode_modified_utf8(value: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(value.len());
+    for unit in value.encode_utf16() {
+        match unit {
+            0x0001..=0x007f => out.push(unit as u8),
+            0x0000 | 0x0080..=0x07ff => {
+                out.push(0xc0 | (unit >> 6) as u8);
+                out.push(0x80 | (unit & 0x3f) as u8);
+            }
+            _ => {
+                out.push(0xe0 | (unit >> 12) as u8);
+                out.push(0x80 | ((unit >> 6) & 0x3f) as u8);
+                out.push(0x80 | (unit & 0x3f) as u8);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Deserializer, Serializer};
+
+    fn sample() -> Nbt {
+        Nbt::Compound(vec![
+            ("name".to_string(), Nbt::String("Steve\0🎮".to_string())),
+            (
+                "lines".to_string(),
+                Nbt::list(vec![
+                    Nbt::String("a".to_string()),
+                    Nbt::String("b".to_string()),
+                ]),
+            ),
+            ("empty".to_string(), Nbt::list(vec![])),
+            // 要素型付きの空リスト (要素型は書き戻しても変わらない)
+            ("no_compounds".to_string(), Nbt::List(TAG_COMPOUND, vec![])),
+            (
+                "inner".to_string(),
+                Nbt::Compound(vec![
+                    ("bytes".to_string(), Nbt::ByteArray(vec![1, -1, 0])),
+                    ("longs".to_string(), Nbt::LongArray(vec![i64::MIN])),
+                ]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn network_and_named_roots_roundtrip() -> io::Result<()> {
+        let nbt = sample();
+
+        let mut network = Vec::new();
+        network.write_nbt(&nbt)?;
+        // root の compound に名前が無い
+        assert_eq!(&network[..3], &[TAG_COMPOUND, TAG_STRING, 0]);
+        let mut cursor = io::Cursor::new(network.as_slice());
+        let read = cursor.read_nbt()?;
+        assert_eq!(read, nbt);
+        assert_eq!(cursor.position() as usize, network.len());
+        let mut rewritten = Vec::new();
+        rewritten.write_nbt(&read)?;
+        assert_eq!(rewritten, network);
+
+        let mut named = Vec::new();
+        named.write_named_nbt("root", &nbt)?;
+        assert_eq!(&named[..3], &[TAG_COMPOUND, 0, 4]);
+        let (name, read) = io::Cursor::new(named.as_slice()).read_named_nbt()?;
+        assert_eq!((name.as_str(), read), ("root", nbt.clone()));
+
+        assert_eq!(
+            nbt.get("inner").and_then(|inner| inner.get("bytes")),
+            Some(&Nbt::ByteArray(vec![1, -1, 0]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tag_end_handling() -> io::Result<()> {
+        // network NBT の root が TAG_End なら値無し
+        assert_eq!(io::Cursor::new([TAG_END]).read_nbt()?, Nbt::End);
+        // 要素型 TAG_End の空リストは読める
+        let empty = [TAG_LIST, TAG_END, 0, 0, 0, 0];
+        assert_eq!(io::Cursor::new(empty).read_nbt()?, Nbt::list(vec![]));
+        // 要素型 TAG_End で要素があるリストは不正
+        let bad = [TAG_LIST, TAG_END, 0, 0, 0, 1];
+        assert!(io::Cursor::new(bad).read_nbt().is_err());
+        // 閉じていない compound
+        assert!(
+            io::Cursor::new([TAG_COMPOUND, TAG_BYTE, 0, 1, b'x', 5])
+                .read_nbt()
+                .is_err()
+        );
+
+        let mixed = Nbt::list(vec![Nbt::Byte(1), Nbt::Int(2)]);
+        assert!(Vec::new().write_nbt(&mixed).is_err());
+        let mistyped = Nbt::List(TAG_STRING, vec![Nbt::Byte(1)]);
+        assert!(Vec::new().write_nbt(&mistyped).is_err());
+        Ok(())
+    }
+}
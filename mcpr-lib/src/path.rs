@@ -0,0 +1,208 @@
+//! 記録したプレイヤー (自分) の移動経路の抽出。
+//!
+//! Replay Mod は自分の移動もエンティティの移動パケットとして記録するので、
+//! Login (play) の entity id を `self_id` に渡せば他のエンティティと同じ
+//! 方法で位置を追える。パケット id は 1.21.5 (protocol 770) 以降の値。
+
+use std::io::{self, Cursor};
+
+use crate::{event::State, mcpr::Packet, protocol::Deserializer};
+
+/// Spawn Entity (add_entity): 出現時の絶対座標と向き。
+pub const ADD_ENTITY_PACKET_ID: i32 = 0x01;
+/// Teleport Entity (entity_position_sync): 絶対座標。
+pub const ENTITY_POSITION_SYNC_PACKET_ID: i32 = 0x1f;
+/// Update Entity Position: 1/4096 ブロック単位の相対移動。
+pub const MOVE_ENTITY_POS_PACKET_ID: i32 = 0x2e;
+/// Update Entity Position and Rotation: 相対移動 + 向き。
+pub const MOVE_ENTITY_POS_ROT_PACKET_ID: i32 = 0x2f;
+/// Update Entity Rotation: 向きだけの更新。
+pub const MOVE_ENTITY_ROT_PACKET_ID: i32 = 0x31;
+/// Remove Entities: 消えたエンティティの id の配列。
+pub const REMOVE_ENTITIES_PACKET_ID: i32 = 0x46;
+/// Synchronize Player Position: 自分への teleport (entity id 無し)。
+pub const PLAYER_POSITION_PACKET_ID: i32 = 0x41;
+
+/// 経路の 1 点 (時刻 ms, x, y, z)。
+pub type PathPoint = (u32, f64, f64, f64);
+
+/// Synchronize Player Position の相対フラグ (x / y / z)。
+const RELATIVE_X: i32 = 0x01;
+const RELATIVE_Y: i32 = 0x02;
+const RELATIVE_Z: i32 = 0x04;
+
+/// Synchronize Player Position の body。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayerPosition {
+    pub teleport_id: i32,
+    pub position: (f64, f64, f64),
+    pub velocity: (f64, f64, f64),
+    pub yaw: f32,
+    pub pitch: f32,
+    /// 相対指定のフラグ (下位 3 bit が x / y / z)。
+    pub flags: i32,
+}
+
+/// Synchronize Player Position ([`PLAYER_POSITION_PACKET_ID`]) の body を読む。
+pub fn read_player_position(data: &[u8]) -> io::Result<PlayerPosition> {
+    let mut cursor = Cursor::new(data);
+    Ok(PlayerPosition {
+        teleport_id: cursor.read_varint()?,
+        position: (
+            cursor.read_double()?,
+            cursor.read_double()?,
+            cursor.read_double()?,
+        ),
+        velocity: (
+            cursor.read_double()?,
+            cursor.read_double()?,
+            cursor.read_double()?,
+        ),
+        yaw: cursor.read_float()?,
+        pitch: cursor.read_float()?,
+        flags: cursor.read_int()?,
+    })
+}
+
+/// `self_id` のエンティティの位置を、移動パケットごとに 1 点ずつ返す。
+///
+/// 絶対座標 (teleport) はそのまま、相対移動は直前の位置に差分を足して求める。
+/// 最初の絶対座標より前の相対移動は基準が無いので捨てる。
+pub fn extract_self_path(
+    packets: impl IntoIterator<Item = (State, Packet)>,
+    self_id: i32,
+) -> io::Result<Vec<PathPoint>> {
+    let mut path = Vec::new();
+    let mut current: Option<(f64, f64, f64)> = None;
+    for (state, packet) in packets {
+        if state != State::Play {
+            continue;
+        }
+        let mut cursor = Cursor::new(packet.data());
+        let next = match packet.id() {
+            ENTITY_POSITION_SYNC_PACKET_ID => {
+                if cursor.read_varint()? != self_id {
+                    continue;
+                }
+                Some((
+                    cursor.read_double()?,
+                    cursor.read_double()?,
+                    cursor.read_double()?,
+                ))
+            }
+            MOVE_ENTITY_POS_PACKET_ID | MOVE_ENTITY_POS_ROT_PACKET_ID => {
+                if cursor.read_varint()? != self_id {
+                    continue;
+                }
+                let dx = cursor.read_short()? as f64 / 4096.0;
+                let dy = cursor.read_short()? as f64 / 4096.0;
+                let dz = cursor.read_short()? as f64 / 4096.0;
+                current.map(|(x, y, z)| (x + dx, y + dy, z + dz))
+            }
+            PLAYER_POSITION_PACKET_ID => {
+                let PlayerPosition {
+                    position: (x, y, z),
+                    flags,
+                    ..
+                } = read_player_position(packet.data())?;
+                let (cx, cy, cz) = current.unwrap_or_default();
+                let relative = |bit: i32, base: f64, value: f64| {
+                    if flags & bit != 0 {
+                        base + value
+                    } else {
+                        value
+                    }
+                };
+                Some((
+                    relative(RELATIVE_X, cx, x),
+                    relative(RELATIVE_Y, cy, y),
+                    relative(RELATIVE_Z, cz, z),
+                ))
+            }
+            _ => continue,
+        };
+        if let Some((x, y, z)) = next {
+            path.push((packet.time(), x, y, z));
+            current = next;
+        }
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Serializer;
+
+    fn sync(time: u32, entity: i32, pos: [f64; 3]) -> (State, Packet) {
+        let mut data = Vec::new();
+        data.write_varint(entity).unwrap();
+        for v in pos.into_iter().chain([0.0; 3]) {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.extend_from_slice(&[0; 9]); // yaw, pitch, on ground
+        (
+            State::Play,
+            Packet::new(time, ENTITY_POSITION_SYNC_PACKET_ID, data.into()),
+        )
+    }
+
+    fn moved(time: u32, entity: i32, delta: [i16; 3]) -> (State, Packet) {
+        let mut data = Vec::new();
+        data.write_varint(entity).unwrap();
+        for d in delta {
+            data.extend_from_slice(&d.to_be_bytes());
+        }
+        data.push(1);
+        (
+            State::Play,
+            Packet::new(time, MOVE_ENTITY_POS_PACKET_ID, data.into()),
+        )
+    }
+
+    fn player_position(time: u32, pos: [f64; 3], flags: i32) -> (State, Packet) {
+        let mut data = Vec::new();
+        data.write_varint(1).unwrap();
+        for v in pos.into_iter().chain([0.0; 3]) {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&flags.to_be_bytes());
+        (
+            State::Play,
+            Packet::new(time, PLAYER_POSITION_PACKET_ID, data.into()),
+        )
+    }
+
+    #[test]
+    fn integrates_relative_moves_from_teleports() {
+        let packets = vec![
+            // 基準が無いので捨てる
+            moved(0, 7, [4096, 0, 0]),
+            sync(50, 7, [10.0, 64.0, -5.0]),
+            // 他のエンティティは無視
+            moved(60, 8, [4096, 0, 0]),
+            moved(100, 7, [4096, -2048, 0]),
+            moved(150, 7, [0, 0, 8192]),
+            player_position(200, [1.0, 0.0, 100.0], RELATIVE_X | RELATIVE_Y),
+        ];
+        let path = extract_self_path(packets, 7).unwrap();
+        assert_eq!(
+            path,
+            vec![
+                (50, 10.0, 64.0, -5.0),
+                (100, 11.0, 63.5, -5.0),
+                (150, 11.0, 63.5, -3.0),
+                (200, 12.0, 63.5, 100.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn one_point_per_movement_packet() {
+        let mut packets = vec![sync(0, 3, [0.0; 3])];
+        packets.extend((1..=20).map(|i| moved(i * 50, 3, [100, 0, -100])));
+        let movements = packets.len();
+        assert_eq!(extract_self_path(packets, 3).unwrap().len(), movements);
+    }
+}
@@ -0,0 +1,232 @@
+//! プレイヤー一覧 (タブリスト) パケットの解釈。
+//!
+//! Player Info Update はアクションのビット集合と、プレイヤーごとの
+//! UUID + 各アクションのデータが並ぶ。パケット id とアクションの並びは
+//! 1.21.5 (protocol 770) 以降の値。
+
+use std::{
+    collections::HashMap,
+    io::{self, Cursor},
+};
+
+use crate::{
+    chat::flatten_component,
+    event::State,
+    mcpr::Packet,
+    protocol::{Deserializer, checked_len_i32},
+};
+
+/// Player Info Remove: UUID の配列。
+pub const PLAYER_INFO_REMOVE_PACKET_ID: i32 = 0x3e;
+/// Player Info Update。
+pub const PLAYER_INFO_UPDATE_PACKET_ID: i32 = 0x3f;
+
+const ADD_PLAYER: u8 = 0x01;
+const INITIALIZE_CHAT: u8 = 0x02;
+const UPDATE_GAME_MODE: u8 = 0x04;
+const UPDATE_LISTED: u8 = 0x08;
+const UPDATE_LATENCY: u8 = 0x10;
+const UPDATE_DISPLAY_NAME: u8 = 0x20;
+const UPDATE_LIST_ORDER: u8 = 0x40;
+const UPDATE_HAT: u8 = 0x80;
+
+/// Player Info Update の 1 人分。一覧に関係するフィールドだけを残す。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerInfoEntry {
+    pub uuid: uuid::Uuid,
+    /// Add Player で送られたプロフィール名。
+    pub name: Option<String>,
+    /// Update Display Name が含まれていれば Some。中身の None は表示名の解除。
+    pub display_name: Option<Option<String>>,
+}
+
+/// Player Info Update の body を 1 人ずつ読む。
+pub fn read_player_info_update(data: &[u8]) -> io::Result<Vec<PlayerInfoEntry>> {
+    let mut cursor = Cursor::new(data);
+    let actions = cursor.read_unsigned_byte()?;
+    let count = checked_len_i32(cursor.read_varint()?, "player count")?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let mut entry = PlayerInfoEntry {
+            uuid: cursor.read_uuid()?,
+            name: None,
+            display_name: None,
+        };
+        if actions & ADD_PLAYER != 0 {
+            entry.name = Some(cursor.read_string()?);
+            for _ in 0..cursor.read_varint()? {
+                cursor.read_string()?; // property name
+                cursor.read_string()?; // value
+                if cursor.read_bool()? {
+                    cursor.read_string()?; // signature
+                }
+            }
+        }
+        if actions & INITIALIZE_CHAT != 0 && cursor.read_bool()? {
+            cursor.read_uuid()?; // chat session id
+            cursor.read_long()?; // public key expiry
+            cursor.read_length_prefixed()?; // public key
+            cursor.read_length_prefixed()?; // key signature
+        }
+        if actions & UPDATE_GAME_MODE != 0 {
+            cursor.read_varint()?;
+        }
+        if actions & UPDATE_LISTED != 0 {
+            cursor.read_bool()?;
+        }
+        if actions & UPDATE_LATENCY != 0 {
+            cursor.read_varint()?;
+        }
+        if actions & UPDATE_DISPLAY_NAME != 0 {
+            entry.display_name = Some(if cursor.read_bool()? {
+                Some(flatten_component(&mut cursor)?)
+            } else {
+                None
+            });
+        }
+        if actions & UPDATE_LIST_ORDER != 0 {
+            cursor.read_varint()?;
+        }
+        if actions & UPDATE_HAT != 0 {
+            cursor.read_bool()?;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Player Info Remove の body (外れたプレイヤーの UUID)。
+pub fn read_player_info_remove(data: &[u8]) -> io::Result<Vec<uuid::Uuid>> {
+    let mut cursor = Cursor::new(data);
+    let count = checked_len_i32(cursor.read_varint()?, "player count")?;
+    (0..count).map(|_| cursor.read_uuid()).collect()
+}
+
+/// パケット列のプレイヤー一覧に現れたプレイヤーの (UUID, 表示名) を、
+/// 最初に現れた順に返す。
+///
+/// 表示名は最後の Update Display Name の値で、無ければ (解除された場合も)
+/// Add Player のプロフィール名。どちらも無いプレイヤーは含めない。
+pub fn list_display_names(
+    packets: impl IntoIterator<Item = (State, Packet)>,
+) -> io::Result<Vec<(uuid::Uuid, String)>> {
+    let mut order = Vec::new();
+    // UUID → (プロフィール名, 表示名)
+    let mut names: HashMap<uuid::Uuid, (Option<String>, Option<String>)> = HashMap::new();
+    for (state, packet) in packets {
+        if state != State::Play || packet.id() != PLAYER_INFO_UPDATE_PACKET_ID {
+            continue;
+        }
+        for entry in read_player_info_update(packet.data())? {
+            let (name, display_name) = names.entry(entry.uuid).or_insert_with(|| {
+                order.push(entry.uuid);
+                (None, None)
+            });
+            if entry.name.is_some() {
+                *name = entry.name;
+            }
+            if let Some(update) = entry.display_name {
+                *display_name = update;
+            }
+        }
+    }
+    Ok(order
+        .into_iter()
+        .filter_map(|uuid| {
+            let (name, display_name) = names.remove(&uuid)?;
+            Some((uuid, display_name.or(name)?))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use crate::protocol::Serializer;
+
+    /// Add Player + Update Listed の Player Info Update body。
+    pub(crate) fn add_players(players: &[(uuid::Uuid, &str)]) -> Vec<u8> {
+        let mut data = vec![ADD_PLAYER | UPDATE_LISTED];
+        data.write_varint(players.len() as i32).unwrap();
+        for (uuid, name) in players {
+            data.write_uuid(uuid).unwrap();
+            data.write_string(name).unwrap();
+            data.write_varint(1).unwrap();
+            data.write_string("textures").unwrap();
+            data.write_string("e30=").unwrap();
+            data.push(0);
+            data.push(1);
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Serializer;
+
+    #[test]
+    fn reads_every_entry_of_multi_player_update() {
+        let a = uuid::Uuid::from_u128(1);
+        let b = uuid::Uuid::from_u128(2);
+        let entries =
+            read_player_info_update(&testing::add_players(&[(a, "alice"), (b, "bob")])).unwrap();
+        let names: Vec<_> = entries
+            .iter()
+            .map(|e| (e.uuid, e.name.as_deref()))
+            .collect();
+        assert_eq!(names, vec![(a, Some("alice")), (b, Some("bob"))]);
+    }
+
+    #[test]
+    fn list_display_names_prefers_display_name() {
+        let alice = uuid::Uuid::from_u128(1);
+        let bob = uuid::Uuid::from_u128(2);
+        let mut rename = vec![UPDATE_DISPLAY_NAME];
+        rename.write_varint(1).unwrap();
+        rename.write_uuid(&bob).unwrap();
+        rename.push(1);
+        rename.push(8); // TAG_String
+        rename.extend_from_slice(&6u16.to_be_bytes());
+        rename.extend_from_slice(b"[Mod] ");
+        let packets = vec![
+            (
+                State::Play,
+                Packet::new(
+                    0,
+                    PLAYER_INFO_UPDATE_PACKET_ID,
+                    testing::add_players(&[(alice, "alice"), (bob, "bob")]).into(),
+                ),
+            ),
+            (
+                State::Play,
+                Packet::new(10, PLAYER_INFO_UPDATE_PACKET_ID, rename.into()),
+            ),
+        ];
+        assert_eq!(
+            list_display_names(packets).unwrap(),
+            vec![(alice, "alice".to_string()), (bob, "[Mod] ".to_string())]
+        );
+    }
+
+    #[test]
+    fn display_name_update_is_flattened() {
+        let mut data = vec![UPDATE_LATENCY | UPDATE_DISPLAY_NAME];
+        data.write_varint(2).unwrap();
+        data.write_uuid(&uuid::Uuid::from_u128(1)).unwrap();
+        data.write_varint(42).unwrap();
+        data.push(1);
+        // TAG_String のルート
+        data.push(8);
+        data.extend_from_slice(&5u16.to_be_bytes());
+        data.extend_from_slice(b"Alice");
+        data.write_uuid(&uuid::Uuid::from_u128(2)).unwrap();
+        data.write_varint(7).unwrap();
+        data.push(0);
+        let entries = read_player_info_update(&data).unwrap();
+        assert_eq!(entries[0].display_name, Some(Some("Alice".to_string())));
+        assert_eq!(entries[1].display_name, Some(None));
+        assert_eq!(entries[0].name, None);
+    }
+}
@@ -2,6 +2,8 @@ use std::io;
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
+use crate::nbt::Nbt;
+
 const MAX_ALLOC_BYTES: usize = 256 * 1024 * 1024;
 
 pub(crate) fn invalid_data(message: impl Into<String>) -> io::Error {
@@ -16,7 +18,7 @@ pub(crate) fn checked_len_u32(value: u32, field: &str) -> io::Result<usize> {
     usize::try_from(value).map_err(|_| invalid_data(format!("{field} is too large: {value}")))
 }
 
-fn ensure_alloc_len(len: usize, field: &str) -> io::Result<()> {
+pub(crate) fn ensure_alloc_len(len: usize, field: &str) -> io::Result<()> {
     if len > isize::MAX as usize {
         return Err(invalid_data(format!("{field} is too large: {len} bytes")));
     }
@@ -28,6 +30,10 @@ fn ensure_alloc_len(len: usize, field: &str) -> io::Result<()> {
     Ok(())
 }
 
+/// [`read_exact_vec`] が読む前に確保する上限。宣言長がこれより大きくても、
+/// 実際に読めた分だけ伸ばしていく (短い入力の巨大な宣言長で大きく確保しない)。
+const PREALLOC_LIMIT: usize = 64 * 1024;
+
 pub(crate) fn read_exact_vec<R: io::Read + ?Sized>(
     reader: &mut R,
     len: usize,
@@ -36,10 +42,18 @@ pub(crate) fn read_exact_vec<R: io::Read + ?Sized>(
     ensure_alloc_len(len, field)?;
     let mut buffer = Vec::new();
     buffer
-        .try_reserve_exact(len)
+        .try_reserve_exact(len.min(PREALLOC_LIMIT))
         .map_err(|_| invalid_data(format!("{field} is too large: {len} bytes")))?;
-    buffer.resize(len, 0);
-    reader.read_exact(&mut buffer)?;
+    io::Read::read_to_end(&mut io::Read::take(reader, len as u64), &mut buffer)?;
+    if buffer.len() < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "{field} length {len} exceeds remaining {} bytes",
+                buffer.len()
+            ),
+        ));
+    }
     Ok(buffer)
 }
 
@@ -87,6 +101,14 @@ pub trait Deserializer: io::Read {
     fn read_double(&mut self) -> io::Result<f64> {
         self.read_f64::<BigEndian>()
     }
+    /// Angle (1 回転 = 256 段階) の生の値。
+    fn read_angle(&mut self) -> io::Result<u8> {
+        self.read_u8()
+    }
+    /// Angle を度数 (0.0..360.0) で読む。
+    fn read_angle_degrees(&mut self) -> io::Result<f32> {
+        Ok(f32::from(self.read_angle()?) * 360.0 / 256.0)
+    }
     fn read_string(&mut self) -> io::Result<String> {
         let length = checked_len_i32(self.read_varint()?, "string length")?;
         let buffer = read_exact_vec(self, length, "string")?;
@@ -96,10 +118,61 @@ pub trait Deserializer: io::Read {
             .to_string();
         Ok(s)
     }
+    /// VarInt 個数 + String の並び (コマンド補完の候補など)。
+    fn read_string_list(&mut self) -> io::Result<Vec<String>> {
+        let count = checked_len_i32(self.read_varint()?, "string list length")?;
+        (0..count).map(|_| self.read_string()).collect()
+    }
+    /// BitSet: VarInt の long 数 + その数の Long。
+    fn read_bitset(&mut self) -> io::Result<Vec<u64>> {
+        let count = checked_len_i32(self.read_varint()?, "bitset length")?;
+        ensure_alloc_len(count.saturating_mul(8), "bitset")?;
+        (0..count).map(|_| self.read_u64::<BigEndian>()).collect()
+    }
+    /// 連続する `n` 個の BitSet (ライト更新のマスク群など)。
+    fn read_bitsets(&mut self, n: usize) -> io::Result<Vec<Vec<u64>>> {
+        (0..n).map(|_| self.read_bitset()).collect()
+    }
+    /// ID or X: VarInt が 0 なら直後に `read_x` で読むインラインの値 (`Err`)、
+    /// それ以外ならレジストリ id + 1 (`Ok(id)`)。
+    fn read_id_or<T, F>(&mut self, read_x: F) -> io::Result<Result<i32, T>>
+    where
+        F: FnOnce(&mut Self) -> io::Result<T>,
+    {
+        match self.read_varint()? {
+            0 => Ok(Err(read_x(self)?)),
+            id => id
+                .checked_sub(1)
+                .map(Ok)
+                .ok_or_else(|| invalid_data(format!("id or x value is out of range: {id}"))),
+        }
+    }
+    /// VarInt 長 + バイト列 (入れ子のパケット等)。[`Self::read_string`] と
+    /// 異なり UTF-8 として解釈せず、中身をそのまま返す。
+    fn read_length_prefixed(&mut self) -> io::Result<Vec<u8>> {
+        let length = checked_len_i32(self.read_varint()?, "length-prefixed data length")?;
+        read_exact_vec(self, length, "length-prefixed data")
+    }
+    /// 残り全部を読む。`max` バイトを超えて読めたらエラー
+    /// (壊れたフレームで際限なく読み続けないための上限)。
+    fn read_to_end_capped(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut limited = io::Read::take(&mut *self, (max as u64).saturating_add(1));
+        io::Read::read_to_end(&mut limited, &mut data)?;
+        if data.len() > max {
+            return Err(invalid_data(format!("remaining data exceeds {max} bytes")));
+        }
+        Ok(data)
+    }
+    /// 5 バイト目は下位 4 ビット (i32 の上位 4 ビット) しか使えない。
+    /// それ以上のビットが立つ冗長なエンコードは `InvalidData`。
     fn read_varint(&mut self) -> io::Result<i32> {
         let mut val = 0;
         for i in 0..5 {
             let byte = self.read_u8()?;
+            if i == 4 && byte & 0xF0 != 0 {
+                return Err(invalid_data("VarInt is too big"));
+            }
             val |= (i32::from(byte) & 0x7F) << (i * 7);
             if byte & 0x80 == 0 {
                 return Ok(val);
@@ -129,6 +202,20 @@ pub trait Deserializer: io::Read {
         self.read_exact(&mut buffer)?;
         Ok(uuid::Uuid::from_bytes(buffer))
     }
+    /// network NBT (1.20.2 以降、root に名前が無い)。root が TAG_End なら [`Nbt::End`]。
+    fn read_nbt(&mut self) -> io::Result<Nbt> {
+        let tag = self.read_u8()?;
+        crate::nbt::read_payload(self, tag, 0)
+    }
+    /// root に名前が付く形式の NBT (1.20.2 より前のパケットや .nbt ファイル)。
+    fn read_named_nbt(&mut self) -> io::Result<(String, Nbt)> {
+        let tag = self.read_u8()?;
+        if tag == crate::nbt::TAG_END {
+            return Ok((String::new(), Nbt::End));
+        }
+        let name = crate::nbt::read_string(self)?;
+        Ok((name, crate::nbt::read_payload(self, tag, 0)?))
+    }
 }
 
 impl<R: io::Read + ?Sized> Deserializer for R {}
@@ -153,7 +240,8 @@ pub trait Serializer: io::Write {
                     "VarInt is too big",
                 ))
         */
-        let mut val = value;
+        // 負数は 2 の補数の 32 ビットとして書く (算術シフトだと 0 にならない)
+        let mut val = value as u32;
         for _ in 0..5 {
             let b: u8 = val as u8 & 0b01111111;
             val >>= 7;
@@ -164,24 +252,177 @@ pub trait Serializer: io::Write {
         }
         Ok(())
     }
+    fn write_bool(&mut self, value: bool) -> io::Result<()> {
+        self.write_u8(u8::from(value))
+    }
+    fn write_byte(&mut self, value: i8) -> io::Result<()> {
+        self.write_i8(value)
+    }
+    fn write_unsigned_byte(&mut self, value: u8) -> io::Result<()> {
+        self.write_u8(value)
+    }
+    fn write_short(&mut self, value: i16) -> io::Result<()> {
+        self.write_i16::<BigEndian>(value)
+    }
+    fn write_unsigned_short(&mut self, value: u16) -> io::Result<()> {
+        self.write_u16::<BigEndian>(value)
+    }
+    fn write_int(&mut self, value: i32) -> io::Result<()> {
+        self.write_i32::<BigEndian>(value)
+    }
+    fn write_long(&mut self, value: i64) -> io::Result<()> {
+        self.write_i64::<BigEndian>(value)
+    }
+    fn write_float(&mut self, value: f32) -> io::Result<()> {
+        self.write_f32::<BigEndian>(value)
+    }
+    fn write_double(&mut self, value: f64) -> io::Result<()> {
+        self.write_f64::<BigEndian>(value)
+    }
+    /// VarInt 長 + UTF-8 バイト列。[`Deserializer::read_string`] の逆。
     fn write_string(&mut self, value: &str) -> io::Result<()> {
-        self.write_varint(value.len() as i32)?;
+        let length = i32::try_from(value.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("string is too long: {} bytes", value.len()),
+            )
+        })?;
+        self.write_varint(length)?;
         self.write_all(value.as_bytes())
     }
+    fn write_varlong(&mut self, value: i64) -> io::Result<()> {
+        let mut val = value as u64;
+        loop {
+            let b = val as u8 & 0b01111111;
+            val >>= 7;
+            if val == 0 {
+                return self.write_u8(b);
+            }
+            self.write_u8(b | 0b10000000)?;
+        }
+    }
+    /// [`Deserializer::read_bitset`] の逆。
+    fn write_bitset(&mut self, bitset: &[u64]) -> io::Result<()> {
+        let count = i32::try_from(bitset.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("bitset is too long: {} longs", bitset.len()),
+            )
+        })?;
+        self.write_varint(count)?;
+        for long in bitset {
+            self.write_u64::<BigEndian>(*long)?;
+        }
+        Ok(())
+    }
+    /// [`Deserializer::read_string_list`] の逆。
+    fn write_string_list<S: AsRef<str>>(&mut self, values: &[S]) -> io::Result<()> {
+        let count = i32::try_from(values.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("string list is too long: {} entries", values.len()),
+            )
+        })?;
+        self.write_varint(count)?;
+        for value in values {
+            self.write_string(value.as_ref())?;
+        }
+        Ok(())
+    }
+    /// [`Deserializer::read_id_or`] の逆。
+    fn write_id_or<T, F>(&mut self, value: &Result<i32, T>, write_x: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Self, &T) -> io::Result<()>,
+    {
+        match value {
+            Ok(id) => {
+                let value = id.checked_add(1).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("id is out of range: {id}"),
+                    )
+                })?;
+                self.write_varint(value)
+            }
+            Err(x) => {
+                self.write_varint(0)?;
+                write_x(self, x)
+            }
+        }
+    }
     fn write_uuid(&mut self, value: &uuid::Uuid) -> io::Result<()> {
         self.write_all(value.as_bytes())
     }
+    /// 度数を Angle (1 回転 = 256 段階) へ丸めて書く。範囲外の角度は 1 回転で折り返す。
+    fn write_angle_degrees(&mut self, degrees: f32) -> io::Result<()> {
+        let steps = (degrees * 256.0 / 360.0).round() as i64;
+        self.write_u8(steps.rem_euclid(256) as u8)
+    }
+    /// [`Deserializer::read_nbt`] の逆。
+    fn write_nbt(&mut self, nbt: &Nbt) -> io::Result<()> {
+        self.write_u8(nbt.tag())?;
+        crate::nbt::write_payload(self, nbt)
+    }
+    /// [`Deserializer::read_named_nbt`] の逆。
+    fn write_named_nbt(&mut self, name: &str, nbt: &Nbt) -> io::Result<()> {
+        self.write_u8(nbt.tag())?;
+        if matches!(nbt, Nbt::End) {
+            return Ok(());
+        }
+        crate::nbt::write_string(self, name)?;
+        crate::nbt::write_payload(self, nbt)
+    }
+    /// `VarInt 長さ` + バイト列。[`Deserializer::read_length_prefixed`] の逆。
+    fn write_length_prefixed(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let length = i32::try_from(bytes.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("length-prefixed data is too large: {} bytes", bytes.len()),
+            )
+        })?;
+        self.write_varint(length)?;
+        self.write_all(bytes)
+    }
 }
 impl<W: io::Write + ?Sized> Serializer for W {}
 
 /// Login phase の遷移パケット id (protocol 764 / 1.20.2 以降で安定)。
 pub const LOGIN_SUCCESS_PACKET_ID: i32 = 0x02;
+/// Login phase の Set Compression パケット id (以降のフレームが圧縮形式になる)。
+pub const SET_COMPRESSION_PACKET_ID: i32 = 0x03;
 /// Configuration phase の遷移パケット id (protocol 764 / 1.20.2 以降で安定)。
 pub const FINISH_CONFIGURATION_PACKET_ID: i32 = 0x03;
 /// Play phase の Login (play) パケット id。
 /// 注意: 遷移 id と異なりバージョン間で安定しない (protocol 774 / 1.21.11 で確認した値)。
 pub const LOGIN_PLAY_PACKET_ID: i32 = 0x2b;
 
+/// Play phase の Respawn パケット id。
+/// 注意: バージョン間で安定しない (1.21.5 以降の値)。
+pub const RESPAWN_PACKET_ID: i32 = 0x4b;
+
+/// Play phase の Chunk Batch Finished パケット id。まとまりの終わりとチャンク数。
+/// 注意: バージョン間で安定しない (1.21.5 の値)。
+pub const CHUNK_BATCH_FINISHED_PACKET_ID: i32 = 0x0b;
+
+/// Play phase の Chunk Batch Start パケット id。この後に続くチャンクが 1 まとまり。
+/// 注意: バージョン間で安定しない (1.21.5 の値)。
+pub const CHUNK_BATCH_START_PACKET_ID: i32 = 0x0c;
+
+/// Play phase の Chunk Data and Update Light パケット id。
+/// 注意: バージョン間で安定しない (1.21.5 の値)。
+pub const LEVEL_CHUNK_WITH_LIGHT_PACKET_ID: i32 = 0x27;
+
+/// Play phase の Keep Alive (clientbound) パケット id。
+/// 注意: バージョン間で安定しない (1.21.5 以降の値)。
+pub const KEEP_ALIVE_PACKET_ID: i32 = 0x26;
+
+/// Configuration phase の Plugin Message (clientbound custom payload) の id
+/// (protocol 764 / 1.20.2 以降で安定)。
+pub const CONFIGURATION_PLUGIN_MESSAGE_PACKET_ID: i32 = 0x01;
+/// Play phase の Plugin Message (clientbound custom payload) の id。
+/// 注意: バージョン間で安定しない (1.21.5 以降の値)。
+pub const PLAY_PLUGIN_MESSAGE_PACKET_ID: i32 = 0x18;
+
 /// `value` を VarInt エンコードしたときのバイト数 (1..=5)。
 /// [`Serializer::write_varint`] の出力長と一致する (負数は常に 5)。
 pub fn varint_len(value: i32) -> usize {
@@ -220,6 +461,198 @@ pub fn login_success_payload(
     Ok(buf)
 }
 
+/// Plugin Message (custom payload) の body。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginMessage {
+    /// チャンネルの Identifier (`minecraft:brand` など)。
+    pub channel: String,
+    /// チャンネル以降の残り全部 (長さ prefix 無し)。
+    pub data: Vec<u8>,
+}
+
+/// Plugin Message の body を channel とペイロードに分ける。
+pub fn read_plugin_message(body: &[u8]) -> io::Result<PluginMessage> {
+    let mut cursor = io::Cursor::new(body);
+    let channel = cursor.read_string()?;
+    let data = body[cursor.position() as usize..].to_vec();
+    Ok(PluginMessage { channel, data })
+}
+
+/// [`read_plugin_message`] の逆。
+pub fn write_plugin_message(channel: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(varint_len(channel.len() as i32) + channel.len() + data.len());
+    buf.write_string(channel)?;
+    buf.extend_from_slice(data);
+    Ok(buf)
+}
+
+/// ID Set (ブロック・エンティティのタグ指定など) を読む。
+///
+/// 先頭の VarInt が 0 ならタグ名 (`Ok`、`#` は付かない)、それ以外は
+/// id の個数 + 1 で、その数の VarInt id が続く (`Err`)。
+pub fn read_id_set<R: io::Read + ?Sized>(reader: &mut R) -> io::Result<Result<String, Vec<i32>>> {
+    match reader.read_varint()? {
+        0 => Ok(Ok(reader.read_string()?)),
+        type_value => {
+            // 0 は上で除いたので、負でなければ 1 を引いても溢れない
+            let count = checked_len_i32(type_value, "id set length")? - 1;
+            Ok(Err((0..count)
+                .map(|_| reader.read_varint())
+                .collect::<io::Result<_>>()?))
+        }
+    }
+}
+
+/// [`read_id_set`] の逆。
+pub fn write_id_set<W: io::Write + ?Sized>(
+    writer: &mut W,
+    value: &Result<String, Vec<i32>>,
+) -> io::Result<()> {
+    match value {
+        Ok(tag_name) => {
+            writer.write_varint(0)?;
+            writer.write_string(tag_name)
+        }
+        Err(ids) => {
+            let type_value = i32::try_from(ids.len() + 1).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("id set is too long: {} ids", ids.len()),
+                )
+            })?;
+            writer.write_varint(type_value)?;
+            for &id in ids {
+                writer.write_varint(id)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Chat Type の装飾 (chat / narration の 1 組)。Chat Type のインライン定義や
+/// registry data に現れる。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatType {
+    pub translation_key: String,
+    /// 埋め込む値の種類 (0: sender, 1: target, 2: content)。
+    pub parameters: Vec<i32>,
+    /// スタイル (network NBT の compound)。
+    pub style: Nbt,
+}
+
+pub fn read_chat_type<R: io::Read + ?Sized>(reader: &mut R) -> io::Result<ChatType> {
+    let translation_key = reader.read_string()?;
+    let count = checked_len_i32(reader.read_varint()?, "chat type parameter count")?;
+    let parameters = (0..count)
+        .map(|_| reader.read_varint())
+        .collect::<io::Result<_>>()?;
+    let style = reader.read_nbt()?;
+    Ok(ChatType {
+        translation_key,
+        parameters,
+        style,
+    })
+}
+
+/// [`read_chat_type`] の逆。
+pub fn write_chat_type<W: io::Write + ?Sized>(
+    writer: &mut W,
+    chat_type: &ChatType,
+) -> io::Result<()> {
+    writer.write_string(&chat_type.translation_key)?;
+    writer.write_varint(chat_type.parameters.len() as i32)?;
+    for &parameter in &chat_type.parameters {
+        writer.write_varint(parameter)?;
+    }
+    writer.write_nbt(&chat_type.style)
+}
+
+/// Login (play) が宣言する描画距離とシミュレーション距離 (チャンク数)。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewDistances {
+    pub view_distance: i32,
+    pub simulation_distance: i32,
+}
+
+/// Login (play) の body 中で view distance が始まる位置。
+///
+/// protocol 764 (1.20.2) 以降: entity id (Int), is hardcore, ディメンション名の
+/// 配列, max players (VarInt) の後ろ。それより前は registry codec (NBT) が
+/// 間に挟まる別の構成なので扱わない。
+fn view_distance_offset(body: &[u8], protocol_version: u32) -> io::Result<usize> {
+    if protocol_version < 764 {
+        return Err(invalid_data(format!(
+            "login (play) layout of protocol {protocol_version} is not supported"
+        )));
+    }
+    let mut cursor = io::Cursor::new(body);
+    cursor.read_i32::<BigEndian>()?;
+    cursor.read_bool()?;
+    cursor.read_string_list()?;
+    cursor.read_varint()?; // max players
+    Ok(cursor.position() as usize)
+}
+
+/// Login (play) の body から描画距離とシミュレーション距離を読む。
+pub fn read_view_distances(body: &[u8], protocol_version: u32) -> io::Result<ViewDistances> {
+    let offset = view_distance_offset(body, protocol_version)?;
+    let mut cursor = io::Cursor::new(&body[offset..]);
+    Ok(ViewDistances {
+        view_distance: cursor.read_varint()?,
+        simulation_distance: cursor.read_varint()?,
+    })
+}
+
+/// Login (play) の body の描画距離とシミュレーション距離を差し替える。
+/// 他のフィールドはバイト列のまま写す。
+pub fn write_view_distances(
+    body: &[u8],
+    protocol_version: u32,
+    distances: ViewDistances,
+) -> io::Result<Vec<u8>> {
+    let offset = view_distance_offset(body, protocol_version)?;
+    let mut cursor = io::Cursor::new(&body[offset..]);
+    cursor.read_varint()?;
+    cursor.read_varint()?;
+    let rest = offset + cursor.position() as usize;
+    let mut buf = body[..offset].to_vec();
+    buf.write_varint(distances.view_distance)?;
+    buf.write_varint(distances.simulation_distance)?;
+    buf.extend_from_slice(&body[rest..]);
+    Ok(buf)
+}
+
+/// ライトデータ (Chunk Data and Update Light / Update Light) 先頭の 4 つのマスク。
+/// ビット i はチャンク最下部の 1 つ下のセクションから数えて i 番目。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LightMasks {
+    /// 空の光 (sky light) の配列が続くセクション。
+    pub sky: Vec<u64>,
+    /// ブロックの光 (block light) の配列が続くセクション。
+    pub block: Vec<u64>,
+    /// sky light がすべて 0 のセクション。
+    pub empty_sky: Vec<u64>,
+    /// block light がすべて 0 のセクション。
+    pub empty_block: Vec<u64>,
+}
+
+impl LightMasks {
+    pub fn read_from<R: io::Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+        Ok(Self {
+            sky: reader.read_bitset()?,
+            block: reader.read_bitset()?,
+            empty_sky: reader.read_bitset()?,
+            empty_block: reader.read_bitset()?,
+        })
+    }
+    pub fn write_to<W: io::Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        for mask in [&self.sky, &self.block, &self.empty_sky, &self.empty_block] {
+            writer.write_bitset(mask)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -233,6 +666,316 @@ mod tests {
         assert_eq!(parse_packet_id(""), None);
         assert_eq!(parse_packet_id("0x"), None);
     }
+
+    #[test]
+    fn read_length_prefixed_extracts_inner_bytes() {
+        // 入れ子パケット (id 0x2c + body) を長さ 4 で包み、後続に別データ
+        let mut buf = Vec::new();
+        buf.write_varint(4).unwrap();
+        buf.extend_from_slice(&[0x2c, 0x00, 0xff, 0x80]);
+        buf.extend_from_slice(&[0x99]);
+        let mut cursor = io::Cursor::new(buf.as_slice());
+        assert_eq!(
+            cursor.read_length_prefixed().unwrap(),
+            vec![0x2c, 0x00, 0xff, 0x80]
+        );
+        assert_eq!(cursor.read_u8().unwrap(), 0x99);
+
+        // 宣言長が残りより長ければエラー
+        let mut short = Vec::new();
+        short.write_varint(8).unwrap();
+        short.extend_from_slice(&[1, 2]);
+        assert!(io::Cursor::new(short).read_length_prefixed().is_err());
+    }
+
+    #[test]
+    fn read_to_end_capped_rejects_oversized_remainder() {
+        let data = vec![7u8; 2000];
+        assert!(io::Cursor::new(&data).read_to_end_capped(1000).is_err());
+        assert_eq!(
+            io::Cursor::new(&data).read_to_end_capped(2000).unwrap(),
+            data
+        );
+        assert_eq!(
+            io::Cursor::new(&data)
+                .read_to_end_capped(usize::MAX)
+                .unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn angle_degrees_roundtrip_within_quantization() {
+        let step = 360.0 / 256.0;
+        for degrees in [0.0f32, 90.0, 45.7, 359.0, -90.0, 720.0 + 10.0] {
+            let mut buf = Vec::new();
+            buf.write_angle_degrees(degrees).unwrap();
+            let read = io::Cursor::new(buf).read_angle_degrees().unwrap();
+            let diff = (read - degrees.rem_euclid(360.0)).abs();
+            // 359° は 256 段階目 = 0° に丸まる
+            assert!(diff.min(360.0 - diff) <= step / 2.0, "{degrees} -> {read}");
+        }
+        let mut buf = Vec::new();
+        buf.write_angle_degrees(90.0).unwrap();
+        assert_eq!(buf, vec![64]);
+    }
+
+    #[test]
+    fn plugin_message_brand_roundtrip() {
+        // minecraft:brand のペイロードは String ("vanilla")
+        let mut payload = Vec::new();
+        payload.write_string("vanilla").unwrap();
+        let mut body = Vec::new();
+        body.write_string("minecraft:brand").unwrap();
+        body.extend_from_slice(&payload);
+
+        let message = read_plugin_message(&body).unwrap();
+        assert_eq!(message.channel, "minecraft:brand");
+        assert_eq!(message.data, payload);
+        assert_eq!(
+            write_plugin_message(&message.channel, &message.data).unwrap(),
+            body
+        );
+        assert!(read_plugin_message(&[0x05, b'a']).is_err());
+    }
+
+    #[test]
+    fn string_list_roundtrip() {
+        let mut buf = Vec::new();
+        buf.write_string_list(&["give", "", "gamemode"]).unwrap();
+        buf.write_string_list::<&str>(&[]).unwrap();
+        let mut cursor = io::Cursor::new(buf.as_slice());
+        assert_eq!(
+            cursor.read_string_list().unwrap(),
+            vec!["give".to_string(), String::new(), "gamemode".to_string()]
+        );
+        assert!(cursor.read_string_list().unwrap().is_empty());
+
+        // 負の個数は弾く
+        let mut negative = Vec::new();
+        negative.write_varint(-1).unwrap();
+        assert!(io::Cursor::new(negative).read_string_list().is_err());
+    }
+
+    #[test]
+    fn light_masks_roundtrip() {
+        let mut bytes = Vec::new();
+        bytes.write_bitset(&[0b1011]).unwrap();
+        bytes.write_bitset(&[]).unwrap();
+        bytes.write_bitset(&[u64::MAX, 1]).unwrap();
+        bytes.write_bitset(&[0x8000_0000_0000_0000]).unwrap();
+        bytes.push(0x99); // 後続の sky light 配列
+        assert_eq!(
+            io::Cursor::new(bytes.as_slice()).read_bitsets(4).unwrap()[2],
+            vec![u64::MAX, 1]
+        );
+
+        let mut cursor = io::Cursor::new(bytes.as_slice());
+        let masks = LightMasks::read_from(&mut cursor).unwrap();
+        assert_eq!(
+            masks,
+            LightMasks {
+                sky: vec![0b1011],
+                block: vec![],
+                empty_sky: vec![u64::MAX, 1],
+                empty_block: vec![0x8000_0000_0000_0000],
+            }
+        );
+        assert_eq!(cursor.read_u8().unwrap(), 0x99);
+
+        let mut written = Vec::new();
+        masks.write_to(&mut written).unwrap();
+        assert_eq!(written, bytes[..bytes.len() - 1]);
+    }
+
+    #[test]
+    fn test_id_or_x() -> io::Result<()> {
+        // Test with ID (VarInt 5 ⇒ registry id 4)
+        let mut buffer_id: Vec<u8> = Vec::new();
+        buffer_id.write_varint(5)?;
+
+        let mut cursor_id = io::Cursor::new(buffer_id);
+        let result_id = cursor_id.read_id_or(|c| c.read_string())?;
+        assert_eq!(result_id, Ok(4)); // Adjusted for id - 1
+
+        let mut written = Vec::new();
+        written.write_id_or(&result_id, |w, x: &String| w.write_string(x))?;
+        assert_eq!(written, vec![5]);
+
+        // Test with X
+        let mut buffer_x: Vec<u8> = Vec::new();
+        let x_value: Result<i32, String> = Err("test".to_string());
+        buffer_x.write_id_or(&x_value, |w, x| w.write_string(x))?;
+
+        let mut cursor_x = io::Cursor::new(buffer_x);
+        let result_x = cursor_x.read_id_or(|c| c.read_string())?;
+        assert_eq!(result_x, Err("test".to_string()));
+
+        // VarInt i32::MIN は id - 1 が溢れるので弾く
+        let min = [0x80, 0x80, 0x80, 0x80, 0x08];
+        let err = io::Cursor::new(min)
+            .read_id_or(|c| c.read_string())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = Vec::new()
+            .write_id_or(&Ok::<_, String>(i32::MAX), |w, x| w.write_string(x))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_id_set() -> io::Result<()> {
+        // Test with Tag Name
+        let mut buffer_tag: Vec<u8> = Vec::new();
+        let tag_name: Result<String, Vec<i32>> = Ok("minecraft:blocks".to_string());
+        write_id_set(&mut buffer_tag, &tag_name)?;
+
+        let mut cursor_tag = io::Cursor::new(buffer_tag);
+        let result_tag = read_id_set(&mut cursor_tag)?;
+        assert_eq!(result_tag, Ok("minecraft:blocks".to_string()));
+
+        // Test with IDs
+        let mut buffer_ids: Vec<u8> = Vec::new();
+        let ids: Result<String, Vec<i32>> = Err(vec![1, 2, 3]);
+        write_id_set(&mut buffer_ids, &ids)?;
+
+        let mut cursor_ids = io::Cursor::new(buffer_ids);
+        let result_ids = read_id_set(&mut cursor_ids)?;
+        assert_eq!(result_ids, Err(vec![1, 2, 3]));
+
+        // 負の個数 (VarInt -1 ⇒ -2 個) は弾く
+        let mut negative = Vec::new();
+        negative.write_varint(-1)?;
+        assert!(read_id_set(&mut io::Cursor::new(negative)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn adversarial_input_is_rejected_without_panic() {
+        let varints: [&[u8]; 3] = [&[], &[0x80], &[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]];
+        for bytes in varints {
+            assert!(io::Cursor::new(bytes).read_varint().is_err(), "{bytes:?}");
+        }
+        assert!(io::Cursor::new([0xff; 11]).read_varlong().is_err());
+
+        let strings: [&[u8]; 5] = [
+            // 宣言長 i32::MAX (残り 0 バイト)
+            &[0xff, 0xff, 0xff, 0xff, 0x07],
+            // 宣言長 200 MiB (残り 3 バイト)
+            &[0x80, 0x80, 0x80, 0x64, b'a', b'b', b'c'],
+            // 負の宣言長
+            &[0xff, 0xff, 0xff, 0xff, 0x0f],
+            // 宣言長より短い
+            &[0x05, b'a'],
+            // 不正な UTF-8
+            &[0x02, 0xc3, 0x28],
+        ];
+        for bytes in strings {
+            assert!(io::Cursor::new(bytes).read_string().is_err(), "{bytes:?}");
+        }
+
+        // VarInt i32::MIN は id - 1 / 個数 - 1 が溢れる値
+        let min: &[u8] = &[0x80, 0x80, 0x80, 0x80, 0x08];
+        assert!(
+            io::Cursor::new(min)
+                .read_id_or(|c| c.read_string())
+                .is_err()
+        );
+        assert!(read_id_set(&mut io::Cursor::new(min)).is_err());
+    }
+
+    #[test]
+    fn varint_fifth_byte_is_limited_to_four_bits() {
+        let read = |bytes: &[u8]| io::Cursor::new(bytes).read_varint();
+        assert_eq!(read(&[0xff, 0xff, 0xff, 0xff, 0x0f]).unwrap(), -1);
+        assert_eq!(read(&[0xff, 0xff, 0xff, 0xff, 0x07]).unwrap(), i32::MAX);
+        assert_eq!(read(&[0x80, 0x80, 0x80, 0x80, 0x08]).unwrap(), i32::MIN);
+        for bad in [
+            [0xff, 0xff, 0xff, 0xff, 0x1f],
+            [0x80, 0x80, 0x80, 0x80, 0x70],
+            [0xff, 0xff, 0xff, 0xff, 0x8f],
+        ] {
+            let err = read(&bad).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{bad:?}");
+        }
+        let mut buf = Vec::new();
+        buf.write_varint(-1).unwrap();
+        assert_eq!(buf, [0xff, 0xff, 0xff, 0xff, 0x0f]);
+    }
+
+    #[test]
+    fn serializer_roundtrips_through_deserializer() -> io::Result<()> {
+        let uuid = uuid::Uuid::from_u128(0x0123_4567_89ab_cdef_0011_2233_4455_6677);
+        let mut buf = Vec::new();
+        buf.write_bool(true)?;
+        buf.write_bool(false)?;
+        buf.write_byte(-2)?;
+        buf.write_unsigned_byte(0xfe)?;
+        buf.write_short(-300)?;
+        buf.write_unsigned_short(0xfffe)?;
+        // byteorder の write_int / read_int と名前が重なるので明示する
+        Serializer::write_int(&mut buf, i32::MIN)?;
+        buf.write_long(-1_234_567_890_123)?;
+        buf.write_float(1.5)?;
+        buf.write_double(-0.25)?;
+        buf.write_string("こんにちは")?;
+        buf.write_varlong(i64::MIN)?;
+        buf.write_varlong(300)?;
+        buf.write_uuid(&uuid)?;
+        // 1 バイト目は big-endian の上位バイト
+        assert_eq!(&buf[4..6], &(-300i16).to_be_bytes());
+
+        let mut cursor = io::Cursor::new(buf.as_slice());
+        assert!(cursor.read_bool()?);
+        assert!(!cursor.read_bool()?);
+        assert_eq!(cursor.read_byte()?, -2);
+        assert_eq!(cursor.read_unsigned_byte()?, 0xfe);
+        assert_eq!(cursor.read_short()?, -300);
+        assert_eq!(cursor.read_unsigned_short()?, 0xfffe);
+        assert_eq!(Deserializer::read_int(&mut cursor)?, i32::MIN);
+        assert_eq!(cursor.read_long()?, -1_234_567_890_123);
+        assert_eq!(cursor.read_float()?, 1.5);
+        assert_eq!(cursor.read_double()?, -0.25);
+        assert_eq!(cursor.read_string()?, "こんにちは");
+        assert_eq!(cursor.read_varlong()?, i64::MIN);
+        assert_eq!(cursor.read_varlong()?, 300);
+        assert_eq!(cursor.read_uuid()?, uuid);
+        assert_eq!(cursor.position() as usize, buf.len());
+        Ok(())
+    }
+
+    #[test]
+    fn chat_type_roundtrips_with_style() -> io::Result<()> {
+        let chat_type = ChatType {
+            translation_key: "chat.type.announcement".to_string(),
+            parameters: vec![0, 2],
+            style: Nbt::Compound(vec![
+                ("color".to_string(), Nbt::String("gray".to_string())),
+                ("italic".to_string(), Nbt::Byte(1)),
+            ]),
+        };
+        let mut buf = Vec::new();
+        write_chat_type(&mut buf, &chat_type)?;
+        let mut cursor = io::Cursor::new(buf.as_slice());
+        assert_eq!(read_chat_type(&mut cursor)?, chat_type);
+        assert_eq!(cursor.position() as usize, buf.len());
+        Ok(())
+    }
+
+    #[test]
+    fn write_length_prefixed_roundtrips_binary() {
+        let blob: Vec<u8> = (0..=255).chain([0, 0, 0]).collect();
+        let mut buf = Vec::new();
+        buf.write_length_prefixed(&blob).unwrap();
+        buf.write_length_prefixed(&[]).unwrap();
+        assert_eq!(buf.len(), varint_len(blob.len() as i32) + blob.len() + 1);
+        let mut cursor = io::Cursor::new(buf.as_slice());
+        assert_eq!(cursor.read_length_prefixed().unwrap(), blob);
+        assert_eq!(cursor.read_length_prefixed().unwrap(), Vec::<u8>::new());
+    }
 }
 
 /*
@@ -535,70 +1278,6 @@ where
     Ok(())
 }
 
-// ID or X
-
-pub fn read_id_or_x<T, F>(cursor: &mut Cursor<&[u8]>, read_x: F) -> io::Result<Result<i32, T>>
-where
-    F: Fn(&mut Cursor<&[u8]>) -> io::Result<T>,
-{
-    let id = read_varint(cursor)?;
-    if id == 0 {
-        let x = read_x(cursor)?;
-        Ok(Result::Err(x))
-    } else {
-        Ok(Result::Ok(id - 1))
-    }
-}
-
-pub fn write_id_or_x<T, F, W: Write>(writer: &mut W, value: &Result<i32, T>, write_x: F) -> io::Result<()>
-where
-    F: Fn(&mut W, &T) -> io::Result<()>,
-{
-    match value {
-        Result::Ok(id) => {
-            write_varint(writer, id + 1)?;
-        }
-        Result::Err(x) => {
-            write_varint(writer, 0)?;
-            write_x(writer, x)?;
-        }
-    }
-    Ok(())
-}
-
-//ID Set
-pub fn read_id_set(cursor: &mut Cursor<&[u8]>) -> io::Result<Result<String, Vec<i32>>> {
-    let type_value = read_varint(cursor)?;
-
-    if type_value == 0 {
-        let tag_name = read_string(cursor)?;
-        Ok(Result::Ok(tag_name))
-    } else {
-        let length = type_value - 1;
-        let mut ids = Vec::with_capacity(length as usize);
-        for _ in 0..length {
-            ids.push(read_varint(cursor)?);
-        }
-        Ok(Result::Err(ids))
-    }
-}
-
-pub fn write_id_set<W: Write>(writer: &mut W, value: &Result<String, Vec<i32>>) -> io::Result<()> {
-    match value {
-        Result::Ok(tag_name) => {
-            write_varint(writer, 0)?;
-            write_string(writer, tag_name)?;
-        }
-        Result::Err(ids) => {
-            write_varint(writer, (ids.len() + 1) as i32)?;
-            for &id in ids {
-                write_varint(writer, id)?;
-            }
-        }
-    }
-    Ok(())
-}
-
 // Sound Event
 #[derive(Debug, PartialEq)]
 pub struct SoundEvent {
@@ -634,36 +1313,6 @@ pub fn write_sound_event<W: Write>(writer: &mut W, sound_event: &SoundEvent) ->
     Ok(())
 }
 
-// Chat Type (Example Structure)
-#[derive(Debug, PartialEq)]
-pub struct ChatType {
-    // The actual structure depends on the specific Minecraft version
-    // This is a simplified example
-    pub translation_key: String,
-    pub parameters: Vec<i32>,
-    //  pub style: NBT // NBT needs a more complex implementation
-}
-
-pub fn read_chat_type(cursor: &mut Cursor<&[u8]>) -> io::Result<ChatType> {
-    let translation_key = read_string(cursor)?;
-    let parameters = read_prefixed_array(cursor, |c| read_varint(c))?;
-    //let style = read_nbt(cursor)?; // NBT implementation is needed
-
-    Ok(ChatType {
-        translation_key,
-        parameters,
-        //style,
-    })
-}
-
-pub fn write_chat_type<W: Write>(writer: &mut W, chat_type: &ChatType) -> io::Result<()> {
-    write_string(writer, &chat_type.translation_key)?;
-    write_prefixed_array(writer, &chat_type.parameters, |w, &p| write_varint(w, p))?;
-    //write_nbt(writer, &chat_type.style)?; // NBT implementation is needed
-
-    Ok(())
-}
-
 // Teleport Flags
 pub fn read_teleport_flags(cursor: &mut Cursor<&[u8]>) -> io::Result<i32> {
     read_int(cursor)
@@ -736,42 +1385,6 @@ pub fn validate_identifier(identifier: &str) -> Result<(), String> {
 }
 
 
-// NBT
-// This requires a separate crate and implementation.  A basic stub is below.
-// You'll need to add `nbt = "0.4"` to your Cargo.toml.
-
-#[cfg(feature = "nbt")]
-pub mod nbt_impl {
-    use std::io::{self, Cursor};
-    use nbt::Blob;
-
-    pub fn read_nbt(cursor: &mut Cursor<&[u8]>) -> io::Result<Blob> {
-        nbt::from_reader(cursor).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-    }
-
-    pub fn write_nbt<W: std::io::Write>(writer: &mut W, blob: &Blob) -> io::Result<()> {
-        nbt::to_writer(writer, blob, None).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-    }
-}
-
-#[cfg(not(feature = "nbt"))]
-pub mod nbt_impl {
-    use std::io::{self, Cursor};
-
-    // Placeholder NBT structure.  Replace with actual NBT parsing.
-    #[derive(Debug, PartialEq)]
-    pub struct Blob {}
-
-    pub fn read_nbt(cursor: &mut Cursor<&[u8]>) -> io::Result<Blob> {
-        Err(io::Error::new(io::ErrorKind::Other, "NBT feature not enabled"))
-    }
-
-    pub fn write_nbt<W: std::io::Write>(writer: &mut W, _blob: &Blob) -> io::Result<()> {
-        Err(io::Error::new(io::ErrorKind::Other, "NBT feature not enabled"))
-    }
-}
-
-pub use nbt_impl::*;
 
 
 // Example usage:
@@ -885,52 +1498,6 @@ mod tests {
         Ok(())
     }
 
-    #[test]
-    fn test_id_or_x() -> io::Result<()> {
-        // Test with ID
-        let mut buffer_id: Vec<u8> = Vec::new();
-        let id_value: Result<i32, String> = Result::Ok(5);
-        write_id_or_x(&mut buffer_id, &id_value, |w, x| write_string(w, x))?;
-
-        let mut cursor_id = Cursor::new(buffer_id);
-        let result_id = read_id_or_x(&mut cursor_id, |c| read_string(c))?;
-        assert_eq!(result_id, Result::Ok(4)); // Adjusted for id - 1
-
-        // Test with X
-        let mut buffer_x: Vec<u8> = Vec::new();
-        let x_value: Result<i32, String> = Result::Err("test".to_string());
-        write_id_or_x(&mut buffer_x, &x_value, |w, x| write_string(w, x))?;
-
-        let mut cursor_x = Cursor::new(buffer_x);
-        let result_x = read_id_or_x(&mut cursor_x, |c| read_string(c))?;
-        assert_eq!(result_x, Result::Err("test".to_string()));
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_id_set() -> io::Result<()> {
-        // Test with Tag Name
-        let mut buffer_tag: Vec<u8> = Vec::new();
-        let tag_name: Result<String, Vec<i32>> = Result::Ok("minecraft:blocks".to_string());
-        write_id_set(&mut buffer_tag, &tag_name)?;
-
-        let mut cursor_tag = Cursor::new(buffer_tag);
-        let result_tag = read_id_set(&mut cursor_tag)?;
-        assert_eq!(result_tag, Result::Ok("minecraft:blocks".to_string()));
-
-        // Test with IDs
-        let mut buffer_ids: Vec<u8> = Vec::new();
-        let ids: Result<String, Vec<i32>> = Result::Err(vec![1, 2, 3]);
-        write_id_set(&mut buffer_ids, &ids)?;
-
-        let mut cursor_ids = Cursor::new(buffer_ids);
-        let result_ids = read_id_set(&mut cursor_ids)?;
-        assert_eq!(result_ids, Result::Err(vec![1, 2, 3]));
-
-        Ok(())
-    }
-
     #[test]
     fn test_sound_event() -> io::Result<()> {
         let mut buffer: Vec<u8> = Vec::new();
@@ -960,25 +1527,6 @@ mod tests {
         Ok(())
     }
 
-    #[test]
-    fn test_chat_type() -> io::Result<()> {
-        let mut buffer: Vec<u8> = Vec::new();
-        let chat_type = ChatType {
-            translation_key: "chat.type.announcement".to_string(),
-            parameters: vec![1, 2, 3],
-            //style: Blob::new(), // Requires proper NBT setup
-        };
-        write_chat_type(&mut buffer, &chat_type)?;
-
-        let mut cursor = Cursor::new(buffer);
-        let result = read_chat_type(&mut cursor)?;
-        assert_eq!(result.translation_key, chat_type.translation_key);
-        assert_eq!(result.parameters, chat_type.parameters);
-        //assert_eq!(result.style, chat_type.style);
-
-        Ok(())
-    }
-
     #[test]
     fn test_teleport_flags() -> io::Result<()> {
         let mut buffer: Vec<u8> = Vec::new();
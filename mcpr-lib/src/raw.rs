@@ -0,0 +1,205 @@
+//! サーバー↔クライアント間の生の通信 (clientbound) をキャプチャしたストリームの読み出し。
+//!
+//! .tmcpr と異なり時刻を持たず、フレームは `VarInt 長さ` + `VarInt id` + body。
+//! Login 中の Set Compression 以降は圧縮フレーム
+//! (`VarInt 長さ` + `VarInt 展開後の長さ` + zlib 圧縮された `id` + body、
+//! 展開後の長さ 0 は閾値未満で非圧縮) に切り替わる。zlib の展開には
+//! `gzip` feature が要る。
+
+use std::io::{self, Cursor, Read};
+
+use crate::{
+    event::State,
+    mcpr::Packet,
+    protocol::{Deserializer, SET_COMPRESSION_PACKET_ID, checked_len_i32, read_exact_vec},
+};
+
+/// 1 パケットの上限 (プロトコル上のパケット長上限 2^21)。
+const MAX_PACKET_LEN: usize = 1 << 21;
+
+/// 生キャプチャを (state, パケット) の列として読む。時刻は全て 0。
+pub struct RawPacketReader<R> {
+    reader: R,
+    state: State,
+    /// Set Compression で通知された閾値。None なら非圧縮フレーム。
+    compression_threshold: Option<i32>,
+    /// Iterator がエラーを返した後か (以降は None を返す)。
+    failed: bool,
+}
+
+impl<R: Read> RawPacketReader<R> {
+    /// Login phase の先頭から始まるキャプチャを読む。
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            state: State::Login,
+            compression_threshold: None,
+            failed: false,
+        }
+    }
+    /// Set Compression を読んでいれば、その閾値。
+    pub fn compression_threshold(&self) -> Option<i32> {
+        self.compression_threshold
+    }
+
+    /// 次のパケット。ストリーム終端 (長さの先頭で EOF) なら `Ok(None)`。
+    /// 長さの VarInt の途中で切れていれば `UnexpectedEof`。
+    pub fn next_packet(&mut self) -> io::Result<Option<(State, Packet)>> {
+        let mut first = [0u8; 1];
+        loop {
+            match self.reader.read(&mut first) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let length = first.as_slice().chain(&mut self.reader).read_varint()?;
+        let length = checked_len_i32(length, "packet length")?;
+        if length > MAX_PACKET_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("packet length {length} exceeds {MAX_PACKET_LEN}"),
+            ));
+        }
+        let frame = read_exact_vec(&mut self.reader, length, "packet")?;
+        let payload = match self.compression_threshold {
+            None => frame,
+            Some(_) => decompress(&frame)?,
+        };
+
+        let mut cursor = Cursor::new(payload.as_slice());
+        let id = cursor.read_varint()?;
+        let data: Box<[u8]> = payload[cursor.position() as usize..].into();
+        let state = self.state;
+        if state == State::Login && id == SET_COMPRESSION_PACKET_ID {
+            let threshold = Cursor::new(&data[..]).read_varint()?;
+            // 負の閾値は圧縮の無効化
+            self.compression_threshold = (threshold >= 0).then_some(threshold);
+        }
+        self.state = state.advance(id);
+        Ok(Some((state, Packet::new(0, id, data))))
+    }
+}
+
+impl<R: Read> Iterator for RawPacketReader<R> {
+    type Item = io::Result<(State, Packet)>;
+    /// エラーを返したら以降は None (壊れた位置から読み続けない)。
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let next = self.next_packet().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+/// 圧縮フレームの中身 (`VarInt 展開後の長さ` + データ) から `id` + body を取り出す。
+fn decompress(frame: &[u8]) -> io::Result<Vec<u8>> {
+    let mut cursor = Cursor::new(frame);
+    let uncompressed_len = checked_len_i32(cursor.read_varint()?, "uncompressed length")?;
+    let rest = &frame[cursor.position() as usize..];
+    if uncompressed_len == 0 {
+        return Ok(rest.to_vec());
+    }
+    if uncompressed_len > MAX_PACKET_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("uncompressed length {uncompressed_len} exceeds {MAX_PACKET_LEN}"),
+        ));
+    }
+    #[cfg(feature = "gzip")]
+    {
+        let mut decoder = flate2::read::ZlibDecoder::new(rest);
+        let payload = read_exact_vec(&mut decoder, uncompressed_len, "compressed packet")?;
+        Ok(payload)
+    }
+    #[cfg(not(feature = "gzip"))]
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "compressed packets require the `gzip` feature",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{LOGIN_SUCCESS_PACKET_ID, Serializer};
+
+    fn frame(out: &mut Vec<u8>, body: &[u8]) {
+        out.write_varint(body.len() as i32).unwrap();
+        out.extend_from_slice(body);
+    }
+
+    /// Set Compression (閾値 256) までの非圧縮フレーム。
+    fn capture_head() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut set_compression = vec![SET_COMPRESSION_PACKET_ID as u8];
+        set_compression.write_varint(256).unwrap();
+        frame(&mut bytes, &set_compression);
+        bytes
+    }
+
+    #[test]
+    fn switches_to_compressed_framing_after_set_compression() {
+        let mut bytes = capture_head();
+        // 閾値未満: 展開後の長さ 0 + 非圧縮の id + body
+        frame(
+            &mut bytes,
+            &[0x00, LOGIN_SUCCESS_PACKET_ID as u8, 0xaa, 0xbb],
+        );
+
+        let mut reader = RawPacketReader::new(bytes.as_slice());
+        let (state, packet) = reader.next_packet().unwrap().unwrap();
+        assert_eq!(
+            (state, packet.id()),
+            (State::Login, SET_COMPRESSION_PACKET_ID)
+        );
+        assert_eq!(reader.compression_threshold(), Some(256));
+
+        let (state, packet) = reader.next_packet().unwrap().unwrap();
+        assert_eq!(state, State::Login);
+        assert_eq!(packet.id(), LOGIN_SUCCESS_PACKET_ID);
+        assert_eq!(packet.data(), &[0xaa, 0xbb]);
+        assert!(reader.next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_length_is_eof_error_and_iteration_stops() {
+        let mut bytes = capture_head();
+        // 継続ビットの立った長さの 1 バイト目で切れている
+        bytes.push(0x80);
+
+        let mut reader = RawPacketReader::new(bytes.as_slice());
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(reader.next().is_none());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decompresses_zlib_packets() {
+        use std::io::Write;
+
+        let mut payload = vec![0x2c];
+        payload.extend_from_slice(&[7; 300]);
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&payload).unwrap();
+        let mut compressed = Vec::new();
+        compressed.write_varint(payload.len() as i32).unwrap();
+        compressed.extend(encoder.finish().unwrap());
+
+        let mut bytes = capture_head();
+        frame(&mut bytes, &compressed);
+        let packets: Vec<_> = RawPacketReader::new(bytes.as_slice())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(packets[1].1.id(), 0x2c);
+        assert_eq!(packets[1].1.data(), &[7; 300]);
+    }
+}
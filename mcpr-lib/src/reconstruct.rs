@@ -0,0 +1,190 @@
+//! ある時刻の時点で「効いている」状態パケットの再構成
+//! (サムネイル描画や分析用)。
+//!
+//! 対象と、最新の 1 件を決めるキー (パケット id は 1.21.5 / protocol 770 の値):
+//!
+//! - LevelChunkWithLight: チャンク座標 (x, z) ごとに最新の 1 件。
+//!   ForgetLevelChunk で該当座標を捨て、Respawn で全チャンクを捨てる
+//!   (クライアントがディメンション移動時に全チャンクを破棄するのに合わせる)。
+//! - Login (play) / Respawn: それぞれ最新の 1 件。
+//! - Set Time: 最新の 1 件。
+//! - Game Event の天候 (雨の開始/終了, 雨の強さ, 雷の強さ): 種類ごとに最新の 1 件。
+//! - Player Info Update / Remove: 一覧は差分の積み重ねなので全件を順に残す。
+//!
+//! それ以外のパケットは状態として扱わず捨てる。
+
+use std::{
+    collections::HashMap,
+    io::{self, Cursor},
+};
+
+use crate::{
+    event::State,
+    mcpr::Packet,
+    players::{PLAYER_INFO_REMOVE_PACKET_ID, PLAYER_INFO_UPDATE_PACKET_ID},
+    protocol::{
+        Deserializer, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID, LOGIN_PLAY_PACKET_ID, RESPAWN_PACKET_ID,
+    },
+};
+
+pub const FORGET_LEVEL_CHUNK_PACKET_ID: i32 = 0x21;
+pub const GAME_EVENT_PACKET_ID: i32 = 0x22;
+pub const SET_TIME_PACKET_ID: i32 = 0x6a;
+
+/// Game Event の種類 (begin raining / end raining は同じ「雨」の状態)。
+const BEGIN_RAINING: u8 = 1;
+const END_RAINING: u8 = 2;
+const RAIN_LEVEL_CHANGE: u8 = 7;
+const THUNDER_LEVEL_CHANGE: u8 = 8;
+
+/// 「最新の 1 件」を決めるキー。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Chunk(i32, i32),
+    Login,
+    Respawn,
+    Time,
+    Weather(u8),
+}
+
+/// `time` (ms, 含む) までの Play パケットを順に適用し、その時点で効いている
+/// 状態パケットだけを元の順序で返す。対象は[モジュールの説明](self)を参照。
+/// 入力は時刻順が前提 (`time` を超えたところで読むのをやめる)。
+pub fn state_at(
+    packets: impl IntoIterator<Item = (State, Packet)>,
+    time: u32,
+) -> io::Result<Vec<Packet>> {
+    let mut kept: Vec<Option<Packet>> = Vec::new();
+    let mut latest: HashMap<Key, usize> = HashMap::new();
+    for (state, packet) in packets {
+        if packet.time() > time {
+            break;
+        }
+        if state != State::Play {
+            continue;
+        }
+        let mut cursor = Cursor::new(packet.data());
+        let key = match packet.id() {
+            LEVEL_CHUNK_WITH_LIGHT_PACKET_ID => {
+                Some(Key::Chunk(cursor.read_int()?, cursor.read_int()?))
+            }
+            FORGET_LEVEL_CHUNK_PACKET_ID => {
+                // クライアントは 1 つの Long として読むので z, x の順
+                let z = cursor.read_int()?;
+                let x = cursor.read_int()?;
+                if let Some(index) = latest.remove(&Key::Chunk(x, z)) {
+                    kept[index] = None;
+                }
+                continue;
+            }
+            LOGIN_PLAY_PACKET_ID => Some(Key::Login),
+            RESPAWN_PACKET_ID => {
+                latest.retain(|key, index| {
+                    let chunk = matches!(key, Key::Chunk(..));
+                    if chunk {
+                        kept[*index] = None;
+                    }
+                    !chunk
+                });
+                Some(Key::Respawn)
+            }
+            SET_TIME_PACKET_ID => Some(Key::Time),
+            GAME_EVENT_PACKET_ID => match cursor.read_unsigned_byte()? {
+                BEGIN_RAINING | END_RAINING => Some(Key::Weather(BEGIN_RAINING)),
+                event @ (RAIN_LEVEL_CHANGE | THUNDER_LEVEL_CHANGE) => Some(Key::Weather(event)),
+                _ => continue,
+            },
+            PLAYER_INFO_UPDATE_PACKET_ID | PLAYER_INFO_REMOVE_PACKET_ID => None,
+            _ => continue,
+        };
+        if let Some(key) = key
+            && let Some(index) = latest.insert(key, kept.len())
+        {
+            kept[index] = None;
+        }
+        kept.push(Some(packet));
+    }
+    Ok(kept.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(time: u32, x: i32, z: i32, marker: u8) -> (State, Packet) {
+        let mut data = Vec::new();
+        data.extend_from_slice(&x.to_be_bytes());
+        data.extend_from_slice(&z.to_be_bytes());
+        data.push(marker);
+        (
+            State::Play,
+            Packet::new(time, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID, data.into()),
+        )
+    }
+
+    fn forget(time: u32, x: i32, z: i32) -> (State, Packet) {
+        let mut data = Vec::new();
+        data.extend_from_slice(&z.to_be_bytes());
+        data.extend_from_slice(&x.to_be_bytes());
+        (
+            State::Play,
+            Packet::new(time, FORGET_LEVEL_CHUNK_PACKET_ID, data.into()),
+        )
+    }
+
+    fn play(time: u32, id: i32, data: &[u8]) -> (State, Packet) {
+        (State::Play, Packet::new(time, id, data.into()))
+    }
+
+    fn summary(packets: &[Packet]) -> Vec<(u32, i32)> {
+        packets.iter().map(|p| (p.time(), p.id())).collect()
+    }
+
+    #[test]
+    fn later_chunk_at_same_coords_supersedes() {
+        let packets = vec![
+            chunk(0, 1, 2, 0xa),
+            chunk(10, 3, 4, 0xb),
+            chunk(20, 1, 2, 0xc),
+            // 指定時刻より後は適用しない
+            chunk(200, 3, 4, 0xd),
+        ];
+        let state = state_at(packets, 100).unwrap();
+        let markers: Vec<_> = state.iter().map(|p| p.data()[8]).collect();
+        assert_eq!(markers, vec![0xb, 0xc]);
+    }
+
+    #[test]
+    fn forget_and_respawn_drop_chunks() {
+        let packets = vec![
+            play(0, LOGIN_PLAY_PACKET_ID, &[]),
+            chunk(10, 0, 0, 0),
+            chunk(10, 5, -5, 0),
+            forget(20, 5, -5),
+            play(30, SET_TIME_PACKET_ID, &[]),
+            play(40, RESPAWN_PACKET_ID, &[]),
+            chunk(50, 9, 9, 0),
+            play(60, SET_TIME_PACKET_ID, &[]),
+            play(60, GAME_EVENT_PACKET_ID, &[BEGIN_RAINING, 0, 0, 0, 0]),
+            play(70, GAME_EVENT_PACKET_ID, &[END_RAINING, 0, 0, 0, 0]),
+            // 状態ではない Game Event は捨てる
+            play(70, GAME_EVENT_PACKET_ID, &[3, 0, 0, 0, 0]),
+            play(80, PLAYER_INFO_UPDATE_PACKET_ID, &[]),
+            play(90, PLAYER_INFO_UPDATE_PACKET_ID, &[]),
+            play(95, 0x2c, &[]),
+        ];
+        let state = state_at(packets, 100).unwrap();
+        assert_eq!(
+            summary(&state),
+            vec![
+                (0, LOGIN_PLAY_PACKET_ID),
+                (40, RESPAWN_PACKET_ID),
+                (50, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID),
+                (60, SET_TIME_PACKET_ID),
+                (70, GAME_EVENT_PACKET_ID),
+                (80, PLAYER_INFO_UPDATE_PACKET_ID),
+                (90, PLAYER_INFO_UPDATE_PACKET_ID),
+            ]
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! JSON で書いた編集手順 (スクリプト) を .mcpr に順に適用する。
+//!
+//! スクリプトは [`Edit`] の配列で、例えば次のように書く:
+//!
+//! ```json
+//! [
+//!   {"op": "drop_ids", "ids": [44, 92]},
+//!   {"op": "time_shift", "ms": -5000},
+//!   {"op": "anonymize"}
+//! ]
+//! ```
+//!
+//! 時刻・id の絞り込みは Play state のパケットだけが対象で、
+//! Login / Configuration のパケットは残す ([`crate::stream::ReplayStream`]
+//! のフィルタと同じ扱い)。時刻で切る手順は Login (play) も
+//! [`HandshakePolicy`] の既定に従って残す。
+
+use std::io::Read;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::{ArchiveReader, ArchiveWriter},
+    event::{Event, EventSource, HandshakePolicy, ReplayInfo, State, Time},
+    mcpr::{Packet, ReplayReader, ReplayWriter},
+    stream::ReplayStream,
+};
+
+/// スクリプトの 1 手順。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Edit {
+    /// 全パケットの時刻を `ms` ずらす。0 未満になる時刻は 0 に詰める。
+    TimeShift { ms: i64 },
+    /// これらの id の Play パケットを落とす。
+    DropIds { ids: Vec<i32> },
+    /// `start..=end` (ms) の外の Play パケットを落とし、`start` を時刻 0 に詰める。
+    /// 省略した側は無制限。[`ReplayStream::time_range`] と同じ扱いで、範囲外の
+    /// 接続初期化は時刻 0 で残す。
+    TimeRange {
+        #[serde(default)]
+        start: Option<u32>,
+        #[serde(default)]
+        end: Option<u32>,
+    },
+    /// `start..end` (ms) の Play パケットを切り取り、後ろを `end - start` だけ詰める。
+    /// 区間内の接続初期化は残し、時刻を `start` にする。
+    CutSegment { start: u32, end: u32 },
+    /// metadata からサーバー名とプレイヤー一覧を消す。
+    Anonymize,
+}
+
+/// JSON のスクリプト ([`Edit`] の配列) を読む。
+pub fn load<R: Read>(reader: R) -> anyhow::Result<Vec<Edit>> {
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// `edits` を先頭から順に適用して `reader` のリプレイを `writer` へ書く。
+///
+/// recording は全件メモリに読み込んでから編集する。metadata の `duration` は
+/// 編集後の最後のパケットの時刻にする。時刻が変わると visibility の
+/// キーフレームが合わなくなるため、thumb だけを引き継ぐ。
+pub fn apply<R: ArchiveReader, W: ArchiveWriter>(
+    reader: &mut ReplayReader<R>,
+    writer: &mut ReplayWriter<W>,
+    edits: &[Edit],
+) -> anyhow::Result<()> {
+    let mut metadata = reader.read_metadata()?;
+    let mut packets: Vec<(State, Packet)> = reader.get_packet_reader()?.collect();
+    for edit in edits {
+        match edit {
+            Edit::TimeShift { ms } => {
+                for (_, packet) in &mut packets {
+                    let time = (packet.time() as i64 + ms).clamp(0, u32::MAX as i64);
+                    *packet.time_mut() = time as u32;
+                }
+            }
+            Edit::DropIds { ids } => {
+                packets
+                    .retain(|(state, packet)| *state != State::Play || !ids.contains(&packet.id()));
+            }
+            Edit::TimeRange { start, end } => {
+                let mut stream = ReplayStream::new();
+                stream.time_range(*start, *end);
+                packets = restream(&mut stream, ReplayInfo::from(&metadata), packets)?;
+            }
+            Edit::CutSegment { start, end } => {
+                anyhow::ensure!(start <= end, "cut_segment: start {start} > end {end}");
+                let policy = HandshakePolicy::default();
+                packets.retain(|(state, packet)| {
+                    !(*start..*end).contains(&packet.time()) || policy.keeps(*state, packet.id())
+                });
+                for (_, packet) in &mut packets {
+                    if packet.time() >= *end {
+                        *packet.time_mut() -= end - start;
+                    } else if packet.time() > *start {
+                        *packet.time_mut() = *start;
+                    }
+                }
+            }
+            Edit::Anonymize => {
+                metadata.serverName.clear();
+                metadata.customServerName.clear();
+                metadata.players.clear();
+            }
+        }
+    }
+
+    metadata.duration = packets.last().map_or(0, |(_, packet)| packet.time() as u64);
+    writer.write_metadata(metadata)?;
+    if let Some(thumbnail) = reader.read_thumbnail()? {
+        writer.write_thumbnail(&thumbnail)?;
+    }
+    let mut out = writer.get_packet_writer()?;
+    for (_, packet) in packets {
+        out.push(packet)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// `packets` を `stream` に通し、残ったパケットを集め直す。
+fn restream(
+    stream: &mut ReplayStream,
+    info: ReplayInfo,
+    packets: Vec<(State, Packet)>,
+) -> anyhow::Result<Vec<(State, Packet)>> {
+    let mut source = PacketSource {
+        info,
+        packets: packets.into_iter(),
+    };
+    let mut kept = Vec::new();
+    stream.stream(&mut source, |event| {
+        if let Event::Packet {
+            time,
+            state,
+            id,
+            data,
+        } = event
+        {
+            kept.push((state, Packet::new(time.as_millis() as u32, id, data)));
+        }
+        Ok(())
+    })?;
+    Ok(kept)
+}
+
+/// 読み込み済みのパケット列を流す EventSource。
+struct PacketSource {
+    info: ReplayInfo,
+    packets: std::vec::IntoIter<(State, Packet)>,
+}
+
+impl EventSource for PacketSource {
+    fn info(&self) -> &ReplayInfo {
+        &self.info
+    }
+    fn next_event(&mut self) -> anyhow::Result<Option<Event>> {
+        Ok(self.packets.next().map(|(state, packet)| Event::Packet {
+            time: Time::from_millis(u64::from(packet.time())),
+            state,
+            id: packet.id(),
+            data: packet.data().into(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        archive::testing::MemArchive,
+        mcpr::MetaData,
+        protocol::{FINISH_CONFIGURATION_PACKET_ID, LOGIN_PLAY_PACKET_ID, LOGIN_SUCCESS_PACKET_ID},
+    };
+
+    fn replay(packets: &[Packet]) -> ReplayReader<MemArchive> {
+        let mut writer = ReplayWriter::new(MemArchive::default());
+        writer
+            .write_metadata(MetaData {
+                serverName: "example.com".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        {
+            let mut out = writer.get_packet_writer().unwrap();
+            for packet in packets {
+                out.push(packet.clone()).unwrap();
+            }
+            out.flush().unwrap();
+        }
+        ReplayReader::new(writer.into_inner())
+    }
+
+    #[test]
+    fn edits_apply_in_order() {
+        let mut reader = replay(&[
+            Packet::new(0, LOGIN_SUCCESS_PACKET_ID, Box::new([])),
+            Packet::new(0, FINISH_CONFIGURATION_PACKET_ID, Box::new([])),
+            Packet::new(1000, 0x2c, Box::new([])),
+            Packet::new(2000, 0x6a, Box::new([])),
+            Packet::new(3000, 0x2c, Box::new([])),
+        ]);
+        let edits = load(
+            r#"[{"op": "drop_ids", "ids": [44]}, {"op": "time_shift", "ms": -1500}]"#.as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            edits,
+            vec![
+                Edit::DropIds { ids: vec![0x2c] },
+                Edit::TimeShift { ms: -1500 }
+            ]
+        );
+
+        let mut writer = ReplayWriter::new(MemArchive::default());
+        apply(&mut reader, &mut writer, &edits).unwrap();
+        let mut written = ReplayReader::new(writer.into_inner());
+        let packets: Vec<_> = written
+            .get_packet_reader()
+            .unwrap()
+            .map(|(_, packet)| (packet.time(), packet.id()))
+            .collect();
+        assert_eq!(
+            packets,
+            vec![
+                (0, LOGIN_SUCCESS_PACKET_ID),
+                (0, FINISH_CONFIGURATION_PACKET_ID),
+                (500, 0x6a),
+            ]
+        );
+        let metadata = written.read_metadata().unwrap();
+        assert_eq!(metadata.duration, 500);
+        assert_eq!(metadata.serverName, "example.com");
+    }
+
+    #[test]
+    fn cut_segment_closes_the_gap() {
+        let mut reader = replay(&[
+            Packet::new(0, LOGIN_SUCCESS_PACKET_ID, Box::new([])),
+            Packet::new(0, FINISH_CONFIGURATION_PACKET_ID, Box::new([])),
+            Packet::new(100, 0x2c, Box::new([])),
+            Packet::new(200, 0x2c, Box::new([])),
+            Packet::new(400, 0x2c, Box::new([])),
+        ]);
+        let mut writer = ReplayWriter::new(MemArchive::default());
+        apply(
+            &mut reader,
+            &mut writer,
+            &[Edit::CutSegment {
+                start: 150,
+                end: 300,
+            }],
+        )
+        .unwrap();
+        let times: Vec<_> = ReplayReader::new(writer.into_inner())
+            .get_packet_reader()
+            .unwrap()
+            .map(|(_, packet)| packet.time())
+            .collect();
+        assert_eq!(times, vec![0, 0, 100, 250]);
+    }
+
+    #[test]
+    fn time_edits_keep_login_play() {
+        let packets = [
+            Packet::new(0, LOGIN_SUCCESS_PACKET_ID, Box::new([])),
+            Packet::new(0, FINISH_CONFIGURATION_PACKET_ID, Box::new([])),
+            Packet::new(10, LOGIN_PLAY_PACKET_ID, Box::new([])),
+            Packet::new(100, 0x2c, Box::new([])),
+            Packet::new(400, 0x2c, Box::new([])),
+        ];
+        let edited = |edit: Edit| {
+            let mut writer = ReplayWriter::new(MemArchive::default());
+            apply(&mut replay(&packets), &mut writer, &[edit]).unwrap();
+            ReplayReader::new(writer.into_inner())
+                .get_packet_reader()
+                .unwrap()
+                .map(|(_, packet)| (packet.time(), packet.id()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            edited(Edit::TimeRange {
+                start: Some(300),
+                end: None,
+            }),
+            vec![
+                (0, LOGIN_SUCCESS_PACKET_ID),
+                (0, FINISH_CONFIGURATION_PACKET_ID),
+                (0, LOGIN_PLAY_PACKET_ID),
+                (100, 0x2c),
+            ]
+        );
+        assert_eq!(
+            edited(Edit::CutSegment { start: 5, end: 200 }),
+            vec![
+                (0, LOGIN_SUCCESS_PACKET_ID),
+                (0, FINISH_CONFIGURATION_PACKET_ID),
+                (5, LOGIN_PLAY_PACKET_ID),
+                (205, 0x2c),
+            ]
+        );
+    }
+}
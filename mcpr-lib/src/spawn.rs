@@ -0,0 +1,77 @@
+//! 最初のスポーン位置 (マーカーの初期位置などに使う)。
+//!
+//! Play に入って最初の Synchronize Player Position (自分への teleport) の
+//! 座標と向きを読む。パケット id は [`crate::path`] と同じ 1.21.5 (protocol 770) の値。
+
+use std::io;
+
+use crate::{
+    event::State,
+    mcpr::Packet,
+    path::{PLAYER_POSITION_PACKET_ID, PlayerPosition, read_player_position},
+};
+
+/// スポーン位置 (x, y, z, yaw, pitch)。
+pub type SpawnPosition = (f64, f64, f64, f32, f32);
+
+/// 最初の Synchronize Player Position の位置と向き。無ければ None。
+///
+/// 最初の teleport には基準となる位置が無いので、相対フラグは見ずに
+/// 値をそのまま絶対座標として返す。
+pub fn initial_position(
+    packets: impl IntoIterator<Item = (State, Packet)>,
+) -> io::Result<Option<SpawnPosition>> {
+    let Some((_, packet)) = packets
+        .into_iter()
+        .find(|(state, packet)| *state == State::Play && packet.id() == PLAYER_POSITION_PACKET_ID)
+    else {
+        return Ok(None);
+    };
+    let PlayerPosition {
+        position: (x, y, z),
+        yaw,
+        pitch,
+        ..
+    } = read_player_position(packet.data())?;
+    Ok(Some((x, y, z, yaw, pitch)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Serializer;
+
+    fn teleport(time: u32, pos: [f64; 3], yaw: f32, pitch: f32) -> (State, Packet) {
+        let mut data = Vec::new();
+        data.write_varint(1).unwrap();
+        for v in pos.into_iter().chain([0.0; 3]) {
+            data.write_double(v).unwrap();
+        }
+        data.write_float(yaw).unwrap();
+        data.write_float(pitch).unwrap();
+        Serializer::write_int(&mut data, 0).unwrap();
+        (
+            State::Play,
+            Packet::new(time, PLAYER_POSITION_PACKET_ID, data.into()),
+        )
+    }
+
+    #[test]
+    fn first_teleport_is_the_spawn() {
+        let packets = vec![
+            // Play 以外の同じ id は無視
+            (
+                State::Configuration,
+                Packet::new(0, PLAYER_POSITION_PACKET_ID, Box::new([])),
+            ),
+            (State::Play, Packet::new(0, 0x2c, Box::new([]))),
+            teleport(50, [8.5, 64.0, -3.5], 90.0, 10.0),
+            teleport(900, [100.0, 70.0, 100.0], 0.0, 0.0),
+        ];
+        assert_eq!(
+            initial_position(packets).unwrap(),
+            Some((8.5, 64.0, -3.5, 90.0, 10.0))
+        );
+        assert_eq!(initial_position(Vec::new()).unwrap(), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! リプレイ全体を眺めるための集計。
+
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    archive::ArchiveReader,
+    event::{Event, EventSource, State},
+    mcpr::ReplayReader,
+    names::packet_name,
+};
+
+/// パケット数の時間推移 (`bucket_ms` ごとのヒストグラム)。
+///
+/// 戻り値は `(バケット先頭時刻 ms, パケット数)` を 0ms から最後のパケットを
+/// 含むバケットまで隙間なく並べたもの。カスタムイベントは数えない。
+/// リプレイの長さ (`duration_ms`) より後のパケットはエラーにする
+/// (壊れた時刻で巨大なヒストグラムを確保しないため)。
+pub fn rate_over_time<S: EventSource + ?Sized>(
+    source: &mut S,
+    bucket_ms: u32,
+) -> anyhow::Result<Vec<(u32, u32)>> {
+    anyhow::ensure!(bucket_ms > 0, "bucket_ms must be positive");
+    let duration_ms = source.info().duration_ms;
+    let mut counts: Vec<u32> = Vec::new();
+    while let Some(event) = source.next_event()? {
+        if let Event::Packet { time, .. } = event {
+            anyhow::ensure!(
+                time.as_millis() <= duration_ms,
+                "packet at {}ms is past the replay duration {}ms",
+                time.as_millis(),
+                duration_ms
+            );
+            let index = usize::try_from(time.as_millis() / bucket_ms as u64)?;
+            if counts.len() <= index {
+                counts.resize(index + 1, 0);
+            }
+            counts[index] += 1;
+        }
+    }
+    Ok(counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| ((i as u32).saturating_mul(bucket_ms), count))
+        .collect())
+}
+
+/// `(state, id)` ごとのフレームの合計バイト数を、flamegraph ツールへ渡せる
+/// folded stack 形式 (`Play;SystemChat 1234` を 1 行ずつ) で返す。
+///
+/// バイト数は [`crate::mcpr::Packet::frame_size`] (ヘッダ込み)。名前が
+/// 分からない id は `Play;0x6c` のように 16 進で書く。行は最初に現れた順。
+pub fn folded_by_id<R: ArchiveReader>(reader: &mut ReplayReader<R>) -> anyhow::Result<String> {
+    let protocol = reader.read_metadata()?.protocol;
+    let mut totals: Vec<((State, i32), u64)> = Vec::new();
+    let mut index: HashMap<(State, i32), usize> = HashMap::new();
+    for (state, packet) in reader.get_packet_reader()? {
+        let key = (state, packet.id());
+        let i = *index.entry(key).or_insert_with(|| {
+            totals.push((key, 0));
+            totals.len() - 1
+        });
+        totals[i].1 += packet.frame_size();
+    }
+    let mut out = String::new();
+    for ((state, id), bytes) in totals {
+        match packet_name(state, id, protocol) {
+            Some(name) => writeln!(out, "{state:?};{name} {bytes}")?,
+            None => writeln!(out, "{state:?};0x{id:02x} {bytes}")?,
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{
+        State,
+        testing::{packet, source},
+    };
+
+    #[test]
+    fn buckets_packets_by_time() {
+        let events = vec![
+            packet(100, State::Play, 0x2c),
+            packet(150, State::Play, 0x2c),
+            packet(1200, State::Play, 0x2d),
+        ];
+        let rate = rate_over_time(&mut source(1200, events), 1000).unwrap();
+        assert_eq!(rate, vec![(0, 2), (1000, 1)]);
+    }
+
+    #[test]
+    fn folded_lines_sum_bytes_per_state_and_id() {
+        use crate::{
+            archive::testing::MemArchive,
+            mcpr::{MetaData, Packet, ReplayWriter},
+            protocol::{FINISH_CONFIGURATION_PACKET_ID, LOGIN_SUCCESS_PACKET_ID},
+        };
+
+        let mut writer = ReplayWriter::new(MemArchive::default());
+        writer
+            .write_metadata(MetaData {
+                protocol: 767,
+                ..Default::default()
+            })
+            .unwrap();
+        {
+            let mut out = writer.get_packet_writer().unwrap();
+            for packet in [
+                Packet::new(0, LOGIN_SUCCESS_PACKET_ID, vec![0; 4].into()),
+                Packet::new(0, FINISH_CONFIGURATION_PACKET_ID, Box::new([])),
+                Packet::new(10, 0x6c, vec![0; 10].into()),
+                Packet::new(20, 0x7f, vec![0; 3].into()),
+                Packet::new(30, 0x6c, vec![0; 20].into()),
+            ] {
+                out.push(packet).unwrap();
+            }
+            out.flush().unwrap();
+        }
+        let mut reader = ReplayReader::new(writer.into_inner());
+        let folded = folded_by_id(&mut reader).unwrap();
+        assert_eq!(
+            folded.lines().collect::<Vec<_>>(),
+            vec![
+                "Login;LoginFinished 13",
+                "Configuration;FinishConfiguration 9",
+                // (8 + 1 + 10) + (8 + 1 + 20)
+                "Play;SystemChat 48",
+                "Play;0x7f 12",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_buckets_are_filled_with_zero() {
+        let events = vec![
+            packet(0, State::Play, 0x2c),
+            packet(2500, State::Play, 0x2c),
+        ];
+        let rate = rate_over_time(&mut source(2500, events), 1000).unwrap();
+        assert_eq!(rate, vec![(0, 1), (1000, 0), (2000, 1)]);
+        assert!(rate_over_time(&mut source(0, vec![]), 0).is_err());
+    }
+
+    #[test]
+    fn packets_past_duration_are_rejected() {
+        let events = vec![
+            packet(0, State::Play, 0x2c),
+            packet(u32::MAX as u64 * 1000, State::Play, 0x2c),
+        ];
+        let error = rate_over_time(&mut source(2500, events), 1).unwrap_err();
+        assert!(error.to_string().contains("past the replay duration"));
+    }
+}
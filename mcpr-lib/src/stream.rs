@@ -0,0 +1,2360 @@
+//! 複数リプレイの論理イベント列を連結・フィルタして 1 本に流すパイプライン。
+//!
+//! mcpr-cli の変換処理の本体。入力ごとに [`ReplayStream::stream`] を呼ぶと、
+//! 時間範囲で切り出し (先頭の無活動区間も削れる)、速度倍率と連結オフセットで時刻を積み直し、Play パケットの
+//! include/exclude フィルタ、連結時の接続初期化除外、plugin message の
+//! チャンネル除外、ディメンション名・描画距離の書き直し、エンティティ移動と
+//! 冪等パケットの間引きを適用してから呼び出し側へイベントを渡す。出力先 ([`crate::event::EventSink`] や統計) は
+//! 呼び出し側の責務 (特定 id の抜き出し先だけはここで持つ)。
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    io::{self, Cursor, Read, Write},
+};
+
+use crate::{
+    archive::ArchiveWriter,
+    event::{
+        Event, EventSource, HandshakePolicy, PlaybackSpeed, ReplayInfo, State, Time,
+        is_connection_init,
+    },
+    mcpr::{McprEventSource, MetaData, Packet, RawFrame, ReplayWriter, filter_frames_verbatim},
+    path::{
+        ADD_ENTITY_PACKET_ID, ENTITY_POSITION_SYNC_PACKET_ID, MOVE_ENTITY_POS_PACKET_ID,
+        MOVE_ENTITY_POS_ROT_PACKET_ID, MOVE_ENTITY_ROT_PACKET_ID, PLAYER_POSITION_PACKET_ID,
+        REMOVE_ENTITIES_PACKET_ID,
+    },
+    protocol::{
+        CHUNK_BATCH_FINISHED_PACKET_ID, CHUNK_BATCH_START_PACKET_ID,
+        CONFIGURATION_PLUGIN_MESSAGE_PACKET_ID, Deserializer, KEEP_ALIVE_PACKET_ID,
+        LEVEL_CHUNK_WITH_LIGHT_PACKET_ID, LOGIN_PLAY_PACKET_ID, PLAY_PLUGIN_MESSAGE_PACKET_ID,
+        RESPAWN_PACKET_ID, Serializer, ViewDistances, checked_len_i32, read_plugin_message,
+        read_view_distances, varint_len, write_view_distances,
+    },
+};
+
+/// このモジュールの Play パケット id 定数が前提とする protocol (1.21.5)。
+pub const PACKET_ID_PROTOCOL: u32 = 770;
+
+/// 見た目だけに効き、ワールドの状態を変えない Play パケット id
+/// (1.21.5 / protocol 770 の値)。[`ReplayStream::world_state_only`] の既定。
+///
+/// Animate, BlockDestruction (破壊の亀裂), DamageEvent, HurtAnimation,
+/// LevelEvent (効果音・パーティクル), LevelParticles, Sound Entity, Sound。
+pub const COSMETIC_PACKET_IDS: [u8; 8] = [0x02, 0x05, 0x19, 0x24, 0x28, 0x29, 0x6d, 0x6e];
+
+/// Update Light (1.21.5 の値)。
+pub const LIGHT_UPDATE_PACKET_ID: i32 = 0x2a;
+
+/// [`ReplayStream::drop_initial_chunks`] がチャンクの読み込みとみなす Play パケット id。
+const INITIAL_CHUNK_PACKET_IDS: [i32; 4] = [
+    CHUNK_BATCH_START_PACKET_ID,
+    CHUNK_BATCH_FINISHED_PACKET_ID,
+    LEVEL_CHUNK_WITH_LIGHT_PACKET_ID,
+    LIGHT_UPDATE_PACKET_ID,
+];
+
+/// [`ReplayStream::camera_only`] が残す Play パケット id の既定
+/// (1.21.5 / protocol 770 の値)。
+///
+/// BlockEntityData, BlockUpdate, ChunkBatchFinished, ChunkBatchStart,
+/// ChunksBiomes, ForgetLevelChunk, LevelChunkWithLight, LightUpdate,
+/// Login (play), Synchronize Player Position, Respawn。
+pub const CAMERA_PACKET_IDS: [i32; 11] = [
+    0x06,
+    0x08,
+    CHUNK_BATCH_FINISHED_PACKET_ID,
+    CHUNK_BATCH_START_PACKET_ID,
+    0x0d,
+    0x21,
+    LEVEL_CHUNK_WITH_LIGHT_PACKET_ID,
+    LIGHT_UPDATE_PACKET_ID,
+    LOGIN_PLAY_PACKET_ID,
+    PLAYER_POSITION_PACKET_ID,
+    RESPAWN_PACKET_ID,
+];
+
+/// 先頭に entity id を持つエンティティの出現・移動パケット (1.21.5 の値)。
+/// [`ReplayStream::camera_only`] は自分の entity id のものだけを残す。
+///
+/// AddEntity, Teleport Entity, Update Entity Position,
+/// Update Entity Position and Rotation, Update Entity Rotation。
+pub const ENTITY_MOVEMENT_PACKET_IDS: [i32; 5] = [
+    ADD_ENTITY_PACKET_ID,
+    ENTITY_POSITION_SYNC_PACKET_ID,
+    MOVE_ENTITY_POS_PACKET_ID,
+    MOVE_ENTITY_POS_ROT_PACKET_ID,
+    MOVE_ENTITY_ROT_PACKET_ID,
+];
+
+/// [`ReplayStream::rewrite_chat`] の書き換え関数。
+type ChatFilter = Box<dyn FnMut(&str) -> String>;
+
+/// 連結・フィルタの設定と、入力を跨いで積み上がる状態。
+pub struct ReplayStream {
+    /// include 指定された Play パケット id (0..256)。None は全採用。
+    include: Option<[bool; 256]>,
+    /// exclude 指定された Play パケット id (0..256)。include より優先。
+    exclude: [bool; 256],
+    /// 0..256 の範囲外 id の Play パケットを残すか。
+    unknown_packets: bool,
+    interval_ms: u64,
+    speed: PlaybackSpeed,
+    /// 落とす plugin message のチャンネル。
+    drop_channels: HashSet<String>,
+    /// 最後の 1 件だけ残せばよい (state, id)。
+    collapse: HashSet<(State, i32)>,
+    /// 間引きの単位となる時間窓 (ms)。
+    collapse_window_ms: u64,
+    /// 抜き出し対象の Play パケット id と書き出し先 (.tmcpr)。
+    extract: Option<(i32, Box<dyn Write>)>,
+    /// 抜き出したパケットを本流から落とすか。
+    suppress_extracted: bool,
+    /// ディメンション名の付け替え (旧 → 新)。
+    dimensions: HashMap<String, String>,
+    /// Login (play) に書き直す描画距離。
+    view_distance: Option<i32>,
+    /// チャット本文の書き換え。
+    chat_filter: Option<ChatFilter>,
+    /// 残す時間範囲 (入力ごとの元の時刻 ms, 両端を含む)。None は端無し。
+    range: (Option<u32>, Option<u32>),
+    /// 時間範囲の外でも残す接続初期化。
+    handshake: HandshakePolicy,
+    /// 範囲の前に落ちた Login (play) / Respawn を範囲の先頭へ補うか。
+    preserve_world_context: bool,
+    /// 先頭の無活動区間を削る閾値 (Keep Alive を除く Play パケット数/秒)。
+    trim_idle: Option<f32>,
+    /// 時間範囲の境目を跨ぐ chunk batch の開始/終了を残すか。
+    keep_chunk_batches: bool,
+    /// 入力ごとの参加直後のチャンク読み込みを落とすか。
+    drop_initial_chunks: bool,
+    /// エンティティの移動を何 tick に 1 回へ間引くか。
+    downsample_ticks: Option<u32>,
+    /// カメラ視点だけを残す設定 (自分の entity id, 残す Play パケット id)。
+    camera: Option<(i32, HashSet<i32>)>,
+    /// 接続初期化以外で流すイベント数の上限。
+    limit: Option<usize>,
+    /// 上限に数えたイベント数 (入力を跨いで積み上がる)。
+    limited_events: usize,
+    /// 次の入力の先頭時刻。
+    offset_ms: u64,
+    inputs: usize,
+    players: BTreeSet<uuid::Uuid>,
+    base_info: Option<ReplayInfo>,
+}
+
+impl Default for ReplayStream {
+    fn default() -> Self {
+        Self {
+            include: None,
+            exclude: [false; 256],
+            unknown_packets: true,
+            interval_ms: 0,
+            speed: PlaybackSpeed::NORMAL,
+            drop_channels: HashSet::new(),
+            collapse: HashSet::new(),
+            collapse_window_ms: 1000,
+            extract: None,
+            suppress_extracted: false,
+            dimensions: HashMap::new(),
+            view_distance: None,
+            chat_filter: None,
+            range: (None, None),
+            handshake: HandshakePolicy::default(),
+            preserve_world_context: false,
+            trim_idle: None,
+            keep_chunk_batches: false,
+            drop_initial_chunks: false,
+            downsample_ticks: None,
+            camera: None,
+            limit: None,
+            limited_events: 0,
+            offset_ms: 0,
+            inputs: 0,
+            players: BTreeSet::new(),
+            base_info: None,
+        }
+    }
+}
+
+impl ReplayStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 指定 id の Play パケットだけを残す。1 件でも指定すると、
+    /// 指定外の id (0..256) は落ちる。
+    pub fn include_packets(&mut self, ids: impl IntoIterator<Item = u8>) -> &mut Self {
+        for id in ids {
+            self.include.get_or_insert([false; 256])[id as usize] = true;
+        }
+        self
+    }
+    /// 指定 id の Play パケットを落とす。include より優先する。
+    pub fn exclude_packets(&mut self, ids: impl IntoIterator<Item = u8>) -> &mut Self {
+        for id in ids {
+            self.exclude[id as usize] = true;
+        }
+        self
+    }
+    /// チャンク・ブロック更新・ブロックエンティティ等のワールド状態だけを残し、
+    /// 演出用のパケット ([`COSMETIC_PACKET_IDS`]) を落とす。
+    pub fn world_state_only(&mut self) -> &mut Self {
+        self.world_state_only_with(COSMETIC_PACKET_IDS)
+    }
+    /// [`Self::world_state_only`] の演出用 id 集合を差し替える版
+    /// (プロトコルごとに id が異なるため)。
+    pub fn world_state_only_with(&mut self, cosmetic: impl IntoIterator<Item = u8>) -> &mut Self {
+        self.exclude_packets(cosmetic)
+    }
+    /// 0..256 の範囲外 id の Play パケットを残すか (既定 true)。
+    pub fn unknown_packets(&mut self, keep: bool) -> &mut Self {
+        self.unknown_packets = keep;
+        self
+    }
+    /// 入力リプレイ間に挿入する間隔 (ms)。
+    pub fn interval(&mut self, interval_ms: u64) -> &mut Self {
+        self.interval_ms = interval_ms;
+        self
+    }
+    pub fn speed(&mut self, speed: PlaybackSpeed) -> &mut Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Configuration / Play の plugin message (custom payload) のうち、
+    /// チャンネルが `channels` に含まれるものを落とす。
+    pub fn drop_plugin_channels(&mut self, channels: HashSet<String>) -> &mut Self {
+        self.drop_channels = channels;
+        self
+    }
+
+    /// 落とす対象チャンネルの plugin message か。
+    /// body が壊れていて読めないものは判断できないので残す。
+    fn is_dropped_plugin_message(&self, state: State, id: i32, data: &[u8]) -> bool {
+        let is_plugin_message = match state {
+            State::Configuration => id == CONFIGURATION_PLUGIN_MESSAGE_PACKET_ID,
+            State::Play => id == PLAY_PLUGIN_MESSAGE_PACKET_ID,
+            _ => false,
+        };
+        is_plugin_message
+            && read_plugin_message(data)
+                .is_ok_and(|message| self.drop_channels.contains(&message.channel))
+    }
+
+    /// Play パケット `id` を (変換後の時刻で) `out` へ .tmcpr として書き出す。
+    /// 本流へはそのまま流れる ([`Self::suppress_extracted`] で落とせる)。
+    /// include/exclude 等のフィルタより前で抜き出すので、除外した id も抜き出せる。
+    pub fn extract_packets(&mut self, id: i32, out: Box<dyn Write>) -> &mut Self {
+        self.extract = Some((id, out));
+        self
+    }
+    /// 抜き出したパケットを本流から落とす (既定 false)。
+    pub fn suppress_extracted(&mut self, suppress: bool) -> &mut Self {
+        self.suppress_extracted = suppress;
+        self
+    }
+    /// 抜き出し先をフラッシュする。全入力を流し終えたら呼ぶ。
+    pub fn flush_extracted(&mut self) -> anyhow::Result<()> {
+        if let Some((_, out)) = &mut self.extract {
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Login (play) と Respawn のディメンション名を `map` に従って付け替える。
+    /// 別ワールドのリプレイを連結するときの名前の衝突避け。
+    /// `map` に無い名前や、body を読めないパケットはそのまま流す。
+    /// id と body の並びは [`PACKET_ID_PROTOCOL`] のものなので、入力の protocol が
+    /// それと違えば [`Self::stream`] はエラーにする。
+    pub fn remap_dimensions(&mut self, map: HashMap<String, String>) -> &mut Self {
+        self.dimensions = map;
+        self
+    }
+
+    /// ディメンション名を付け替えた body。対象外のパケットなら None。
+    fn remapped_dimensions(&self, state: State, id: i32, data: &[u8]) -> Option<Box<[u8]>> {
+        if self.dimensions.is_empty() || state != State::Play {
+            return None;
+        }
+        let remapped = match id {
+            LOGIN_PLAY_PACKET_ID => remap_login_dimensions(data, &self.dimensions),
+            RESPAWN_PACKET_ID => remap_respawn_dimension(data, &self.dimensions),
+            _ => return None,
+        };
+        remapped.ok().map(Vec::into_boxed_slice)
+    }
+
+    /// 自分 (`self_id`) の視点の再現に要るパケットだけを残す。
+    ///
+    /// チャンク等の [`CAMERA_PACKET_IDS`] と、[`ENTITY_MOVEMENT_PACKET_IDS`] のうち
+    /// entity id が `self_id` のものを残し、他のエンティティの出現・移動を含む
+    /// それ以外の Play パケットを落とす。id は [`PACKET_ID_PROTOCOL`] の値なので、
+    /// 入力の protocol がそれと違えば [`Self::stream`] はエラーにする。
+    pub fn camera_only(&mut self, self_id: i32) -> &mut Self {
+        self.camera_only_with(self_id, CAMERA_PACKET_IDS)
+    }
+    /// [`Self::camera_only`] の残す id 集合を差し替える版。
+    /// エンティティの判定 ([`ENTITY_MOVEMENT_PACKET_IDS`]) は差し替わらないので、
+    /// 入力の protocol の制限は同じ。
+    pub fn camera_only_with(
+        &mut self,
+        self_id: i32,
+        keep: impl IntoIterator<Item = i32>,
+    ) -> &mut Self {
+        self.camera = Some((self_id, keep.into_iter().collect()));
+        self
+    }
+
+    /// Play パケットが [`Self::camera_only`] の絞り込みを通るか。
+    /// entity id を読めないエンティティのパケットは落とす。
+    fn keeps_camera_packet(&self, id: i32, data: &[u8]) -> bool {
+        let Some((self_id, keep)) = &self.camera else {
+            return true;
+        };
+        if ENTITY_MOVEMENT_PACKET_IDS.contains(&id) {
+            Cursor::new(data)
+                .read_varint()
+                .is_ok_and(|entity| entity == *self_id)
+        } else {
+            keep.contains(&id)
+        }
+    }
+
+    /// Login (play) の描画距離を `chunks` に書き直す。シミュレーション距離は
+    /// `chunks` を超えていればそこまで下げる。クライアントが保持するチャンクが
+    /// 減るので、縮めたリプレイの再生が軽くなる。レイアウトは入力の
+    /// protocol_version で判断し、読めない body はそのまま流す。
+    pub fn set_view_distance(&mut self, chunks: i32) -> &mut Self {
+        self.view_distance = Some(chunks);
+        self
+    }
+
+    /// 描画距離を書き直した body。対象外のパケットなら None。
+    fn rewritten_view_distance(
+        &self,
+        state: State,
+        id: i32,
+        data: &[u8],
+        protocol_version: u32,
+    ) -> Option<Box<[u8]>> {
+        let chunks = self.view_distance?;
+        if state != State::Play || id != LOGIN_PLAY_PACKET_ID {
+            return None;
+        }
+        let current = read_view_distances(data, protocol_version).ok()?;
+        let distances = ViewDistances {
+            view_distance: chunks,
+            simulation_distance: current.simulation_distance.min(chunks),
+        };
+        write_view_distances(data, protocol_version, distances)
+            .ok()
+            .map(Vec::into_boxed_slice)
+    }
+
+    /// Play のチャット (System / Player / Disguised Chat) の本文を `f` に通して
+    /// 書き換える (伏せ字・フィルタ用)。
+    ///
+    /// 本文は装飾を捨てた `{text: ...}` のコンポーネントに作り直す。
+    /// Player Chat は署名を外す ([`crate::chat::rewrite_chat_text`])。
+    /// 読めない body はそのまま流す。
+    /// [`Self::estimate_output_size`] の見積もりでは書き換えない。
+    /// id は [`PACKET_ID_PROTOCOL`] の値なので、入力の protocol がそれと違えば
+    /// [`Self::stream`] はエラーにする。
+    pub fn rewrite_chat(&mut self, f: impl FnMut(&str) -> String + 'static) -> &mut Self {
+        self.chat_filter = Some(Box::new(f));
+        self
+    }
+
+    /// 接続初期化 ([`is_connection_init`]) 以外のイベントを `n` 件流したら、
+    /// それ以降は流さずに打ち切る (巨大なリプレイの先頭だけのプレビュー用)。
+    ///
+    /// 時刻による切り出し ([`Self::time_range`]) と違い、件数は全入力の合計。
+    pub fn limit_packets(&mut self, n: usize) -> &mut Self {
+        self.limit = Some(n);
+        self
+    }
+
+    /// [`Self::limit_packets`] の上限内なら数えて true。
+    fn within_limit(&mut self, event: &Event) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+        if matches!(event, Event::Packet { state, id, .. } if is_connection_init(*state, *id)) {
+            return true;
+        }
+        if self.limited_events >= limit {
+            return false;
+        }
+        self.limited_events += 1;
+        true
+    }
+
+    /// 各入力の元の時刻 (ms) が `[start, end]` に入るイベントだけを残し、
+    /// `start` が時刻 0 になるよう詰め直す (ハイライトの切り出し用)。
+    /// 範囲外の接続初期化は [`Self::handshake_policy`] に従って残し、
+    /// `start` より前のものは時刻 0 にする。
+    pub fn time_range(&mut self, start: Option<u32>, end: Option<u32>) -> &mut Self {
+        self.range = (start, end);
+        self
+    }
+    /// [`Self::time_range`] の範囲外にある接続初期化の扱い (既定は全て残す)。
+    pub fn handshake_policy(&mut self, policy: HandshakePolicy) -> &mut Self {
+        self.handshake = policy;
+        self
+    }
+    /// [`Self::time_range`] の開始より前に落ちた最新の Login (play) と、
+    /// その後の最新の Respawn を、範囲内で最初に残る Play パケットの直前
+    /// (その時刻) に補う (既定 false)。
+    ///
+    /// Respawn の後から始まるクリップでも、クライアントがディメンションや
+    /// ゲームモードを正しく知った状態で始まる。接続初期化として
+    /// ([`Self::handshake_policy`]) 既に残っている Login (play) は補わない。
+    /// 範囲内の先頭が Login (play) / Respawn 自身なら、それより古いものは補わない。
+    /// id は [`PACKET_ID_PROTOCOL`] の値なので、入力の protocol がそれと違えば
+    /// [`Self::stream`] はエラーにする。
+    pub fn preserve_world_context(&mut self, preserve: bool) -> &mut Self {
+        self.preserve_world_context = preserve;
+        self
+    }
+    /// 各入力の先頭の無活動区間 (立っているだけの待ち時間など) を削り、
+    /// 活動が始まった時刻を 0 として時刻を詰め直す。
+    ///
+    /// 判定: Keep Alive 以外の Play パケットについて、各パケットの時刻から
+    /// [`IDLE_WINDOW_MS`] の窓に入る件数を数え、`threshold_packets_per_sec`
+    /// を超えた最初の窓の先頭を活動開始とする。それより前のパケットは落とし、
+    /// 接続初期化だけを [`Self::handshake_policy`] に従って時刻 0 で残す。
+    /// 最後まで閾値を超えなければ削らない。活動開始が分かるまでイベントを
+    /// メモリに溜める。[`Self::time_range`] は詰め直した後の時刻に掛かる。
+    pub fn trim_leading_idle(&mut self, threshold_packets_per_sec: f32) -> &mut Self {
+        self.trim_idle = Some(threshold_packets_per_sec);
+        self
+    }
+    /// [`Self::time_range`] の境目が chunk batch の途中に来たとき、
+    /// 範囲内に残ったチャンクを囲む Chunk Batch Start / Finished を
+    /// 範囲外でも残す (既定 false)。
+    ///
+    /// ヒューリスティックであり、Finished のチャンク数は書き換えないので
+    /// 実際に残ったチャンク数とずれることがある。開始が範囲外なら
+    /// 範囲内の最初のチャンクの直前に出す。
+    /// id は [`PACKET_ID_PROTOCOL`] の値なので、入力の protocol がそれと違えば
+    /// [`Self::stream`] はエラーにする。
+    pub fn keep_chunk_batches(&mut self, keep: bool) -> &mut Self {
+        self.keep_chunk_batches = keep;
+        self
+    }
+
+    /// 入力の時刻を出力の時刻へ。範囲の開始を 0 に詰め (それより前は 0)、
+    /// 速度倍率を掛けて連結オフセットを足す。
+    fn output_time(&self, time: Time) -> Time {
+        let millis = time.as_millis().saturating_sub(self.range_start());
+        Time::from_millis(
+            self.speed
+                .scale_millis(millis)
+                .saturating_add(self.offset_ms),
+        )
+    }
+
+    fn range_start(&self) -> u64 {
+        self.range.0.map_or(0, u64::from)
+    }
+
+    /// 先頭 `trim_ms` を削った時刻へ詰め直す。削る区間のイベントは、
+    /// handshake として残すものだけを時刻 0 にし、それ以外は None。
+    fn trim_leading(&self, mut event: Event, trim_ms: u64) -> Option<Event> {
+        let millis = event.time().as_millis();
+        if millis >= trim_ms {
+            *event.time_mut() = Time::from_millis(millis - trim_ms);
+            return Some(event);
+        }
+        match &event {
+            Event::Packet { state, id, .. } if self.handshake.keeps(*state, *id) => {
+                *event.time_mut() = Time::from_millis(0);
+                Some(event)
+            }
+            _ => None,
+        }
+    }
+
+    fn in_range(&self, millis: u64) -> bool {
+        let (start, end) = self.range;
+        start.is_none_or(|start| millis >= u64::from(start))
+            && end.is_none_or(|end| millis <= u64::from(end))
+    }
+
+    /// 各入力の参加直後に一気に送られるチャンク (Chunk Batch Start / Finished,
+    /// Chunk Data, Update Light) を落とす (既定 false)。参加の瞬間だけの
+    /// 短いクリップでは、この読み込みがサイズの大半を占める。
+    ///
+    /// Play に入ってから最初に現れたチャンクパケットの連続を、チャンク以外の
+    /// Play パケットが挟まる (スポーンして落ち着いた) ところまで落とす。
+    /// それ以降のチャンクは残す。周囲の地形は新しいチャンクが届くまで描画されない。
+    /// id は [`PACKET_ID_PROTOCOL`] の値なので、入力の protocol がそれと違えば
+    /// [`Self::stream`] はエラーにする。
+    pub fn drop_initial_chunks(&mut self, drop: bool) -> &mut Self {
+        self.drop_initial_chunks = drop;
+        self
+    }
+
+    /// エンティティの相対移動 (Update Entity Position / and Rotation) を、
+    /// エンティティごとに `keep_every` tick の時間窓あたり 1 件へ間引く。
+    ///
+    /// 窓の中で最後の移動だけを、位置を積算した絶対座標の
+    /// Teleport Entity に置き換えて残すので、間引いても位置はずれない。
+    /// 置き換えたパケットは窓が終わった後の最初のイベントの直前
+    /// (その時刻) に出る。出現 (AddEntity) や teleport を見ていない
+    /// エンティティは基準の位置が分からないので間引かない。1 以下は間引き無し。
+    /// id は [`PACKET_ID_PROTOCOL`] の値なので、入力の protocol がそれと違えば
+    /// [`Self::stream`] はエラーにする。
+    pub fn downsample_movement(&mut self, keep_every: u32) -> &mut Self {
+        self.downsample_ticks = Some(keep_every);
+        self
+    }
+
+    /// 時刻更新や体力更新のように最新値だけが意味を持つパケットを、
+    /// 時間窓 ([`Self::collapse_window`]) ごとに最後の 1 件へ間引く。
+    pub fn collapse_idempotent(&mut self, ids: HashSet<(State, i32)>) -> &mut Self {
+        self.collapse = ids;
+        self
+    }
+    /// [`Self::collapse_idempotent`] の時間窓 (ms, 既定 1000)。0 は間引き無し。
+    pub fn collapse_window(&mut self, window_ms: u64) -> &mut Self {
+        self.collapse_window_ms = window_ms;
+        self
+    }
+
+    fn is_collapsible(&self, event: &Event) -> bool {
+        match event {
+            Event::Packet { state, id, .. } => self.collapse.contains(&(*state, *id)),
+            Event::Custom { .. } => false,
+        }
+    }
+
+    /// 時間窓 1 つ分のイベントを、間引き対象は (state, id) ごとの最後の 1 件だけ
+    /// 残して順序どおり渡す。
+    fn flush_window(
+        &self,
+        window: &mut Vec<Event>,
+        emit: &mut impl FnMut(Event) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut seen = HashSet::new();
+        let mut keep = vec![true; window.len()];
+        for (i, event) in window.iter().enumerate().rev() {
+            if let Event::Packet { state, id, .. } = event
+                && self.collapse.contains(&(*state, *id))
+            {
+                keep[i] = seen.insert((*state, *id));
+            }
+        }
+        for (event, keep) in window.drain(..).zip(keep) {
+            if keep {
+                emit(event)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// パケット単位のフィルタ (include/exclude と plugin message のチャンネル除外)
+    /// を通るか。時刻に依存する変換は含まない
+    /// ([`crate::mcpr::filter_frames_verbatim`] 等のフレーム単位の経路用)。
+    pub fn keeps_packet(&self, state: State, id: i32, data: &[u8]) -> bool {
+        if state == State::Play
+            && !(self.keeps_play_packet(id) && self.keeps_camera_packet(id, data))
+        {
+            return false;
+        }
+        self.drop_channels.is_empty() || !self.is_dropped_plugin_message(state, id, data)
+    }
+
+    /// パケット単位のフィルタ ([`Self::keeps_packet`]) が何も落とさない設定か。
+    pub fn is_passthrough(&self) -> bool {
+        self.include.is_none()
+            && !self.exclude.contains(&true)
+            && self.unknown_packets
+            && self.drop_channels.is_empty()
+            && self.camera.is_none()
+    }
+
+    /// tmcpr をフレーム単位で [`Self::keeps_packet`] に通し、残すフレームは
+    /// 元のバイト列のまま書く。時刻の変換 (速度・連結) は行わない。
+    ///
+    /// フィルタが何も落とさない設定 ([`Self::is_passthrough`]) では id の
+    /// VarInt を一切デコードせず、ヘッダの長さだけを見てフレームを写す
+    /// (再圧縮だけしたいときの近道)。戻り値は書いたフレーム数。
+    pub fn filter_frames<R: Read + ?Sized, W: Write + ?Sized>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> io::Result<usize> {
+        self.filter_frames_with(reader, writer, || {})
+    }
+
+    /// [`Self::filter_frames`] の本体。`on_decode` は id をデコードするたびに呼ぶ。
+    fn filter_frames_with<R: Read + ?Sized, W: Write + ?Sized>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        mut on_decode: impl FnMut(),
+    ) -> io::Result<usize> {
+        if self.is_passthrough() {
+            let mut copied = 0;
+            while let Some(frame) = RawFrame::read_from(reader)? {
+                frame.write_to(writer)?;
+                copied += 1;
+            }
+            return Ok(copied);
+        }
+        filter_frames_verbatim(reader, writer, |state, id, data| {
+            on_decode();
+            self.keeps_packet(state, id, data)
+        })
+    }
+
+    /// パケット単位のフィルタに加えて、時刻以外の変換もすべて既定のままか。
+    /// このときの [`Self::stream`] はイベントの時刻を詰め直すだけで済む。
+    fn is_identity(&self) -> bool {
+        self.is_passthrough()
+            && self.collapse.is_empty()
+            && self.extract.is_none()
+            && self.dimensions.is_empty()
+            && self.view_distance.is_none()
+            && self.chat_filter.is_none()
+            && self.range == (None, None)
+            && self.trim_idle.is_none()
+            && !self.drop_initial_chunks
+            && self.downsample_ticks.is_none_or(|ticks| ticks <= 1)
+            && self.limit.is_none()
+    }
+
+    /// Play パケット `id` が include/exclude フィルタを通るか。
+    fn keeps_play_packet(&self, id: i32) -> bool {
+        if (0..256).contains(&id) {
+            let id = id as usize;
+            self.include.is_none_or(|include| include[id]) && !self.exclude[id]
+        } else {
+            self.unknown_packets
+        }
+    }
+
+    /// 有効な設定のうち、[`PACKET_ID_PROTOCOL`] の id で Play パケットを
+    /// 見分けるものの名前 (最初の 1 つ)。
+    fn protocol_bound_option(&self) -> Option<&'static str> {
+        [
+            (self.camera.is_some(), "camera_only"),
+            (
+                self.downsample_ticks.is_some_and(|ticks| ticks > 1),
+                "downsample_movement",
+            ),
+            (self.chat_filter.is_some(), "rewrite_chat"),
+            (self.keep_chunk_batches, "keep_chunk_batches"),
+            (self.drop_initial_chunks, "drop_initial_chunks"),
+            (!self.dimensions.is_empty(), "remap_dimensions"),
+            (self.preserve_world_context, "preserve_world_context"),
+        ]
+        .into_iter()
+        .find_map(|(enabled, name)| enabled.then_some(name))
+    }
+
+    /// 入力の protocol が、有効な設定の前提 ([`PACKET_ID_PROTOCOL`]) と合うか。
+    fn ensure_protocol(&self, info: &ReplayInfo) -> anyhow::Result<()> {
+        if info.protocol_version != PACKET_ID_PROTOCOL
+            && let Some(option) = self.protocol_bound_option()
+        {
+            anyhow::bail!(
+                "{} uses protocol {} packet ids, but the input is protocol {}",
+                option,
+                PACKET_ID_PROTOCOL,
+                info.protocol_version
+            );
+        }
+        Ok(())
+    }
+
+    /// Login から始まる tmcpr 1 入力分を [`Self::stream`] と同じ設定で流し、
+    /// 残ったパケットを `writer` へ tmcpr のフレームとして書く。
+    /// 戻り値は入力のメタ情報。
+    ///
+    /// 時刻の詰め直しだけで済む設定 ([`Self::is_identity`]) の最初の入力では、
+    /// id をデコードせずフレームの時刻だけを書き換えて body をそのまま写す
+    /// (速度変更・再圧縮だけしたいときの近道)。2 個目以降の入力は接続初期化を
+    /// 見分けるためにイベントへ読み解く。
+    pub fn stream_frames<R: Read, W: Write>(
+        &mut self,
+        reader: R,
+        info: ReplayInfo,
+        writer: &mut W,
+    ) -> anyhow::Result<ReplayInfo> {
+        self.stream_frames_with(reader, info, writer, || {})
+    }
+
+    /// [`Self::stream_frames`] の本体。`on_decode` は id をデコードするたびに呼ぶ。
+    fn stream_frames_with<R: Read, W: Write>(
+        &mut self,
+        mut reader: R,
+        info: ReplayInfo,
+        writer: &mut W,
+        mut on_decode: impl FnMut(),
+    ) -> anyhow::Result<ReplayInfo> {
+        self.ensure_protocol(&info)?;
+        if self.inputs == 0 && self.is_identity() {
+            while let Some(mut frame) = RawFrame::read_from(&mut reader)? {
+                let time = self.output_time(Time::from_millis(u64::from(frame.time)));
+                frame.time = u32::try_from(time.as_millis()).unwrap_or(u32::MAX);
+                frame.write_to(writer)?;
+            }
+            self.finish_input(&info, 0);
+            return Ok(info);
+        }
+        let mut source = McprEventSource::new(reader, info);
+        self.stream(&mut source, |event| {
+            on_decode();
+            if let Event::Packet { time, id, data, .. } = event {
+                let time = u32::try_from(time.as_millis()).unwrap_or(u32::MAX);
+                Packet::new(time, id, data).write_to(writer)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// 1 入力分のイベントを流し、採用したものを `emit` へ渡す。
+    /// 戻り値は入力のメタ情報 (連結オフセットは次の入力へ向けて進む)。
+    ///
+    /// 入力が tmcpr のフレーム列で、id をデコードせずに写したいときは
+    /// [`Self::stream_frames`] を使う。
+    pub fn stream<S: EventSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+        mut emit: impl FnMut(Event) -> anyhow::Result<()>,
+    ) -> anyhow::Result<ReplayInfo> {
+        let info = source.info().clone();
+        self.ensure_protocol(&info)?;
+        let is_first_input = self.inputs == 0;
+        if self.is_identity() {
+            // 変換が無ければ各段の判定を通さず、時刻と接続初期化の重複だけを扱う
+            while let Some(mut event) = source.next_event()? {
+                if !is_first_input
+                    && matches!(&event, Event::Packet { state, id, .. } if is_connection_init(*state, *id))
+                {
+                    continue;
+                }
+                *event.time_mut() = self.output_time(event.time());
+                emit(event)?;
+            }
+            self.finish_input(&info, 0);
+            return Ok(info);
+        }
+        // 間引き中は現在の時間窓のイベントを溜め、窓が変わったら吐き出す
+        let mut window = Vec::new();
+        let mut window_index = 0;
+        // 範囲外で落とした Chunk Batch Start (keep_chunk_batches 用)
+        let mut dropped_batch_start = None;
+        // 残した Chunk Batch Start に対応する Finished をまだ見ていないか
+        let mut batch_open = false;
+        // 参加直後のチャンクの連続をまだ抜けていないか
+        let mut in_initial_chunks = self.drop_initial_chunks;
+        let mut seen_initial_chunk = false;
+        let mut downsampler = self
+            .downsample_ticks
+            .filter(|ticks| *ticks > 1)
+            .map(MovementDownsampler::new);
+        // 範囲の前に落とした最新の Login (play) / Respawn (preserve_world_context 用)
+        let mut world_context: [Option<Event>; 2] = [None, None];
+        // 入力より先に流す (補った world context と、その後ろへ戻したイベント)。末尾から取る
+        let mut pending: Vec<Event> = Vec::new();
+        // 活動開始を探すために先読みしたイベントと、削る先頭の長さ (trim_leading_idle 用)
+        let (mut lookahead, trim_ms) = match self.trim_idle {
+            Some(threshold) => find_active_start(source, threshold)?,
+            None => (VecDeque::new(), 0),
+        };
+
+        while let Some(mut event) = match pending.pop() {
+            Some(event) => Some(event),
+            // 削る区間で落ちたイベントは読み飛ばす
+            None => loop {
+                let next = match lookahead.pop_front() {
+                    Some(event) => Some(event),
+                    None => source.next_event()?,
+                };
+                let Some(next) = next else { break None };
+                if let Some(event) = self.trim_leading(next, trim_ms) {
+                    break Some(event);
+                }
+            },
+        } {
+            let mut keep = self.in_range(event.time().as_millis())
+                || matches!(&event, Event::Packet { state, id, .. } if self.handshake.keeps(*state, *id));
+            if self.preserve_world_context
+                && let Event::Packet {
+                    state: State::Play,
+                    id,
+                    ..
+                } = &event
+            {
+                let before_start = self
+                    .range
+                    .0
+                    .is_some_and(|start| event.time().as_millis() < u64::from(start));
+                match *id {
+                    LOGIN_PLAY_PACKET_ID if !keep && before_start => {
+                        world_context = [Some(event), None];
+                        continue;
+                    }
+                    RESPAWN_PACKET_ID if !keep && before_start => {
+                        world_context[1] = Some(event);
+                        continue;
+                    }
+                    LOGIN_PLAY_PACKET_ID if keep => world_context = [None, None],
+                    RESPAWN_PACKET_ID if keep => world_context[1] = None,
+                    _ => {}
+                }
+                if keep && world_context.iter().any(Option::is_some) {
+                    let time = event.time();
+                    pending.push(event);
+                    for mut context in world_context.iter_mut().rev().filter_map(Option::take) {
+                        *context.time_mut() = time;
+                        pending.push(context);
+                    }
+                    continue;
+                }
+            }
+            if self.keep_chunk_batches
+                && let Event::Packet {
+                    state: State::Play,
+                    id,
+                    ..
+                } = &event
+            {
+                match *id {
+                    CHUNK_BATCH_START_PACKET_ID => {
+                        dropped_batch_start = (!keep).then(|| event.clone());
+                        batch_open = keep;
+                    }
+                    CHUNK_BATCH_FINISHED_PACKET_ID => {
+                        keep |= batch_open;
+                        dropped_batch_start = None;
+                        batch_open = false;
+                    }
+                    _ => {}
+                }
+            }
+            if !keep {
+                continue;
+            }
+            *event.time_mut() = self.output_time(event.time());
+
+            if let (
+                Event::Packet {
+                    time,
+                    state: State::Play,
+                    id,
+                    data,
+                },
+                Some((target, out)),
+            ) = (&event, &mut self.extract)
+                && id == target
+            {
+                let time = u32::try_from(time.as_millis()).unwrap_or(u32::MAX);
+                Packet::new(time, *id, data.clone()).write_to(out)?;
+                if self.suppress_extracted {
+                    continue;
+                }
+            }
+
+            if let Event::Packet {
+                state, id, data, ..
+            } = &event
+            {
+                // Play パケットの include/exclude フィルタ
+                if *state == State::Play && !self.keeps_play_packet(*id) {
+                    continue;
+                }
+                if *state == State::Play && !self.keeps_camera_packet(*id, data) {
+                    continue;
+                }
+                if in_initial_chunks && *state == State::Play {
+                    if INITIAL_CHUNK_PACKET_IDS.contains(id) {
+                        seen_initial_chunk = true;
+                        continue;
+                    }
+                    in_initial_chunks = !seen_initial_chunk;
+                }
+                // 2 個目以降の入力では接続初期化の重複を避ける
+                if !is_first_input && is_connection_init(*state, *id) {
+                    continue;
+                }
+                if !self.drop_channels.is_empty()
+                    && self.is_dropped_plugin_message(*state, *id, data)
+                {
+                    continue;
+                }
+            }
+            if let Event::Packet {
+                state, id, data, ..
+            } = &mut event
+                && let Some(remapped) = self.remapped_dimensions(*state, *id, data)
+            {
+                *data = remapped;
+            }
+            if let Event::Packet {
+                state, id, data, ..
+            } = &mut event
+                && let Some(rewritten) =
+                    self.rewritten_view_distance(*state, *id, data, info.protocol_version)
+            {
+                *data = rewritten;
+            }
+            if let (
+                Event::Packet {
+                    state: State::Play,
+                    id,
+                    data,
+                    ..
+                },
+                Some(filter),
+            ) = (&mut event, &mut self.chat_filter)
+                && let Ok(Some(rewritten)) =
+                    crate::chat::rewrite_chat_text(*id, data, filter.as_mut())
+            {
+                *data = rewritten.into_boxed_slice();
+            }
+            // 範囲内に残った最初のチャンクの前へ、落とした batch の開始を戻す
+            let batch_start = match &event {
+                Event::Packet {
+                    state: State::Play,
+                    id: LEVEL_CHUNK_WITH_LIGHT_PACKET_ID,
+                    ..
+                } if dropped_batch_start.is_some() => {
+                    batch_open = true;
+                    dropped_batch_start.take().map(|mut start: Event| {
+                        *start.time_mut() = self.output_time(start.time());
+                        start
+                    })
+                }
+                _ => None,
+            };
+            let mut outputs: Vec<Event> = batch_start.into_iter().collect();
+            match &mut downsampler {
+                Some(downsampler) => downsampler.push(event, &mut outputs),
+                None => outputs.push(event),
+            }
+            let mut limit_reached = false;
+            for event in outputs {
+                if !self.within_limit(&event) {
+                    limit_reached = true;
+                    break;
+                }
+                self.output(event, &mut window, &mut window_index, &mut emit)?;
+            }
+            if limit_reached {
+                break;
+            }
+        }
+        if let Some(downsampler) = downsampler {
+            for event in downsampler.finish() {
+                if !self.within_limit(&event) {
+                    break;
+                }
+                self.output(event, &mut window, &mut window_index, &mut emit)?;
+            }
+        }
+        self.flush_window(&mut window, &mut emit)?;
+        self.finish_input(&info, trim_ms);
+        Ok(info)
+    }
+
+    /// 1 入力を流し終えたあと、連結オフセットを進めて入力の情報を積む。
+    /// `trim_ms` は先頭から削った長さ。
+    fn finish_input(&mut self, info: &ReplayInfo, trim_ms: u64) {
+        self.inputs += 1;
+        self.players.extend(info.players.iter().cloned());
+        // 先頭を削り、範囲で切り出した後の長さ
+        let duration = info.duration_ms.saturating_sub(trim_ms);
+        let duration = self
+            .range
+            .1
+            .map_or(duration, |end| duration.min(u64::from(end)))
+            .saturating_sub(self.range_start());
+        self.offset_ms += self.speed.scale_millis(duration) + self.interval_ms;
+        self.base_info.get_or_insert_with(|| info.clone());
+    }
+
+    /// 変換を終えたイベントを、間引き ([`Self::collapse_idempotent`]) の
+    /// 時間窓を通して `emit` へ渡す。
+    fn output(
+        &self,
+        event: Event,
+        window: &mut Vec<Event>,
+        window_index: &mut u64,
+        emit: &mut impl FnMut(Event) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if self.collapse.is_empty() || self.collapse_window_ms == 0 {
+            return emit(event);
+        }
+        let index = event.time().as_millis() / self.collapse_window_ms;
+        if index != *window_index {
+            self.flush_window(window, emit)?;
+            *window_index = index;
+        }
+        // 間引き対象が出るまでは溜める必要がない
+        if window.is_empty() && !self.is_collapsible(&event) {
+            emit(event)?;
+        } else {
+            window.push(event);
+        }
+        Ok(())
+    }
+
+    /// 同じフィルタ設定で、入力を跨ぐ状態が空のコピー。抜き出し先は捨てる。
+    fn dry_run(&self) -> ReplayStream {
+        ReplayStream {
+            include: self.include,
+            exclude: self.exclude,
+            unknown_packets: self.unknown_packets,
+            interval_ms: self.interval_ms,
+            speed: self.speed,
+            drop_channels: self.drop_channels.clone(),
+            collapse: self.collapse.clone(),
+            collapse_window_ms: self.collapse_window_ms,
+            extract: self
+                .extract
+                .as_ref()
+                .map(|(id, _)| (*id, Box::new(io::sink()) as Box<dyn Write>)),
+            suppress_extracted: self.suppress_extracted,
+            dimensions: self.dimensions.clone(),
+            view_distance: self.view_distance,
+            range: self.range,
+            handshake: self.handshake,
+            preserve_world_context: self.preserve_world_context,
+            trim_idle: self.trim_idle,
+            keep_chunk_batches: self.keep_chunk_batches,
+            drop_initial_chunks: self.drop_initial_chunks,
+            downsample_ticks: self.downsample_ticks,
+            camera: self.camera.clone(),
+            limit: self.limit,
+            ..Default::default()
+        }
+    }
+
+    /// 書き出す前に、出力 .tmcpr のバイト数を見積もる。
+    ///
+    /// `sources` を同じ設定で dry run し、残ったパケットの
+    /// `length() + 8` (フレームヘッダ) を合計する。自身の状態は変えない。
+    /// Custom イベントと、sink が補う phase 遷移パケットは含まない。
+    /// .mcpr は zip 圧縮されるので、実際のファイルはこれより小さくなる。
+    pub fn estimate_output_size<'a, S: EventSource + ?Sized + 'a>(
+        &self,
+        sources: impl IntoIterator<Item = &'a mut S>,
+    ) -> anyhow::Result<u64> {
+        let mut dry_run = self.dry_run();
+        let mut total = 0u64;
+        for source in sources {
+            dry_run.stream(source, |event| {
+                if let Event::Packet { id, data, .. } = &event {
+                    total += 8 + (varint_len(*id) + data.len()) as u64;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(total)
+    }
+
+    /// `sources` を流した結果を .mcpr の形で `archive` へ書く。
+    ///
+    /// パケットは `recording.tmcpr`、`metadata` は `metaData.json` へ入る
+    /// (`duration` と `players` は流した結果で上書きする)。zip でも展開済み
+    /// ディレクトリでも同じように使える。Custom イベントは書かない。
+    /// zip の場合は戻り値の archive を finish すること。
+    pub fn stream_to_archive<'a, S: EventSource + ?Sized + 'a, W: ArchiveWriter>(
+        &mut self,
+        sources: impl IntoIterator<Item = &'a mut S>,
+        archive: W,
+        mut metadata: MetaData,
+    ) -> anyhow::Result<W> {
+        let mut writer = ReplayWriter::new(archive);
+        let mut last_time = 0;
+        {
+            let mut packets = writer.get_packet_writer()?;
+            for source in sources {
+                self.stream(source, |event| {
+                    if let Event::Packet { time, id, data, .. } = event {
+                        let time = u32::try_from(time.as_millis()).unwrap_or(u32::MAX);
+                        last_time = last_time.max(time);
+                        packets.push(Packet::new(time, id, data))?;
+                    }
+                    Ok(())
+                })?;
+            }
+            packets.flush()?;
+        }
+        if let Some(info) = self.merged_info() {
+            metadata.duration = info.duration_ms.max(u64::from(last_time));
+            metadata.players = info.players;
+        }
+        writer.write_metadata(metadata)?;
+        Ok(writer.into_inner())
+    }
+
+    /// ここまでに流した全入力を連結したメタ情報。入力が無ければ None。
+    ///
+    /// duration は末尾の interval を含まない。players は全入力の union、
+    /// mc_version / protocol_version / data_version は先頭から継承する。
+    pub fn merged_info(&self) -> Option<ReplayInfo> {
+        let base = self.base_info.clone()?;
+        Some(ReplayInfo {
+            duration_ms: self.offset_ms.saturating_sub(self.interval_ms),
+            players: self.players.clone(),
+            ..base
+        })
+    }
+}
+
+/// [`ReplayStream::trim_leading_idle`] でパケット数を数える窓の幅 (ms)。
+pub const IDLE_WINDOW_MS: u64 = 1000;
+
+/// `source` を活動開始 ([`ReplayStream::trim_leading_idle`]) が見つかるまで
+/// 先読みし、読んだイベントと活動開始の時刻 (ms) を返す。
+/// 見つからなければ全イベントと 0。
+fn find_active_start<S: EventSource + ?Sized>(
+    source: &mut S,
+    threshold_packets_per_sec: f32,
+) -> anyhow::Result<(VecDeque<Event>, u64)> {
+    let threshold = f64::from(threshold_packets_per_sec) * IDLE_WINDOW_MS as f64 / 1000.0;
+    let mut read = VecDeque::new();
+    // 窓に入っている数えるパケットの時刻
+    let mut window: VecDeque<u64> = VecDeque::new();
+    while let Some(event) = source.next_event()? {
+        let counted = matches!(
+            &event,
+            Event::Packet { state: State::Play, id, .. } if *id != KEEP_ALIVE_PACKET_ID
+        );
+        let millis = event.time().as_millis();
+        read.push_back(event);
+        if !counted {
+            continue;
+        }
+        while window
+            .front()
+            .is_some_and(|front| front + IDLE_WINDOW_MS <= millis)
+        {
+            window.pop_front();
+        }
+        window.push_back(millis);
+        if window.len() as f64 > threshold {
+            return Ok((read, window[0]));
+        }
+    }
+    Ok((read, 0))
+}
+
+/// [`ReplayStream::downsample_movement`] の状態。
+struct MovementDownsampler {
+    window_ms: u64,
+    /// 現在の時間窓の番号。
+    window: u64,
+    /// entity id ごとの位置 (x, y, z) と向き (yaw, pitch)。
+    entities: HashMap<i32, ([f64; 3], [f32; 2])>,
+    /// 現在の窓で最後に見た移動を置き換えた Teleport Entity (entity id 順)。
+    pending: BTreeMap<i32, Event>,
+}
+
+impl MovementDownsampler {
+    fn new(keep_every: u32) -> Self {
+        Self {
+            window_ms: u64::from(keep_every) * Time::MS_PER_TICK,
+            window: 0,
+            entities: HashMap::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// `event` を受け取り、出すべきイベントを `out` へ積む。
+    fn push(&mut self, event: Event, out: &mut Vec<Event>) {
+        let window = event.time().as_millis() / self.window_ms;
+        if window != self.window {
+            // 窓が終わった移動は、今のイベントの時刻で出す
+            let time = event.time();
+            out.extend(self.pending.values().cloned().map(|mut teleport| {
+                *teleport.time_mut() = time;
+                teleport
+            }));
+            self.pending.clear();
+            self.window = window;
+        }
+        if let Event::Packet {
+            time,
+            state: State::Play,
+            id,
+            data,
+        } = &event
+            && let Ok(Some(teleport)) = self.track(*time, *id, data)
+        {
+            self.pending.insert(teleport.0, teleport.1);
+            return;
+        }
+        out.push(event);
+    }
+
+    /// 位置と向きを更新する。相対移動なら置き換えた (entity id, Teleport Entity)。
+    ///
+    /// 出現・消滅したエンティティの置き換え待ちの移動は捨てる
+    /// (同じ id の別のエンティティへ古い位置を送らないため)。
+    fn track(&mut self, time: Time, id: i32, data: &[u8]) -> io::Result<Option<(i32, Event)>> {
+        let mut cursor = Cursor::new(data);
+        if id == REMOVE_ENTITIES_PACKET_ID {
+            let count = checked_len_i32(cursor.read_varint()?, "entity count")?;
+            for _ in 0..count {
+                let entity = cursor.read_varint()?;
+                self.entities.remove(&entity);
+                self.pending.remove(&entity);
+            }
+            return Ok(None);
+        }
+        let entity = cursor.read_varint()?;
+        match id {
+            ADD_ENTITY_PACKET_ID => {
+                cursor.read_uuid()?;
+                cursor.read_varint()?; // entity type
+                let position = [
+                    cursor.read_double()?,
+                    cursor.read_double()?,
+                    cursor.read_double()?,
+                ];
+                let pitch = cursor.read_angle_degrees()?;
+                let yaw = cursor.read_angle_degrees()?;
+                self.entities.insert(entity, (position, [yaw, pitch]));
+                self.pending.remove(&entity);
+            }
+            ENTITY_POSITION_SYNC_PACKET_ID => {
+                let position = [
+                    cursor.read_double()?,
+                    cursor.read_double()?,
+                    cursor.read_double()?,
+                ];
+                for _ in 0..3 {
+                    cursor.read_double()?; // velocity
+                }
+                let rotation = [cursor.read_float()?, cursor.read_float()?];
+                self.entities.insert(entity, (position, rotation));
+                // 窓の途中の teleport より前の移動は置き換える必要が無い
+                self.pending.remove(&entity);
+            }
+            MOVE_ENTITY_POS_PACKET_ID | MOVE_ENTITY_POS_ROT_PACKET_ID => {
+                let Some((position, rotation)) = self.entities.get_mut(&entity) else {
+                    return Ok(None);
+                };
+                for axis in position.iter_mut() {
+                    *axis += f64::from(cursor.read_short()?) / 4096.0;
+                }
+                if id == MOVE_ENTITY_POS_ROT_PACKET_ID {
+                    *rotation = [cursor.read_angle_degrees()?, cursor.read_angle_degrees()?];
+                }
+                let on_ground = cursor.read_bool()?;
+                let mut body = Vec::new();
+                body.write_varint(entity)?;
+                for axis in position.iter().chain(&[0.0; 3]) {
+                    body.extend_from_slice(&axis.to_be_bytes());
+                }
+                for angle in rotation.iter() {
+                    body.extend_from_slice(&angle.to_be_bytes());
+                }
+                body.push(u8::from(on_ground));
+                let teleport = Event::Packet {
+                    time,
+                    state: State::Play,
+                    id: ENTITY_POSITION_SYNC_PACKET_ID,
+                    data: body.into(),
+                };
+                return Ok(Some((entity, teleport)));
+            }
+            MOVE_ENTITY_ROT_PACKET_ID => {
+                if let Some((_, rotation)) = self.entities.get_mut(&entity) {
+                    *rotation = [cursor.read_angle_degrees()?, cursor.read_angle_degrees()?];
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// 入力の終わりで、残っている置き換え済みの移動を (元の時刻で) 返す。
+    fn finish(self) -> impl Iterator<Item = Event> {
+        let mut pending: Vec<_> = self.pending.into_values().collect();
+        pending.sort_by_key(Event::time);
+        pending.into_iter()
+    }
+}
+
+fn remap_dimension<'a>(map: &'a HashMap<String, String>, name: &'a str) -> &'a str {
+    map.get(name).map_or(name, String::as_str)
+}
+
+/// Login (play): entity id, hardcore, ディメンション名の配列, 各種設定,
+/// dimension type, ディメンション名, 以降そのまま。
+fn remap_login_dimensions(data: &[u8], map: &HashMap<String, String>) -> io::Result<Vec<u8>> {
+    let mut cursor = Cursor::new(data);
+    cursor.read_int()?;
+    cursor.read_bool()?;
+    let mut out = data[..cursor.position() as usize].to_vec();
+    let count = cursor.read_varint()?;
+    out.write_varint(count)?;
+    for _ in 0..count {
+        out.write_string(remap_dimension(map, &cursor.read_string()?))?;
+    }
+    // max players, view distance, simulation distance, reduced debug info,
+    // enable respawn screen, do limited crafting, dimension type
+    let start = cursor.position() as usize;
+    for _ in 0..3 {
+        cursor.read_varint()?;
+    }
+    for _ in 0..3 {
+        cursor.read_bool()?;
+    }
+    cursor.read_varint()?;
+    out.extend_from_slice(&data[start..cursor.position() as usize]);
+    out.write_string(remap_dimension(map, &cursor.read_string()?))?;
+    out.extend_from_slice(&data[cursor.position() as usize..]);
+    Ok(out)
+}
+
+/// Respawn: dimension type, ディメンション名, 以降そのまま。
+fn remap_respawn_dimension(data: &[u8], map: &HashMap<String, String>) -> io::Result<Vec<u8>> {
+    let mut cursor = Cursor::new(data);
+    cursor.read_varint()?;
+    let mut out = data[..cursor.position() as usize].to_vec();
+    out.write_string(remap_dimension(map, &cursor.read_string()?))?;
+    out.extend_from_slice(&data[cursor.position() as usize..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event::testing::{VecSource, packet, source},
+        protocol::{KEEP_ALIVE_PACKET_ID, LOGIN_PLAY_PACKET_ID},
+    };
+
+    fn run(stream: &mut ReplayStream, source: &mut VecSource) -> Vec<Event> {
+        let mut out = Vec::new();
+        stream
+            .stream(source, |event| {
+                out.push(event);
+                Ok(())
+            })
+            .unwrap();
+        out
+    }
+
+    fn ids(events: &[Event]) -> Vec<i32> {
+        events
+            .iter()
+            .map(|e| match e {
+                Event::Packet { id, .. } => *id,
+                Event::Custom { .. } => -1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn include_and_exclude_apply_to_play_only() {
+        let events = vec![
+            packet(0, State::Configuration, 0x07),
+            packet(0, State::Play, 0x2c),
+            packet(0, State::Play, 0x2d),
+            packet(0, State::Play, 0x2e),
+        ];
+        let mut stream = ReplayStream::new();
+        stream.include_packets([0x2c, 0x2d]).exclude_packets([0x2d]);
+        let out = run(&mut stream, &mut source(0, events));
+        assert_eq!(ids(&out), vec![0x07, 0x2c]);
+    }
+
+    #[test]
+    fn unknown_ids_follow_unknown_packets_flag() {
+        let events = || vec![packet(0, State::Play, 0x1234)];
+        let mut keep = ReplayStream::new();
+        assert_eq!(run(&mut keep, &mut source(0, events())).len(), 1);
+        let mut drop = ReplayStream::new();
+        drop.unknown_packets(false);
+        assert!(run(&mut drop, &mut source(0, events())).is_empty());
+    }
+
+    #[test]
+    fn later_inputs_are_offset_and_skip_connection_init() {
+        let input = || {
+            vec![
+                packet(0, State::Login, 0x02),
+                packet(0, State::Play, LOGIN_PLAY_PACKET_ID),
+                packet(100, State::Play, 0x2c),
+            ]
+        };
+        let mut stream = ReplayStream::new();
+        stream
+            .interval(1000)
+            .speed(PlaybackSpeed::new(2.0).unwrap());
+        let first = run(&mut stream, &mut source(500, input()));
+        let second = run(&mut stream, &mut source(500, input()));
+
+        assert_eq!(ids(&first), vec![0x02, LOGIN_PLAY_PACKET_ID, 0x2c]);
+        assert_eq!(ids(&second), vec![0x2c]);
+        // 2 倍速: 500ms → 250ms、+ interval 1000ms
+        assert_eq!(second[0].time().as_millis(), 250 + 1000 + 50);
+        // 末尾 interval は duration に含めない
+        assert_eq!(stream.merged_info().unwrap().duration_ms, 250 + 1000 + 250);
+    }
+
+    #[test]
+    fn merged_info_is_none_without_inputs() {
+        assert!(ReplayStream::new().merged_info().is_none());
+    }
+
+    #[test]
+    fn collapse_idempotent_keeps_last_per_window() {
+        const SET_TIME: i32 = 0x6b;
+        let events = vec![
+            packet(0, State::Play, SET_TIME),
+            packet(100, State::Play, 0x2c),
+            packet(200, State::Play, SET_TIME),
+            packet(900, State::Play, SET_TIME),
+            packet(950, State::Play, 0x2d),
+            packet(1000, State::Play, SET_TIME),
+            packet(1500, State::Play, SET_TIME),
+        ];
+        let mut stream = ReplayStream::new();
+        stream
+            .collapse_idempotent(HashSet::from([(State::Play, SET_TIME)]))
+            .collapse_window(1000);
+        let out = run(&mut stream, &mut source(2000, events));
+        // 窓 [0, 1000) は 900ms の 1 件、[1000, 2000) は 1500ms の 1 件
+        assert_eq!(ids(&out), vec![0x2c, SET_TIME, 0x2d, SET_TIME]);
+        let times: Vec<_> = out.iter().map(|e| e.time().as_millis()).collect();
+        assert_eq!(times, vec![100, 900, 950, 1500]);
+    }
+
+    #[test]
+    fn drop_plugin_channels_drops_matching_channel() {
+        use crate::protocol::write_plugin_message;
+
+        let plugin = |time_ms, state, id, channel: &str| Event::Packet {
+            time: Time::from_millis(time_ms),
+            state,
+            id,
+            data: write_plugin_message(channel, b"\x07vanilla")
+                .unwrap()
+                .into(),
+        };
+        let events = vec![
+            plugin(
+                0,
+                State::Configuration,
+                CONFIGURATION_PLUGIN_MESSAGE_PACKET_ID,
+                "minecraft:brand",
+            ),
+            plugin(
+                0,
+                State::Play,
+                PLAY_PLUGIN_MESSAGE_PACKET_ID,
+                "minecraft:brand",
+            ),
+            plugin(
+                0,
+                State::Play,
+                PLAY_PLUGIN_MESSAGE_PACKET_ID,
+                "voicechat:state",
+            ),
+            // 別 id のパケットは body がたまたま一致しても触らない
+            plugin(0, State::Play, 0x2c, "minecraft:brand"),
+        ];
+        let mut stream = ReplayStream::new();
+        stream.drop_plugin_channels(HashSet::from(["minecraft:brand".to_string()]));
+        let out = run(&mut stream, &mut source(0, events));
+        assert_eq!(ids(&out), vec![PLAY_PLUGIN_MESSAGE_PACKET_ID, 0x2c]);
+    }
+
+    /// 複製してもバッファを共有する書き出し先 (抜き出し結果の検証用)。
+    #[derive(Clone, Default)]
+    struct SharedBuf(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn extract_packets_writes_only_matching_id() {
+        use crate::mcpr::ReadablePacketStream;
+
+        let events = vec![
+            packet(0, State::Configuration, 0x3a),
+            packet(50, State::Play, 0x3a),
+            packet(60, State::Play, 0x2c),
+            packet(70, State::Play, 0x3a),
+        ];
+        let buf = SharedBuf::default();
+        let mut stream = ReplayStream::new();
+        stream
+            .extract_packets(0x3a, Box::new(buf.clone()))
+            .exclude_packets([0x3a]);
+        let out = run(&mut stream, &mut source(100, events));
+        stream.flush_extracted().unwrap();
+
+        // 本流では exclude が効き、抜き出しには Play の 0x3a だけが入る
+        assert_eq!(ids(&out), vec![0x3a, 0x2c]);
+        let bytes = buf.0.borrow().clone();
+        let extracted: Vec<_> = ReadablePacketStream::new(State::Play, bytes.as_slice())
+            .map(|(_, p)| (p.time(), p.id()))
+            .collect();
+        assert_eq!(extracted, vec![(50, 0x3a), (70, 0x3a)]);
+    }
+
+    #[test]
+    fn suppress_extracted_removes_from_main_stream() {
+        let events = vec![packet(0, State::Play, 0x3a), packet(0, State::Play, 0x2c)];
+        let mut stream = ReplayStream::new();
+        stream
+            .extract_packets(0x3a, Box::new(std::io::sink()))
+            .suppress_extracted(true);
+        let out = run(&mut stream, &mut source(0, events));
+        assert_eq!(ids(&out), vec![0x2c]);
+    }
+
+    #[test]
+    fn world_state_only_drops_cosmetic_packets() {
+        const BLOCK_ENTITY_DATA: i32 = 0x06;
+        const BLOCK_UPDATE: i32 = 0x08;
+        const LEVEL_PARTICLES: i32 = 0x29;
+        const SOUND: i32 = 0x6e;
+        let events = || {
+            vec![
+                packet(0, State::Play, LEVEL_PARTICLES),
+                packet(0, State::Play, BLOCK_ENTITY_DATA),
+                packet(0, State::Play, SOUND),
+                packet(0, State::Play, BLOCK_UPDATE),
+            ]
+        };
+        let mut stream = ReplayStream::new();
+        stream.world_state_only();
+        assert_eq!(
+            ids(&run(&mut stream, &mut source(0, events()))),
+            vec![BLOCK_ENTITY_DATA, BLOCK_UPDATE]
+        );
+
+        // 別プロトコル向けに集合を差し替え
+        let mut stream = ReplayStream::new();
+        stream.world_state_only_with([BLOCK_UPDATE as u8]);
+        assert_eq!(
+            ids(&run(&mut stream, &mut source(0, events()))),
+            vec![LEVEL_PARTICLES, BLOCK_ENTITY_DATA, SOUND]
+        );
+    }
+
+    #[test]
+    fn limit_packets_keeps_handshake_and_first_packets() {
+        let mut events = vec![
+            packet(0, State::Login, 0x02),
+            packet(0, State::Configuration, 0x07),
+            packet(0, State::Configuration, 0x03),
+            packet(0, State::Play, LOGIN_PLAY_PACKET_ID),
+        ];
+        events.extend((0..20).map(|i| packet(100 + i * 50, State::Play, 0x2c)));
+        let mut stream = ReplayStream::new();
+        stream.limit_packets(5);
+        let out = run(&mut stream, &mut source(1100, events));
+        assert_eq!(
+            ids(&out),
+            vec![
+                0x02,
+                0x07,
+                0x03,
+                LOGIN_PLAY_PACKET_ID,
+                0x2c,
+                0x2c,
+                0x2c,
+                0x2c,
+                0x2c
+            ]
+        );
+        // 上限に達した後の入力からは流さない
+        let more = run(
+            &mut stream,
+            &mut source(100, vec![packet(0, State::Play, 0x2c)]),
+        );
+        assert!(more.is_empty());
+    }
+
+    #[test]
+    fn rewrite_chat_uppercases_system_chat() {
+        use crate::chat::{SYSTEM_CHAT_PACKET_ID, export_jsonl};
+
+        // plain な string tag のコンポーネント + overlay
+        let mut body = vec![8];
+        body.extend_from_slice(&5u16.to_be_bytes());
+        body.extend_from_slice(b"hello");
+        body.push(0);
+        let events = vec![
+            packet(0, State::Play, 0x2c),
+            Event::Packet {
+                time: Time::from_millis(100),
+                state: State::Play,
+                id: SYSTEM_CHAT_PACKET_ID,
+                data: body.into(),
+            },
+        ];
+        let mut stream = ReplayStream::new();
+        stream.rewrite_chat(|text| text.to_uppercase());
+        let out = run(&mut stream, &mut source(100, events));
+
+        let packets = out.into_iter().map(|event| match event {
+            Event::Packet {
+                time,
+                state,
+                id,
+                data,
+            } => (state, Packet::new(time.as_millis() as u32, id, data)),
+            Event::Custom { .. } => unreachable!(),
+        });
+        let mut jsonl = Vec::new();
+        assert_eq!(export_jsonl(packets, &mut jsonl).unwrap(), 1);
+        let line: serde_json::Value = serde_json::from_slice(&jsonl).unwrap();
+        assert_eq!(line["text"], "HELLO");
+    }
+
+    #[test]
+    fn rewrite_chat_passes_malformed_chat_through() {
+        use crate::chat::SYSTEM_CHAT_PACKET_ID;
+
+        // コンポーネントの途中で切れた body
+        let broken: Box<[u8]> = vec![8, 0, 5, b'h'].into();
+        let events = vec![
+            packet(0, State::Play, 0x2c),
+            Event::Packet {
+                time: Time::from_millis(100),
+                state: State::Play,
+                id: SYSTEM_CHAT_PACKET_ID,
+                data: broken.clone(),
+            },
+        ];
+        let mut stream = ReplayStream::new();
+        stream.rewrite_chat(|text| text.to_uppercase());
+        let out = run(&mut stream, &mut source(100, events.clone()));
+        assert!(matches!(&out[1], Event::Packet { data, .. } if *data == broken));
+
+        // id の前提と違う protocol の入力は断る
+        let mut other = source(100, events);
+        other.info.protocol_version = 767;
+        let err = stream.stream(&mut other, |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("rewrite_chat"), "{err}");
+    }
+
+    #[test]
+    fn trim_leading_idle_starts_at_active_region() {
+        let mut events = vec![
+            packet(0, State::Login, 0x02),
+            packet(0, State::Configuration, 0x03),
+            packet(0, State::Play, LOGIN_PLAY_PACKET_ID),
+        ];
+        for second in 1..10 {
+            events.push(packet(second * 1000, State::Play, KEEP_ALIVE_PACKET_ID));
+        }
+        events.push(packet(3000, State::Play, 0x2c));
+        for i in 0..10 {
+            events.push(packet(10_000 + i * 100, State::Play, 0x2d));
+        }
+        events.sort_by_key(|event| event.time());
+
+        let mut stream = ReplayStream::new();
+        stream.trim_leading_idle(5.0);
+        let out = run(&mut stream, &mut source(11_000, events.clone()));
+        let times: Vec<_> = out.iter().map(|e| e.time().as_millis()).collect();
+        assert_eq!(
+            ids(&out[..3]),
+            vec![0x02, 0x03, LOGIN_PLAY_PACKET_ID],
+            "handshake は時刻 0 で残る"
+        );
+        assert_eq!(&times[..4], &[0, 0, 0, 0]);
+        assert_eq!(ids(&out[3..]), vec![0x2d; 10]);
+        assert_eq!(times.last(), Some(&900));
+        assert_eq!(stream.merged_info().unwrap().duration_ms, 1000);
+
+        // 閾値を超える窓が無ければ削らない
+        let mut quiet = ReplayStream::new();
+        quiet.trim_leading_idle(50.0);
+        assert_eq!(run(&mut quiet, &mut source(11_000, events.clone())), events);
+    }
+
+    #[test]
+    fn time_range_keeps_connection_init_per_policy() {
+        let events = || {
+            vec![
+                packet(0, State::Login, 0x02),
+                packet(0, State::Configuration, 0x07),
+                packet(0, State::Configuration, 0x03),
+                packet(0, State::Play, LOGIN_PLAY_PACKET_ID),
+                packet(100, State::Play, 0x2c),
+                packet(500, State::Play, 0x2d),
+                packet(900, State::Play, 0x2e),
+            ]
+        };
+        let mut stream = ReplayStream::new();
+        stream.time_range(Some(200), Some(800));
+        assert_eq!(
+            ids(&run(&mut stream, &mut source(1000, events()))),
+            vec![0x02, 0x07, 0x03, LOGIN_PLAY_PACKET_ID, 0x2d]
+        );
+
+        let mut stream = ReplayStream::new();
+        stream
+            .time_range(Some(200), None)
+            .handshake_policy(HandshakePolicy::KeepLoginOnly);
+        assert_eq!(
+            ids(&run(&mut stream, &mut source(1000, events()))),
+            vec![0x02, 0x03, LOGIN_PLAY_PACKET_ID, 0x2d, 0x2e]
+        );
+    }
+
+    #[test]
+    fn preserve_world_context_restores_last_respawn() {
+        let events = || {
+            vec![
+                packet(0, State::Login, 0x02),
+                packet(0, State::Configuration, 0x03),
+                packet(0, State::Play, LOGIN_PLAY_PACKET_ID),
+                packet(100, State::Play, RESPAWN_PACKET_ID),
+                packet(150, State::Play, 0x2c),
+                packet(300, State::Play, RESPAWN_PACKET_ID),
+                packet(400, State::Play, 0x2c),
+                packet(500, State::Play, 0x2d),
+            ]
+        };
+        let mut stream = ReplayStream::new();
+        stream.time_range(Some(350), None);
+        assert_eq!(
+            ids(&run(&mut stream, &mut source(1000, events()))),
+            vec![0x02, 0x03, LOGIN_PLAY_PACKET_ID, 0x2c, 0x2d]
+        );
+
+        let mut stream = ReplayStream::new();
+        stream
+            .time_range(Some(350), None)
+            .preserve_world_context(true);
+        let out = run(&mut stream, &mut source(1000, events()));
+        assert_eq!(
+            ids(&out),
+            vec![
+                0x02,
+                0x03,
+                LOGIN_PLAY_PACKET_ID,
+                RESPAWN_PACKET_ID,
+                0x2c,
+                0x2d
+            ]
+        );
+        // 補った Respawn は直後のパケットの時刻 (範囲の開始からの 400 - 350) に出る
+        assert_eq!(out[3].time().as_millis(), 50);
+
+        // Login (play) も落とす設定なら、それも補う
+        let mut stream = ReplayStream::new();
+        stream
+            .time_range(Some(350), None)
+            .handshake_policy(HandshakePolicy::KeepNone)
+            .preserve_world_context(true);
+        assert_eq!(
+            ids(&run(&mut stream, &mut source(1000, events()))),
+            vec![LOGIN_PLAY_PACKET_ID, RESPAWN_PACKET_ID, 0x2c, 0x2d]
+        );
+
+        // id の前提と違う protocol の入力は断る
+        let mut other = source(1000, events());
+        other.info.protocol_version = 767;
+        let err = stream.stream(&mut other, |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("preserve_world_context"), "{err}");
+    }
+
+    #[test]
+    fn time_range_rebases_to_start() {
+        let events = vec![
+            packet(0, State::Login, 0x02),
+            packet(0, State::Configuration, 0x03),
+            packet(0, State::Play, 0x2c),
+            packet(500, State::Play, 0x2d),
+            packet(1000, State::Play, 0x2e),
+            packet(1500, State::Play, 0x2f),
+        ];
+        let mut stream = ReplayStream::new();
+        stream.time_range(Some(500), Some(1000));
+        let out = run(&mut stream, &mut source(1500, events));
+        let summary: Vec<_> = ids(&out)
+            .into_iter()
+            .zip(out.iter().map(|e| e.time().as_millis()))
+            .collect();
+        assert_eq!(summary, vec![(0x02, 0), (0x03, 0), (0x2d, 0), (0x2e, 500)]);
+        assert_eq!(stream.merged_info().unwrap().duration_ms, 500);
+    }
+
+    #[test]
+    fn keep_chunk_batches_drags_boundaries_into_range() {
+        let events = || {
+            vec![
+                packet(100, State::Play, CHUNK_BATCH_START_PACKET_ID),
+                packet(150, State::Play, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID),
+                packet(200, State::Play, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID),
+                packet(250, State::Play, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID),
+                packet(300, State::Play, CHUNK_BATCH_FINISHED_PACKET_ID),
+                // 範囲内にチャンクが無い batch は引きずらない
+                packet(400, State::Play, CHUNK_BATCH_START_PACKET_ID),
+                packet(450, State::Play, CHUNK_BATCH_FINISHED_PACKET_ID),
+            ]
+        };
+        let mut plain = ReplayStream::new();
+        plain.time_range(Some(200), Some(250));
+        assert_eq!(
+            ids(&run(&mut plain, &mut source(500, events()))),
+            vec![LEVEL_CHUNK_WITH_LIGHT_PACKET_ID; 2]
+        );
+
+        let mut stream = ReplayStream::new();
+        stream
+            .time_range(Some(200), Some(250))
+            .keep_chunk_batches(true);
+        let out = run(&mut stream, &mut source(500, events()));
+        assert_eq!(
+            ids(&out),
+            vec![
+                CHUNK_BATCH_START_PACKET_ID,
+                LEVEL_CHUNK_WITH_LIGHT_PACKET_ID,
+                LEVEL_CHUNK_WITH_LIGHT_PACKET_ID,
+                CHUNK_BATCH_FINISHED_PACKET_ID,
+            ]
+        );
+        let times: Vec<_> = out.iter().map(|e| e.time().as_millis()).collect();
+        // 範囲の開始より前の batch 開始は時刻 0 に詰まる
+        assert_eq!(times, vec![0, 0, 50, 100]);
+
+        // id の前提と違う protocol の入力は断る
+        let mut other = source(500, events());
+        other.info.protocol_version = 767;
+        let err = stream.stream(&mut other, |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("keep_chunk_batches"), "{err}");
+    }
+
+    #[test]
+    fn drop_initial_chunks_removes_only_the_join_flood() {
+        let events = vec![
+            packet(0, State::Play, LOGIN_PLAY_PACKET_ID),
+            packet(0, State::Play, 0x3c),
+            packet(10, State::Play, CHUNK_BATCH_START_PACKET_ID),
+            packet(10, State::Play, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID),
+            packet(10, State::Play, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID),
+            packet(10, State::Play, CHUNK_BATCH_FINISHED_PACKET_ID),
+            packet(20, State::Play, PLAYER_POSITION_PACKET_ID),
+            packet(500, State::Play, CHUNK_BATCH_START_PACKET_ID),
+            packet(500, State::Play, LEVEL_CHUNK_WITH_LIGHT_PACKET_ID),
+            packet(500, State::Play, CHUNK_BATCH_FINISHED_PACKET_ID),
+        ];
+        let mut stream = ReplayStream::new();
+        stream.drop_initial_chunks(true);
+        assert_eq!(
+            ids(&run(&mut stream, &mut source(1000, events.clone()))),
+            vec![
+                LOGIN_PLAY_PACKET_ID,
+                0x3c,
+                PLAYER_POSITION_PACKET_ID,
+                CHUNK_BATCH_START_PACKET_ID,
+                LEVEL_CHUNK_WITH_LIGHT_PACKET_ID,
+                CHUNK_BATCH_FINISHED_PACKET_ID,
+            ]
+        );
+
+        // id の前提と違う protocol の入力は断る
+        let mut other = source(1000, events);
+        other.info.protocol_version = 767;
+        let err = stream.stream(&mut other, |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("drop_initial_chunks"), "{err}");
+    }
+
+    #[test]
+    fn downsample_movement_collapses_moves_into_teleport() {
+        let play = |time_ms, id, data: Vec<u8>| Event::Packet {
+            time: Time::from_millis(time_ms),
+            state: State::Play,
+            id,
+            data: data.into(),
+        };
+        let teleport_body = |entity: i32, position: [f64; 3], rotation: [f32; 2]| {
+            let mut data = Vec::new();
+            data.write_varint(entity).unwrap();
+            for axis in position.into_iter().chain([0.0; 3]) {
+                data.extend_from_slice(&axis.to_be_bytes());
+            }
+            for angle in rotation {
+                data.extend_from_slice(&angle.to_be_bytes());
+            }
+            data.push(1);
+            data
+        };
+        let moved = |time_ms, entity: i32, dx: i16| {
+            let mut data = Vec::new();
+            data.write_varint(entity).unwrap();
+            for delta in [dx, 0, 0] {
+                data.extend_from_slice(&delta.to_be_bytes());
+            }
+            data.push(1);
+            play(time_ms, MOVE_ENTITY_POS_PACKET_ID, data)
+        };
+        let events = vec![
+            play(
+                0,
+                ENTITY_POSITION_SYNC_PACKET_ID,
+                teleport_body(5, [0.0, 64.0, 0.0], [90.0, 0.0]),
+            ),
+            // tick 4..=7 (窓 1 つ分) の 4 回の移動
+            moved(200, 5, 4096),
+            moved(250, 5, 4096),
+            moved(300, 5, 2048),
+            moved(350, 5, -1024),
+            // 基準の位置が分からないエンティティはそのまま
+            moved(360, 9, 4096),
+        ];
+        let mut stream = ReplayStream::new();
+        stream.downsample_movement(4);
+        let out = run(&mut stream, &mut source(400, events));
+
+        assert_eq!(
+            ids(&out),
+            vec![
+                ENTITY_POSITION_SYNC_PACKET_ID,
+                MOVE_ENTITY_POS_PACKET_ID,
+                ENTITY_POSITION_SYNC_PACKET_ID,
+            ]
+        );
+        let Event::Packet { time, data, .. } = &out[2] else {
+            unreachable!()
+        };
+        assert_eq!(time.as_millis(), 350);
+        assert_eq!(
+            data.as_ref(),
+            teleport_body(5, [2.25, 64.0, 0.0], [90.0, 0.0]).as_slice()
+        );
+
+        // 窓の途中で消えたエンティティの移動は、窓の終わりに出さない
+        let mut removed = Vec::new();
+        removed.write_varint(1).unwrap();
+        removed.write_varint(5).unwrap();
+        let events = vec![
+            play(
+                0,
+                ENTITY_POSITION_SYNC_PACKET_ID,
+                teleport_body(5, [0.0, 64.0, 0.0], [90.0, 0.0]),
+            ),
+            moved(200, 5, 4096),
+            play(250, REMOVE_ENTITIES_PACKET_ID, removed),
+            moved(300, 5, 4096),
+            packet(400, State::Play, 0x2c),
+        ];
+        let mut stream = ReplayStream::new();
+        stream.downsample_movement(4);
+        let out = run(&mut stream, &mut source(400, events));
+        assert_eq!(
+            ids(&out),
+            vec![
+                ENTITY_POSITION_SYNC_PACKET_ID,
+                REMOVE_ENTITIES_PACKET_ID,
+                MOVE_ENTITY_POS_PACKET_ID,
+                0x2c,
+            ]
+        );
+
+        // id の前提と違う protocol の入力は断る
+        let mut other = source(400, vec![moved(200, 5, 4096)]);
+        other.info.protocol_version = 767;
+        let err = ReplayStream::new()
+            .downsample_movement(4)
+            .stream(&mut other, |_| Ok(()))
+            .unwrap_err();
+        assert!(err.to_string().contains("downsample_movement"), "{err}");
+        assert!(err.to_string().contains("protocol 767"), "{err}");
+    }
+
+    #[test]
+    fn camera_only_keeps_self_movement_and_chunks() {
+        const LEVEL_PARTICLES: i32 = 0x29;
+        let moved = |entity: i32| {
+            let mut data = Vec::new();
+            data.write_varint(entity).unwrap();
+            data.extend_from_slice(&[0; 7]);
+            Event::Packet {
+                time: Time::from_millis(0),
+                state: State::Play,
+                id: MOVE_ENTITY_POS_PACKET_ID,
+                data: data.into(),
+            }
+        };
+        let events = || {
+            vec![
+                packet(0, State::Login, 0x02),
+                packet(0, State::Play, LOGIN_PLAY_PACKET_ID),
+                packet(0, State::Play, 0x27),
+                moved(8),
+                moved(7),
+                packet(0, State::Play, LEVEL_PARTICLES),
+            ]
+        };
+        let input = || {
+            let mut input = source(0, events());
+            input.info.protocol_version = PACKET_ID_PROTOCOL;
+            input
+        };
+        let mut stream = ReplayStream::new();
+        stream.camera_only(7);
+        let out = run(&mut stream, &mut input());
+        assert_eq!(
+            ids(&out),
+            vec![0x02, LOGIN_PLAY_PACKET_ID, 0x27, MOVE_ENTITY_POS_PACKET_ID]
+        );
+        let Event::Packet { data, .. } = &out[3] else {
+            unreachable!()
+        };
+        assert_eq!(data[0], 7);
+
+        // 残す id 集合を差し替えても、エンティティの絞り込みは変わらない
+        let mut stream = ReplayStream::new();
+        stream.camera_only_with(7, [LEVEL_PARTICLES]);
+        assert_eq!(
+            ids(&run(&mut stream, &mut input())),
+            vec![0x02, MOVE_ENTITY_POS_PACKET_ID, LEVEL_PARTICLES]
+        );
+        assert!(!stream.is_passthrough());
+
+        // id の前提と違う protocol の入力は断る
+        let mut other = source(0, events());
+        other.info.protocol_version = 767;
+        let err = ReplayStream::new()
+            .camera_only(7)
+            .stream(&mut other, |_| Ok(()))
+            .unwrap_err();
+        assert!(err.to_string().contains("protocol 767"));
+    }
+
+    #[test]
+    fn remap_dimensions_rewrites_respawn_dimension() {
+        let respawn = |dimension: &str| {
+            let mut data = Vec::new();
+            data.write_varint(0).unwrap();
+            data.write_string(dimension).unwrap();
+            data.extend_from_slice(&42i64.to_be_bytes());
+            data
+        };
+        let event = |data: Vec<u8>| Event::Packet {
+            time: Time::ZERO,
+            state: State::Play,
+            id: RESPAWN_PACKET_ID,
+            data: data.into(),
+        };
+        let events = vec![
+            event(respawn("minecraft:overworld")),
+            event(respawn("minecraft:the_nether")),
+            // 読めない body は触らない
+            event(vec![0x00, 0x7f]),
+        ];
+        let mut stream = ReplayStream::new();
+        stream.remap_dimensions(HashMap::from([(
+            "minecraft:overworld".to_string(),
+            "replay:world_b".to_string(),
+        )]));
+        let out = run(&mut stream, &mut source(0, events.clone()));
+        let bodies: Vec<_> = out
+            .iter()
+            .map(|e| match e {
+                Event::Packet { data, .. } => data.to_vec(),
+                Event::Custom { .. } => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            bodies,
+            vec![
+                respawn("replay:world_b"),
+                respawn("minecraft:the_nether"),
+                vec![0x00, 0x7f],
+            ]
+        );
+
+        // body の並びの前提と違う protocol の入力は断る
+        let mut other = source(0, events);
+        other.info.protocol_version = 767;
+        let err = stream.stream(&mut other, |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("remap_dimensions"), "{err}");
+    }
+
+    #[test]
+    fn remap_login_dimensions_rewrites_list_and_current() {
+        let login = |names: &[&str], current: &str| {
+            let mut data = Vec::new();
+            data.extend_from_slice(&7i32.to_be_bytes());
+            data.push(0);
+            data.write_varint(names.len() as i32).unwrap();
+            for name in names {
+                data.write_string(name).unwrap();
+            }
+            data.extend_from_slice(&[20, 10, 10, 0, 1, 0, 3]);
+            data.write_string(current).unwrap();
+            data.extend_from_slice(&[0xaa; 4]);
+            data
+        };
+        let map = HashMap::from([("a:x".to_string(), "b:x".to_string())]);
+        assert_eq!(
+            remap_login_dimensions(&login(&["a:x", "a:y"], "a:x"), &map).unwrap(),
+            login(&["b:x", "a:y"], "b:x")
+        );
+    }
+
+    #[test]
+    fn set_view_distance_rewrites_login_play() {
+        let login = |view: i32, simulation: i32| {
+            let mut data = Vec::new();
+            data.extend_from_slice(&7i32.to_be_bytes());
+            data.push(0);
+            data.write_varint(1).unwrap();
+            data.write_string("minecraft:overworld").unwrap();
+            data.write_varint(20).unwrap();
+            data.write_varint(view).unwrap();
+            data.write_varint(simulation).unwrap();
+            data.extend_from_slice(&[0, 1, 0, 3]);
+            Event::Packet {
+                time: Time::from_millis(0),
+                state: State::Play,
+                id: LOGIN_PLAY_PACKET_ID,
+                data: data.into(),
+            }
+        };
+        let mut input = source(0, vec![login(200, 12)]);
+        input.info.protocol_version = 770;
+        let mut stream = ReplayStream::new();
+        stream.set_view_distance(8);
+        let out = run(&mut stream, &mut input);
+
+        let Event::Packet { data, .. } = &out[0] else {
+            unreachable!()
+        };
+        assert_eq!(
+            read_view_distances(data, 770).unwrap(),
+            ViewDistances {
+                view_distance: 8,
+                simulation_distance: 8,
+            }
+        );
+        // 距離以外のフィールドはそのまま
+        let Event::Packet { data: expected, .. } = login(8, 8) else {
+            unreachable!()
+        };
+        assert_eq!(data, &expected);
+        // registry codec を挟む古い構成は読まない
+        assert!(read_view_distances(&expected, 763).is_err());
+    }
+
+    #[test]
+    fn estimate_output_size_matches_written_tmcpr() {
+        let input = || {
+            let mut events = vec![
+                packet(0, State::Login, 0x02),
+                packet(0, State::Play, LOGIN_PLAY_PACKET_ID),
+            ];
+            for i in 0..10 {
+                events.push(Event::Packet {
+                    time: Time::from_millis(i * 50),
+                    state: State::Play,
+                    id: 0x2c + (i % 3) as i32,
+                    data: vec![0xab; i as usize * 7].into(),
+                });
+            }
+            events.push(Event::Packet {
+                time: Time::from_millis(600),
+                state: State::Play,
+                id: 0x1234,
+                data: Box::new([1, 2, 3]),
+            });
+            events
+        };
+        let mut stream = ReplayStream::new();
+        stream.exclude_packets([0x2d]).unknown_packets(false);
+
+        let mut sources = [source(600, input()), source(600, input())];
+        let estimate = stream.estimate_output_size(&mut sources).unwrap();
+        // 見積もりは stream の状態を進めない
+        assert!(stream.merged_info().is_none());
+
+        let mut written = Vec::new();
+        for mut source in [source(600, input()), source(600, input())] {
+            for event in run(&mut stream, &mut source) {
+                if let Event::Packet { time, id, data, .. } = event {
+                    Packet::new(time.as_millis() as u32, id, data)
+                        .write_to(&mut written)
+                        .unwrap();
+                }
+            }
+        }
+        assert_eq!(estimate, written.len() as u64);
+    }
+
+    #[test]
+    fn stream_to_archive_writes_recording_and_metadata() {
+        use crate::{archive::testing::MemArchive, mcpr::ReplayReader};
+
+        let events = vec![
+            packet(0, State::Login, 0x02),
+            packet(100, State::Play, 0x2c),
+            packet(200, State::Play, 0x2d),
+        ];
+        let mut stream = ReplayStream::new();
+        stream.exclude_packets([0x2d]);
+        let metadata = MetaData {
+            serverName: "test".to_string(),
+            ..Default::default()
+        };
+        let mut archive = stream
+            .stream_to_archive([&mut source(500, events)], MemArchive::default(), metadata)
+            .unwrap();
+
+        let mut reader = ReplayReader::new(&mut archive);
+        let metadata = reader.read_metadata().unwrap();
+        assert_eq!(metadata.serverName, "test");
+        assert_eq!(metadata.duration, 500);
+        let packets: Vec<_> = reader
+            .get_packet_reader()
+            .unwrap()
+            .map(|(_, p)| (p.time(), p.id()))
+            .collect();
+        assert_eq!(packets, vec![(0, 0x02), (100, 0x2c)]);
+    }
+
+    #[test]
+    fn identity_stream_matches_full_path() {
+        let inputs = || {
+            [
+                vec![
+                    packet(0, State::Login, 0x02),
+                    packet(0, State::Play, LOGIN_PLAY_PACKET_ID),
+                    packet(100, State::Play, 0x2c),
+                ],
+                vec![
+                    packet(0, State::Login, 0x02),
+                    packet(0, State::Play, LOGIN_PLAY_PACKET_ID),
+                    packet(50, State::Play, 0x2d),
+                ],
+            ]
+        };
+        let mut fast = ReplayStream::new();
+        fast.speed(PlaybackSpeed::new(2.0).unwrap());
+        assert!(fast.is_identity());
+        // 上限は実質何も落とさないが、全段を通る経路になる
+        let mut full = ReplayStream::new();
+        full.speed(PlaybackSpeed::new(2.0).unwrap())
+            .limit_packets(usize::MAX);
+        assert!(!full.is_identity());
+
+        let mut outputs = [Vec::new(), Vec::new()];
+        for (stream, out) in [&mut fast, &mut full].into_iter().zip(&mut outputs) {
+            for events in inputs() {
+                out.extend(run(stream, &mut source(200, events)));
+            }
+        }
+        assert_eq!(outputs[0], outputs[1]);
+        assert_eq!(
+            ids(&outputs[0]),
+            vec![0x02, LOGIN_PLAY_PACKET_ID, 0x2c, 0x2d]
+        );
+        assert_eq!(fast.offset_ms, full.offset_ms);
+    }
+
+    #[test]
+    fn passthrough_copies_frames_without_decoding() {
+        let mut input = Vec::new();
+        // 冗長な 2 バイト VarInt の id も再エンコードされない
+        RawFrame::new(0, vec![0x87, 0x00, 0xaa])
+            .write_to(&mut input)
+            .unwrap();
+        for (time, id) in [(0, 0x02), (0, 0x03), (50, 0x2c), (100, 0x2d)] {
+            Packet::new(time, id, vec![id as u8; 4].into())
+                .write_to(&mut input)
+                .unwrap();
+        }
+
+        let stream = ReplayStream::new();
+        assert!(stream.is_passthrough());
+        let mut output = Vec::new();
+        let (copied, decoded) = filter_frames_counted(&stream, &input, &mut output);
+        assert_eq!(copied, 5);
+        assert_eq!(output, input);
+        assert_eq!(decoded, 0);
+
+        // フィルタがあればデコードする
+        let mut stream = ReplayStream::new();
+        stream.exclude_packets([0x2d]);
+        assert!(!stream.is_passthrough());
+        let mut output = Vec::new();
+        let (kept, decoded) = filter_frames_counted(&stream, &input, &mut output);
+        assert_eq!(kept, 4);
+        assert_eq!(decoded, 5);
+    }
+
+    /// [`ReplayStream::filter_frames`] の結果と、id をデコードしたフレーム数。
+    fn filter_frames_counted(
+        stream: &ReplayStream,
+        input: &[u8],
+        output: &mut Vec<u8>,
+    ) -> (usize, usize) {
+        let mut decoded = 0;
+        let kept = stream
+            .filter_frames_with(&mut &input[..], output, || decoded += 1)
+            .unwrap();
+        (kept, decoded)
+    }
+
+    #[test]
+    fn identity_stream_frames_copies_bodies_without_decoding() {
+        let mut input = Vec::new();
+        for (time, id) in [(0, 0x02), (0, 0x03), (0, LOGIN_PLAY_PACKET_ID), (100, 0x2c)] {
+            Packet::new(time, id, vec![id as u8; 4].into())
+                .write_to(&mut input)
+                .unwrap();
+        }
+        // 冗長な 2 バイト VarInt の id も再エンコードされない
+        RawFrame::new(200, vec![0xad, 0x00, 0xaa])
+            .write_to(&mut input)
+            .unwrap();
+        let info = ReplayInfo {
+            duration_ms: 200,
+            protocol_version: PACKET_ID_PROTOCOL,
+            ..Default::default()
+        };
+        let frames = |bytes: &[u8]| {
+            let mut reader = bytes;
+            std::iter::from_fn(|| RawFrame::read_from(&mut reader).unwrap()).collect::<Vec<_>>()
+        };
+
+        let mut stream = ReplayStream::new();
+        stream.speed(PlaybackSpeed::new(2.0).unwrap());
+        let mut output = Vec::new();
+        let mut decoded = 0;
+        stream
+            .stream_frames_with(input.as_slice(), info.clone(), &mut output, || decoded += 1)
+            .unwrap();
+        assert_eq!(decoded, 0);
+        let expected: Vec<_> = frames(&input)
+            .into_iter()
+            .map(|frame| RawFrame::new(frame.time / 2, frame.body))
+            .collect();
+        assert_eq!(frames(&output), expected);
+
+        // 2 個目の入力は接続初期化を落とすためにデコードする
+        let mut more = Vec::new();
+        stream
+            .stream_frames_with(input.as_slice(), info, &mut more, || decoded += 1)
+            .unwrap();
+        assert_eq!(decoded, 2);
+        assert_eq!(frames(&more).len(), 2);
+    }
+}
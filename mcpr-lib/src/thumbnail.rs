@@ -0,0 +1,198 @@
+//! `.mcpr` の `thumb` エントリ用のプレースホルダ画像生成。
+//!
+//! 一から生成したリプレイにはサムネイルが無く、ランチャーによっては
+//! 壊れた画像として表示される。ここでは単色の baseline JPEG を
+//! 外部クレート無しで組み立てる。単色なので各 8x8 ブロックは DC 成分だけで
+//! 表せ、AC 成分は常に EOB になる。
+
+/// 単色の baseline JPEG (YCbCr 4:4:4, 量子化テーブル全 1)。
+///
+/// `width` / `height` が 0 の場合は 1 に切り上げる。
+pub fn solid_color_jpeg(width: u16, height: u16, rgb: [u8; 3]) -> Vec<u8> {
+    let width = width.max(1);
+    let height = height.max(1);
+
+    let mut out = Vec::new();
+    // SOI
+    out.extend_from_slice(&[0xff, 0xd8]);
+    // APP0 (JFIF 1.01, アスペクト比 1:1, 埋め込みサムネイル無し)
+    segment(
+        &mut out,
+        0xe0,
+        b"JFIF\0\x01\x01\x00\x00\x01\x00\x01\x00\x00",
+    );
+    // DQT: テーブル 0 を全 1 (DC 値をそのまま持つ)
+    let mut dqt = vec![0x00];
+    dqt.extend_from_slice(&[1; 64]);
+    segment(&mut out, 0xdb, &dqt);
+    // SOF0: 8bit, 3 成分とも サンプリング 1x1 / 量子化テーブル 0
+    let mut sof = vec![8];
+    sof.extend_from_slice(&height.to_be_bytes());
+    sof.extend_from_slice(&width.to_be_bytes());
+    sof.push(3);
+    for component_id in 1..=3 {
+        sof.extend_from_slice(&[component_id, 0x11, 0x00]);
+    }
+    segment(&mut out, 0xc0, &sof);
+    // DHT: DC は JPEG 規格 Annex K の輝度 DC 表、AC は EOB だけの表
+    let mut dht = vec![0x00];
+    dht.extend_from_slice(&DC_BITS);
+    dht.extend_from_slice(&DC_VALUES);
+    dht.push(0x10);
+    dht.extend_from_slice(&AC_BITS);
+    dht.push(EOB);
+    segment(&mut out, 0xc4, &dht);
+    // SOS: 3 成分とも DC/AC テーブル 0、スペクトル 0..63
+    let mut sos = vec![3];
+    for component_id in 1..=3 {
+        sos.extend_from_slice(&[component_id, 0x00]);
+    }
+    sos.extend_from_slice(&[0, 63, 0]);
+    segment(&mut out, 0xda, &sos);
+
+    let dc = ycbcr(rgb).map(|v| (8.0 * (v - 128.0)).round() as i32);
+    let dc_codes = huffman_codes(&DC_BITS);
+    let blocks = width.div_ceil(8) as usize * height.div_ceil(8) as usize;
+    let mut bits = BitWriter::new(&mut out);
+    let mut predictor = [0; 3];
+    for _ in 0..blocks {
+        for (component, &value) in dc.iter().enumerate() {
+            let diff = value - predictor[component];
+            predictor[component] = value;
+            let category = 32 - diff.unsigned_abs().leading_zeros();
+            let (code, len) = dc_codes[category as usize];
+            bits.write(code, len);
+            if category > 0 {
+                // 負の差分は 1 の補数表現
+                let extra = if diff > 0 {
+                    diff
+                } else {
+                    diff + (1 << category) - 1
+                };
+                bits.write(extra as u16, category as u8);
+            }
+            // AC 表の唯一の符号 (長さ 1 の "0") が EOB
+            bits.write(0, 1);
+        }
+    }
+    bits.finish();
+
+    // EOI
+    out.extend_from_slice(&[0xff, 0xd9]);
+    out
+}
+
+/// Annex K.3 輝度 DC 表の符号長ごとの個数とシンボル (カテゴリ 0..=11)。
+const DC_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+/// EOB 1 シンボルのみ (符号長 1)。
+const AC_BITS: [u8; 16] = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const EOB: u8 = 0x00;
+
+fn segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    out.extend_from_slice(&[0xff, marker]);
+    out.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// JFIF の RGB → YCbCr 変換。
+fn ycbcr([r, g, b]: [u8; 3]) -> [f32; 3] {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        -0.168_736 * r - 0.331_264 * g + 0.5 * b + 128.0,
+        0.5 * r - 0.418_688 * g - 0.081_312 * b + 128.0,
+    ]
+}
+
+/// 符号長ごとの個数から canonical Huffman 符号 (符号, 長さ) を組み立てる。
+/// シンボルは出現順に 0, 1, 2, ... と振られている前提。
+fn huffman_codes(bits: &[u8; 16]) -> Vec<(u16, u8)> {
+    let mut codes = Vec::new();
+    let mut code = 0u16;
+    for (i, &count) in bits.iter().enumerate() {
+        for _ in 0..count {
+            codes.push((code, i as u8 + 1));
+            code += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+/// エントロピー符号化データ用の MSB-first ビット列 (0xff の後に 0x00 を挿入)。
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    acc: u32,
+    len: u8,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(out: &'a mut Vec<u8>) -> Self {
+        Self {
+            out,
+            acc: 0,
+            len: 0,
+        }
+    }
+    fn write(&mut self, value: u16, len: u8) {
+        self.acc = (self.acc << len) | (value as u32 & ((1 << len) - 1));
+        self.len += len;
+        while self.len >= 8 {
+            self.len -= 8;
+            self.push_byte((self.acc >> self.len) as u8);
+        }
+    }
+    fn push_byte(&mut self, byte: u8) {
+        self.out.push(byte);
+        if byte == 0xff {
+            self.out.push(0x00);
+        }
+    }
+    /// 端数ビットを 1 で埋めて書き出す。
+    fn finish(mut self) {
+        if self.len > 0 {
+            let pad = 8 - self.len;
+            self.write((1 << pad) - 1, pad);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// マーカー `marker` のセグメント本体を探す。
+    fn find_segment(jpeg: &[u8], marker: u8) -> Option<&[u8]> {
+        let pos = jpeg.windows(2).position(|w| w == [0xff, marker])?;
+        let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        Some(&jpeg[pos + 4..pos + 2 + len])
+    }
+
+    #[test]
+    fn has_markers_and_dimensions() {
+        let jpeg = solid_color_jpeg(20, 10, [255, 0, 0]);
+        assert_eq!(&jpeg[..2], &[0xff, 0xd8]);
+        assert_eq!(&jpeg[jpeg.len() - 2..], &[0xff, 0xd9]);
+        let sof = find_segment(&jpeg, 0xc0).unwrap();
+        assert_eq!(u16::from_be_bytes([sof[1], sof[2]]), 10);
+        assert_eq!(u16::from_be_bytes([sof[3], sof[4]]), 20);
+    }
+
+    #[test]
+    fn scan_encodes_one_dc_per_block() {
+        // 中間灰色は全成分 DC 0 → 1 ブロックあたり (DC "00" + EOB "0") x 3 = 9 bit
+        let jpeg = solid_color_jpeg(8, 8, [128, 128, 128]);
+        let sos = jpeg.windows(2).position(|w| w == [0xff, 0xda]).unwrap();
+        let scan = &jpeg[sos + 2 + 12..jpeg.len() - 2];
+        assert_eq!(scan, &[0b0000_0000, 0b0111_1111]);
+    }
+
+    #[test]
+    fn huffman_codes_match_annex_k() {
+        let codes = huffman_codes(&DC_BITS);
+        assert_eq!(codes[0], (0b00, 2));
+        assert_eq!(codes[5], (0b110, 3));
+        assert_eq!(codes[11], (0b1_1111_1110, 9));
+    }
+}
@@ -0,0 +1,65 @@
+//! ワールド時刻 (昼夜サイクル) の時系列。
+//!
+//! Update Time (Set Time, 1.21.5 / protocol 770 では 0x6a) の body は
+//! world age (Long), time of day (Long) と、1.21.2 以降は昼夜が進むかの bool。
+//! 1.21.2 より前は time of day が負なら昼夜サイクルが止まっている
+//! (`doDaylightCycle` false) ことを表し、実際の時刻はその絶対値。
+
+use std::io::{self, Cursor};
+
+use crate::{event::State, mcpr::Packet, protocol::Deserializer, reconstruct::SET_TIME_PACKET_ID};
+
+/// Update Time を順に読み、`(リプレイ上の時刻 ms, world age, time of day)` を返す。
+///
+/// time of day は負の値 (時刻固定) も絶対値に直した、実際の時刻 (tick)。
+pub fn world_time_series(
+    packets: impl IntoIterator<Item = (State, Packet)>,
+) -> io::Result<Vec<(u32, i64, i64)>> {
+    let mut series = Vec::new();
+    for (state, packet) in packets {
+        if state != State::Play || packet.id() != SET_TIME_PACKET_ID {
+            continue;
+        }
+        let mut cursor = Cursor::new(packet.data());
+        let world_age = cursor.read_long()?;
+        let time_of_day = cursor.read_long()?;
+        series.push((packet.time(), world_age, time_of_day.saturating_abs()));
+    }
+    Ok(series)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_time(time: u32, world_age: i64, time_of_day: i64) -> (State, Packet) {
+        let mut data = Vec::new();
+        data.extend_from_slice(&world_age.to_be_bytes());
+        data.extend_from_slice(&time_of_day.to_be_bytes());
+        data.push(1);
+        (
+            State::Play,
+            Packet::new(time, SET_TIME_PACKET_ID, data.into()),
+        )
+    }
+
+    #[test]
+    fn extracts_each_update() {
+        let packets = vec![
+            set_time(0, 100, 6000),
+            (State::Play, Packet::new(10, 0x2c, Box::new([]))),
+            // 時刻固定 (負の time of day)
+            set_time(1000, 120, -18000),
+        ];
+        assert_eq!(
+            world_time_series(packets).unwrap(),
+            vec![(0, 100, 6000), (1000, 120, 18000)]
+        );
+
+        let truncated = vec![(
+            State::Play,
+            Packet::new(0, SET_TIME_PACKET_ID, vec![0; 4].into()),
+        )];
+        assert!(world_time_series(truncated).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+//! アーカイブに入っていない単体の `.tmcpr` ファイルの読み出し。
+//!
+//! 容量節約のため gzip された `.tmcpr` もあるので、先頭の gzip マジックを
+//! 見て透過的に展開する (`gzip` feature)。
+//!
+//! 単体書き出しの整合性検証用に、末尾へ CRC32 フッタを付けた非標準の
+//! 形式も扱う ([`write_packets`] / [`verify_checksum`])。
+//! mmap して読むツール向けに、フレーム先頭を揃えた非標準の形式も書ける
+//! ([`write_aligned`] / [`read_aligned`])。
+//! 録画ツールによっては複数のセッションを 1 つの `.tmcpr` に追記するので、
+//! その境目を [`split_sessions`] で探せる。
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    ops::Range,
+};
+
+#[cfg(feature = "fs")]
+use crate::{event::State, mcpr::ReadablePacketStream};
+use crate::{
+    mcpr::{Packet, RawFrame},
+    protocol::{Deserializer, LOGIN_SUCCESS_PACKET_ID},
+};
+
+/// gzip ストリームの先頭 2 バイト。
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// チェックサムフッタ付きファイルの拡張子。通常の `.tmcpr` と区別する。
+pub const CHECKSUM_EXTENSION: &str = "tmcprc";
+/// チェックサムフッタの先頭マーカー。直後に全フレームの CRC32 (BE) が続く。
+pub const CHECKSUM_MAGIC: [u8; 8] = *b"TMCPRCRC";
+const FOOTER_LEN: usize = CHECKSUM_MAGIC.len() + 4;
+
+/// パケット列を `.tmcpr` として書く。
+///
+/// `checksum` が true なら末尾に [`CHECKSUM_MAGIC`] + CRC32 のフッタを付ける。
+/// フッタ付きは通常のリーダーからは壊れた末尾フレームに見えるので、
+/// 拡張子は [`CHECKSUM_EXTENSION`] にすること。
+pub fn write_packets<'a, W: Write>(
+    packets: impl IntoIterator<Item = &'a Packet>,
+    writer: &mut W,
+    checksum: bool,
+) -> io::Result<()> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut frame = Vec::new();
+    for packet in packets {
+        frame.clear();
+        packet.write_to(&mut frame)?;
+        hasher.update(&frame);
+        writer.write_all(&frame)?;
+    }
+    if checksum {
+        writer.write_all(&CHECKSUM_MAGIC)?;
+        writer.write_all(&hasher.finalize().to_be_bytes())?;
+    }
+    Ok(())
+}
+
+/// フッタ付き `.tmcpr` の CRC32 を検証する。
+///
+/// フッタが無ければ `InvalidData` エラー、CRC が合わなければ `Ok(false)`。
+pub fn verify_checksum<R: Read>(mut reader: R) -> io::Result<bool> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let footer_start = bytes
+        .len()
+        .checked_sub(FOOTER_LEN)
+        .filter(|&start| bytes[start..].starts_with(&CHECKSUM_MAGIC))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no checksum footer"))?;
+    let (frames, footer) = bytes.split_at(footer_start);
+    let expected = u32::from_be_bytes(footer[CHECKSUM_MAGIC.len()..].try_into().unwrap());
+    Ok(crc32fast::hash(frames) == expected)
+}
+
+/// パディングフレームの時刻欄。実パケットの時刻としては現れない値を使う。
+pub const PADDING_TIME: u32 = u32::MAX;
+const FRAME_HEADER_LEN: usize = 8;
+
+/// 各フレームのヘッダ位置が `align` バイトの倍数になるように書く。
+///
+/// 隙間には時刻 [`PADDING_TIME`]・中身ゼロ埋めのパディングフレームを挟む
+/// (ヘッダ 8 バイトが入らない隙間は `align` ずつ広げる)。
+/// **非標準の形式**で、Replay Mod や通常のリーダーはパディングを id 0 の
+/// パケットとして読んでしまう。読むときは [`read_aligned`] を使うこと。
+pub fn write_aligned<'a, W: Write>(
+    packets: impl IntoIterator<Item = &'a Packet>,
+    writer: &mut W,
+    align: usize,
+) -> io::Result<()> {
+    if align == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "alignment must be non-zero",
+        ));
+    }
+    let mut offset = 0usize;
+    for packet in packets {
+        let mut gap = (align - offset % align) % align;
+        if gap != 0 {
+            while gap < FRAME_HEADER_LEN {
+                gap += align;
+            }
+            RawFrame::new(PADDING_TIME, vec![0; gap - FRAME_HEADER_LEN]).write_to(writer)?;
+            offset += gap;
+        }
+        let frame = RawFrame::from(packet);
+        frame.write_to(writer)?;
+        offset += FRAME_HEADER_LEN + frame.body.len();
+    }
+    Ok(())
+}
+
+/// [`write_aligned`] で書いたファイルを、パディングフレームを飛ばして読む。
+pub fn read_aligned<R: Read>(mut reader: R) -> io::Result<Vec<Packet>> {
+    let mut packets = Vec::new();
+    while let Some(frame) = RawFrame::read_from(&mut reader)? {
+        if frame.time != PADDING_TIME {
+            packets.push(frame.into_packet()?);
+        }
+    }
+    Ok(packets)
+}
+
+/// 展開済みの `.tmcpr` 全体から、パケットを末尾から先頭の順で返す。
+///
+/// フレームは前からしか辿れないので、先に全フレームの開始位置の索引を作る。
+/// そのためバッファ全体がメモリ上に必要。最終状態だけ見たいツール向け。
+/// 途中で切れたフレームがあれば `UnexpectedEof`。
+pub fn read_reversed(bytes: &[u8]) -> io::Result<Vec<Packet>> {
+    let mut offsets = Vec::new();
+    let mut offset = 0usize;
+    while offset < bytes.len() {
+        let header = bytes
+            .get(offset..offset + FRAME_HEADER_LEN)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame header")
+            })?;
+        let length = u32::from_be_bytes(header[4..].try_into().unwrap()) as usize;
+        let end = offset + FRAME_HEADER_LEN + length;
+        if end > bytes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated frame body",
+            ));
+        }
+        offsets.push(offset);
+        offset = end;
+    }
+    offsets
+        .into_iter()
+        .rev()
+        .map(|offset| {
+            let frame =
+                RawFrame::read_from(&mut &bytes[offset..])?.expect("frame was indexed above");
+            frame.into_packet()
+        })
+        .collect()
+}
+
+/// 連結された `.tmcpr` の中の 1 セッション分の範囲。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionRange {
+    /// フレームの通し番号の範囲。
+    pub frames: Range<usize>,
+    /// ファイル先頭からのバイト位置の範囲 (そのまま切り出せば単体の `.tmcpr` になる)。
+    pub bytes: Range<u64>,
+}
+
+/// 時刻がこれ以上巻き戻ったら、新しいセッションの始まりとみなす (ms)。
+pub const SESSION_RESET_THRESHOLD_MS: u32 = 1000;
+
+/// 複数セッションを追記した `.tmcpr` をセッションごとの範囲に区切る。
+///
+/// 直前のフレームより時刻が [`SESSION_RESET_THRESHOLD_MS`] 以上巻き戻り、
+/// かつ id が Login Success のフレームを新しいセッションの先頭とする
+/// (単独のパケットの時刻の乱れでは区切らない)。空のファイルは空の Vec。
+/// 途中で切れたフレームがあれば `UnexpectedEof`。
+pub fn split_sessions<R: Read>(mut reader: R) -> io::Result<Vec<SessionRange>> {
+    let mut sessions = Vec::new();
+    let mut start = (0usize, 0u64);
+    let (mut index, mut offset) = (0usize, 0u64);
+    let mut previous_time = 0u32;
+    while let Some(frame) = RawFrame::read_from(&mut reader)? {
+        let id = (&frame.body[..]).read_varint()?;
+        if index > 0
+            && id == LOGIN_SUCCESS_PACKET_ID
+            && previous_time.saturating_sub(frame.time) >= SESSION_RESET_THRESHOLD_MS
+        {
+            sessions.push(SessionRange {
+                frames: start.0..index,
+                bytes: start.1..offset,
+            });
+            start = (index, offset);
+        }
+        previous_time = frame.time;
+        index += 1;
+        offset += (FRAME_HEADER_LEN + frame.body.len()) as u64;
+    }
+    if index > start.0 {
+        sessions.push(SessionRange {
+            frames: start.0..index,
+            bytes: start.1..offset,
+        });
+    }
+    Ok(sessions)
+}
+
+/// `.tmcpr` のバイト列を、gzip なら展開して返す。
+pub fn decode<'a, R: Read + 'a>(reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    let is_gzip = reader.fill_buf()?.starts_with(&GZIP_MAGIC);
+    if !is_gzip {
+        return Ok(Box::new(reader));
+    }
+    #[cfg(feature = "gzip")]
+    {
+        Ok(Box::new(BufReader::new(flate2::bufread::GzDecoder::new(
+            reader,
+        ))))
+    }
+    #[cfg(not(feature = "gzip"))]
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "gzip-compressed .tmcpr requires the `gzip` feature",
+        ))
+    }
+}
+
+/// 単体の `.tmcpr` (gzip 可) をパケット列として開く。
+#[cfg(feature = "fs")]
+pub fn open<P: AsRef<std::path::Path>>(path: P) -> io::Result<ReadablePacketStream<Box<dyn Read>>> {
+    let file = std::fs::File::open(path)?;
+    Ok(ReadablePacketStream::new(State::Login, decode(file)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event::State,
+        mcpr::{Packet, ReadablePacketStream},
+    };
+
+    fn tmcpr() -> (Vec<Packet>, Vec<u8>) {
+        let packets = vec![
+            Packet::new(0, 0x02, Box::new([])),
+            Packet::new(0, 0x03, Box::new([])),
+            Packet::new(50, 0x2c, vec![0x1f, 0x8b, 0x00].into()),
+        ];
+        let mut bytes = Vec::new();
+        for packet in &packets {
+            packet.write_to(&mut bytes).unwrap();
+        }
+        (packets, bytes)
+    }
+
+    #[test]
+    fn checksum_detects_corruption() {
+        let (packets, plain) = tmcpr();
+        let mut bytes = Vec::new();
+        write_packets(&packets, &mut bytes, true).unwrap();
+        assert_eq!(&bytes[..plain.len()], plain.as_slice());
+        assert!(verify_checksum(bytes.as_slice()).unwrap());
+
+        // フレーム部分の 1 バイトを壊す
+        bytes[10] ^= 0x01;
+        assert!(!verify_checksum(bytes.as_slice()).unwrap());
+
+        // フッタ無しは検証できない
+        assert!(verify_checksum(plain.as_slice()).is_err());
+    }
+
+    #[test]
+    fn aligned_frames_start_on_boundaries() {
+        let (packets, _) = tmcpr();
+        for align in [1, 4, 16, 64] {
+            let mut bytes = Vec::new();
+            write_aligned(&packets, &mut bytes, align).unwrap();
+
+            // ヘッダを辿り、パディング以外のフレーム先頭位置を確かめる
+            let mut offset = 0;
+            let mut real = 0;
+            while offset < bytes.len() {
+                let time = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+                let len = u32::from_be_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+                if time != PADDING_TIME {
+                    assert_eq!(offset % align, 0, "align {align}");
+                    real += 1;
+                }
+                offset += 8 + len as usize;
+            }
+            assert_eq!(real, packets.len());
+            assert_eq!(read_aligned(bytes.as_slice()).unwrap(), packets);
+        }
+    }
+
+    #[test]
+    fn read_reversed_yields_last_first() {
+        let (packets, bytes) = tmcpr();
+        let reversed = read_reversed(&bytes).unwrap();
+        assert_eq!(reversed, packets.into_iter().rev().collect::<Vec<_>>());
+        assert!(reversed.windows(2).all(|w| w[0].time() >= w[1].time()));
+
+        assert!(read_reversed(&bytes[..bytes.len() - 1]).is_err());
+        assert!(read_reversed(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn concatenated_sessions_are_split() {
+        let (mut first, mut bytes) = tmcpr();
+        first.push(Packet::new(5000, 0x2c, Box::new([])));
+        first.last().unwrap().write_to(&mut bytes).unwrap();
+        let first_len = bytes.len() as u64;
+        let second = vec![
+            Packet::new(0, 0x02, Box::new([])),
+            Packet::new(0, 0x03, Box::new([])),
+            // 少しの巻き戻りや Login Success 以外では区切らない
+            Packet::new(3000, 0x2c, Box::new([])),
+            Packet::new(2990, 0x2c, Box::new([])),
+            Packet::new(500, 0x2c, Box::new([])),
+        ];
+        write_packets(&second, &mut bytes, false).unwrap();
+
+        let sessions = split_sessions(bytes.as_slice()).unwrap();
+        assert_eq!(
+            sessions,
+            vec![
+                SessionRange {
+                    frames: 0..first.len(),
+                    bytes: 0..first_len,
+                },
+                SessionRange {
+                    frames: first.len()..first.len() + second.len(),
+                    bytes: first_len..bytes.len() as u64,
+                },
+            ]
+        );
+        let range = &sessions[1].bytes;
+        let packets = read_all(Box::new(&bytes[range.start as usize..range.end as usize]));
+        assert_eq!(packets, second);
+
+        assert!(split_sessions(&[][..]).unwrap().is_empty());
+    }
+
+    fn read_all(reader: Box<dyn Read + '_>) -> Vec<Packet> {
+        ReadablePacketStream::new(State::Login, reader)
+            .map(|(_, packet)| packet)
+            .collect()
+    }
+
+    #[test]
+    fn plain_is_passed_through() {
+        let (packets, bytes) = tmcpr();
+        assert_eq!(read_all(decode(bytes.as_slice()).unwrap()), packets);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_is_decompressed() {
+        use std::io::Write;
+
+        let (packets, bytes) = tmcpr();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        let gz = encoder.finish().unwrap();
+        assert_eq!(read_all(decode(gz.as_slice()).unwrap()), packets);
+    }
+
+    #[cfg(all(feature = "gzip", feature = "fs"))]
+    #[test]
+    fn open_reads_plain_and_gzip_files_identically() {
+        use std::io::Write;
+
+        let (packets, bytes) = tmcpr();
+        let dir = std::env::temp_dir();
+        let plain = dir.join("mcpr_editor_tmcpr_open_plain.tmcpr");
+        let gzip = dir.join("mcpr_editor_tmcpr_open_gzip.tmcpr");
+        std::fs::write(&plain, &bytes).unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&gzip).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(&bytes).unwrap();
+        encoder.finish().unwrap();
+
+        let from_plain: Vec<_> = open(&plain).unwrap().map(|(_, p)| p).collect();
+        let from_gzip: Vec<_> = open(&gzip).unwrap().map(|(_, p)| p).collect();
+        assert_eq!(from_plain, packets);
+        assert_eq!(from_gzip, packets);
+        std::fs::remove_file(plain).unwrap();
+        std::fs::remove_file(gzip).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+//! 変換パイプラインで起きた「落としたもの」の集計。
+//!
+//! mcpr ↔ flashback の変換では表現できない action やパケットが必ず出る。
+//! stderr へ逐次出すのではなく種別・詳細ごとに件数を数えて持ち、
+//! 変換結果と一緒に呼び出し側へ渡す。
+
+use std::{collections::BTreeMap, fmt};
+
+/// 警告の種別。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WarningKind {
+    /// 出力フォーマットに対応物が無く落としたパケット。
+    SkippedPacket,
+    /// 出力フォーマットに対応物が無く落とした Custom イベント (flashback action)。
+    SkippedCustom,
+}
+
+/// 集計済みの警告 1 件。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// 落としたものの識別 (action 名、`Login 0x02` など)。
+    pub detail: String,
+    pub count: usize,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            WarningKind::SkippedPacket => "packet",
+            WarningKind::SkippedCustom => "action",
+        };
+        write!(f, "skipped {} {} (x{})", what, self.detail, self.count)
+    }
+}
+
+/// (種別, 詳細) ごとの件数。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Warnings {
+    counts: BTreeMap<(WarningKind, String), usize>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn record(&mut self, kind: WarningKind, detail: &str) {
+        *self.counts.entry((kind, detail.to_string())).or_default() += 1;
+    }
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+    /// `kind` の件数の合計。
+    pub fn total(&self, kind: WarningKind) -> usize {
+        self.counts
+            .iter()
+            .filter(|((k, _), _)| *k == kind)
+            .map(|(_, count)| count)
+            .sum()
+    }
+    /// 特定の (種別, 詳細) の件数。
+    pub fn count(&self, kind: WarningKind, detail: &str) -> usize {
+        self.counts
+            .get(&(kind, detail.to_string()))
+            .copied()
+            .unwrap_or(0)
+    }
+    /// 種別・詳細順に並べた警告。
+    pub fn iter(&self) -> impl Iterator<Item = Warning> + '_ {
+        self.counts.iter().map(|((kind, detail), count)| Warning {
+            kind: *kind,
+            detail: detail.clone(),
+            count: *count,
+        })
+    }
+    /// 別の集計を足し込む (複数入力・複数段の合算用)。
+    pub fn merge(&mut self, other: &Warnings) {
+        for (key, count) in &other.counts {
+            *self.counts.entry(key.clone()).or_default() += count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_by_kind_and_detail() {
+        let mut warnings = Warnings::new();
+        for _ in 0..3 {
+            warnings.record(WarningKind::SkippedCustom, "flashback:action/move_entities");
+        }
+        warnings.record(WarningKind::SkippedPacket, "Login 0x02");
+        assert_eq!(warnings.total(WarningKind::SkippedCustom), 3);
+        assert_eq!(warnings.total(WarningKind::SkippedPacket), 1);
+
+        let mut merged = warnings.clone();
+        merged.merge(&warnings);
+        let list: Vec<_> = merged.iter().collect();
+        assert_eq!(
+            list,
+            vec![
+                Warning {
+                    kind: WarningKind::SkippedPacket,
+                    detail: "Login 0x02".to_string(),
+                    count: 2,
+                },
+                Warning {
+                    kind: WarningKind::SkippedCustom,
+                    detail: "flashback:action/move_entities".to_string(),
+                    count: 6,
+                },
+            ]
+        );
+        assert_eq!(
+            list[1].to_string(),
+            "skipped action flashback:action/move_entities (x6)"
+        );
+    }
+}
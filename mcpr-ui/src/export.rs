@@ -11,7 +11,7 @@
 use std::{collections::BTreeSet, io::Cursor};
 
 use mcpr_lib::{
-    archive::zip::{ZipArchiveReader, ZipArchiveWriter},
+    archive::zip::{EntryCompression, ZipArchiveReader, ZipArchiveWriter, ZipCompressionPolicy},
     event::{EventSink, EventSource, PlaybackSpeed, ReplayFormat, ReplayInfo, Time, detect_format},
     flashback::{FlashbackEventSink, FlashbackReader},
     mcpr::{McprEventSink, ReplayReader},
@@ -111,8 +111,12 @@ impl ExportSink {
         info: &ReplayInfo,
         replay_uuid: uuid::Uuid,
     ) -> anyhow::Result<Self> {
-        // 圧縮レベルは CLI のデフォルト (--compression-level 無指定) に合わせる。
-        let archive = ZipArchiveWriter::new(Cursor::new(Vec::new()), None);
+        // 圧縮設定は CLI のデフォルト (--compression-level 無指定) に合わせる。
+        let policy = match format {
+            ExportFormat::Mcpr => ZipCompressionPolicy::mcpr(None),
+            ExportFormat::Flashback => ZipCompressionPolicy::new(EntryCompression::Deflated(None)),
+        };
+        let archive = ZipArchiveWriter::with_policy(Cursor::new(Vec::new()), policy);
         Ok(match format {
             ExportFormat::Mcpr => {
                 ExportSink::Mcpr(McprEventSink::new(archive, info.protocol_version))